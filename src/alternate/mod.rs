@@ -0,0 +1,248 @@
+// Host- and OS-specific alternate files
+//
+// A managed file or directory can have variants next to it whose names add
+// `##` and a comma-separated list of conditions, in the style of yadm:
+// `.bashrc##host.laptop`, `.tmux.conf##os.linux` or
+// `.gitconfig##class.work,user.alice`. A variant matches when all of its
+// conditions hold on this machine, and the most specific match is deployed
+// at the file's original location. `default` always matches, and the plain
+// file is used when no variant does.
+use crate::config;
+use crate::template;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Separates a file's name from the conditions of a variant
+pub const SEPARATOR: &str = "##";
+
+/// One condition in a variant's name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Condition {
+    Default,
+    Os(String),
+    Arch(String),
+    Distro(String),
+    Class(String),
+    Host(String),
+    User(String),
+}
+
+impl Condition {
+    fn parse(text: &str) -> Option<Self> {
+        if text == "default" {
+            return Some(Condition::Default);
+        }
+        let (kind, value) = text.split_once('.')?;
+        let value = value.to_string();
+        if value.is_empty() {
+            return None;
+        }
+        Some(match kind {
+            "os" | "o" => Condition::Os(value),
+            "arch" | "a" => Condition::Arch(value),
+            "distro" | "d" => Condition::Distro(value),
+            "class" | "c" => Condition::Class(value),
+            "hostname" | "host" | "h" => Condition::Host(value),
+            "user" | "u" => Condition::User(value),
+            _ => return None,
+        })
+    }
+
+    /// How much a match of this condition counts: the more specific to one
+    /// machine, the more it counts
+    fn weight(&self) -> u32 {
+        match self {
+            Condition::Default => 0,
+            Condition::Os(_) | Condition::Arch(_) => 1,
+            Condition::Distro(_) => 2,
+            Condition::Class(_) => 4,
+            Condition::Host(_) => 8,
+            Condition::User(_) => 16,
+        }
+    }
+
+    fn holds(&self, machine: &Machine) -> bool {
+        let same = |a: &str, b: &str| a.eq_ignore_ascii_case(b);
+        match self {
+            Condition::Default => true,
+            Condition::Os(os) => same(os, &machine.os),
+            Condition::Arch(arch) => same(arch, &machine.arch),
+            Condition::Distro(distro) => same(distro, &machine.distro),
+            Condition::Class(class) => machine.classes.iter().any(|c| c == class),
+            Condition::Host(host) => same(host, &machine.hostname),
+            Condition::User(user) => *user == machine.user,
+        }
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::Default => write!(f, "default"),
+            Condition::Os(os) => write!(f, "os is {}", os),
+            Condition::Arch(arch) => write!(f, "arch is {}", arch),
+            Condition::Distro(distro) => write!(f, "distro is {}", distro),
+            Condition::Class(class) => write!(f, "class {} is set", class),
+            Condition::Host(host) => write!(f, "host is {}", host),
+            Condition::User(user) => write!(f, "user is {}", user),
+        }
+    }
+}
+
+/// The facts about this machine that conditions are checked against
+#[derive(Debug, Clone)]
+pub struct Machine {
+    pub hostname: String,
+    pub os: String,
+    pub arch: String,
+    pub distro: String,
+    pub user: String,
+    /// Set with `forge config class NAME[,NAME...]`
+    pub classes: Vec<String>,
+}
+
+impl Machine {
+    pub fn current() -> Self {
+        let classes = config::get_setting(config::CLASS_KEY)
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        Machine {
+            hostname: template::hostname(),
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            distro: distro(),
+            user: template::username(),
+            classes: classes
+                .split(',')
+                .map(str::trim)
+                .filter(|class| !class.is_empty())
+                .map(String::from)
+                .collect(),
+        }
+    }
+}
+
+/// ID of the Linux distribution from /etc/os-release, empty elsewhere
+pub fn distro() -> String {
+    fs::read_to_string("/etc/os-release")
+        .ok()
+        .and_then(|release| {
+            release.lines().find_map(|line| {
+                line.strip_prefix("ID=")
+                    .map(|id| id.trim_matches('"').to_string())
+            })
+        })
+        .unwrap_or_default()
+}
+
+/// A variant of a managed file
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub path: PathBuf,
+    pub conditions: Vec<Condition>,
+}
+
+/// The path a variant stands in for, if `path` is one; a name with unknown
+/// conditions is not a variant
+pub fn base(path: &Path) -> Option<PathBuf> {
+    let name = path.file_name()?.to_str()?;
+    let (base, conditions) = name.split_once(SEPARATOR)?;
+    parse_conditions(conditions)?;
+    (!base.is_empty()).then(|| path.with_file_name(base))
+}
+
+fn parse_conditions(text: &str) -> Option<Vec<Condition>> {
+    text.split(',').map(Condition::parse).collect()
+}
+
+/// Every variant of `base` in its directory, in name order
+pub fn variants(base: &Path) -> Vec<Variant> {
+    let (Some(dir), Some(name)) = (base.parent(), base.file_name().and_then(|n| n.to_str())) else {
+        return Vec::new();
+    };
+    let prefix = format!("{}{}", name, SEPARATOR);
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut variants: Vec<Variant> = entries
+        .flatten()
+        .filter_map(|entry| {
+            let file_name = entry.file_name();
+            let conditions = file_name.to_str()?.strip_prefix(&prefix)?;
+            Some(Variant {
+                path: entry.path(),
+                conditions: parse_conditions(conditions)?,
+            })
+        })
+        .collect();
+    variants.sort_by(|a, b| a.path.cmp(&b.path));
+    variants
+}
+
+/// Which file is deployed for a managed path on this machine
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selection {
+    /// There are no variants; the path itself is deployed
+    Plain,
+    /// The best matching variant
+    Variant { path: PathBuf, reason: String },
+    /// There are variants but none matches, so the plain file is deployed
+    Fallback,
+}
+
+/// Pick the variant of `base` to deploy on `machine`
+pub fn select(base: &Path, machine: &Machine) -> Selection {
+    let variants = variants(base);
+    if variants.is_empty() {
+        return Selection::Plain;
+    }
+
+    let mut best: Option<(u32, &Variant)> = None;
+    for variant in &variants {
+        if !variant.conditions.iter().all(|c| c.holds(machine)) {
+            continue;
+        }
+        let score = variant.conditions.iter().map(Condition::weight).sum();
+        // Ties go to the first in name order
+        if best.is_none_or(|(top, _)| score > top) {
+            best = Some((score, variant));
+        }
+    }
+
+    match best {
+        Some((_, variant)) => Selection::Variant {
+            path: variant.path.clone(),
+            reason: variant
+                .conditions
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        },
+        None => Selection::Fallback,
+    }
+}
+
+/// The file to deploy for a managed path on this machine: its best matching
+/// variant, or the path itself
+pub fn resolve(base: &Path) -> PathBuf {
+    match select(base, &Machine::current()) {
+        Selection::Variant { path, .. } => path,
+        Selection::Plain | Selection::Fallback => base.to_path_buf(),
+    }
+}
+
+/// The path a file is deployed as when it is the one selected among its
+/// alternates on `machine` (a file without alternates always is), or None
+/// when another variant is deployed in its place
+pub fn stands_for(path: &Path, machine: &Machine) -> Option<PathBuf> {
+    let base = base(path).unwrap_or_else(|| path.to_path_buf());
+    let selected = match select(&base, machine) {
+        Selection::Variant { path, .. } => path,
+        Selection::Plain | Selection::Fallback => base.clone(),
+    };
+    (selected == path).then_some(base)
+}
@@ -1,5 +1,8 @@
 // CLI command implementations
 use crate::config;
+use crate::dotfile::{DeployMode, DotFile, backup};
+use crate::forgeignore::{IgnoreRules, Verdict};
+use crate::history;
 use crate::symlink;
 use crate::utils::path_utils;
 use std::env;
@@ -8,7 +11,7 @@ use std::path::{Path, PathBuf};
 use walkdir;
 
 /// Initialize a directory as a forge managed folder
-pub fn init_command(name: Option<&str>, dir: Option<&Path>) {
+pub fn init_command(name: Option<&str>, dir: Option<&Path>, git: bool) {
     // Determine the directory to initialize
     let init_dir = match dir {
         Some(d) => path_utils::normalize(d),
@@ -65,10 +68,26 @@ pub fn init_command(name: Option<&str>, dir: Option<&Path>) {
         }
     }
 
+    // Keep the folder in git, without forge's own metadata
+    if git {
+        match crate::git::init(&init_dir) {
+            Ok(true) => println!("Initialized git repository"),
+            Ok(false) => println!("Using the existing git repository"),
+            Err(e) => {
+                eprintln!("Failed to set up git: {:#}", e);
+                return;
+            }
+        }
+    }
+
     // Add to managed folders
     match config::add_managed_folder(&folder_name, &init_dir) {
-        Ok(_) => {
-            println!("Added '{}' to managed folders", folder_name);
+        Ok(added) => {
+            if added {
+                println!("Added '{}' to managed folders", folder_name);
+            } else {
+                println!("Managed folder '{}' already exists", folder_name);
+            }
             println!("\nForge repository initialized successfully!");
             println!("You can now use 'forge stage' to stage files for tracking.");
         }
@@ -77,8 +96,34 @@ pub fn init_command(name: Option<&str>, dir: Option<&Path>) {
         }
     }
 }
+
+/// Whether the ignore rules keep `path`, which would be managed as `target`,
+/// out of the managed folder; says so, and which rule did it
+fn skip_ignored(rules: &IgnoreRules, path: &Path, target: &Path, is_dir: bool) -> bool {
+    match rules.check(target, is_dir) {
+        Verdict::Ignored(rule) => {
+            println!("Ignored: {} ({})", path.display(), rule);
+            true
+        }
+        _ => false,
+    }
+}
+
+/// Record a deploy mode other than the default for a tracked file
+fn record_mode(target: &Path, mode: Option<DeployMode>) -> rusqlite::Result<()> {
+    match mode {
+        Some(mode) if !mode.is_symlink() => config::set_dotfile_mode(target, mode).map(|_| ()),
+        _ => Ok(()),
+    }
+}
+
 /// Stage files to be tracked for symlinking
-pub fn stage_command(files: &[PathBuf], recursive: bool, max_depth: Option<usize>) {
+pub fn stage_command(
+    files: &[PathBuf],
+    recursive: bool,
+    max_depth: Option<usize>,
+    mode: Option<DeployMode>,
+) {
     if recursive {
         println!("Staging files and directories recursively");
     } else if let Some(depth) = max_depth {
@@ -107,7 +152,7 @@ pub fn stage_command(files: &[PathBuf], recursive: bool, max_depth: Option<usize
     );
 
     if !forge_path.exists() {
-        match fs::create_dir_all(&forge_path) {
+        match history::create_dir_all(&forge_path) {
             Ok(_) => println!("Created forge directory: {}", forge_path.display()),
             Err(e) => {
                 eprintln!(
@@ -120,6 +165,14 @@ pub fn stage_command(files: &[PathBuf], recursive: bool, max_depth: Option<usize
         }
     }
 
+    let rules = match IgnoreRules::for_folder(&forge_path) {
+        Ok(rules) => rules,
+        Err(e) => {
+            eprintln!("Invalid ignore rules: {:#}", e);
+            return;
+        }
+    };
+
     // Process each file or directory
     for file in files {
         // Normalize path
@@ -148,17 +201,26 @@ pub fn stage_command(files: &[PathBuf], recursive: bool, max_depth: Option<usize
                     }
                 );
 
-                // Use walkdir to recursively process directory
+                // Get the directory name to create proper nesting
+                let dir_name = abs_source.file_name().unwrap_or_default();
+                if skip_ignored(&rules, &abs_source, &forge_path.join(dir_name), true) {
+                    continue;
+                }
+
+                // Use walkdir to recursively process directory, leaving out
+                // ignored directories as a whole
                 for entry in walkdir::WalkDir::new(&abs_source)
                     .min_depth(1) // Skip the root dir itself
                     .max_depth(walkdir_depth)
                     .into_iter()
+                    .filter_entry(|e| {
+                        let rel_path = e.path().strip_prefix(&abs_source).unwrap_or(e.path());
+                        let target = forge_path.join(dir_name).join(rel_path);
+                        !skip_ignored(&rules, e.path(), &target, e.file_type().is_dir())
+                    })
                     .filter_map(|e| e.ok())
                     .filter(|e| e.path().is_file())
                 {
-                    // Get the directory name to create proper nesting
-                    let dir_name = abs_source.file_name().unwrap_or_default();
-
                     // Calculate relative path from original directory
                     let rel_path = entry
                         .path()
@@ -172,7 +234,7 @@ pub fn stage_command(files: &[PathBuf], recursive: bool, max_depth: Option<usize
                     // Ensure target parent directory exists
                     if let Some(parent) = target.parent()
                         && !parent.exists()
-                        && let Err(e) = fs::create_dir_all(parent)
+                        && let Err(e) = history::create_dir_all(parent)
                     {
                         eprintln!("Failed to create directory {}: {}", parent.display(), e);
                         continue;
@@ -188,7 +250,7 @@ pub fn stage_command(files: &[PathBuf], recursive: bool, max_depth: Option<usize
                     }
 
                     // Create a symlink from forge folder TO original file (reverse of final state)
-                    match symlink::create_symlink(entry.path(), &target) {
+                    match history::create_symlink(entry.path(), &target) {
                         Ok(_) => {
                             println!(
                                 "Created staging symlink: {} → {}",
@@ -197,7 +259,9 @@ pub fn stage_command(files: &[PathBuf], recursive: bool, max_depth: Option<usize
                             );
 
                             // Add to database as staged
-                            match config::stage_dotfile(entry.path(), &target, None) {
+                            match config::stage_dotfile(entry.path(), &target, None)
+                                .and_then(|_| record_mode(&target, mode))
+                            {
                                 Ok(_) => println!(
                                     "Staged file: {} (use 'link' to make permanent)",
                                     entry.path().display()
@@ -224,6 +288,9 @@ pub fn stage_command(files: &[PathBuf], recursive: bool, max_depth: Option<usize
                 // Extract directory name for the target
                 if let Some(dirname) = file.file_name() {
                     let target = forge_path.join(dirname);
+                    if skip_ignored(&rules, &abs_source, &target, true) {
+                        continue;
+                    }
 
                     // Check if target exists
                     if target.exists() {
@@ -235,7 +302,7 @@ pub fn stage_command(files: &[PathBuf], recursive: bool, max_depth: Option<usize
                     }
 
                     // Create the directory in the forge folder
-                    if let Err(e) = fs::create_dir_all(&target) {
+                    if let Err(e) = history::create_dir_all(&target) {
                         eprintln!("Failed to create directory {}: {}", target.display(), e);
                         continue;
                     }
@@ -243,7 +310,9 @@ pub fn stage_command(files: &[PathBuf], recursive: bool, max_depth: Option<usize
                     println!("Created directory in forge folder: {}", target.display());
 
                     // Add to database as staged directory
-                    match config::stage_dotfile(&abs_source, &target, None) {
+                    match config::stage_dotfile(&abs_source, &target, None)
+                        .and_then(|_| record_mode(&target, mode))
+                    {
                         Ok(_) => println!(
                             "Staged directory: {} (use 'link' to make permanent)",
                             abs_source.display()
@@ -261,6 +330,9 @@ pub fn stage_command(files: &[PathBuf], recursive: bool, max_depth: Option<usize
             // Extract filename for the target
             if let Some(filename) = file.file_name() {
                 let target = forge_path.join(filename);
+                if skip_ignored(&rules, &abs_source, &target, false) {
+                    continue;
+                }
 
                 // Create a temporary symlink (or copy) from forge folder TO original file
                 if target.exists() {
@@ -272,7 +344,7 @@ pub fn stage_command(files: &[PathBuf], recursive: bool, max_depth: Option<usize
                 }
 
                 // Create a symlink from forge folder TO original file (reverse of final state)
-                match symlink::create_symlink(&abs_source, &target) {
+                match history::create_symlink(&abs_source, &target) {
                     Ok(_) => {
                         println!(
                             "Created staging symlink: {} → {}",
@@ -281,7 +353,9 @@ pub fn stage_command(files: &[PathBuf], recursive: bool, max_depth: Option<usize
                         );
 
                         // Add to database as staged
-                        match config::stage_dotfile(&abs_source, &target, None) {
+                        match config::stage_dotfile(&abs_source, &target, None)
+                            .and_then(|_| record_mode(&target, mode))
+                        {
                             Ok(_) => println!(
                                 "Staged file: {} (use 'link' to make permanent)",
                                 abs_source.display()
@@ -304,8 +378,213 @@ pub fn stage_command(files: &[PathBuf], recursive: bool, max_depth: Option<usize
     println!("\nNOTE: Files are only staged. Use 'forge link' to create permanent symlinks.");
 }
 
-/// Create symlinks for all staged/tracked files
-pub fn link_command(files: &[PathBuf]) {
+/// List settings, show one, or change one. Keys starting with `var.` are
+/// user-defined template values.
+pub fn config_command(key: Option<&str>, value: Option<&str>, unset: bool) -> bool {
+    let current = |setting: &config::UserSetting| match config::get_setting(setting.key) {
+        Ok(value) => Ok(value.unwrap_or_else(|| setting.default.to_string())),
+        Err(e) => Err(e),
+    };
+
+    let Some(key) = key else {
+        for setting in config::USER_SETTINGS {
+            match current(setting) {
+                Ok(value) => println!("{} = {}    # {}", setting.key, value, setting.description),
+                Err(e) => {
+                    eprintln!("Failed to read settings: {}", e);
+                    return false;
+                }
+            }
+        }
+        return match config::get_settings_with_prefix(crate::template::VAR_PREFIX) {
+            Ok(vars) => {
+                for (key, value) in vars {
+                    println!("{} = {}", key, value);
+                }
+                true
+            }
+            Err(e) => {
+                eprintln!("Failed to read settings: {}", e);
+                false
+            }
+        };
+    };
+
+    if key.starts_with(crate::template::VAR_PREFIX) {
+        return template_var(key, value, unset);
+    }
+    if unset {
+        eprintln!("Only template values (var.NAME) can be unset");
+        return false;
+    }
+
+    let Some(setting) = config::USER_SETTINGS.iter().find(|s| s.key == key) else {
+        eprintln!("Unknown setting: {}", key);
+        return false;
+    };
+
+    let Some(value) = value else {
+        return match current(setting) {
+            Ok(value) => {
+                println!("{}", value);
+                true
+            }
+            Err(e) => {
+                eprintln!("Failed to read settings: {}", e);
+                false
+            }
+        };
+    };
+
+    let boolean = matches!(setting.default, "true" | "false");
+    if boolean && !matches!(value, "true" | "false") {
+        eprintln!("{} must be true or false", setting.key);
+        return false;
+    }
+    // A key file is found again from any directory
+    let key_file;
+    let value = if setting.key == config::SECRET_KEY_FILE_KEY && !value.is_empty() {
+        key_file = path_utils::normalize(value).to_string_lossy().into_owned();
+        key_file.as_str()
+    } else {
+        value
+    };
+
+    match config::set_setting(setting.key, value) {
+        Ok(()) => {
+            println!("{} = {}", setting.key, value);
+            true
+        }
+        Err(e) => {
+            eprintln!("Failed to save setting: {}", e);
+            false
+        }
+    }
+}
+
+// Show, set or remove a user-defined template value
+fn template_var(key: &str, value: Option<&str>, unset: bool) -> bool {
+    let name = &key[crate::template::VAR_PREFIX.len()..];
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+    {
+        eprintln!("Invalid template value name: {}", key);
+        return false;
+    }
+
+    let result = match (value, unset) {
+        (_, true) => config::remove_setting(key).map(|removed| {
+            if !removed {
+                println!("{} was not set", key);
+            }
+            true
+        }),
+        (Some(value), false) => config::set_setting(key, value).map(|()| {
+            println!("{} = {}", key, value);
+            true
+        }),
+        (None, false) => config::get_setting(key).map(|value| match value {
+            Some(value) => {
+                println!("{}", value);
+                true
+            }
+            None => {
+                eprintln!("{} is not set", key);
+                false
+            }
+        }),
+    };
+    result.unwrap_or_else(|e| {
+        eprintln!("Failed to update settings: {}", e);
+        false
+    })
+}
+
+/// Apply pending database schema migrations, or list them with --dry-run
+pub fn db_migrate_command(dry_run: bool) -> bool {
+    let (current, pending) = match config::pending_migrations() {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Failed to read database schema: {}", e);
+            return false;
+        }
+    };
+
+    if pending.is_empty() {
+        println!("Database schema is up to date (version {})", current);
+        return true;
+    }
+
+    if dry_run {
+        println!(
+            "Database schema is at version {}. Pending migrations:",
+            current
+        );
+        for migration in &pending {
+            println!("  {:>3}  {}", migration.version, migration.description);
+        }
+        return true;
+    }
+
+    match config::migrate_database() {
+        Ok(applied) => {
+            for migration in &applied {
+                println!(
+                    "Applied migration {}: {}",
+                    migration.version, migration.description
+                );
+            }
+            println!(
+                "Database schema is now at version {}",
+                config::migrations::latest_version()
+            );
+            true
+        }
+        Err(e) => {
+            eprintln!("Migration failed, database left unchanged: {}", e);
+            false
+        }
+    }
+}
+
+/// Rewrite stored paths relative to $HOME, $XDG_CONFIG_HOME or their managed folder
+pub fn db_rebase_command(from_home: Option<&Path>, dry_run: bool) -> bool {
+    let from_home = from_home.map(path_utils::normalize);
+    match config::rebase_paths(from_home.as_deref(), dry_run) {
+        Ok(changes) if changes.is_empty() => {
+            println!("All stored paths are already portable");
+            true
+        }
+        Ok(changes) => {
+            for (before, after) in &changes {
+                println!("  {} → {}", before.display(), after.display());
+            }
+            if dry_run {
+                println!("Would rebase {} stored path(s)", changes.len());
+            } else {
+                println!("Rebased {} stored path(s)", changes.len());
+            }
+            true
+        }
+        Err(e) => {
+            eprintln!("Rebase failed, database left unchanged: {}", e);
+            false
+        }
+    }
+}
+
+/// Name of the managed folder commands operate on, if any is registered
+fn active_folder_name() -> Option<String> {
+    config::get_active_managed_folder()
+        .ok()
+        .flatten()
+        .map(|(name, _)| name)
+}
+
+/// Create symlinks (or hard links or copies) for all staged/tracked files
+pub fn link_command(files: &[PathBuf], mode: Option<DeployMode>) {
     println!("Creating symlinks");
 
     // Get the active managed folder
@@ -327,9 +606,18 @@ pub fn link_command(files: &[PathBuf]) {
         forge_path.display()
     );
 
+    // Linked templates are rendered again, encrypted files decrypted again and
+    // links to alternates switched to the variant now selected, picking up
+    // changes since they were deployed
+    let mut refresh = Vec::new();
+
     // Get all staged dotfiles from the database
     let dotfiles = if files.is_empty() {
-        match config::get_staged_dotfiles(None) {
+        match config::get_dotfiles(None, Some(&folder_name)) {
+            Ok(all) => refresh = all.into_iter().filter(needs_refresh).collect(),
+            Err(e) => eprintln!("Error fetching linked files: {}", e),
+        }
+        match config::get_staged_dotfiles(None, Some(&folder_name)) {
             Ok(df) => df,
             Err(e) => {
                 eprintln!("Error fetching staged files: {}", e);
@@ -346,7 +634,7 @@ pub fn link_command(files: &[PathBuf]) {
             // Check if it's a specific file or a directory name
             if abs_path.is_dir() {
                 // If it's a directory, find all staged files under that directory
-                match config::get_staged_dotfiles(None) {
+                match config::get_staged_dotfiles(None, Some(&folder_name)) {
                     Ok(all_dotfiles) => {
                         for df in all_dotfiles {
                             // Check if this file is within the specified directory
@@ -379,7 +667,7 @@ pub fn link_command(files: &[PathBuf]) {
                     let forge_dir_path = forge_path.join(dir_name);
 
                     // Check if dotfiles are in the forge directory with this name
-                    match config::get_staged_dotfiles(None) {
+                    match config::get_staged_dotfiles(None, Some(&folder_name)) {
                         Ok(all_dotfiles) => {
                             for df in all_dotfiles {
                                 if df.is_staged() && df.target.starts_with(&forge_dir_path) {
@@ -405,6 +693,8 @@ pub fn link_command(files: &[PathBuf]) {
                     Ok(Some(df)) => {
                         if df.is_staged() {
                             result.push(df);
+                        } else if needs_refresh(&df) {
+                            refresh.push(df);
                         } else {
                             println!("File already linked: {}", abs_path.display());
                         }
@@ -415,6 +705,8 @@ pub fn link_command(files: &[PathBuf]) {
                             Ok(Some(df)) => {
                                 if df.is_staged() {
                                     result.push(df);
+                                } else if needs_refresh(&df) {
+                                    refresh.push(df);
                                 } else {
                                     println!("File already linked: {}", abs_path.display());
                                 }
@@ -437,228 +729,1426 @@ pub fn link_command(files: &[PathBuf]) {
         result
     };
 
-    if dotfiles.is_empty() {
+    if dotfiles.is_empty() && refresh.is_empty() {
         println!("No files to link. Use 'stage' command to stage files first.");
         return;
     }
 
     let mut success_count = 0;
     let mut error_count = 0;
+    let linking = !dotfiles.is_empty();
 
     // Link each dotfile
-    for dotfile in dotfiles {
+    for mut dotfile in dotfiles {
+        if let Some(mode) = mode
+            && mode != dotfile.mode
+        {
+            if let Err(e) = config::set_dotfile_mode(&dotfile.target, mode) {
+                eprintln!("Failed to set mode of {}: {}", dotfile.target.display(), e);
+                error_count += 1;
+                continue;
+            }
+            dotfile.mode = mode;
+        }
+
         println!("---");
         println!("Processing dotfile:");
         println!("  Source: {}", dotfile.source.display());
         println!("  Target: {}", dotfile.target.display());
         if dotfile.source.is_dir() {
             println!("  Type: Directory");
-            // For directories, we have a different process
-            println!("Processing directory: {}", dotfile.source.display());
-
-            // We can update the database status to linked but don't move the directory itself
-            if let Err(e) = config::link_dotfile(&dotfile.source, &dotfile.target) {
-                eprintln!("Failed to update directory tracking status: {}", e);
-                error_count += 1;
-            } else {
-                println!("Directory tracking status updated to linked.");
-                success_count += 1;
-            }
-
-            // Continue to the next item
-            continue;
         } else {
             println!("  Type: File");
         }
-
-        // Process files - first check if target is a file or symlink (not a dir)
-        if dotfile.target.is_file() || symlink::is_symlink(&dotfile.target) {
-            println!(
-                "  Target is a file or symlink, removing staging symlink: {}",
-                dotfile.target.display()
-            );
-            if let Err(e) = fs::remove_file(&dotfile.target) {
-                eprintln!(
-                    "Failed to remove staging symlink {}: {}",
-                    dotfile.target.display(),
-                    e
-                );
-                error_count += 1;
-                continue;
-            } else {
-                println!("  Removed staging symlink: {}", dotfile.target.display());
-            }
-        } else if dotfile.target.is_dir() {
-            // For directory targets, just skip removal
-            println!(
-                "  Target is a directory, skipping removal: {}",
-                dotfile.target.display()
-            );
-        }
-
-        // Ensure target parent directory exists
-        if let Some(parent) = dotfile.target.parent()
-            && !parent.exists()
-        {
-            println!("  Creating parent directory: {}", parent.display());
-            if let Err(e) = fs::create_dir_all(parent) {
-                eprintln!("Failed to create directory {}: {}", parent.display(), e);
-                error_count += 1;
-                continue;
-            }
+        if !dotfile.mode.is_symlink() {
+            println!("  Mode: {}", dotfile.mode);
         }
 
-        // Move the original file to the forge directory
-        println!(
-            "  Copying file from {} to {}",
-            dotfile.source.display(),
-            dotfile.target.display()
-        );
-        // If the target file in the forge directory exists, back it up before copying
-        if dotfile.target.exists() {
-            let backup_path = dotfile.target.with_extension("bak");
-            println!(
-                "  Target file {} exists, backing up to {}",
-                dotfile.target.display(),
-                backup_path.display()
-            );
-            if let Err(e) = fs::rename(&dotfile.target, &backup_path) {
-                eprintln!(
-                    "Failed to back up existing target file: {}: {}",
-                    dotfile.target.display(),
-                    e
-                );
-                error_count += 1;
-                continue;
-            }
-        }
-        match fs::copy(&dotfile.source, &dotfile.target) {
+        // Copy into the forge folder and replace the original with a symlink
+        // (one link for a whole directory). Every step is journaled so an interruption can be rolled forward or back.
+        match crate::journal::link_file(&forge_path, &dotfile) {
             Ok(_) => {
-                println!("  File copied successfully.");
-                // Step 1: Validate copy succeeded (file exists at target and matches size)
-                let src_metadata = fs::metadata(&dotfile.source);
-                let tgt_metadata = fs::metadata(&dotfile.target);
-                let copy_valid = match (&src_metadata, &tgt_metadata) {
-                    (Ok(src), Ok(tgt)) => src.len() == tgt.len(),
-                    _ => false,
-                };
-                if !copy_valid {
-                    eprintln!(
-                        "  Copy validation failed: source and target file sizes do not match or metadata error."
-                    );
-                    error_count += 1;
-                    continue;
-                }
-                // Create symlink from original location to forge directory FIRST
                 println!(
-                    "  Creating symlink from {} to {}",
+                    "Created {}: {} → {}",
+                    dotfile.mode,
                     dotfile.source.display(),
                     dotfile.target.display()
                 );
-                // Remove the original file only after confirming the copy
-                if dotfile.source.exists() {
-                    println!("  Removing original file: {}", dotfile.source.display());
-                    if let Err(e) = fs::remove_file(&dotfile.source) {
-                        eprintln!(
-                            "Failed to remove original file after copy: {}: {}",
-                            dotfile.source.display(),
-                            e
-                        );
-                        error_count += 1;
-                        continue;
-                    } else {
-                        println!("  Original file removed.");
-                    }
-                }
-                // Final check: if the original file still exists, abort before symlinking
-                if dotfile.source.exists() {
-                    eprintln!(
-                        "  ERROR: Original file still exists at symlink location ({}), cannot create symlink. File removal may have failed.",
-                        dotfile.source.display()
-                    );
-                    error_count += 1;
-                    continue;
-                }
-                match symlink::create_symlink(&dotfile.target, &dotfile.source) {
-                    Ok(_) => {
-                        println!("  Symlink created successfully.");
-                        println!(
-                            "Created symlink: {} → {}",
-                            dotfile.source.display(),
-                            dotfile.target.display()
-                        );
-
-                        // Update database status to linked
-                        if let Err(e) = config::link_dotfile(&dotfile.source, &dotfile.target) {
-                            eprintln!("Failed to update database: {}", e);
-                        } else {
-                            println!("  Database status updated to linked.");
-                        }
-
-                        success_count += 1;
-                    }
-                    Err(e) => {
-                        eprintln!(
-                            "Failed to create symlink {} → {}: {}",
-                            dotfile.source.display(),
-                            dotfile.target.display(),
-                            e
-                        );
-
-                        // Original file is still intact, no need to restore
-                        println!("Original file preserved at: {}", dotfile.source.display());
-
-                        error_count += 1;
-                    }
-                }
+                success_count += 1;
             }
             Err(e) => {
                 eprintln!(
-                    "Failed to copy file {} to {}: {}",
+                    "Failed to link {} → {}: {}",
                     dotfile.source.display(),
                     dotfile.target.display(),
                     e
                 );
+                if dotfile.source.exists() {
+                    println!("Original file preserved at: {}", dotfile.source.display());
+                }
                 error_count += 1;
             }
         }
     }
 
+    let (templates, refresh): (Vec<_>, Vec<_>) = refresh
+        .into_iter()
+        .partition(|d| d.mode == DeployMode::Template);
+    let (secrets, alternates): (Vec<_>, Vec<_>) = refresh
+        .into_iter()
+        .partition(|d| d.mode == DeployMode::Encrypted);
+    if !templates.is_empty() {
+        println!("---");
+        let (rendered, up_to_date, failed) = render_templates(&templates);
+        println!(
+            "Templates: {} rendered, {} already up to date, {} failed",
+            rendered, up_to_date, failed
+        );
+    }
+    if !secrets.is_empty() {
+        println!("---");
+        let (decrypted, up_to_date, failed) = decrypt_secrets(&secrets);
+        println!(
+            "Encrypted files: {} decrypted, {} already up to date, {} failed",
+            decrypted, up_to_date, failed
+        );
+    }
+    if !alternates.is_empty() {
+        println!("---");
+        switch_variants(&alternates);
+    }
+    if !linking {
+        return;
+    }
+
     println!(
         "\nSymlink creation completed: {} succeeded, {} failed",
         success_count, error_count
     );
 }
 
-/// List all tracked files
-pub fn list_command(profile: Option<&str>) {
-    crate::dotfile::list::print_dotfiles(profile);
+// Whether `link` has work to do for an already linked dotfile: rendering a
+// template, decrypting a changed encrypted file, or switching a symlink to the
+// alternate now selected
+fn needs_refresh(dotfile: &DotFile) -> bool {
+    dotfile.is_linked()
+        && match dotfile.mode {
+            DeployMode::Template | DeployMode::Encrypted => true,
+            DeployMode::Symlink => !crate::alternate::variants(&dotfile.target).is_empty(),
+            // Hard links and copies pick up another variant through sync
+            DeployMode::Hardlink | DeployMode::Copy => false,
+        }
 }
 
-/// Remove symlinks but keep files in forge folder
-pub fn unlink_command(files: &[PathBuf], skip_confirm: bool) {
-    if files.is_empty() {
-        println!("No files specified to unlink. Here are all tracked files:");
-        crate::dotfile::list::print_dotfiles(None);
-        return;
+// Decrypt encrypted files again where the encrypted copy changed, e.g. after
+// a pull. A decrypted file with changes of its own is left alone.
+fn decrypt_secrets(secrets: &[DotFile]) -> (usize, usize, usize) {
+    let (mut decrypted, mut up_to_date, mut failed) = (0, 0, 0);
+    for dotfile in secrets {
+        let edited = fs::symlink_metadata(&dotfile.source).is_ok()
+            && match crate::deploy::changed_side(dotfile) {
+                Ok(None) => {
+                    up_to_date += 1;
+                    continue;
+                }
+                Ok(Some(crate::deploy::Changed::Managed)) => false,
+                // No record of the last decryption, or both sides changed
+                Ok(Some(crate::deploy::Changed::Both)) | Err(_) => !crate::deploy::is_deployed(
+                    dotfile.mode,
+                    &crate::deploy::managed_path(dotfile),
+                    &dotfile.source,
+                ),
+                Ok(Some(crate::deploy::Changed::Deployed)) => true,
+            };
+        if edited {
+            eprintln!(
+                "Not decrypting {}: it has changes that are not in the encrypted copy; run 'forge secret add {}' to keep them",
+                dotfile.source.display(),
+                dotfile.source.display()
+            );
+            failed += 1;
+            continue;
+        }
+
+        match crate::deploy::decrypt(dotfile) {
+            Ok(true) => {
+                println!("Decrypted: {}", dotfile.source.display());
+                decrypted += 1;
+            }
+            Ok(false) => up_to_date += 1,
+            Err(e) => {
+                eprintln!("Failed to decrypt {}: {:#}", dotfile.source.display(), e);
+                failed += 1;
+            }
+        }
     }
+    (decrypted, up_to_date, failed)
+}
 
-    // Get the active managed folder
+// Point symlinks to alternates at the variant selected for this machine
+fn switch_variants(dotfiles: &[DotFile]) {
+    for dotfile in dotfiles {
+        let selected = crate::deploy::managed_path(dotfile);
+        if crate::deploy::is_deployed(dotfile.mode, &selected, &dotfile.source) {
+            continue;
+        }
+        // Only a link to the managed file or one of its variants is replaced
+        let ours = symlink::resolve_link(&dotfile.source).is_some_and(|dest| {
+            dest == dotfile.target
+                || crate::alternate::base(&dest).as_deref() == Some(&dotfile.target)
+        });
+        if !ours {
+            continue;
+        }
+
+        let switched = history::remove_file(&dotfile.source)
+            .and_then(|_| history::create_link(&selected, &dotfile.source));
+        match switched {
+            Ok(()) => println!(
+                "Switched variant: {} → {}",
+                dotfile.source.display(),
+                selected.display()
+            ),
+            Err(e) => eprintln!(
+                "Failed to switch {} to {}: {}",
+                dotfile.source.display(),
+                selected.display(),
+                e
+            ),
+        }
+    }
+}
+
+/// Converge the filesystem to the active managed folder's forge.toml: create
+/// missing links, report conflicts and, if asked, remove links that are no
+/// longer declared. Returns false if anything could not be applied.
+pub fn apply_command(prune: bool, dry_run: bool) -> bool {
     let (folder_name, forge_path) = match config::get_active_managed_folder() {
         Ok(Some((name, path))) => (name, path),
         Ok(None) => {
             eprintln!("No managed folders found. Please run 'forge init' first.");
-            return;
+            return false;
         }
         Err(e) => {
             eprintln!("Failed to get managed folder: {}", e);
-            return;
+            return false;
         }
     };
 
-    println!(
-        "Using managed folder '{}' at {}",
-        folder_name,
+    if !crate::manifest::exists(&forge_path) {
+        println!(
+            "No {} in managed folder '{}', nothing to apply",
+            crate::manifest::MANIFEST_FILE,
+            folder_name
+        );
+        return true;
+    }
+    let manifest = match crate::manifest::load(&forge_path) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            return false;
+        }
+    };
+
+    println!(
+        "Applying {} in managed folder '{}' at {}",
+        crate::manifest::MANIFEST_FILE,
+        folder_name,
+        forge_path.display()
+    );
+
+    let (mut linked, mut in_place, mut failed) = (0, 0, 0);
+    for entry in &manifest.files {
+        let managed = entry.managed(&forge_path);
+        let deploy = entry.deploy();
+
+        // A staged entry's managed copy is still a link back to its original
+        if managed.is_symlink() {
+            println!("Staged, not yet linked: {}", managed.display());
+            continue;
+        }
+        // The variant selected for this machine, if the file has alternates
+        let selected = crate::alternate::resolve(&managed);
+        if !selected.exists() {
+            eprintln!("Missing from managed folder: {}", managed.display());
+            failed += 1;
+            continue;
+        }
+
+        let already_linked = crate::deploy::is_deployed(entry.mode, &selected, &deploy);
+        // A link to another variant is switched over like a missing one
+        let other_variant = !already_linked
+            && symlink::resolve_link(&deploy).is_some_and(|dest| {
+                dest == managed || crate::alternate::base(&dest).as_ref() == Some(&managed)
+            });
+        if !already_linked && !other_variant && fs::symlink_metadata(&deploy).is_ok() {
+            let expected = match entry.mode {
+                DeployMode::Symlink => "a link to",
+                DeployMode::Hardlink => "a hard link to",
+                DeployMode::Copy => "a copy of",
+                DeployMode::Template => "the rendered output of",
+                DeployMode::Encrypted => "the decrypted content of",
+            };
+            eprintln!(
+                "Conflict: {} exists and is not {} {}",
+                deploy.display(),
+                expected,
+                selected.display()
+            );
+            failed += 1;
+            continue;
+        }
+
+        if dry_run {
+            if already_linked {
+                in_place += 1;
+            } else {
+                println!("Would link: {} → {}", deploy.display(), selected.display());
+                linked += 1;
+            }
+            continue;
+        }
+
+        if !already_linked {
+            if let Some(parent) = deploy.parent()
+                && !parent.exists()
+                && let Err(e) = history::create_dir_all(parent)
+            {
+                eprintln!("Failed to create directory {}: {}", parent.display(), e);
+                failed += 1;
+                continue;
+            }
+            if other_variant && let Err(e) = history::remove_file(&deploy) {
+                eprintln!("Failed to remove {}: {}", deploy.display(), e);
+                failed += 1;
+                continue;
+            }
+            if let Err(e) = crate::deploy::deploy(entry.mode, &selected, &deploy) {
+                eprintln!("Failed to link {}: {}", deploy.display(), e);
+                failed += 1;
+                continue;
+            }
+            println!("Linked: {} → {}", deploy.display(), selected.display());
+            linked += 1;
+        } else {
+            in_place += 1;
+        }
+
+        // Record the link, so a fresh clone gets the same database state
+        let add = || {
+            config::add_dotfile(&deploy, &managed, entry.profile.as_deref())?;
+            if !entry.mode.is_symlink() {
+                config::set_dotfile_mode(&managed, entry.mode)?;
+                let hash = crate::deploy::synced_hash(entry.mode, &deploy, &selected).ok();
+                config::set_synced_hash(&managed, hash.as_deref())?;
+            }
+            Ok(())
+        };
+        let recorded = match config::find_dotfile_by_target(&managed) {
+            Ok(Some(dotfile))
+                if dotfile.is_linked()
+                    && dotfile.source == deploy
+                    && dotfile.mode == entry.mode =>
+            {
+                Ok(())
+            }
+            Ok(Some(_)) => config::deactivate_dotfile(&managed).and_then(|_| add()),
+            Ok(None) => add(),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = recorded {
+            eprintln!("Failed to record {}: {}", managed.display(), e);
+            failed += 1;
+        }
+    }
+
+    // Links forge knows about whose managed copy is no longer declared
+    let mut pruned = 0;
+    let dotfiles = match config::get_dotfiles(None, Some(&folder_name)) {
+        Ok(dotfiles) => dotfiles,
+        Err(e) => {
+            eprintln!("Error fetching tracked files: {}", e);
+            return false;
+        }
+    };
+    for dotfile in dotfiles.iter().filter(|d| d.is_linked()) {
+        let declared = dotfile
+            .target
+            .strip_prefix(&forge_path)
+            .is_ok_and(|relative| manifest.find(relative).is_some());
+        if declared {
+            continue;
+        }
+
+        if !prune {
+            println!(
+                "Not declared: {} (use --prune to remove the link)",
+                dotfile.source.display()
+            );
+            continue;
+        }
+        if dry_run {
+            println!("Would prune: {}", dotfile.source.display());
+            pruned += 1;
+            continue;
+        }
+
+        // Only remove the link if it still leads to (or matches) the managed copy
+        let managed = crate::deploy::managed_path(dotfile);
+        let removed = if !crate::deploy::is_deployed(dotfile.mode, &managed, &dotfile.source) {
+            Ok(())
+        } else if dotfile.source.is_dir() && !symlink::is_symlink(&dotfile.source) {
+            history::remove_tree(&dotfile.source)
+        } else {
+            history::remove_file(&dotfile.source)
+        };
+        if let Err(e) = removed {
+            eprintln!("Failed to remove {}: {}", dotfile.source.display(), e);
+            failed += 1;
+            continue;
+        }
+        match config::deactivate_dotfile(&dotfile.target) {
+            Ok(_) => {
+                println!("Pruned: {}", dotfile.source.display());
+                pruned += 1;
+            }
+            Err(e) => {
+                eprintln!("Failed to untrack {}: {}", dotfile.target.display(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    let verb = if dry_run { "Would link" } else { "Linked" };
+    print!("\n{} {}, {} already in place", verb, linked, in_place);
+    if prune {
+        print!(", {} pruned", pruned);
+    }
+    println!(", {} failed", failed);
+
+    failed == 0
+}
+
+/// Bring hard-linked and copied dotfiles of the active managed folder back in
+/// step with their managed copies and, when the folder is a git repository
+/// with the configured remote, commit, pull and push it. Returns false on
+/// conflicts or failures.
+pub fn sync_command(files: &[PathBuf], dry_run: bool) -> bool {
+    let (folder_name, forge_path) = match config::get_active_managed_folder() {
+        Ok(Some((name, path))) => (name, path),
+        Ok(None) => {
+            eprintln!("No managed folders found. Please run 'forge init' first.");
+            return false;
+        }
+        Err(e) => {
+            eprintln!("Failed to get managed folder: {}", e);
+            return false;
+        }
+    };
+
+    let requested: Vec<PathBuf> = files.iter().map(path_utils::normalize).collect();
+    let dotfiles: Vec<_> = match config::get_dotfiles(None, Some(&folder_name)) {
+        Ok(dotfiles) => dotfiles
+            .into_iter()
+            .filter(|d| d.is_linked() && matches!(d.mode, DeployMode::Hardlink | DeployMode::Copy))
+            .filter(|d| {
+                requested.is_empty()
+                    || requested
+                        .iter()
+                        .any(|path| *path == d.source || *path == d.target)
+            })
+            .collect(),
+        Err(e) => {
+            eprintln!("Error fetching tracked files: {}", e);
+            return false;
+        }
+    };
+
+    // Without files named, a folder kept in git is also synced with its remote
+    let remote = config::get_setting(config::GIT_REMOTE_KEY)
+        .ok()
+        .flatten()
+        .filter(|remote| !remote.is_empty())
+        .unwrap_or_else(|| "origin".to_string());
+    let use_git = requested.is_empty()
+        && crate::git::is_repo(&forge_path)
+        && crate::git::has_remote(&forge_path, &remote);
+
+    if dotfiles.is_empty() && !use_git {
+        println!(
+            "No hard-linked or copied files to sync in '{}' at {}",
+            folder_name,
+            forge_path.display()
+        );
+        return requested.is_empty();
+    }
+
+    let mut success = dotfiles.is_empty() || sync_files(&dotfiles, dry_run);
+    if use_git {
+        if dry_run {
+            println!(
+                "Would commit changes in {}, pull from '{}' and push",
+                forge_path.display(),
+                remote
+            );
+            return success;
+        }
+        match crate::git::sync(&forge_path, &remote) {
+            Ok(true) => {
+                // Pulled managed copies replace hard links; deploy them again
+                if !dotfiles.is_empty() {
+                    success &= sync_files(&dotfiles, false);
+                }
+                println!("Pulled new commits; run 'forge link' to deploy new or changed files");
+            }
+            Ok(false) => {}
+            Err(e) => {
+                eprintln!("Git sync failed: {:#}", e);
+                success = false;
+            }
+        }
+    }
+    success
+}
+
+/// Copy changes between hard-linked or copied files and their managed copies
+/// in whichever direction they were made; false on conflicts or failures
+fn sync_files(dotfiles: &[DotFile], dry_run: bool) -> bool {
+    use crate::deploy::SyncAction;
+
+    let (mut synced, mut in_sync, mut conflicts, mut failed) = (0, 0, 0, 0);
+    for dotfile in dotfiles {
+        let action = match crate::deploy::plan_sync(dotfile) {
+            Ok(action) => action,
+            Err(e) => {
+                eprintln!("Cannot sync {}: {:#}", dotfile.source.display(), e);
+                failed += 1;
+                continue;
+            }
+        };
+        let (from, to) = match action {
+            SyncAction::Conflict => {
+                eprintln!(
+                    "Conflict: both {} and {} changed since the last sync; make them the same by hand, then sync again",
+                    dotfile.source.display(),
+                    dotfile.target.display()
+                );
+                conflicts += 1;
+                continue;
+            }
+            SyncAction::Absorb => (&dotfile.source, &dotfile.target),
+            SyncAction::Deploy => (&dotfile.target, &dotfile.source),
+            SyncAction::InSync => {
+                in_sync += 1;
+                // Remember the common content, e.g. after a conflict was resolved
+                if !dry_run && let Err(e) = crate::deploy::sync(dotfile, action) {
+                    eprintln!("Failed to record {}: {:#}", dotfile.target.display(), e);
+                    failed += 1;
+                }
+                continue;
+            }
+        };
+
+        if dry_run {
+            println!("Would copy: {} → {}", from.display(), to.display());
+            synced += 1;
+            continue;
+        }
+        match crate::deploy::sync(dotfile, action) {
+            Ok(()) => {
+                println!("Synced: {} → {}", from.display(), to.display());
+                synced += 1;
+            }
+            Err(e) => {
+                eprintln!("Failed to sync {}: {:#}", dotfile.source.display(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    let verb = if dry_run { "Would sync" } else { "Synced" };
+    println!(
+        "\n{} {}, {} already in sync, {} conflict(s), {} failed",
+        verb, synced, in_sync, conflicts, failed
+    );
+    conflicts == 0 && failed == 0
+}
+
+/// Render the linked templates of the active managed folder again, or only
+/// the given ones. With `check`, print what each renders to and write
+/// nothing; false if any is out of date.
+pub fn render_command(files: &[PathBuf], check: bool) -> bool {
+    let (folder_name, forge_path) = match config::get_active_managed_folder() {
+        Ok(Some((name, path))) => (name, path),
+        Ok(None) => {
+            eprintln!("No managed folders found. Please run 'forge init' first.");
+            return false;
+        }
+        Err(e) => {
+            eprintln!("Failed to get managed folder: {}", e);
+            return false;
+        }
+    };
+
+    let requested: Vec<PathBuf> = files.iter().map(path_utils::normalize).collect();
+    let templates: Vec<_> = match linked_templates(&folder_name) {
+        Ok(templates) => templates
+            .into_iter()
+            .filter(|d| {
+                requested.is_empty()
+                    || requested
+                        .iter()
+                        .any(|path| *path == d.source || *path == d.target)
+            })
+            .collect(),
+        Err(e) => {
+            eprintln!("Error fetching tracked files: {}", e);
+            return false;
+        }
+    };
+    if templates.is_empty() {
+        println!(
+            "No templates to render in '{}' at {}",
+            folder_name,
+            forge_path.display()
+        );
+        return requested.is_empty();
+    }
+
+    if !check {
+        let (rendered, up_to_date, failed) = render_templates(&templates);
+        println!(
+            "\nRendered {}, {} already up to date, {} failed",
+            rendered, up_to_date, failed
+        );
+        return failed == 0;
+    }
+
+    let context = match crate::template::Context::current() {
+        Ok(context) => context,
+        Err(e) => {
+            eprintln!("Failed to read template values: {}", e);
+            return false;
+        }
+    };
+    let (mut up_to_date, mut outdated, mut failed) = (0, 0, 0);
+    for dotfile in &templates {
+        let rendered = match context.render_file(&crate::deploy::managed_path(dotfile)) {
+            Ok(rendered) => rendered,
+            Err(e) => {
+                eprintln!("{:#}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        let state = match crate::deploy::changed_side(dotfile) {
+            Ok(None) => {
+                up_to_date += 1;
+                "up to date"
+            }
+            Ok(Some(crate::deploy::Changed::Managed)) => {
+                outdated += 1;
+                "would change"
+            }
+            // Includes a missing deployed file
+            _ if !dotfile.source.is_file() => {
+                outdated += 1;
+                "would be written"
+            }
+            _ => {
+                outdated += 1;
+                "edited since the last render; would be backed up and replaced"
+            }
+        };
+        println!(
+            "==> {} → {} ({})",
+            dotfile.target.display(),
+            dotfile.source.display(),
+            state
+        );
+        print!("{}", rendered);
+        if !rendered.is_empty() && !rendered.ends_with('\n') {
+            println!();
+        }
+    }
+
+    println!(
+        "\n{} template(s): {} up to date, {} out of date, {} failed",
+        templates.len(),
+        up_to_date,
+        outdated,
+        failed
+    );
+    outdated == 0 && failed == 0
+}
+
+// Linked dotfiles of a folder that are deployed as rendered templates
+fn linked_templates(folder_name: &str) -> rusqlite::Result<Vec<DotFile>> {
+    Ok(config::get_dotfiles(None, Some(folder_name))?
+        .into_iter()
+        .filter(|d| d.is_linked() && d.mode == DeployMode::Template)
+        .collect())
+}
+
+// Write the rendered output of each template where it differs from the
+// deployed file; returns how many were rendered, up to date and failed
+fn render_templates(templates: &[DotFile]) -> (usize, usize, usize) {
+    let context = match crate::template::Context::current() {
+        Ok(context) => context,
+        Err(e) => {
+            eprintln!("Failed to read template values: {}", e);
+            return (0, 0, templates.len());
+        }
+    };
+
+    let (mut rendered, mut up_to_date, mut failed) = (0, 0, 0);
+    for dotfile in templates {
+        let output = match context.render_file(&crate::deploy::managed_path(dotfile)) {
+            Ok(output) => output,
+            Err(e) => {
+                eprintln!("{:#}", e);
+                failed += 1;
+                continue;
+            }
+        };
+        let current = crate::deploy::rendered_hash(&output);
+        let unchanged = !symlink::is_symlink(&dotfile.source)
+            && crate::utils::hash::file_hash(&dotfile.source).is_ok_and(|hash| hash == current);
+
+        match crate::deploy::render(dotfile, &output) {
+            Ok(_) if unchanged => up_to_date += 1,
+            Ok(backup) => {
+                println!(
+                    "Rendered: {} → {}",
+                    dotfile.target.display(),
+                    dotfile.source.display()
+                );
+                if let Some(id) = backup {
+                    println!("  The edited file was saved as backup #{}", id);
+                }
+                rendered += 1;
+            }
+            Err(e) => {
+                eprintln!("Failed to render {}: {:#}", dotfile.source.display(), e);
+                failed += 1;
+            }
+        }
+    }
+    (rendered, up_to_date, failed)
+}
+
+/// Move the active managed folder, its records and every symlink into it
+pub fn relocate_command(to: &Path, dry_run: bool) -> bool {
+    let (name, from) = match config::get_active_managed_folder() {
+        Ok(Some(folder)) => folder,
+        Ok(None) => {
+            eprintln!("No managed folders found. Please run 'forge init' first.");
+            return false;
+        }
+        Err(e) => {
+            eprintln!("Failed to get managed folder: {}", e);
+            return false;
+        }
+    };
+    let to = path_utils::normalize(to);
+
+    let plan = match crate::relocate::plan(&name, &from, &to) {
+        Ok(plan) => plan,
+        Err(e) => {
+            eprintln!("Cannot relocate '{}': {:#}", name, e);
+            return false;
+        }
+    };
+
+    if dry_run {
+        println!(
+            "Would move managed folder '{}': {} → {}",
+            name,
+            from.display(),
+            to.display()
+        );
+        for relink in &plan.relinks {
+            println!(
+                "Would relink: {} ({} → {})",
+                relink.link.display(),
+                relink.from.display(),
+                relink.to.display()
+            );
+        }
+        println!(
+            "\nWould move {} tracked file(s) and relink {} symlink(s)",
+            plan.dotfiles.len(),
+            plan.relinks.len()
+        );
+        return true;
+    }
+
+    match crate::relocate::relocate(&plan) {
+        Ok(()) => {
+            for relink in &plan.relinks {
+                println!(
+                    "Relinked: {} → {}",
+                    relink.link.display(),
+                    relink.to.display()
+                );
+            }
+            println!(
+                "\nMoved managed folder '{}' to {}: {} tracked file(s), {} symlink(s) relinked",
+                name,
+                to.display(),
+                plan.dotfiles.len(),
+                plan.relinks.len()
+            );
+            true
+        }
+        Err(e) => {
+            eprintln!("Failed to relocate '{}': {:#}", name, e);
+            false
+        }
+    }
+}
+
+/// Register the links GNU Stow made for packages in a stow directory as
+/// linked dotfiles, with the stow directory as their managed folder. Nothing
+/// on disk is moved or relinked. Returns false if anything failed.
+pub fn adopt_stow_command(
+    stow_dir: &Path,
+    packages: &[String],
+    target: Option<&Path>,
+    name: Option<&str>,
+    dry_run: bool,
+) -> bool {
+    let stow_dir = path_utils::normalize(stow_dir);
+    if !stow_dir.is_dir() {
+        eprintln!("Stow directory does not exist: {}", stow_dir.display());
+        return false;
+    }
+    // Stow's default target is the parent of the stow directory
+    let target_dir = match target {
+        Some(dir) => path_utils::normalize(dir),
+        None => match stow_dir.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => {
+                eprintln!("Stow directory has no parent, please pass --target");
+                return false;
+            }
+        },
+    };
+
+    let packages = if packages.is_empty() {
+        match crate::stow::packages(&stow_dir) {
+            Ok(packages) => packages,
+            Err(e) => {
+                eprintln!("Failed to read {}: {}", stow_dir.display(), e);
+                return false;
+            }
+        }
+    } else {
+        packages.to_vec()
+    };
+
+    // The stow directory becomes the managed folder, reusing its registration if it has one
+    let folders = match config::get_managed_folders() {
+        Ok(folders) => folders,
+        Err(e) => {
+            eprintln!("Failed to get managed folders: {}", e);
+            return false;
+        }
+    };
+    let folder_name = match folders.iter().find(|(_, path)| *path == stow_dir) {
+        Some((existing, _)) => existing.clone(),
+        None => {
+            let folder_name = match name {
+                Some(n) => n.to_string(),
+                None => stow_dir
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| "stow".to_string()),
+            };
+            if folders.iter().any(|(n, _)| *n == folder_name) {
+                eprintln!(
+                    "A managed folder named '{}' already exists. Please choose another with --name",
+                    folder_name
+                );
+                return false;
+            }
+            if !dry_run {
+                match config::add_managed_folder(&folder_name, &stow_dir) {
+                    Ok(_) => println!("Added '{}' to managed folders", folder_name),
+                    Err(e) => {
+                        eprintln!("Failed to add to managed folders: {}", e);
+                        return false;
+                    }
+                }
+            }
+            folder_name
+        }
+    };
+
+    println!(
+        "Adopting stow packages from {} (target {}) into managed folder '{}'",
+        stow_dir.display(),
+        target_dir.display(),
+        folder_name
+    );
+
+    let (mut adopted, mut skipped, mut failed) = (0, 0, 0);
+    for package in &packages {
+        let package_dir = stow_dir.join(package);
+        if !package_dir.is_dir() {
+            eprintln!("No such package: {}", package);
+            failed += 1;
+            continue;
+        }
+
+        let found = match crate::stow::find_links(&package_dir, &target_dir) {
+            Ok(found) => found,
+            Err(e) => {
+                eprintln!("Failed to read package {}: {}", package, e);
+                failed += 1;
+                continue;
+            }
+        };
+        if found.links.is_empty() {
+            println!(
+                "Package '{}' is not stowed in {}",
+                package,
+                target_dir.display()
+            );
+        }
+
+        for link in &found.links {
+            match config::find_dotfile_by_source(&link.link) {
+                Ok(Some(_)) => {
+                    println!("Already tracked: {}", link.link.display());
+                    skipped += 1;
+                    continue;
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    eprintln!("Error checking {}: {}", link.link.display(), e);
+                    failed += 1;
+                    continue;
+                }
+            }
+
+            if dry_run {
+                println!(
+                    "Would adopt: {} → {}",
+                    link.link.display(),
+                    link.entry.display()
+                );
+                adopted += 1;
+                continue;
+            }
+            match config::add_dotfile(&link.link, &link.entry, None) {
+                Ok(_) => {
+                    println!(
+                        "Adopted: {} → {}",
+                        link.link.display(),
+                        link.entry.display()
+                    );
+                    adopted += 1;
+                }
+                Err(e) => {
+                    eprintln!("Failed to record {}: {}", link.link.display(), e);
+                    failed += 1;
+                }
+            }
+        }
+
+        for entry in &found.unstowed {
+            println!("Not stowed, left untracked: {}", entry.display());
+        }
+    }
+
+    let verb = if dry_run { "Would adopt" } else { "Adopted" };
+    println!(
+        "\n{} {} link(s) from {} package(s): {} already tracked, {} failed",
+        verb,
+        adopted,
+        packages.len(),
+        skipped,
+        failed
+    );
+
+    failed == 0
+}
+
+/// Active managed folder (name, path) and the directory its packages are
+/// stowed into: `target`, or the folder's parent as with GNU Stow
+fn stow_dirs(target: Option<&Path>) -> Option<(String, PathBuf, PathBuf)> {
+    let (folder_name, forge_path) = match config::get_active_managed_folder() {
+        Ok(Some((name, path))) => (name, path),
+        Ok(None) => {
+            eprintln!("No managed folders found. Please run 'forge init' first.");
+            return None;
+        }
+        Err(e) => {
+            eprintln!("Failed to get managed folder: {}", e);
+            return None;
+        }
+    };
+    let target_dir = match target {
+        Some(dir) => path_utils::normalize(dir),
+        None => match forge_path.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => {
+                eprintln!("Managed folder has no parent, please pass --target");
+                return None;
+            }
+        },
+    };
+    Some((folder_name, forge_path, target_dir))
+}
+
+/// Directory of a package in a managed folder, if it is one
+fn package_dir(forge_path: &Path, package: &str) -> Option<PathBuf> {
+    let dir = forge_path.join(package);
+    if package.starts_with('.') || package.contains('/') || !dir.is_dir() {
+        eprintln!("No such package: {}", package);
+        return None;
+    }
+    Some(dir)
+}
+
+/// Mirror packages (top-level directories of the active managed folder) into
+/// the target directory as GNU Stow does. Returns false if any package could
+/// not be stowed.
+pub fn stow_command(packages: &[String], target: Option<&Path>, dry_run: bool) -> bool {
+    let Some((folder_name, forge_path, target_dir)) = stow_dirs(target) else {
+        return false;
+    };
+
+    let mut ok = true;
+    for package in packages {
+        let Some(package_dir) = package_dir(&forge_path, package) else {
+            ok = false;
+            continue;
+        };
+        println!(
+            "Stowing package '{}' from managed folder '{}' into {}",
+            package,
+            folder_name,
+            target_dir.display()
+        );
+
+        // Work out every change first, so a conflict leaves the package untouched
+        let plan = match crate::stow::plan_stow(&package_dir, &target_dir, &forge_path) {
+            Ok(plan) => plan,
+            Err(e) => {
+                eprintln!("Failed to read package {}: {}", package, e);
+                ok = false;
+                continue;
+            }
+        };
+        if !plan.conflicts.is_empty() {
+            for conflict in &plan.conflicts {
+                eprintln!(
+                    "Conflict: {} exists and is not owned by forge",
+                    conflict.display()
+                );
+            }
+            eprintln!("Package '{}' not stowed", package);
+            ok = false;
+            continue;
+        }
+        if plan.actions.is_empty() {
+            println!("Package '{}' is already stowed", package);
+            continue;
+        }
+
+        for action in &plan.actions {
+            let (done, planned, detail) = match action {
+                crate::stow::Action::Link { link, entry } => (
+                    "Linked",
+                    "Would link",
+                    format!("{} → {}", link.display(), entry.display()),
+                ),
+                crate::stow::Action::Unfold { link, dir } => (
+                    "Unfolded",
+                    "Would unfold",
+                    format!("{} (was → {})", link.display(), dir.display()),
+                ),
+            };
+            if dry_run {
+                println!("{}: {}", planned, detail);
+                continue;
+            }
+            if let Err(e) = crate::stow::perform(action) {
+                eprintln!("Failed to stow {}: {:#}", detail, e);
+                ok = false;
+                break;
+            }
+            println!("{}: {}", done, detail);
+        }
+    }
+
+    ok
+}
+
+/// Remove the links of packages stowed with 'forge stow', folding directories
+/// back where only one package is left. Returns false if anything failed.
+pub fn unstow_command(packages: &[String], target: Option<&Path>) -> bool {
+    let Some((_, forge_path, target_dir)) = stow_dirs(target) else {
+        return false;
+    };
+
+    let mut ok = true;
+    for package in packages {
+        let Some(package_dir) = package_dir(&forge_path, package) else {
+            ok = false;
+            continue;
+        };
+
+        match crate::stow::unstow(&package_dir, &target_dir, &forge_path) {
+            Ok(unstowed) if unstowed.removed_links.is_empty() => {
+                println!("Package '{}' is not stowed", package);
+            }
+            Ok(unstowed) => {
+                for link in &unstowed.removed_links {
+                    println!("Removed: {}", link.display());
+                }
+                for dir in &unstowed.removed_dirs {
+                    println!("Removed empty directory: {}", dir.display());
+                }
+                for dir in &unstowed.folded {
+                    println!("Folded: {}", dir.display());
+                }
+                println!(
+                    "Unstowed package '{}': {} link(s) removed",
+                    package,
+                    unstowed.removed_links.len()
+                );
+            }
+            Err(e) => {
+                eprintln!("Failed to unstow package {}: {:#}", package, e);
+                ok = false;
+            }
+        }
+    }
+
+    ok
+}
+
+/// List all tracked files
+pub fn list_command(profile: Option<&str>) {
+    // Profile files live outside the managed folders, so only filter by folder without one
+    let folder = if profile.is_none() {
+        active_folder_name()
+    } else {
+        None
+    };
+    crate::dotfile::list::print_dotfiles(profile, folder.as_deref());
+}
+
+/// Compare tracked files against the filesystem, returning false if anything has drifted
+/// or the database cannot be read
+pub fn status_command(profile: Option<&str>) -> bool {
+    let folder = if profile.is_none() {
+        active_folder_name()
+    } else {
+        None
+    };
+    let clean = match crate::dotfile::status::print_status(profile, folder.as_deref()) {
+        Ok(drifted) => drifted == 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            false
+        }
+    };
+    if folder.is_some() {
+        warn_uncommitted();
+    }
+    clean
+}
+
+/// Warn about uncommitted changes when the active managed folder is kept in git
+fn warn_uncommitted() {
+    const SHOWN: usize = 10;
+    let Ok(Some((folder_name, forge_path))) = config::get_active_managed_folder() else {
+        return;
+    };
+    if !crate::git::is_repo(&forge_path) {
+        return;
+    }
+    let changes = match crate::git::uncommitted(&forge_path) {
+        Ok(changes) if !changes.is_empty() => changes,
+        Ok(_) => return,
+        Err(e) => {
+            eprintln!("Could not check git status: {:#}", e);
+            return;
+        }
+    };
+    println!(
+        "\nWarning: {} uncommitted change(s) in managed folder '{}':",
+        changes.len(),
+        folder_name
+    );
+    for change in changes.iter().take(SHOWN) {
+        println!("  {}", change);
+    }
+    if changes.len() > SHOWN {
+        println!("  ... and {} more", changes.len() - SHOWN);
+    }
+    println!("Commit them with 'forge sync' or 'forge git commit'");
+}
+
+/// Explain whether the ignore rules keep `path` out of the active managed
+/// folder. A path outside the folder is checked where staging would put it.
+/// Returns whether it is ignored.
+pub fn check_ignore_command(path: &Path) -> bool {
+    let rules = match IgnoreRules::for_active_folder() {
+        Ok(Some(rules)) => rules,
+        Ok(None) => {
+            eprintln!("No managed folders found. Please run 'forge init' first.");
+            return false;
+        }
+        Err(e) => {
+            eprintln!("Invalid ignore rules: {}", e);
+            return false;
+        }
+    };
+
+    let path = path_utils::normalize(path);
+    let relative = if path.starts_with(rules.root()) {
+        path.clone()
+    } else {
+        match config::find_dotfile_by_source(&path) {
+            Ok(Some(dotfile)) => dotfile.target,
+            _ => rules.root().join(path.file_name().unwrap_or_default()),
+        }
+    };
+
+    match rules.check(&relative, path.is_dir()) {
+        Verdict::Ignored(rule) => {
+            println!("{} is ignored by {}", path.display(), rule);
+            true
+        }
+        Verdict::Reincluded(rule) => {
+            println!("{} is not ignored: {} re-includes it", path.display(), rule);
+            false
+        }
+        Verdict::NotIgnored => {
+            println!("{} is not ignored", path.display());
+            false
+        }
+    }
+}
+
+/// Show how files that replaced their symlinks differ from the managed
+/// copies, resolve each as given or as the user picks, and link them again
+pub fn reconcile_command(
+    files: &[PathBuf],
+    resolution: Option<crate::reconcile::Resolution>,
+) -> bool {
+    use crate::reconcile::{self, Resolution};
+
+    let (folder_name, forge_path) = match config::get_active_managed_folder() {
+        Ok(Some((name, path))) => (name, path),
+        Ok(None) => {
+            eprintln!("No managed folders found. Please run 'forge init' first.");
+            return false;
+        }
+        Err(e) => {
+            eprintln!("Failed to get managed folder: {}", e);
+            return false;
+        }
+    };
+
+    let requested: Vec<PathBuf> = files.iter().map(path_utils::normalize).collect();
+    let dotfiles: Vec<_> = match config::get_dotfiles(None, Some(&folder_name)) {
+        Ok(dotfiles) => dotfiles
+            .into_iter()
+            .filter(|d| {
+                requested.is_empty()
+                    || requested
+                        .iter()
+                        .any(|path| *path == d.source || *path == d.target)
+            })
+            .filter(reconcile::is_clobbered)
+            .collect(),
+        Err(e) => {
+            eprintln!("Error fetching tracked files: {}", e);
+            return false;
+        }
+    };
+    if dotfiles.is_empty() {
+        println!(
+            "No symlinks replaced by regular files in '{}' at {}",
+            folder_name,
+            forge_path.display()
+        );
+        return requested.is_empty();
+    }
+
+    // Answers to the prompt; anything after the resolutions skips the file
+    const CHOICES: [&str; 4] = ["absorb", "discard", "keep both", "skip"];
+    const RESOLUTIONS: [Resolution; 3] = [
+        Resolution::Absorb,
+        Resolution::Discard,
+        Resolution::KeepBoth,
+    ];
+    let (mut reconciled, mut skipped, mut failed) = (0, 0, 0);
+    for dotfile in &dotfiles {
+        println!(
+            "\n{} has replaced its symlink to {}",
+            dotfile.source.display(),
+            dotfile.target.display()
+        );
+        match reconcile::diff(dotfile) {
+            Ok(diff) => print!("{}", diff),
+            Err(e) => eprintln!("Cannot compare: {:#}", e),
+        }
+
+        let resolution = match resolution {
+            Some(resolution) => resolution,
+            None => {
+                match crate::utils::ui::choose(
+                    "Absorb it into the managed copy, discard it, keep both, or skip?",
+                    &CHOICES,
+                ) {
+                    Some(i) if i < RESOLUTIONS.len() => RESOLUTIONS[i],
+                    _ => {
+                        println!("Skipped: {}", dotfile.source.display());
+                        skipped += 1;
+                        continue;
+                    }
+                }
+            }
+        };
+
+        match reconcile::resolve(dotfile, resolution) {
+            Ok(kept) => {
+                match (resolution, kept) {
+                    (Resolution::KeepBoth, Some(kept)) => {
+                        println!("Kept the new content as {}", kept.display())
+                    }
+                    (Resolution::Discard, _) => {
+                        println!("Discarded the new content (a backup was kept)")
+                    }
+                    _ => println!("Absorbed into {}", dotfile.target.display()),
+                }
+                println!("Linked: {}", dotfile.source.display());
+                reconciled += 1;
+            }
+            Err(e) => {
+                eprintln!("Failed to reconcile {}: {:#}", dotfile.source.display(), e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!(
+        "\nReconciled {}, {} skipped, {} failed",
+        reconciled, skipped, failed
+    );
+    failed == 0
+}
+
+/// Watch the linked files of the active managed folder and handle drift
+/// according to `policy` until interrupted
+pub fn watch_command(policy: crate::watch::Policy) -> bool {
+    let (folder_name, forge_path) = match config::get_active_managed_folder() {
+        Ok(Some((name, path))) => (name, path),
+        Ok(None) => {
+            eprintln!("No managed folders found. Please run 'forge init' first.");
+            return false;
+        }
+        Err(e) => {
+            eprintln!("Failed to get managed folder: {}", e);
+            return false;
+        }
+    };
+    match crate::watch::run(&folder_name, &forge_path, policy) {
+        Ok(()) => true,
+        Err(e) => {
+            eprintln!("Watch failed: {:#}", e);
+            false
+        }
+    }
+}
+
+/// Run git in the active managed folder, returning its exit code
+pub fn git_command(args: &[String]) -> i32 {
+    let forge_path = match config::get_active_managed_folder() {
+        Ok(Some((_, path))) => path,
+        Ok(None) => {
+            eprintln!("No managed folders found. Please run 'forge init' first.");
+            return 1;
+        }
+        Err(e) => {
+            eprintln!("Failed to get managed folder: {}", e);
+            return 1;
+        }
+    };
+    match std::process::Command::new("git")
+        .arg("-C")
+        .arg(&forge_path)
+        .args(args)
+        .status()
+    {
+        Ok(status) => status.code().unwrap_or(1),
+        Err(e) => {
+            eprintln!("Failed to run git: {}", e);
+            1
+        }
+    }
+}
+
+/// Commit the active managed folder after `operation` changed it, if the
+/// git_auto_commit setting is on and the folder is a git repository
+pub fn auto_commit(operation: &str) {
+    let enabled = config::get_setting(config::GIT_AUTO_COMMIT_KEY)
+        .ok()
+        .flatten()
+        .is_some_and(|value| value == "true");
+    if !enabled {
+        return;
+    }
+    let Ok(Some((_, forge_path))) = config::get_active_managed_folder() else {
+        return;
+    };
+    if !crate::git::is_repo(&forge_path) {
+        return;
+    }
+    match crate::git::commit_all(&forge_path, operation) {
+        Ok(Some(message)) => println!("Committed: {}", message),
+        Ok(None) => {}
+        Err(e) => eprintln!("Auto-commit failed: {:#}", e),
+    }
+}
+
+/// Check the database and managed folders for inconsistencies, repairing them if requested
+pub fn doctor_command(fix: bool) -> bool {
+    crate::doctor::run(fix)
+}
+
+/// Remove symlinks but keep files in forge folder
+pub fn unlink_command(files: &[PathBuf], skip_confirm: bool) {
+    if files.is_empty() {
+        println!("No files specified to unlink. Here are all tracked files:");
+        crate::dotfile::list::print_dotfiles(None, active_folder_name().as_deref());
+        return;
+    }
+
+    // Get the active managed folder
+    let (folder_name, forge_path) = match config::get_active_managed_folder() {
+        Ok(Some((name, path))) => (name, path),
+        Ok(None) => {
+            eprintln!("No managed folders found. Please run 'forge init' first.");
+            return;
+        }
+        Err(e) => {
+            eprintln!("Failed to get managed folder: {}", e);
+            return;
+        }
+    };
+
+    println!(
+        "Using managed folder '{}' at {}",
+        folder_name,
         forge_path.display()
     );
 
@@ -698,16 +2188,105 @@ pub fn unlink_command(files: &[PathBuf], skip_confirm: bool) {
                             }
                         }
 
-                        match fs::remove_file(&orig_path) {
-                            Ok(_) => println!("Removed untracked symlink: {}", orig_path.display()),
-                            Err(e) => {
-                                println!("Failed to remove symlink {}: {}", orig_path.display(), e)
-                            }
-                        }
-                    } else {
-                        println!("Not a symlink or doesn't exist: {}", orig_path.display());
-                    }
-                }
+                        match history::remove_file(&orig_path) {
+                            Ok(_) => println!("Removed untracked symlink: {}", orig_path.display()),
+                            Err(e) => {
+                                println!("Failed to remove symlink {}: {}", orig_path.display(), e)
+                            }
+                        }
+                    } else {
+                        println!("Not a symlink or doesn't exist: {}", orig_path.display());
+                    }
+                }
+                continue;
+            }
+            Err(e) => {
+                eprintln!("Error looking up dotfile: {}", e);
+                continue;
+            }
+        };
+
+        // Confirm with user
+        if !skip_confirm {
+            let msg = format!(
+                "This will remove the {} at {} but keep the file in the forge folder.\nThe original file will be restored to {}.",
+                dotfile.mode,
+                dotfile.source.display(),
+                dotfile.source.display()
+            );
+            if !crate::utils::ui::confirm(&msg) {
+                println!("Skipping {}", dotfile.source.display());
+                continue;
+            }
+        }
+
+        // Replace the symlink with a copy of the managed file (journaled)
+        match crate::journal::unlink_file(&forge_path, &dotfile) {
+            Ok(_) => println!(
+                "Removed {} and restored file: {}",
+                dotfile.mode,
+                dotfile.source.display()
+            ),
+            Err(e) => eprintln!("Failed to unlink {}: {}", dotfile.source.display(), e),
+        }
+    }
+}
+
+// Remove a file or symlink, or a directory with everything in it
+fn remove_path(path: &Path) -> std::io::Result<()> {
+    if path.is_dir() && !symlink::is_symlink(path) {
+        history::remove_tree(path)
+    } else {
+        history::remove_file(path)
+    }
+}
+
+/// Remove files from forge folder but keep original files
+pub fn remove_command(files: &[PathBuf], skip_confirm: bool) {
+    if files.is_empty() {
+        println!("No files specified to remove. Here are all tracked files:");
+        crate::dotfile::list::print_dotfiles(None, active_folder_name().as_deref());
+        return;
+    }
+
+    // Get the active managed folder
+    let (folder_name, forge_path) = match config::get_active_managed_folder() {
+        Ok(Some((name, path))) => (name, path),
+        Ok(None) => {
+            eprintln!("No managed folders found. Please run 'forge init' first.");
+            return;
+        }
+        Err(e) => {
+            eprintln!("Failed to get managed folder: {}", e);
+            return;
+        }
+    };
+
+    println!(
+        "Using managed folder '{}' at {}",
+        folder_name,
+        forge_path.display()
+    );
+
+    // For each file, remove tracking and delete from forge folder
+    for file in files {
+        // Determine target path
+        let target = if file.is_absolute() {
+            // If absolute path, use it directly (might be a target)
+            file.clone()
+        } else if let Some(filename) = file.file_name() {
+            // If relative, assume it's a filename to be combined with forge dir
+            forge_path.join(filename)
+        } else {
+            eprintln!("Invalid file path: {}", file.display());
+            continue;
+        };
+
+        // Try to find the dotfile in the database
+        let dotfile = match config::find_dotfile_by_target(&target) {
+            Ok(Some(df)) => df,
+            Ok(None) => {
+                println!("No tracking record found for {}", target.display());
                 continue;
             }
             Err(e) => {
@@ -719,7 +2298,7 @@ pub fn unlink_command(files: &[PathBuf], skip_confirm: bool) {
         // Confirm with user
         if !skip_confirm {
             let msg = format!(
-                "This will remove the symlink at {} but keep the file in the forge folder.\nThe original file will be restored to {}.",
+                "This will:\n- Remove the symlink at {}\n- Delete the file from the forge folder\n- Keep the original file at {}\n- Remove tracking information from the database",
                 dotfile.source.display(),
                 dotfile.source.display()
             );
@@ -729,44 +2308,42 @@ pub fn unlink_command(files: &[PathBuf], skip_confirm: bool) {
             }
         }
 
-        // Copy from forge to original location
-        match fs::copy(&dotfile.target, &dotfile.source) {
+        // Put a real copy back at the original location first (journaled)
+        if symlink::is_symlink(&dotfile.source)
+            && let Err(e) = crate::journal::unlink_file(&forge_path, &dotfile)
+        {
+            println!(
+                "Failed to restore file from {}: {}",
+                dotfile.target.display(),
+                e
+            );
+            continue;
+        }
+
+        // Delete from forge folder
+        match remove_path(&dotfile.target) {
             Ok(_) => {
-                // Remove the symlink
-                match fs::remove_file(&dotfile.source) {
-                    Ok(_) => {
-                        println!(
-                            "Removed symlink and restored file: {}",
-                            dotfile.source.display()
-                        );
+                println!(
+                    "Removed file from forge folder: {}",
+                    dotfile.target.display()
+                );
 
-                        // Update status in database
-                        match config::deactivate_dotfile(&dotfile.target) {
-                            Ok(_) => (),
-                            Err(e) => eprintln!("Failed to update database: {}", e),
-                        }
-                    }
-                    Err(e) => println!(
-                        "Failed to remove symlink {}: {}",
-                        dotfile.source.display(),
-                        e
-                    ),
+                // Remove from database
+                match config::remove_dotfile(&dotfile.target) {
+                    Ok(_) => println!("Removed tracking for {}", dotfile.target.display()),
+                    Err(e) => eprintln!("Failed to update database: {}", e),
                 }
             }
-            Err(e) => println!(
-                "Failed to restore file from {}: {}",
-                dotfile.target.display(),
-                e
-            ),
+            Err(e) => println!("Failed to remove file {}: {}", dotfile.target.display(), e),
         }
     }
 }
 
-/// Remove files from forge folder but keep original files
-pub fn remove_command(files: &[PathBuf], skip_confirm: bool) {
+/// Delete files completely from the system
+pub fn delete_command(files: &[PathBuf], skip_confirm: bool) {
     if files.is_empty() {
-        println!("No files specified to remove. Here are all tracked files:");
-        crate::dotfile::list::print_dotfiles(None);
+        println!("No files specified to delete. Here are all tracked files:");
+        crate::dotfile::list::print_dotfiles(None, active_folder_name().as_deref());
         return;
     }
 
@@ -789,7 +2366,7 @@ pub fn remove_command(files: &[PathBuf], skip_confirm: bool) {
         forge_path.display()
     );
 
-    // For each file, remove tracking and delete from forge folder
+    // For each file, delete it completely
     for file in files {
         // Determine target path
         let target = if file.is_absolute() {
@@ -808,6 +2385,37 @@ pub fn remove_command(files: &[PathBuf], skip_confirm: bool) {
             Ok(Some(df)) => df,
             Ok(None) => {
                 println!("No tracking record found for {}", target.display());
+
+                // Even if not tracked, confirm deletion
+                if !skip_confirm {
+                    let msg = format!(
+                        "WARNING: This will PERMANENTLY DELETE the file {} from your system.\nUse 'forge undo' to reverse it.",
+                        file.display()
+                    );
+                    if !crate::utils::ui::confirm_with_text(&msg, "DELETE") {
+                        println!("Deletion cancelled.");
+                        continue;
+                    }
+                }
+
+                if file.is_file() && !symlink::is_symlink(file) {
+                    match backup::save(file, "delete") {
+                        Ok(id) => println!("Backed up {} (backup {})", file.display(), id),
+                        Err(e) => {
+                            eprintln!(
+                                "Failed to back up {}, not deleting it: {:#}",
+                                file.display(),
+                                e
+                            );
+                            continue;
+                        }
+                    }
+                }
+
+                match history::remove_file(file) {
+                    Ok(_) => println!("Deleted file: {}", file.display()),
+                    Err(e) => println!("Failed to delete file {}: {}", file.display(), e),
+                }
                 continue;
             }
             Err(e) => {
@@ -819,35 +2427,34 @@ pub fn remove_command(files: &[PathBuf], skip_confirm: bool) {
         // Confirm with user
         if !skip_confirm {
             let msg = format!(
-                "This will:\n- Remove the symlink at {}\n- Delete the file from the forge folder\n- Keep the original file at {}\n- Remove tracking information from the database",
+                "WARNING: This will PERMANENTLY DELETE the file from your system.\n\
+                - The symlink at {} will be removed\n\
+                - The file will be deleted from {}\n\
+                - The file will be deleted from the forge folder\n\
+                - All tracking information will be removed from the database\n\
+                Use 'forge undo' to reverse it.",
                 dotfile.source.display(),
                 dotfile.source.display()
             );
-            if !crate::utils::ui::confirm(&msg) {
-                println!("Skipping {}", dotfile.source.display());
+            if !crate::utils::ui::confirm_with_text(&msg, "DELETE") {
+                println!("Deletion cancelled.");
                 continue;
             }
         }
 
-        // Unlink first
-        if symlink::is_symlink(&dotfile.source) {
-            // Copy from forge to original location
-            match fs::copy(&dotfile.target, &dotfile.source) {
-                Ok(_) => {
-                    // Remove the symlink
-                    if let Err(e) = fs::remove_file(&dotfile.source) {
-                        println!(
-                            "Failed to remove symlink {}: {}",
-                            dotfile.source.display(),
-                            e
-                        );
-                        continue;
-                    }
-                }
+        // Keep a copy of the content under the path it is deployed at
+        let content = if symlink::is_symlink(&dotfile.source) {
+            &dotfile.target
+        } else {
+            &dotfile.source
+        };
+        if content.exists() {
+            match backup::save_as(content, &dotfile.source, "delete") {
+                Ok(id) => println!("Backed up {} (backup {})", dotfile.source.display(), id),
                 Err(e) => {
-                    println!(
-                        "Failed to restore file from {}: {}",
-                        dotfile.target.display(),
+                    eprintln!(
+                        "Failed to back up {}, not deleting it: {:#}",
+                        dotfile.source.display(),
                         e
                     );
                     continue;
@@ -855,11 +2462,33 @@ pub fn remove_command(files: &[PathBuf], skip_confirm: bool) {
             }
         }
 
+        // Remove symlink if it exists
+        if symlink::is_symlink(&dotfile.source) {
+            if let Err(e) = history::remove_file(&dotfile.source) {
+                println!(
+                    "Failed to remove symlink {}: {}",
+                    dotfile.source.display(),
+                    e
+                );
+                println!("Continuing with deletion...");
+            }
+        } else {
+            // Remove original file if it's not a symlink
+            if let Err(e) = remove_path(&dotfile.source) {
+                println!(
+                    "Failed to remove original file {}: {}",
+                    dotfile.source.display(),
+                    e
+                );
+                println!("Continuing with deletion...");
+            }
+        }
+
         // Delete from forge folder
-        match fs::remove_file(&dotfile.target) {
+        match remove_path(&dotfile.target) {
             Ok(_) => {
                 println!(
-                    "Removed file from forge folder: {}",
+                    "Deleted file from forge folder: {}",
                     dotfile.target.display()
                 );
 
@@ -869,143 +2498,484 @@ pub fn remove_command(files: &[PathBuf], skip_confirm: bool) {
                     Err(e) => eprintln!("Failed to update database: {}", e),
                 }
             }
-            Err(e) => println!("Failed to remove file {}: {}", dotfile.target.display(), e),
+            Err(e) => println!("Failed to delete file {}: {}", dotfile.target.display(), e),
+        }
+
+        println!(
+            "File {} has been completely deleted from the system.",
+            dotfile.source.display()
+        );
+    }
+}
+
+pub mod backups {
+    use crate::config::{self, BackupRecord};
+    use crate::dotfile::backup::{self, Comparison};
+    use crate::symlink;
+    use crate::utils::path_utils;
+    use std::path::Path;
+
+    fn find(id: i64) -> Option<BackupRecord> {
+        match config::get_backup(id) {
+            Ok(Some(record)) => Some(record),
+            Ok(None) => {
+                eprintln!("No backup with id {}", id);
+                None
+            }
+            Err(e) => {
+                eprintln!("Failed to read backups: {}", e);
+                None
+            }
+        }
+    }
+
+    /// List backups, newest first, optionally only those of one path
+    pub fn list(path: Option<&Path>) -> bool {
+        let original = path.map(path_utils::normalize);
+        let backups = match config::get_backups(original.as_deref()) {
+            Ok(backups) => backups,
+            Err(e) => {
+                eprintln!("Failed to read backups: {}", e);
+                return false;
+            }
+        };
+
+        if backups.is_empty() {
+            println!("No backups.");
+            return true;
+        }
+
+        for record in backups {
+            println!(
+                "{:>4}  {}  {:<8} {}",
+                record.id,
+                record.created_at,
+                record.reason,
+                record.original.display()
+            );
+        }
+        true
+    }
+
+    /// Show where a backup came from and how it compares with that path now
+    pub fn show(id: i64) -> bool {
+        let Some(record) = find(id) else {
+            return false;
+        };
+
+        println!("Backup {}", record.id);
+        println!("  Original: {}", record.original.display());
+        println!("  Stored:   {}", backup::location(&record).display());
+        println!("  Taken:    {} ({})", record.created_at, record.reason);
+        println!("  Size:     {} bytes", record.size);
+        println!("  Hash:     {}", record.hash);
+        let now = match backup::compare(&record) {
+            Comparison::Identical => "identical to the backup",
+            Comparison::Differs => "differs from the backup",
+            Comparison::Missing => "original is missing",
+        };
+        println!("  Now:      {}", now);
+        true
+    }
+
+    /// Copy a backup back to where it came from, or to `to`
+    pub fn restore(id: i64, to: Option<&Path>) -> bool {
+        let Some(record) = find(id) else {
+            return false;
+        };
+
+        let dest = match to {
+            Some(to) => path_utils::normalize(to),
+            None if symlink::is_symlink(&record.original) => {
+                eprintln!(
+                    "{} is a symlink now; use --to to restore the backup elsewhere",
+                    record.original.display()
+                );
+                return false;
+            }
+            None => record.original.clone(),
+        };
+
+        match backup::restore(&record, &dest) {
+            Ok(replaced) => {
+                if let Some(replaced) = replaced {
+                    println!("Backed up {} (backup {})", dest.display(), replaced);
+                }
+                println!("Restored backup {} to {}", record.id, dest.display());
+                true
+            }
+            Err(e) => {
+                eprintln!("Failed to restore backup {}: {:#}", record.id, e);
+                false
+            }
+        }
+    }
+
+    /// Delete surplus and old backups
+    pub fn prune(keep: Option<usize>, older_than: Option<u32>, dry_run: bool) -> bool {
+        if keep.is_none() && older_than.is_none() {
+            eprintln!("Nothing to prune by: give --keep, --older-than or both");
+            return false;
+        }
+
+        let prunable = match backup::prunable(keep, older_than) {
+            Ok(prunable) => prunable,
+            Err(e) => {
+                eprintln!("Failed to read backups: {:#}", e);
+                return false;
+            }
+        };
+
+        let mut ok = true;
+        let mut pruned = 0;
+        for record in &prunable {
+            if dry_run {
+                println!(
+                    "Would prune: {} ({}, {})",
+                    record.id,
+                    record.original.display(),
+                    record.created_at
+                );
+                continue;
+            }
+            match backup::remove(record) {
+                Ok(()) => {
+                    println!(
+                        "Pruned: {} ({}, {})",
+                        record.id,
+                        record.original.display(),
+                        record.created_at
+                    );
+                    pruned += 1;
+                }
+                Err(e) => {
+                    eprintln!("Failed to prune backup {}: {:#}", record.id, e);
+                    ok = false;
+                }
+            }
+        }
+
+        if dry_run {
+            println!("\nWould prune {} backup(s)", prunable.len());
+        } else {
+            println!("\nPruned {} backup(s)", pruned);
+        }
+        ok
+    }
+}
+
+pub mod secret {
+    use super::{active_folder_name, link_command, stage_command};
+    use crate::alternate;
+    use crate::config;
+    use crate::deploy::{self, Changed};
+    use crate::dotfile::{DeployMode, DotFile};
+    use crate::history;
+    use crate::secret::{self, Passphrase};
+    use crate::utils::{hash, path_utils, ui};
+    use anyhow::{Result, anyhow, bail};
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process::Command;
+
+    /// Environment variable holding the new passphrase for `forge secret rekey`
+    const NEW_PASSPHRASE_ENV: &str = "FORGE_NEW_PASSPHRASE";
+
+    // The tracked dotfile for a file's original location or managed copy
+    fn find(path: &Path) -> rusqlite::Result<Option<DotFile>> {
+        match config::find_dotfile_by_source(path)? {
+            Some(dotfile) => Ok(Some(dotfile)),
+            None => config::find_dotfile_by_target(path),
+        }
+    }
+
+    // A linked encrypted dotfile, or an error saying why `path` is not one
+    fn find_secret(path: &Path) -> Result<DotFile> {
+        match find(path)? {
+            Some(dotfile) if dotfile.is_linked() && dotfile.mode == DeployMode::Encrypted => {
+                Ok(dotfile)
+            }
+            Some(_) => bail!(
+                "{} is not linked as an encrypted file; use 'forge secret add'",
+                path.display()
+            ),
+            None => bail!("{} is not tracked by forge", path.display()),
+        }
+    }
+
+    // Whether the decrypted file has changes the encrypted copy lacks
+    fn edited(dotfile: &DotFile) -> bool {
+        if fs::symlink_metadata(&dotfile.source).is_err() {
+            return false;
+        }
+        match deploy::changed_side(dotfile) {
+            Ok(None) | Ok(Some(Changed::Managed)) => false,
+            Ok(Some(Changed::Deployed)) => true,
+            Ok(Some(Changed::Both)) | Err(_) => !deploy::is_deployed(
+                dotfile.mode,
+                &deploy::managed_path(dotfile),
+                &dotfile.source,
+            ),
+        }
+    }
+
+    /// Encrypt files into the managed folder. Untracked and staged files are
+    /// linked in encrypted mode; for a linked encrypted file, changes made to
+    /// the decrypted file are encrypted into the managed copy.
+    pub fn add(files: &[PathBuf]) -> bool {
+        let mut ok = true;
+        let (mut to_stage, mut to_link) = (Vec::new(), Vec::new());
+
+        for file in files {
+            let path = path_utils::normalize(file);
+            match find(&path) {
+                Ok(Some(dotfile)) if dotfile.is_staged() => to_link.push(dotfile.source),
+                Ok(Some(dotfile)) if dotfile.mode == DeployMode::Encrypted => {
+                    match reencrypt(&dotfile) {
+                        Ok(true) => println!(
+                            "Encrypted: {} → {}",
+                            dotfile.source.display(),
+                            deploy::managed_path(&dotfile).display()
+                        ),
+                        Ok(false) => println!("Already encrypted: {}", dotfile.source.display()),
+                        Err(e) => {
+                            eprintln!("{:#}", e);
+                            ok = false;
+                        }
+                    }
+                }
+                Ok(Some(dotfile)) => {
+                    eprintln!(
+                        "{} is linked as a {}; unlink it before encrypting it",
+                        dotfile.source.display(),
+                        dotfile.mode
+                    );
+                    ok = false;
+                }
+                Ok(None) if path.is_dir() => {
+                    eprintln!(
+                        "{} is a directory; only files can be encrypted",
+                        path.display()
+                    );
+                    ok = false;
+                }
+                Ok(None) if !path.is_file() => {
+                    eprintln!("File does not exist: {}", path.display());
+                    ok = false;
+                }
+                Ok(None) => {
+                    to_link.push(path.clone());
+                    to_stage.push(path);
+                }
+                Err(e) => {
+                    eprintln!("Error checking file {}: {}", path.display(), e);
+                    ok = false;
+                }
+            }
+        }
+
+        if !to_stage.is_empty() {
+            stage_command(&to_stage, false, None, Some(DeployMode::Encrypted));
+        }
+        if !to_link.is_empty() {
+            link_command(&to_link, Some(DeployMode::Encrypted));
+        }
+        // Staging and linking report their own errors; check what they left
+        for path in &to_link {
+            let linked = config::find_dotfile_by_source(path).is_ok_and(|dotfile| {
+                dotfile.is_some_and(|d| d.is_linked() && d.mode == DeployMode::Encrypted)
+            });
+            ok &= linked;
+        }
+        ok
+    }
+
+    // Encrypt a linked file's decrypted content again if it changed. The
+    // encrypted copy must not have changed since it was decrypted, or those
+    // changes would be lost.
+    fn reencrypt(dotfile: &DotFile) -> Result<bool> {
+        let changed = match deploy::changed_side(dotfile) {
+            Ok(changed) => changed,
+            Err(_) => bail!(
+                "{} is missing; run 'forge link' to decrypt it",
+                dotfile.source.display()
+            ),
+        };
+        match changed {
+            None => Ok(false),
+            Some(Changed::Deployed) => deploy::encrypt(dotfile).map(|()| true),
+            Some(Changed::Managed) => bail!(
+                "The encrypted copy of {} changed since it was decrypted; run 'forge link' first",
+                dotfile.source.display()
+            ),
+            Some(Changed::Both) if edited(dotfile) => bail!(
+                "{} and its encrypted copy both changed; merge them with 'forge secret edit'",
+                dotfile.source.display()
+            ),
+            // Same content, only the record of the last decryption is missing
+            Some(Changed::Both) => deploy::decrypt(dotfile).map(|_| false),
         }
     }
-}
 
-/// Delete files completely from the system
-pub fn delete_command(files: &[PathBuf], skip_confirm: bool) {
-    if files.is_empty() {
-        println!("No files specified to delete. Here are all tracked files:");
-        crate::dotfile::list::print_dotfiles(None);
-        return;
+    /// Decrypt a linked encrypted file into a private temporary file, open it
+    /// in $VISUAL or $EDITOR, and encrypt and deploy the result
+    pub fn edit(file: &Path) -> bool {
+        match edit_impl(&path_utils::normalize(file)) {
+            Ok(()) => true,
+            Err(e) => {
+                eprintln!("{:#}", e);
+                false
+            }
+        }
     }
 
-    // Get the active managed folder
-    let (folder_name, forge_path) = match config::get_active_managed_folder() {
-        Ok(Some((name, path))) => (name, path),
-        Ok(None) => {
-            eprintln!("No managed folders found. Please run 'forge init' first.");
-            return;
+    fn edit_impl(path: &Path) -> Result<()> {
+        let dotfile = find_secret(path)?;
+        if edited(&dotfile) {
+            bail!(
+                "{} has changes that are not in the encrypted copy; run 'forge secret add {}' first",
+                dotfile.source.display(),
+                dotfile.source.display()
+            );
         }
-        Err(e) => {
-            eprintln!("Failed to get managed folder: {}", e);
-            return;
+        let managed = deploy::managed_path(&dotfile);
+        let plain = secret::decrypt_file(&managed)?;
+
+        // Only the owner can enter the directory, so the decrypted file is
+        // never readable by anyone else
+        let dir = tempfile::Builder::new().prefix("forge-secret-").tempdir()?;
+        let name = dotfile
+            .source
+            .file_name()
+            .ok_or_else(|| anyhow!("Invalid file name: {}", dotfile.source.display()))?;
+        let scratch = dir.path().join(name);
+        fs::write(&scratch, &plain)?;
+
+        let editor = std::env::var("VISUAL")
+            .or_else(|_| std::env::var("EDITOR"))
+            .unwrap_or_else(|_| "vi".to_string());
+        // Through the shell, so an editor given with arguments works
+        let status = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} \"$@\"", editor))
+            .arg("forge-editor")
+            .arg(&scratch)
+            .status()
+            .map_err(|e| anyhow!("Failed to run {}: {}", editor, e))?;
+        if !status.success() {
+            bail!("{} exited with {}; nothing was changed", editor, status);
         }
-    };
-
-    println!(
-        "Using managed folder '{}' at {}",
-        folder_name,
-        forge_path.display()
-    );
-
-    // For each file, delete it completely
-    for file in files {
-        // Determine target path
-        let target = if file.is_absolute() {
-            // If absolute path, use it directly (might be a target)
-            file.clone()
-        } else if let Some(filename) = file.file_name() {
-            // If relative, assume it's a filename to be combined with forge dir
-            forge_path.join(filename)
-        } else {
-            eprintln!("Invalid file path: {}", file.display());
-            continue;
-        };
-
-        // Try to find the dotfile in the database
-        let dotfile = match config::find_dotfile_by_target(&target) {
-            Ok(Some(df)) => df,
-            Ok(None) => {
-                println!("No tracking record found for {}", target.display());
 
-                // Even if not tracked, confirm deletion
-                if !skip_confirm {
-                    let msg = format!(
-                        "WARNING: This will PERMANENTLY DELETE the file {} from your system.\nThis action CANNOT be undone.",
-                        file.display()
-                    );
-                    if !crate::utils::ui::confirm_with_text(&msg, "DELETE") {
-                        println!("Deletion cancelled.");
-                        continue;
-                    }
-                }
+        let edited = fs::read(&scratch)?;
+        if edited == plain {
+            println!("No changes to {}", dotfile.source.display());
+            return Ok(());
+        }
+        history::write(&managed, &secret::encrypt(&edited)?)?;
+        deploy::decrypt(&dotfile)?;
+        println!(
+            "Encrypted: {} → {}",
+            dotfile.source.display(),
+            managed.display()
+        );
+        Ok(())
+    }
 
-                match fs::remove_file(file) {
-                    Ok(_) => println!("Deleted file: {}", file.display()),
-                    Err(e) => println!("Failed to delete file {}: {}", file.display(), e),
-                }
-                continue;
+    /// Encrypt every encrypted file of the active managed folder, alternates
+    /// included, with a new passphrase, or with a key file that is used from
+    /// then on
+    pub fn rekey(key_file: Option<&Path>) -> bool {
+        match rekey_impl(key_file) {
+            Ok(count) => {
+                println!("Re-encrypted {} file(s)", count);
+                true
             }
             Err(e) => {
-                eprintln!("Error looking up dotfile: {}", e);
-                continue;
+                eprintln!("{:#}", e);
+                false
             }
+        }
+    }
+
+    fn rekey_impl(key_file: Option<&Path>) -> Result<usize> {
+        let Some(folder_name) = active_folder_name() else {
+            bail!("No managed folders found. Please run 'forge init' first.");
+        };
+        let dotfiles: Vec<_> = config::get_dotfiles(None, Some(&folder_name))?
+            .into_iter()
+            .filter(|d| d.is_linked() && d.mode == DeployMode::Encrypted)
+            .collect();
+
+        let key_file = key_file.map(path_utils::normalize);
+        let mut new = match &key_file {
+            Some(path) => Passphrase::from_key_file(path)?,
+            None => new_passphrase()?,
         };
 
-        // Confirm with user
-        if !skip_confirm {
-            let msg = format!(
-                "WARNING: This will PERMANENTLY DELETE the file from your system.\n\
-                - The symlink at {} will be removed\n\
-                - The file will be deleted from {}\n\
-                - The file will be deleted from the forge folder\n\
-                - All tracking information will be removed from the database\n\
-                This action CANNOT be undone.",
-                dotfile.source.display(),
-                dotfile.source.display()
+        // Decrypt everything first, so a file the current passphrase cannot
+        // open leaves every file under the old one
+        let mut files = Vec::new();
+        for dotfile in &dotfiles {
+            let mut paths = vec![dotfile.target.clone()];
+            paths.extend(
+                alternate::variants(&dotfile.target)
+                    .into_iter()
+                    .map(|v| v.path),
             );
-            if !crate::utils::ui::confirm_with_text(&msg, "DELETE") {
-                println!("Deletion cancelled.");
-                continue;
+            for path in paths {
+                if secret::is_encrypted_file(&path) {
+                    let plain = secret::decrypt_file(&path)?;
+                    files.push((dotfile, path, plain));
+                }
             }
         }
 
-        // Remove symlink if it exists
-        if symlink::is_symlink(&dotfile.source) {
-            if let Err(e) = fs::remove_file(&dotfile.source) {
-                println!(
-                    "Failed to remove symlink {}: {}",
-                    dotfile.source.display(),
-                    e
-                );
-                println!("Continuing with deletion...");
-            }
-        } else {
-            // Remove original file if it's not a symlink
-            if let Err(e) = fs::remove_file(&dotfile.source) {
-                println!(
-                    "Failed to remove original file {}: {}",
-                    dotfile.source.display(),
-                    e
-                );
-                println!("Continuing with deletion...");
+        for (dotfile, path, plain) in &files {
+            let before = hash::path_hash(path)?;
+            history::write(path, &new.encrypt(plain)?)?;
+            // Keep the record of the last decryption current, unless it
+            // already tells that the encrypted copy changed since
+            if *path == deploy::managed_path(dotfile)
+                && let Some(synced) = config::get_synced_hash(&dotfile.target)?
+                && let Some((deployed, managed)) = synced.split_once(':')
+                && managed == before
+            {
+                let hash = format!("{}:{}", deployed, hash::path_hash(path)?);
+                config::set_synced_hash(&dotfile.target, Some(&hash))?;
             }
+            println!("Re-encrypted: {}", path.display());
         }
 
-        // Delete from forge folder
-        match fs::remove_file(&dotfile.target) {
-            Ok(_) => {
-                println!(
-                    "Deleted file from forge folder: {}",
-                    dotfile.target.display()
-                );
-
-                // Remove from database
-                match config::remove_dotfile(&dotfile.target) {
-                    Ok(_) => println!("Removed tracking for {}", dotfile.target.display()),
-                    Err(e) => eprintln!("Failed to update database: {}", e),
+        match &key_file {
+            Some(path) => {
+                config::set_setting(config::SECRET_KEY_FILE_KEY, &path.to_string_lossy())?;
+                println!("Using key file {} from now on", path.display());
+            }
+            None => {
+                if config::remove_setting(config::SECRET_KEY_FILE_KEY)? {
+                    println!("No longer using a key file; the new passphrase is asked for instead");
                 }
             }
-            Err(e) => println!("Failed to delete file {}: {}", dotfile.target.display(), e),
         }
+        Ok(files.len())
+    }
 
-        println!(
-            "File {} has been completely deleted from the system.",
-            dotfile.source.display()
-        );
+    // The passphrase to re-encrypt with, from FORGE_NEW_PASSPHRASE or asked
+    // for twice on the terminal
+    fn new_passphrase() -> Result<Passphrase> {
+        if let Ok(passphrase) = std::env::var(NEW_PASSPHRASE_ENV) {
+            return Passphrase::new(passphrase);
+        }
+        let Some(first) = ui::prompt_hidden("New passphrase") else {
+            bail!(
+                "No new passphrase: set {} or give --key-file",
+                NEW_PASSPHRASE_ENV
+            );
+        };
+        if ui::prompt_hidden("Repeat the new passphrase").as_deref() != Some(first.as_str()) {
+            bail!("The passphrases do not match");
+        }
+        Passphrase::new(first)
     }
 }
 
@@ -1013,6 +2983,7 @@ pub mod pack;
 
 pub mod profile {
     use crate::config;
+    use crate::dotfile::DeployMode;
     use std::fs;
     use std::path::PathBuf;
 
@@ -1073,8 +3044,8 @@ pub mod profile {
         }
     }
 
-    /// Switch to a profile
-    pub fn switch(name: &str) {
+    /// Switch to a profile, deploying its files in `mode`
+    pub fn switch(name: &str, mode: DeployMode) {
         println!("Switching to profile: {}", name);
 
         // Check if profile exists
@@ -1088,29 +3059,20 @@ pub mod profile {
 
         // Get default target directory from config
         let target_dir = config::read_default_path();
-        let target_path = PathBuf::from(&target_dir);
 
         // Create symlinks from profile directory to target
-        match crate::symlink::create_symlinks(&profile_dir, &target_dir) {
-            Ok(_) => {
+        match crate::symlink::create_symlinks(&profile_dir, &target_dir, mode) {
+            Ok(deployed) => {
                 println!("Created symlinks from profile '{}' successfully", name);
 
-                // Track the files in the database with the profile
+                // Track the deployed files in the database with the profile
                 let mut success_count = 0;
                 let mut error_count = 0;
 
-                for entry in walkdir::WalkDir::new(&profile_dir)
-                    .min_depth(1)
-                    .max_depth(1)
-                    .into_iter()
-                    .filter_map(|e| e.ok())
-                    .filter(|e| e.path().is_file())
-                {
-                    let source = entry.path();
-                    let filename = entry.file_name();
-                    let target = target_path.join(filename);
-
-                    match config::add_dotfile(source, &target, Some(name)) {
+                for (source, target) in &deployed {
+                    let recorded = config::add_dotfile(source, target, Some(name))
+                        .and_then(|_| super::record_mode(target, Some(mode)));
+                    match recorded {
                         Ok(_) => {
                             println!(
                                 "Added to profile '{}': {} → {}",
@@ -1143,9 +3105,286 @@ pub mod profile {
     }
 }
 
+pub mod rules {
+    use crate::config;
+    use crate::selection::{self, Action, Kind, Outcome, Rule, Rules};
+    use crate::utils::path_utils;
+    use std::path::Path;
+
+    fn load() -> Option<Rules> {
+        match Rules::load() {
+            Ok(rules) => Some(rules),
+            Err(e) => {
+                eprintln!("Failed to read selection rules: {}", e);
+                None
+            }
+        }
+    }
+
+    /// List the selection rules in the order they were added
+    pub fn list() -> bool {
+        let Some(rules) = load() else {
+            return false;
+        };
+        let mut rules = rules.iter().peekable();
+        if rules.peek().is_none() {
+            println!("No selection rules; every file is selected.");
+            return true;
+        }
+        for rule in rules {
+            println!(
+                "{:>4}  {:<8} {:<6} {}",
+                rule.id,
+                rule.action.as_str(),
+                rule.kind.as_str(),
+                rule.pattern
+            );
+        }
+        true
+    }
+
+    /// Add a rule that includes or excludes the files matching `pattern`
+    pub fn add(action: Action, kind: Kind, pattern: &str) -> bool {
+        if let Err(e) = Rule::new(0, action, kind, pattern) {
+            eprintln!("Cannot add rule: {}", e);
+            return false;
+        }
+        match config::add_selection_rule(action.as_str(), kind.as_str(), pattern) {
+            Ok(Some(id)) => {
+                println!(
+                    "Added rule {}: {} {} {}",
+                    id,
+                    action.as_str(),
+                    kind.as_str(),
+                    pattern
+                );
+                true
+            }
+            Ok(None) => {
+                println!("The rule already exists.");
+                true
+            }
+            Err(e) => {
+                eprintln!("Failed to add rule: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Remove a rule by id
+    pub fn remove(id: i64) -> bool {
+        match config::remove_selection_rule(id) {
+            Ok(true) => {
+                println!("Removed rule {}", id);
+                true
+            }
+            Ok(false) => {
+                eprintln!("No selection rule with id {}", id);
+                false
+            }
+            Err(e) => {
+                eprintln!("Failed to remove rule: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Show which files of `dir` bulk linking would select, and why
+    pub fn test(dir: &Path) -> bool {
+        let Some(rules) = load() else {
+            return false;
+        };
+        let dir = path_utils::normalize(dir);
+        let candidates = match selection::scan(&dir, &rules) {
+            Ok(candidates) => candidates,
+            Err(e) => {
+                eprintln!("Cannot scan {}: {}", dir.display(), e);
+                return false;
+            }
+        };
+
+        let mut selected = 0;
+        for candidate in &candidates {
+            let relative = candidate.path.strip_prefix(&dir).unwrap_or(&candidate.path);
+            let name = if relative == candidate.deployed {
+                relative.display().to_string()
+            } else {
+                format!(
+                    "{} (as {})",
+                    relative.display(),
+                    candidate.deployed.display()
+                )
+            };
+            let (verdict, reason) = match &candidate.outcome {
+                Outcome::Selected(Some(rule)) => ("selected", rule.clone()),
+                Outcome::Selected(None) => ("selected", "no include rules".to_string()),
+                Outcome::Excluded(rule) => ("excluded", rule.clone()),
+                Outcome::NotIncluded => ("skipped", "no include rule matches".to_string()),
+                Outcome::Ignored(reason) => ("ignored", reason.clone()),
+                Outcome::OtherVariant => (
+                    "skipped",
+                    "another variant is selected for this machine".to_string(),
+                ),
+            };
+            if candidate.outcome.is_selected() {
+                selected += 1;
+            }
+            println!("  {:<9} {}  ({})", verdict, name, reason);
+        }
+        println!(
+            "\n{} selected, {} not selected",
+            selected,
+            candidates.len() - selected
+        );
+        true
+    }
+}
+
+pub mod folders {
+    use crate::config;
+
+    /// List managed folders, marking the active and default ones
+    pub fn list() {
+        let folders = match config::get_managed_folders() {
+            Ok(folders) => folders,
+            Err(e) => {
+                eprintln!("Failed to read managed folders: {}", e);
+                return;
+            }
+        };
+
+        if folders.is_empty() {
+            println!("No managed folders found. Please run 'forge init' first.");
+            return;
+        }
+
+        let active = config::get_active_managed_folder().ok().flatten();
+        let default = config::get_default_managed_folder().ok().flatten();
+
+        println!("Managed folders:");
+        for (name, path) in &folders {
+            let marker = if active.as_ref().is_some_and(|(n, _)| n == name) {
+                "*"
+            } else {
+                " "
+            };
+            let suffix = if default.as_deref() == Some(name.as_str()) {
+                " (default)"
+            } else {
+                ""
+            };
+            println!("{} {} → {}{}", marker, name, path.display(), suffix);
+        }
+    }
+
+    /// Make a folder the active one for later commands
+    pub fn use_folder(name: &str) -> bool {
+        match config::use_managed_folder(name) {
+            Ok(true) => {
+                println!("Now using managed folder '{}'", name);
+                true
+            }
+            Ok(false) => {
+                eprintln!("No managed folder named '{}'", name);
+                false
+            }
+            Err(e) => {
+                eprintln!("Failed to switch managed folder: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Rename a managed folder
+    pub fn rename(old: &str, new: &str) -> bool {
+        if config::get_managed_folders().is_ok_and(|folders| folders.iter().any(|(n, _)| n == new))
+        {
+            eprintln!("A managed folder named '{}' already exists", new);
+            return false;
+        }
+
+        match config::rename_managed_folder(old, new) {
+            Ok(true) => {
+                println!("Renamed managed folder '{}' to '{}'", old, new);
+                true
+            }
+            Ok(false) => {
+                eprintln!("No managed folder named '{}'", old);
+                false
+            }
+            Err(e) => {
+                eprintln!("Failed to rename managed folder: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Forget a managed folder; its files are left where they are
+    pub fn remove(name: &str) -> bool {
+        let path = match config::get_managed_folders() {
+            Ok(folders) => match folders.into_iter().find(|(n, _)| n == name) {
+                Some((_, path)) => path,
+                None => {
+                    eprintln!("No managed folder named '{}'", name);
+                    return false;
+                }
+            },
+            Err(e) => {
+                eprintln!("Failed to read managed folders: {}", e);
+                return false;
+            }
+        };
+
+        let tracked = config::get_dotfiles(None, Some(name))
+            .map(|dotfiles| dotfiles.len())
+            .unwrap_or(0);
+        if tracked > 0 {
+            eprintln!(
+                "Managed folder '{}' still tracks {} file(s). Unlink or purge them first.",
+                name, tracked
+            );
+            return false;
+        }
+
+        match config::remove_managed_folder(name) {
+            Ok(_) => {
+                println!(
+                    "Removed managed folder '{}' (files in {} were left in place)",
+                    name,
+                    path.display()
+                );
+                true
+            }
+            Err(e) => {
+                eprintln!("Failed to remove managed folder: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Mark a folder as the default, used when no folder has been selected
+    pub fn set_default(name: &str) -> bool {
+        match config::set_default_managed_folder(name) {
+            Ok(true) => {
+                println!("Managed folder '{}' is now the default", name);
+                true
+            }
+            Ok(false) => {
+                eprintln!("No managed folder named '{}'", name);
+                false
+            }
+            Err(e) => {
+                eprintln!("Failed to set default managed folder: {}", e);
+                false
+            }
+        }
+    }
+}
+
 /// Unstage (deactivate) staged files by target path, with optional recursive support
 pub fn unstage_command(files: &[PathBuf], recursive: bool, max_depth: Option<usize>) {
-    let staged_dotfiles = match config::get_staged_dotfiles(None) {
+    let folder_name = active_folder_name();
+    let staged_dotfiles = match config::get_staged_dotfiles(None, folder_name.as_deref()) {
         Ok(df) => df,
         Err(e) => {
             eprintln!("Failed to fetch staged files: {}", e);
@@ -1160,7 +3399,7 @@ pub fn unstage_command(files: &[PathBuf], recursive: bool, max_depth: Option<usi
                 println!("Unstaged {} files.", count);
                 // Remove the staging symlinks in the forge directory
                 for dotfile in &staged_dotfiles {
-                    if let Err(e) = std::fs::remove_file(&dotfile.target) {
+                    if let Err(e) = history::remove_file(&dotfile.target) {
                         if e.kind() != std::io::ErrorKind::NotFound {
                             eprintln!(
                                 "Failed to remove staging symlink {}: {}",
@@ -1222,6 +3461,25 @@ pub fn unstage_command(files: &[PathBuf], recursive: bool, max_depth: Option<usi
     }
 }
 
+// Replace the symlink at a dotfile's original location with a copy of the
+// managed file or directory; the symlink is put back if the copy fails
+fn restore_original(dotfile: &DotFile) -> std::io::Result<()> {
+    let text = fs::read_link(&dotfile.source)?;
+    history::remove_file(&dotfile.source)?;
+    let copied = if dotfile.target.is_dir() {
+        history::copy_tree(&dotfile.target, &dotfile.source)
+    } else {
+        history::copy(&dotfile.target, &dotfile.source).map(|_| ())
+    };
+    if copied.is_err() {
+        if fs::symlink_metadata(&dotfile.source).is_ok() {
+            let _ = remove_path(&dotfile.source);
+        }
+        let _ = history::create_symlink(text, &dotfile.source);
+    }
+    copied
+}
+
 /// Purge all dotfile records and managed files for a specified folder, restoring originals to prevent data loss
 pub fn purge_command_safe(folder: &Path, recursive: bool) {
     let abs_folder = path_utils::normalize(folder);
@@ -1237,28 +3495,22 @@ pub fn purge_command_safe(folder: &Path, recursive: bool) {
             return;
         }
     };
+    // Records whose original could not be restored are kept, so nothing is
+    // left only in the managed folder without a record
+    let mut unrestored = Vec::new();
     for dotfile in &dotfiles {
         // If the original location is a symlink to the managed file, restore the real file
         if dotfile.source.is_symlink() {
-            match std::fs::read_link(&dotfile.source) {
-                Ok(target_path) if target_path == dotfile.target => {
-                    // Remove the symlink at the original location
-                    if let Err(e) = std::fs::remove_file(&dotfile.source) {
-                        eprintln!(
-                            "Failed to remove symlink {}: {}",
-                            dotfile.source.display(),
-                            e
-                        );
-                        continue;
-                    }
-                    // Copy the managed file back to the original location
-                    if let Err(e) = std::fs::copy(&dotfile.target, &dotfile.source) {
+            match symlink::resolve_link(&dotfile.source) {
+                Some(target_path) if target_path == dotfile.target => {
+                    if let Err(e) = restore_original(dotfile) {
                         eprintln!(
-                            "Failed to restore file from managed folder: {} -> {}: {}",
+                            "Failed to restore from managed folder: {} -> {}: {}",
                             dotfile.target.display(),
                             dotfile.source.display(),
                             e
                         );
+                        unrestored.push(dotfile.target.clone());
                         continue;
                     }
                     println!("Restored and removed symlink: {}", dotfile.source.display());
@@ -1272,7 +3524,7 @@ pub fn purge_command_safe(folder: &Path, recursive: bool) {
         {
             continue;
         }
-        if let Err(e) = std::fs::remove_file(&dotfile.target) {
+        if let Err(e) = remove_path(&dotfile.target) {
             if e.kind() != std::io::ErrorKind::NotFound {
                 eprintln!(
                     "Failed to remove managed file {}: {}",
@@ -1285,7 +3537,7 @@ pub fn purge_command_safe(folder: &Path, recursive: bool) {
         }
     }
     // Purge from database
-    match crate::config::purge_dotfiles_in_folder(&abs_folder, recursive) {
+    match crate::config::purge_dotfiles_in_folder(&abs_folder, recursive, &unrestored) {
         Ok(count) => println!("Purged {} database records.", count),
         Err(e) => eprintln!("Failed to purge database records: {}", e),
     }
@@ -1301,7 +3553,7 @@ pub fn purge_command_safe(folder: &Path, recursive: bool) {
                 remove_empty_dirs(&p);
             }
             // Try to remove the directory (will only succeed if empty)
-            let _ = std::fs::remove_dir(path);
+            let _ = history::remove_dir(path);
         }
     }
     remove_empty_dirs(&abs_folder);
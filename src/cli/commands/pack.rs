@@ -1,13 +1,18 @@
 // Pack-and-Go functionality for Forge
 use crate::config;
-use crate::utils::path_utils;
+use crate::deploy;
+use crate::dotfile::{DeployMode, backup};
+use crate::forgeignore::{IgnoreRules, Verdict};
+use crate::history;
+use crate::secret;
+use crate::utils::{hash, path_utils};
 use anyhow::{Result, anyhow};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::env;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::{Component, Path, PathBuf};
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PackManifest {
@@ -19,11 +24,19 @@ pub struct PackManifest {
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PackFile {
+    /// Original location, for display (lossy if it is not valid UTF-8)
     pub target_path: String,
+    /// Base64 of the original location's raw bytes, when it is not valid UTF-8
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_path_raw: Option<String>,
     pub relative_path: String,
     pub hash: Option<String>,
     pub size: u64,
     pub modified: DateTime<Utc>,
+    /// The file is packed as the encrypted managed copy and is decrypted when
+    /// installed or restored
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub encrypted: bool,
 }
 
 impl PackManifest {
@@ -41,6 +54,7 @@ impl PackManifest {
         target_path: &Path,
         relative_path: &Path,
         hash: Option<String>,
+        encrypted: bool,
     ) -> Result<()> {
         let metadata = fs::metadata(target_path)?;
         let modified = metadata.modified()?;
@@ -48,19 +62,69 @@ impl PackManifest {
 
         let pack_file = PackFile {
             target_path: target_path.to_string_lossy().to_string(),
+            target_path_raw: target_path
+                .to_str()
+                .is_none()
+                .then(|| path_utils::encode_base64(target_path)),
             relative_path: relative_path.to_string_lossy().to_string(),
             hash,
             size: metadata.len(),
             modified: modified_dt,
+            encrypted,
         };
 
-        self.files
-            .insert(target_path.to_string_lossy().to_string(), pack_file);
+        self.files.insert(manifest_key(target_path), pack_file);
 
         Ok(())
     }
 }
 
+impl PackFile {
+    /// The exact original location of the file
+    pub fn target(&self) -> Result<PathBuf> {
+        match &self.target_path_raw {
+            Some(raw) => Ok(path_utils::decode_base64(raw)?),
+            None => Ok(PathBuf::from(&self.target_path)),
+        }
+    }
+}
+
+/// Key of a file in the manifest: its path, or the base64 of its raw bytes if
+/// the path is not valid UTF-8
+fn manifest_key(path: &Path) -> String {
+    match path.to_str() {
+        Some(path) => path.to_string(),
+        None => format!("base64:{}", path_utils::encode_base64(path)),
+    }
+}
+
+/// Name a file is stored under inside the pack. Zip entry names must be UTF-8,
+/// so a path that is not gets its lossy form plus a hash of the raw bytes to
+/// keep it distinct; the manifest records where it really belongs.
+fn archive_path(relative_path: &Path) -> PathBuf {
+    if relative_path.to_str().is_some() {
+        return relative_path.to_path_buf();
+    }
+    let digest = blake3::hash(&path_utils::to_bytes(relative_path)).to_hex();
+    PathBuf::from(format!(
+        "{}.{}",
+        relative_path.to_string_lossy(),
+        &digest[..12]
+    ))
+}
+
+/// What is packed for a file: an encrypted dotfile's managed copy rather than
+/// its decrypted content, so that secrets never enter a pack in plain text
+fn packed_content(path: &Path) -> (PathBuf, bool) {
+    if let Ok(Some(dotfile)) = config::find_dotfile_by_source(path)
+        && dotfile.is_linked()
+        && dotfile.mode == DeployMode::Encrypted
+    {
+        return (deploy::managed_path(&dotfile), true);
+    }
+    (path.to_path_buf(), secret::is_encrypted_file(path))
+}
+
 /// Get the pack staging directory for a given scope
 fn get_pack_staging_dir(scope: &str) -> Result<PathBuf> {
     let (_, forge_path) = config::get_active_managed_folder()?
@@ -208,6 +272,8 @@ fn pack_files_impl(
 
     let files_dir = staging_dir.join("files");
     let mut added_count = 0;
+    let rules = IgnoreRules::for_active_folder()?
+        .ok_or_else(|| anyhow!("No managed folders found. Please run 'forge init' first."))?;
 
     // Collect all files to process (including from directories if recursive)
     let mut files_to_process = Vec::new();
@@ -249,19 +315,20 @@ fn pack_files_impl(
                     );
                 }
 
-                // Use walkdir to recursively collect files from directory
+                // Use walkdir to recursively collect files from directory,
+                // leaving out what the ignore rules (and .forge) exclude
+                let dir_name = abs_source.file_name().unwrap_or_default();
                 for entry in walkdir::WalkDir::new(&abs_source)
                     .min_depth(1) // Skip the root dir itself
                     .max_depth(walkdir_depth)
                     .into_iter()
-                    .filter_map(|e| e.ok())
-                    .filter(|e| {
-                        // Skip .forge directory and its contents
-                        if e.path().components().any(|c| c.as_os_str() == ".forge") {
-                            return false;
-                        }
-                        e.path().is_file()
+                    .filter_entry(|e| {
+                        let rel_path = e.path().strip_prefix(&abs_source).unwrap_or(e.path());
+                        let relative = Path::new(dir_name).join(rel_path);
+                        !rules.is_ignored(&relative, e.file_type().is_dir())
                     })
+                    .filter_map(|e| e.ok())
+                    .filter(|e| e.path().is_file())
                 {
                     // Calculate relative path from original directory
                     let rel_path = entry
@@ -270,7 +337,6 @@ fn pack_files_impl(
                         .unwrap_or_else(|_| Path::new(entry.file_name()));
 
                     // Include directory name in the relative path to preserve structure
-                    let dir_name = abs_source.file_name().unwrap_or_default();
                     let full_relative_path = Path::new(dir_name).join(rel_path);
 
                     files_to_process.push((entry.path().to_path_buf(), full_relative_path));
@@ -301,6 +367,11 @@ fn pack_files_impl(
 
     // Process all collected files
     for (abs_source, relative_path) in files_to_process {
+        if let Verdict::Ignored(rule) = rules.check(&relative_path, false) {
+            println!("Ignored: {} ({})", abs_source.display(), rule);
+            continue;
+        }
+        let relative_path = archive_path(&relative_path);
         let target_in_pack = files_dir.join(&relative_path);
 
         // Check if already exists
@@ -313,11 +384,15 @@ fn pack_files_impl(
             continue;
         }
 
+        let (content, encrypted) = packed_content(&abs_source);
+        let note = if encrypted { " (encrypted)" } else { "" };
+
         if dry_run {
             println!(
-                "Would pack: {} → {}",
+                "Would pack: {} → {}{}",
                 abs_source.display(),
-                relative_path.display()
+                relative_path.display(),
+                note
             );
             added_count += 1;
             continue;
@@ -327,18 +402,19 @@ fn pack_files_impl(
         if let Some(parent) = target_in_pack.parent() {
             fs::create_dir_all(parent)?;
         }
-        fs::copy(&abs_source, &target_in_pack)?;
+        fs::copy(&content, &target_in_pack)?;
 
         // Calculate hash
         let hash = calculate_file_hash(&target_in_pack)?;
 
         // Add to manifest
-        manifest.add_file(&abs_source, &relative_path, Some(hash))?;
+        manifest.add_file(&abs_source, &relative_path, Some(hash), encrypted)?;
 
         println!(
-            "Packed: {} → {}",
+            "Packed: {} → {}{}",
             abs_source.display(),
-            relative_path.display()
+            relative_path.display(),
+            note
         );
         added_count += 1;
     }
@@ -354,9 +430,7 @@ fn pack_files_impl(
 
 /// Calculate BLAKE3 hash of a file
 fn calculate_file_hash(path: &Path) -> Result<String> {
-    let content = fs::read(path)?;
-    let hash = blake3::hash(&content);
-    Ok(hash.to_hex().to_string())
+    Ok(hash::file_hash(path)?)
 }
 
 /// Seal a pack into a portable archive
@@ -403,6 +477,8 @@ fn seal_pack_impl(scope: &str) -> Result<PathBuf> {
         ));
     }
 
+    check_encrypted(&staging_dir)?;
+
     // Create archives directory
     let archives_dir = get_pack_archives_dir()?;
     fs::create_dir_all(&archives_dir)?;
@@ -423,6 +499,29 @@ fn seal_pack_impl(scope: &str) -> Result<PathBuf> {
     Ok(archive_path)
 }
 
+/// Refuse to seal a pack holding a secret in plain text: a file packed before
+/// it became an encrypted dotfile, or one whose packed copy is not encrypted
+fn check_encrypted(staging_dir: &Path) -> Result<()> {
+    let manifest_path = staging_dir.join("manifest.toml");
+    if !manifest_path.exists() {
+        return Ok(());
+    }
+    let manifest: PackManifest = toml::from_str(&fs::read_to_string(&manifest_path)?)?;
+
+    for pack_file in manifest.files.values() {
+        let packed = staging_dir.join("files").join(&pack_file.relative_path);
+        let (_, secret) = packed_content(&pack_file.target()?);
+        if (secret || pack_file.encrypted) && !secret::is_encrypted_file(&packed) {
+            return Err(anyhow!(
+                "{} is an encrypted dotfile but was packed in plain text; run 'forge unpack {}' and pack it again",
+                pack_file.target_path,
+                pack_file.target_path
+            ));
+        }
+    }
+    Ok(())
+}
+
 /// Create a ZIP archive from the staging directory
 fn create_zip_archive(staging_dir: &Path, archive_path: &Path) -> Result<()> {
     use std::io::Write;
@@ -608,7 +707,7 @@ fn install_pack_impl(
         }
 
         // Calculate target path based on options (install mode)
-        let target_path = calculate_install_target_path(&pack_file.target_path, target, map_home)?;
+        let target_path = calculate_install_target_path(&pack_file.target()?, target, map_home)?;
 
         if dry_run {
             println!(
@@ -647,15 +746,26 @@ fn install_pack_impl(
             }
         }
 
+        // Keep what --force is about to overwrite
+        if target_path.is_file() {
+            let id = backup::save(&target_path, "install")?;
+            println!("Backed up {} (backup {})", target_path.display(), id);
+        }
+
         // Create target directory if needed
         if let Some(parent) = target_path.parent() {
-            fs::create_dir_all(parent)?;
+            history::create_dir_all(parent)?;
         }
 
-        // Copy file to target location
-        fs::copy(&source_in_archive, &target_path)?;
-
-        println!("Installed: {}", target_path.display());
+        // Copy file to target location, decrypting a secret
+        if pack_file.encrypted {
+            let plain = secret::decrypt_file(&source_in_archive)?;
+            history::write_private(&target_path, &plain)?;
+            println!("Installed: {} (decrypted)", target_path.display());
+        } else {
+            history::copy(&source_in_archive, &target_path)?;
+            println!("Installed: {}", target_path.display());
+        }
         installed_count += 1;
     }
 
@@ -712,7 +822,7 @@ fn restore_pack_impl(
         }
 
         // Calculate target path based on options (restore mode)
-        let target_path = calculate_restore_target_path(&pack_file.target_path, test)?;
+        let target_path = calculate_restore_target_path(&pack_file.target()?, test)?;
 
         if dry_run {
             println!(
@@ -751,15 +861,26 @@ fn restore_pack_impl(
             }
         }
 
+        // Keep what --force is about to overwrite
+        if target_path.is_file() {
+            let id = backup::save(&target_path, "restore")?;
+            println!("Backed up {} (backup {})", target_path.display(), id);
+        }
+
         // Create target directory if needed
         if let Some(parent) = target_path.parent() {
-            fs::create_dir_all(parent)?;
+            history::create_dir_all(parent)?;
         }
 
-        // Copy file to target location
-        fs::copy(&source_in_archive, &target_path)?;
-
-        println!("Restored: {}", target_path.display());
+        // Copy file to target location, decrypting a secret
+        if pack_file.encrypted {
+            let plain = secret::decrypt_file(&source_in_archive)?;
+            history::write_private(&target_path, &plain)?;
+            println!("Restored: {} (decrypted)", target_path.display());
+        } else {
+            history::copy(&source_in_archive, &target_path)?;
+            println!("Restored: {}", target_path.display());
+        }
         restored_count += 1;
     }
 
@@ -768,23 +889,19 @@ fn restore_pack_impl(
 
 /// Calculate the target installation path for install command
 fn calculate_install_target_path(
-    original_path: &str,
+    original: &Path,
     target_dir: Option<&Path>,
     map_home: bool,
 ) -> Result<PathBuf> {
-    let original = Path::new(original_path);
-
     if let Some(target) = target_dir {
         // Install relative to specified target directory
-        if map_home && original_path.starts_with('/') {
+        if map_home && original.is_absolute() {
             // For absolute paths, try to map home directory
-            if let Some(home_dir) = dirs::home_dir() {
-                let home_str = home_dir.to_string_lossy();
-                if original_path.starts_with(&*home_str) {
-                    // Map /home/user/... to target/...
-                    let relative_to_home = original.strip_prefix(&home_dir).unwrap_or(original);
-                    return Ok(target.join(relative_to_home));
-                }
+            if let Some(home_dir) = dirs::home_dir()
+                && let Ok(relative_to_home) = original.strip_prefix(&home_dir)
+            {
+                // Map /home/user/... to target/...
+                return Ok(target.join(relative_to_home));
             }
 
             // For other absolute paths, use just the filename in target
@@ -801,22 +918,20 @@ fn calculate_install_target_path(
         } else {
             Err(anyhow!(
                 "Cannot determine target path for: {}",
-                original_path
+                original.display()
             ))
         }
     } else if map_home {
         // Map to current user's home directory
         if let Some(current_home) = dirs::home_dir() {
-            if original_path.starts_with('/') {
-                // Try to detect if this was a home directory path
-                let path_parts: Vec<&str> = original_path.split('/').collect();
-                if path_parts.len() >= 3 && path_parts[1] == "home" {
-                    // Replace /home/username with current home
-                    let relative_path = Path::new(&original_path)
-                        .strip_prefix(format!("/home/{}", path_parts[2]))
-                        .unwrap_or(Path::new(original_path));
-                    return Ok(current_home.join(relative_path));
-                }
+            // Try to detect if this was a home directory path
+            let mut components = original.components();
+            if let (Some(Component::RootDir), Some(home), Some(Component::Normal(_))) =
+                (components.next(), components.next(), components.next())
+                && home.as_os_str() == "home"
+            {
+                // Replace /home/username with current home
+                return Ok(current_home.join(components.as_path()));
             }
 
             // Fallback: put file in current home
@@ -825,7 +940,7 @@ fn calculate_install_target_path(
             } else {
                 Err(anyhow!(
                     "Cannot determine target path for: {}",
-                    original_path
+                    original.display()
                 ))
             }
         } else {
@@ -841,15 +956,13 @@ fn calculate_install_target_path(
         } else {
             Err(anyhow!(
                 "Cannot determine filename from path: {}",
-                original_path
+                original.display()
             ))
         }
     }
 }
 
-fn calculate_restore_target_path(original_path: &str, test: bool) -> Result<PathBuf> {
-    let original = Path::new(original_path);
-
+fn calculate_restore_target_path(original: &Path, test: bool) -> Result<PathBuf> {
     if test {
         // Test mode: restore to current directory using filenames only
         let cwd = std::env::current_dir()?;
@@ -858,12 +971,12 @@ fn calculate_restore_target_path(original_path: &str, test: bool) -> Result<Path
         } else {
             Err(anyhow!(
                 "Cannot determine filename from path: {}",
-                original_path
+                original.display()
             ))
         }
     } else {
         // Default for restore: Use original absolute paths
-        Ok(PathBuf::from(original_path))
+        Ok(original.to_path_buf())
     }
 }
 
@@ -936,8 +1049,11 @@ fn repack_files_impl(scope: &str, files: &[PathBuf]) -> Result<usize> {
         let content = fs::read_to_string(&manifest_path)?;
         let manifest: PackManifest = toml::from_str(&content)?;
 
-        let files_to_repack: Vec<PathBuf> =
-            manifest.files.keys().map(PathBuf::from).collect();
+        let files_to_repack = manifest
+            .files
+            .values()
+            .map(PackFile::target)
+            .collect::<Result<Vec<_>>>()?;
 
         pack_files_impl(&files_to_repack, scope, false, None, false)
     } else {
@@ -995,9 +1111,7 @@ fn unpack_files_impl(files: &[PathBuf], scope: &str) -> Result<usize> {
 
     for file in files {
         let abs_path = path_utils::normalize(file);
-        let key = abs_path.to_string_lossy().to_string();
-
-        if let Some(pack_file) = manifest.files.remove(&key) {
+        if let Some(pack_file) = manifest.files.remove(&manifest_key(&abs_path)) {
             let file_in_pack = files_dir.join(&pack_file.relative_path);
 
             if file_in_pack.exists() {
@@ -1073,9 +1187,14 @@ fn explain_pack_impl(
             .as_ref()
             .map(|h| format!(" ({})", &h[..8]))
             .unwrap_or_default();
+        let encrypted = if pack_file.encrypted {
+            " [encrypted]"
+        } else {
+            ""
+        };
         println!(
-            "   {} → {} ({} bytes){}",
-            pack_file.relative_path, pack_file.target_path, pack_file.size, hash_display
+            "   {} → {} ({} bytes){}{}",
+            pack_file.relative_path, pack_file.target_path, pack_file.size, hash_display, encrypted
         );
     }
 
@@ -1091,7 +1210,7 @@ fn explain_pack_impl(
 
         for pack_file in manifest.files.values() {
             let install_target =
-                calculate_install_target_path(&pack_file.target_path, target, false)?;
+                calculate_install_target_path(&pack_file.target()?, target, false)?;
             let status = if install_target.exists() {
                 "⚠️  CONFLICT"
             } else {
@@ -1111,7 +1230,7 @@ fn explain_pack_impl(
         println!("   Target: Original absolute paths");
 
         for pack_file in manifest.files.values() {
-            let restore_target = calculate_restore_target_path(&pack_file.target_path, false)?;
+            let restore_target = calculate_restore_target_path(&pack_file.target()?, false)?;
             let status = if restore_target.exists() {
                 "⚠️  CONFLICT"
             } else {
@@ -1131,7 +1250,7 @@ fn explain_pack_impl(
         let mut conflicts = 0;
         for pack_file in manifest.files.values() {
             let install_target =
-                calculate_install_target_path(&pack_file.target_path, target, false)?;
+                calculate_install_target_path(&pack_file.target()?, target, false)?;
             if install_target.exists() {
                 conflicts += 1;
             }
@@ -1144,7 +1263,7 @@ fn explain_pack_impl(
     let restore_conflicts = if show_restore || !show_install {
         let mut conflicts = 0;
         for pack_file in manifest.files.values() {
-            let restore_target = calculate_restore_target_path(&pack_file.target_path, false)?;
+            let restore_target = calculate_restore_target_path(&pack_file.target()?, false)?;
             if restore_target.exists() {
                 conflicts += 1;
             }
@@ -0,0 +1,375 @@
+// Versioned database schema migrations
+//
+// The schema version is the number of migrations that have been applied and is
+// stored under `schema_version` in the settings table. Pending migrations run
+// in a single transaction when a connection is opened, after the existing
+// database has been copied aside. Every migration is written to be safe on
+// databases created before versioning existed, which report version 0.
+use rusqlite::Connection;
+use std::path::{Path, PathBuf};
+
+const SCHEMA_VERSION_KEY: &str = "schema_version";
+
+/// A single schema change
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    apply: fn(&Connection) -> rusqlite::Result<()>,
+}
+
+/// All migrations, in the order they are applied
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create dotfiles, settings, filetypes and ignored_paths tables",
+        apply: create_base_tables,
+    },
+    Migration {
+        version: 2,
+        description: "create operations table for undo history",
+        apply: create_operations_table,
+    },
+    Migration {
+        version: 3,
+        description: "create managed_folders table",
+        apply: create_managed_folders_table,
+    },
+    Migration {
+        version: 4,
+        description: "add folder_id to dotfiles",
+        apply: add_dotfile_folder_id,
+    },
+    Migration {
+        version: 5,
+        description: "store dotfile and managed folder paths as raw bytes",
+        apply: store_paths_as_blobs,
+    },
+    Migration {
+        version: 6,
+        description: "create backups index table",
+        apply: create_backups_table,
+    },
+    Migration {
+        version: 7,
+        description: "add deploy mode and last synced hash to dotfiles",
+        apply: add_dotfile_mode,
+    },
+    Migration {
+        version: 8,
+        description: "replace filetypes with selection rules",
+        apply: create_selection_rules_table,
+    },
+];
+
+/// Schema version this build of forge expects
+pub fn latest_version() -> u32 {
+    MIGRATIONS.len() as u32
+}
+
+fn schema_error(message: String) -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(
+        rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_ERROR),
+        Some(message),
+    )
+}
+
+fn table_exists(conn: &Connection, name: &str) -> rusqlite::Result<bool> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?",
+        [name],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// Schema version recorded in the database (0 for unversioned databases)
+pub fn current_version(conn: &Connection) -> rusqlite::Result<u32> {
+    if !table_exists(conn, "settings")? {
+        return Ok(0);
+    }
+
+    match conn.query_row(
+        "SELECT value FROM settings WHERE key = ?",
+        [SCHEMA_VERSION_KEY],
+        |row| row.get::<_, String>(0),
+    ) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| schema_error(format!("Invalid schema version in database: {}", value))),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(0),
+        Err(e) => Err(e),
+    }
+}
+
+/// Migrations that have not been applied yet. Fails if the database was
+/// written by a newer version of forge.
+pub fn pending(conn: &Connection) -> rusqlite::Result<Vec<&'static Migration>> {
+    let current = current_version(conn)?;
+    if current > latest_version() {
+        return Err(schema_error(format!(
+            "Database schema version {} is newer than this forge supports ({}); please upgrade forge",
+            current,
+            latest_version()
+        )));
+    }
+
+    Ok(MIGRATIONS.iter().filter(|m| m.version > current).collect())
+}
+
+/// Copy the database aside before changing its schema
+fn backup(conn: &Connection, db_path: &Path, from: u32) -> rusqlite::Result<PathBuf> {
+    let mut name = db_path.file_name().unwrap_or_default().to_owned();
+    name.push(format!(
+        ".v{}-{}.bak",
+        from,
+        chrono::Utc::now().format("%Y%m%d%H%M%S")
+    ));
+    let backup_path = db_path.with_file_name(name);
+
+    // A lossy name would back up somewhere other than next to the database
+    let Some(backup_name) = backup_path.to_str() else {
+        return Err(schema_error(format!(
+            "cannot back up the database to {}: the path is not valid UTF-8",
+            backup_path.display()
+        )));
+    };
+    conn.execute("VACUUM INTO ?", [backup_name])?;
+    Ok(backup_path)
+}
+
+/// Apply all pending migrations in one transaction, returning those applied
+pub fn migrate(conn: &mut Connection, db_path: &Path) -> rusqlite::Result<Vec<&'static Migration>> {
+    let pending = pending(conn)?;
+    if pending.is_empty() {
+        return Ok(pending);
+    }
+
+    // Only existing databases need a backup; a brand new one has nothing to lose
+    let has_tables: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table'",
+        [],
+        |row| row.get(0),
+    )?;
+    if has_tables > 0 {
+        let from = current_version(conn)?;
+        let backup_path = backup(conn, db_path, from)?;
+        println!(
+            "Upgrading database schema from version {} to {} (backup: {})",
+            from,
+            latest_version(),
+            backup_path.display()
+        );
+    }
+
+    let tx = conn.transaction()?;
+    for migration in &pending {
+        (migration.apply)(&tx)?;
+        tx.execute(
+            "INSERT OR REPLACE INTO settings (key, value) VALUES (?, ?)",
+            [SCHEMA_VERSION_KEY, &migration.version.to_string()],
+        )?;
+    }
+    tx.commit()?;
+
+    Ok(pending)
+}
+
+// ---- Migrations ----
+
+fn create_base_tables(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS dotfiles (
+            id INTEGER PRIMARY KEY,
+            source TEXT NOT NULL,
+            target TEXT NOT NULL,
+            profile TEXT,
+            status TEXT NOT NULL DEFAULT 'staged',
+            active BOOLEAN NOT NULL DEFAULT 1,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS filetypes (
+            extension TEXT PRIMARY KEY,
+            active BOOLEAN NOT NULL DEFAULT 1,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE TABLE IF NOT EXISTS ignored_paths (
+            path TEXT PRIMARY KEY,
+            active BOOLEAN NOT NULL DEFAULT 1,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        );",
+    )
+}
+
+fn create_operations_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS operations (
+            id INTEGER PRIMARY KEY,
+            command TEXT NOT NULL,
+            changes TEXT NOT NULL,
+            undone BOOLEAN NOT NULL DEFAULT 0,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn create_managed_folders_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS managed_folders (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE,
+            path TEXT NOT NULL,
+            is_default BOOLEAN NOT NULL DEFAULT 0,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )",
+        [],
+    )?;
+    Ok(())
+}
+
+fn add_dotfile_folder_id(conn: &Connection) -> rusqlite::Result<()> {
+    let has_column: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info('dotfiles') WHERE name = 'folder_id'",
+        [],
+        |row| row.get(0),
+    )?;
+    if has_column == 0 {
+        conn.execute(
+            "ALTER TABLE dotfiles ADD COLUMN folder_id INTEGER REFERENCES managed_folders(id)",
+            [],
+        )?;
+    }
+    Ok(())
+}
+
+// SQLite cannot change a column's type in place, so both tables are rebuilt
+// with BLOB path columns and the existing text converted byte for byte
+fn store_paths_as_blobs(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE managed_folders_new (
+            id INTEGER PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE,
+            path BLOB NOT NULL,
+            is_default BOOLEAN NOT NULL DEFAULT 0,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        );
+        INSERT INTO managed_folders_new (id, name, path, is_default, created_at)
+            SELECT id, name, CAST(path AS BLOB), is_default, created_at FROM managed_folders;
+        DROP TABLE managed_folders;
+        ALTER TABLE managed_folders_new RENAME TO managed_folders;
+
+        CREATE TABLE dotfiles_new (
+            id INTEGER PRIMARY KEY,
+            source BLOB NOT NULL,
+            target BLOB NOT NULL,
+            profile TEXT,
+            status TEXT NOT NULL DEFAULT 'staged',
+            active BOOLEAN NOT NULL DEFAULT 1,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            folder_id INTEGER REFERENCES managed_folders(id)
+        );
+        INSERT INTO dotfiles_new (id, source, target, profile, status, active, created_at, folder_id)
+            SELECT id, CAST(source AS BLOB), CAST(target AS BLOB), profile, status, active,
+                created_at, folder_id
+            FROM dotfiles;
+        DROP TABLE dotfiles;
+        ALTER TABLE dotfiles_new RENAME TO dotfiles;",
+    )
+}
+
+fn create_backups_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS backups (
+            id INTEGER PRIMARY KEY,
+            original BLOB NOT NULL,
+            stored BLOB NOT NULL UNIQUE,
+            reason TEXT NOT NULL,
+            hash TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE INDEX IF NOT EXISTS backups_by_original ON backups (original);",
+    )
+}
+
+fn add_dotfile_mode(conn: &Connection) -> rusqlite::Result<()> {
+    for (column, definition) in [
+        ("mode", "mode TEXT NOT NULL DEFAULT 'symlink'"),
+        ("synced_hash", "synced_hash TEXT"),
+    ] {
+        let has_column: i64 = conn.query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('dotfiles') WHERE name = ?",
+            [column],
+            |row| row.get(0),
+        )?;
+        if has_column == 0 {
+            conn.execute(
+                &format!("ALTER TABLE dotfiles ADD COLUMN {}", definition),
+                [],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Files a new database selects for bulk linking
+const DEFAULT_SELECTION: &[&str] = &[".bashrc", ".zshrc", ".vimrc", ".tmux.conf"];
+
+/// The glob for an entry of the old file types list. Entries were compared
+/// with the extension, so `.conf` meant `*.conf`; the old defaults such as
+/// `.bashrc`, which have no extension, never matched, and become the same
+/// bare names a new database selects.
+pub(crate) fn filetype_glob(extension: &str) -> String {
+    if DEFAULT_SELECTION.contains(&extension) {
+        return extension.to_string();
+    }
+    match extension.strip_prefix('.') {
+        Some(_) => format!("*{}", extension),
+        None => format!("*.{}", extension),
+    }
+}
+
+fn create_selection_rules_table(conn: &Connection) -> rusqlite::Result<()> {
+    conn.execute(
+        "CREATE TABLE IF NOT EXISTS selection_rules (
+            id INTEGER PRIMARY KEY,
+            action TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            pattern TEXT NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE (action, kind, pattern)
+        )",
+        [],
+    )?;
+
+    let mut globs: Vec<String> = Vec::new();
+    if table_exists(conn, "filetypes")? {
+        let mut stmt =
+            conn.prepare("SELECT extension FROM filetypes WHERE active = 1 ORDER BY rowid")?;
+        let extensions = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        for extension in extensions {
+            globs.push(filetype_glob(&extension?));
+        }
+    }
+    // A database that never had file types gets the old defaults as names
+    if globs.is_empty() {
+        globs = DEFAULT_SELECTION
+            .iter()
+            .map(|name| name.to_string())
+            .collect();
+    }
+    for glob in globs {
+        conn.execute(
+            "INSERT OR IGNORE INTO selection_rules (action, kind, pattern)
+             VALUES ('include', 'glob', ?)",
+            [glob],
+        )?;
+    }
+    conn.execute("DROP TABLE IF EXISTS filetypes", [])?;
+    Ok(())
+}
@@ -1,15 +1,136 @@
+use crate::dotfile::DeployMode;
+use crate::history::{self, Change};
+use crate::manifest;
+use crate::utils::path_utils;
+pub use migrations::Migration;
 use rusqlite::Connection;
+use rusqlite::types::ValueRef;
+use serde::{Deserialize, Serialize};
 use std::fs::{self, File, OpenOptions};
 use std::io::{self, BufRead, BufReader, Write};
 use std::path::{Path, PathBuf};
 
+pub mod migrations;
+
 // Constants for configuration file paths
 const DEFAULT_CONFIG_DIR: &str = ".forge";
 const DEFAULT_PATH_FILE: &str = "default_path";
 const FILETYPES_FILE: &str = "filetypes";
 const IGNORED_PATHS_FILE: &str = "ignored_paths";
 const MANAGED_FOLDERS_FILE: &str = "managed_folders";
+const ACTIVE_FOLDER_KEY: &str = "active_folder";
+/// Environment variable set by the global --folder flag to override the active folder
+pub const FOLDER_OVERRIDE_ENV: &str = "FORGE_FOLDER";
 const DEFAULT_MANAGED_FOLDER: &str = "default";
+/// Setting that makes deployed symlinks relative to the directory they are in
+pub const RELATIVE_LINKS_KEY: &str = "relative_links";
+/// Environment variable set by --relative to create relative symlinks for one command
+pub const RELATIVE_LINKS_ENV: &str = "FORGE_RELATIVE_LINKS";
+/// Setting that holds this machine's classes for `##class.NAME` alternate files
+pub const CLASS_KEY: &str = "class";
+/// Setting that names a key file used instead of a passphrase for secrets
+pub const SECRET_KEY_FILE_KEY: &str = "secret_key_file";
+/// Setting that commits the managed folder after commands that change it
+pub const GIT_AUTO_COMMIT_KEY: &str = "git_auto_commit";
+/// Setting that names the git remote `forge sync` pulls from and pushes to
+pub const GIT_REMOTE_KEY: &str = "git_remote";
+
+/// A setting users can change with `forge config`
+pub struct UserSetting {
+    pub key: &'static str,
+    pub default: &'static str,
+    pub description: &'static str,
+}
+
+/// Settings users can change with `forge config`
+pub const USER_SETTINGS: &[UserSetting] = &[
+    UserSetting {
+        key: RELATIVE_LINKS_KEY,
+        default: "false",
+        description: "Create symlinks relative to the directory they are in (true/false)",
+    },
+    UserSetting {
+        key: CLASS_KEY,
+        default: "",
+        description: "Classes of this machine for ##class.NAME alternate files (comma separated)",
+    },
+    UserSetting {
+        key: SECRET_KEY_FILE_KEY,
+        default: "",
+        description: "Key file that unlocks encrypted files instead of a passphrase",
+    },
+    UserSetting {
+        key: GIT_AUTO_COMMIT_KEY,
+        default: "false",
+        description: "Commit the managed folder after stage, link, unlink and remove (true/false)",
+    },
+    UserSetting {
+        key: GIT_REMOTE_KEY,
+        default: "origin",
+        description: "Git remote that 'forge sync' pulls from and pushes to",
+    },
+];
+
+/// Columns read by map_dotfile. Stored paths may be relative to the row's
+/// managed folder, so its (stored) path is selected alongside.
+const DOTFILE_COLUMNS: &str = "source, target, profile, status,
+    (SELECT path FROM managed_folders WHERE managed_folders.id = dotfiles.folder_id), mode";
+
+/// A raw snapshot of a row in the dotfiles table, used to undo database changes
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DotfileRow {
+    pub id: i64,
+    #[serde(with = "path_utils::serde_path")]
+    pub source: PathBuf,
+    #[serde(with = "path_utils::serde_path")]
+    pub target: PathBuf,
+    pub profile: Option<String>,
+    pub status: String,
+    pub active: bool,
+    pub created_at: Option<String>,
+    #[serde(default)]
+    pub folder_id: Option<i64>,
+    #[serde(default)]
+    pub mode: DeployMode,
+    #[serde(default)]
+    pub synced_hash: Option<String>,
+}
+
+/// A recorded run of a mutating command
+#[derive(Debug, Clone)]
+pub struct OperationRecord {
+    pub id: i64,
+    pub command: String,
+    pub changes: String,
+    pub undone: bool,
+    pub created_at: String,
+}
+
+/// An entry in the backup store index
+#[derive(Debug, Clone)]
+pub struct BackupRecord {
+    pub id: i64,
+    /// Where the backed up content used to live
+    pub original: PathBuf,
+    /// Location of the copy, relative to the backup store
+    pub stored: PathBuf,
+    /// The command that made the backup
+    pub reason: String,
+    pub hash: String,
+    pub size: u64,
+    pub created_at: String,
+}
+
+/// A rule deciding which files bulk linking selects, as stored
+#[derive(Debug, Clone)]
+pub struct SelectionRuleRecord {
+    pub id: i64,
+    /// "include" or "exclude"
+    pub action: String,
+    /// "glob", "ext", "regex" or "size"
+    pub kind: String,
+    pub pattern: String,
+}
 
 pub struct Config {
     db_path: PathBuf,
@@ -120,40 +241,44 @@ impl Config {
 
     pub fn connect(&mut self) -> rusqlite::Result<()> {
         // Connect to the database
-        self.connection = Some(Connection::open(&self.db_path)?);
+        self.open()?;
 
-        // Initialize database if needed
+        // Bring the schema up to date, then fill in default data
+        self.migrate()?;
         self.init_database()?;
 
         Ok(())
     }
 
-    // Initialize database tables if they don't exist
-    fn init_database(&self) -> rusqlite::Result<()> {
-        if let Some(conn) = &self.connection {
-            // Create dotfiles table
-            conn.execute(
-                "CREATE TABLE IF NOT EXISTS dotfiles (
-                    id INTEGER PRIMARY KEY,
-                    source TEXT NOT NULL,
-                    target TEXT NOT NULL,
-                    profile TEXT,
-                    status TEXT NOT NULL DEFAULT 'staged',
-                    active BOOLEAN NOT NULL DEFAULT 1,
-                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-                )",
-                [],
-            )?;
+    // Open the database without touching its schema
+    pub fn open(&mut self) -> rusqlite::Result<()> {
+        self.connection = Some(Connection::open(&self.db_path)?);
+        Ok(())
+    }
 
-            // Create settings table
-            conn.execute(
-                "CREATE TABLE IF NOT EXISTS settings (
-                    key TEXT PRIMARY KEY,
-                    value TEXT NOT NULL
-                )",
-                [],
-            )?;
+    // Apply any pending schema migrations, returning those applied
+    pub fn migrate(&mut self) -> rusqlite::Result<Vec<&'static Migration>> {
+        match self.connection.as_mut() {
+            Some(conn) => migrations::migrate(conn, &self.db_path),
+            None => Ok(Vec::new()),
+        }
+    }
 
+    // Get the schema version of the database and the migrations not yet applied
+    pub fn pending_migrations(&self) -> rusqlite::Result<(u32, Vec<&'static Migration>)> {
+        match &self.connection {
+            Some(conn) => Ok((
+                migrations::current_version(conn)?,
+                migrations::pending(conn)?,
+            )),
+            None => Ok((0, Vec::new())),
+        }
+    }
+
+    // Fill in default data and import legacy config files. The tables themselves
+    // are created by the migrations.
+    fn init_database(&self) -> rusqlite::Result<()> {
+        if let Some(conn) = &self.connection {
             // Insert default settings if they don't exist
             let default_path = self.read_default_path();
             let count: i64 = conn.query_row(
@@ -169,47 +294,63 @@ impl Config {
                 )?;
             }
 
-            // Create filetypes table
-            conn.execute(
-                "CREATE TABLE IF NOT EXISTS filetypes (
-                    extension TEXT PRIMARY KEY,
-                    active BOOLEAN NOT NULL DEFAULT 1,
-                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-                )",
-                [],
-            )?;
-
-            // Create ignored_paths table
-            conn.execute(
-                "CREATE TABLE IF NOT EXISTS ignored_paths (
-                    path TEXT PRIMARY KEY,
-                    active BOOLEAN NOT NULL DEFAULT 1,
-                    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-                )",
-                [],
-            )?;
-
-            // Initialize with default filetypes if table is empty
-            let count: i64 =
-                conn.query_row("SELECT COUNT(*) FROM filetypes", [], |row| row.get(0))?;
+            // Import managed folders from the legacy flat file, then retire it so
+            // folders removed later are not imported again
+            if self.managed_folders_file.exists() {
+                match self.read_lines(&self.managed_folders_file) {
+                    Ok(lines) => {
+                        for line in lines {
+                            if let Some((name, path)) = line.split_once(':') {
+                                conn.execute(
+                                    "INSERT OR IGNORE INTO managed_folders (name, path) VALUES (?, ?)",
+                                    rusqlite::params![name, path_utils::to_portable(path, None)],
+                                )?;
+                            }
+                        }
+                        let mut retired = self.managed_folders_file.as_os_str().to_owned();
+                        retired.push(".imported");
+                        if let Err(e) = fs::rename(&self.managed_folders_file, retired) {
+                            eprintln!("Failed to retire managed folders file: {}", e);
+                        }
+                    }
+                    Err(e) => eprintln!("Failed to read managed folders file: {}", e),
+                }
+            }
 
-            if count == 0 {
-                let default_filetypes = [".bashrc", ".zshrc", ".vimrc", ".tmux.conf"];
-                for ext in default_filetypes.iter() {
-                    conn.execute("INSERT INTO filetypes (extension) VALUES (?)", [ext])?;
+            // Backfill rows created before folders were recorded, by target path
+            let unassigned = {
+                let mut stmt =
+                    conn.prepare("SELECT id, target FROM dotfiles WHERE folder_id IS NULL")?;
+                stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, path_column(row, 1)?)))?
+                    .collect::<rusqlite::Result<Vec<_>>>()?
+            };
+            for (id, target) in unassigned {
+                let target = path_utils::from_portable(&path_utils::to_bytes(&target), None);
+                if let Some((folder_id, _)) = self.folder_for(&target)? {
+                    conn.execute(
+                        "UPDATE dotfiles SET folder_id = ? WHERE id = ?",
+                        [folder_id, id],
+                    )?;
                 }
             }
 
-            // Import existing filetypes from file
+            // Import the legacy file types list as selection rules, then retire
+            // it so rules removed later are not imported again
             if self.filetypes_file.exists() {
                 match self.read_lines(&self.filetypes_file) {
                     Ok(filetypes) => {
                         for ext in filetypes {
                             conn.execute(
-                                "INSERT OR IGNORE INTO filetypes (extension) VALUES (?)",
-                                [&ext],
+                                "INSERT OR IGNORE INTO selection_rules (action, kind, pattern)
+                                 VALUES ('include', 'glob', ?)",
+                                [migrations::filetype_glob(&ext)],
                             )?;
                         }
+                        let mut retired = self.filetypes_file.as_os_str().to_owned();
+                        retired.push(".imported");
+                        if let Err(e) = fs::rename(&self.filetypes_file, retired) {
+                            eprintln!("Failed to retire filetypes file: {}", e);
+                        }
                     }
                     Err(e) => eprintln!("Failed to read filetypes file: {}", e),
                 }
@@ -234,16 +375,16 @@ impl Config {
         Ok(())
     }
 
+    // Get the global config directory
+    pub fn get_config_dir(&self) -> &PathBuf {
+        &self.config_dir
+    }
+
     // Get default path file
     pub fn get_default_path_file(&self) -> &PathBuf {
         &self.default_path_file
     }
 
-    // Get filetypes file
-    pub fn get_filetypes_file(&self) -> &PathBuf {
-        &self.filetypes_file
-    }
-
     // Get ignored paths file
     pub fn get_ignored_paths_file(&self) -> &PathBuf {
         &self.ignored_paths_file
@@ -262,37 +403,6 @@ impl Config {
         }
     }
 
-    // Add file types
-    pub fn add_filetypes(&self, extensions: &[String]) -> io::Result<()> {
-        for ext in extensions {
-            let ext = ext.trim();
-            if ext.is_empty() {
-                continue;
-            }
-
-            if self.file_exists(&self.filetypes_file, ext)? {
-                println!("File type '{}' is already approved.", ext);
-            } else {
-                self.append_to_file(&self.filetypes_file, ext)?;
-                println!("File type '{}' added to the approved list.", ext);
-            }
-        }
-        Ok(())
-    }
-
-    // Remove file types
-    pub fn remove_filetypes(&self, extensions: &[String]) -> io::Result<()> {
-        for ext in extensions {
-            self.remove_item_from_list(&self.filetypes_file, ext)?;
-        }
-        Ok(())
-    }
-
-    // List file types
-    pub fn list_filetypes(&self) -> io::Result<()> {
-        self.list_items(&self.filetypes_file, "Approved File Types")
-    }
-
     // Add ignored paths
     pub fn add_ignored_paths(&self, paths: &[String]) -> io::Result<()> {
         for path in paths {
@@ -324,48 +434,181 @@ impl Config {
 
     // ---- Managed Folders operations ----
 
-    // Get the managed folders file path
+    // Get the legacy managed folders file path
     pub fn get_managed_folders_file(&self) -> &PathBuf {
         &self.managed_folders_file
     }
 
-    // Add a managed folder
-    pub fn add_managed_folder(&self, name: &str, path: &Path) -> io::Result<()> {
-        let entry = format!("{}:{}", name, path.to_string_lossy());
-
-        // Check if entry already exists (by name)
-        let managed_folders = self.get_managed_folders()?;
-        if managed_folders.iter().any(|(n, _)| n == name) {
-            println!("Managed folder '{}' already exists", name);
-            return Ok(());
+    // Add a managed folder; returns false if the name is already taken
+    pub fn add_managed_folder(&self, name: &str, path: &Path) -> rusqlite::Result<bool> {
+        if let Some(conn) = &self.connection {
+            let affected = conn.execute(
+                "INSERT OR IGNORE INTO managed_folders (name, path) VALUES (?, ?)",
+                rusqlite::params![name, path_utils::to_portable(path, None)],
+            )?;
+            Ok(affected > 0)
+        } else {
+            Ok(false)
         }
+    }
 
-        // Add the entry
-        self.append_to_file(&self.managed_folders_file, &entry)
+    // Get managed folders (name, path) in the order they were added
+    pub fn get_managed_folders(&self) -> rusqlite::Result<Vec<(String, PathBuf)>> {
+        Ok(self
+            .folder_rows()?
+            .into_iter()
+            .map(|(_, name, path)| (name, path))
+            .collect())
     }
 
-    // Get managed folders (name, path)
-    pub fn get_managed_folders(&self) -> io::Result<Vec<(String, PathBuf)>> {
-        let lines = self.read_lines(&self.managed_folders_file)?;
+    // Get managed folders (id, name, path) in the order they were added
+    fn folder_rows(&self) -> rusqlite::Result<Vec<(i64, String, PathBuf)>> {
         let mut folders = Vec::new();
 
-        for line in lines {
-            if let Some((name, path_str)) = line.split_once(':') {
-                folders.push((name.to_string(), PathBuf::from(path_str)));
+        if let Some(conn) = &self.connection {
+            let mut stmt =
+                conn.prepare("SELECT id, name, path FROM managed_folders ORDER BY id")?;
+            let rows = stmt.query_map([], |row| {
+                let path = path_utils::from_portable(&path_bytes(row, 2)?, None);
+                Ok((row.get(0)?, row.get(1)?, path))
+            })?;
+            for row in rows {
+                folders.push(row?);
             }
         }
 
         Ok(folders)
     }
 
+    // Get the managed folder (id, path) a path lies in, the innermost if nested
+    fn folder_for(&self, path: &Path) -> rusqlite::Result<Option<(i64, PathBuf)>> {
+        let folders: Vec<(i64, PathBuf)> = self
+            .folder_rows()?
+            .into_iter()
+            .map(|(id, _, folder)| (id, folder))
+            .collect();
+        Ok(innermost_folder(&folders, path).cloned())
+    }
+
+    // Rewrite every stored path in its portable form: relative to the managed
+    // folder, $XDG_CONFIG_HOME or $HOME where possible. Absolute paths under
+    // `from_home` are first moved under the current home directory, for a
+    // database carried over from another machine. Returns the (old, new)
+    // stored forms of every path that changed.
+    pub fn rebase_paths(
+        &self,
+        from_home: Option<&Path>,
+        dry_run: bool,
+    ) -> rusqlite::Result<Vec<(PathBuf, PathBuf)>> {
+        let Some(conn) = &self.connection else {
+            return Ok(Vec::new());
+        };
+        let home = dirs::home_dir();
+        let remap = |path: PathBuf| match (from_home, &home) {
+            (Some(from), Some(home)) => match path.strip_prefix(from) {
+                Ok(rest) => home.join(rest),
+                Err(_) => path,
+            },
+            _ => path,
+        };
+
+        let stored_folders = {
+            let mut stmt = conn.prepare("SELECT id, path FROM managed_folders ORDER BY id")?;
+            stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, path_bytes(row, 1)?)))?
+                .collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        let rows = {
+            let mut stmt = conn.prepare(
+                "SELECT id, source, target, folder_id,
+                    (SELECT path FROM managed_folders WHERE managed_folders.id = dotfiles.folder_id)
+                 FROM dotfiles ORDER BY id",
+            )?;
+            stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    path_bytes(row, 1)?,
+                    path_bytes(row, 2)?,
+                    row.get::<_, Option<i64>>(3)?,
+                    row.get::<_, Option<Vec<u8>>>(4)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?
+        };
+
+        let tx = conn.unchecked_transaction()?;
+        let mut changes = Vec::new();
+
+        let mut folders = Vec::new();
+        for (id, stored) in stored_folders {
+            let path = remap(path_utils::from_portable(&stored, None));
+            let rebased = path_utils::to_portable(&path, None);
+            if rebased != stored {
+                tx.execute(
+                    "UPDATE managed_folders SET path = ? WHERE id = ?",
+                    rusqlite::params![rebased, id],
+                )?;
+                changes.push((
+                    path_utils::from_bytes(&stored),
+                    path_utils::from_bytes(&rebased),
+                ));
+            }
+            folders.push((id, path));
+        }
+
+        for (id, source, target, folder_id, folder) in rows {
+            let folder = folder.map(|folder| path_utils::from_portable(&folder, None));
+            let resolve =
+                |stored: &[u8]| remap(path_utils::from_portable(stored, folder.as_deref()));
+            let (source_path, target_path) = (resolve(&source), resolve(&target));
+
+            let owner = innermost_folder(&folders, &target_path);
+            let new_source = path_utils::to_portable(&source_path, None);
+            let new_target =
+                path_utils::to_portable(&target_path, owner.map(|(_, path)| path.as_path()));
+            let new_folder_id = owner.map(|(id, _)| *id).or(folder_id);
+            if new_source == source && new_target == target && new_folder_id == folder_id {
+                continue;
+            }
+
+            tx.execute(
+                "UPDATE dotfiles SET source = ?, target = ?, folder_id = ? WHERE id = ?",
+                rusqlite::params![new_source, new_target, new_folder_id, id],
+            )?;
+            for (before, after) in [(source, new_source), (target, new_target)] {
+                if before != after {
+                    changes.push((
+                        path_utils::from_bytes(&before),
+                        path_utils::from_bytes(&after),
+                    ));
+                }
+            }
+        }
+
+        if !dry_run {
+            tx.commit()?;
+        }
+        Ok(changes)
+    }
+
+    // Stored forms a target path may have in the dotfiles table: relative to its
+    // managed folder or another root, or absolute for rows written before paths
+    // were stored portably
+    fn target_keys(&self, target: &Path) -> rusqlite::Result<[Vec<u8>; 2]> {
+        let folder = self.folder_for(target)?.map(|(_, folder)| folder);
+        Ok([
+            path_utils::to_portable(target, folder.as_deref()),
+            path_utils::to_bytes(target),
+        ])
+    }
+
     // Check if a path is a managed folder
-    pub fn is_managed_folder(&self, path: &Path) -> io::Result<bool> {
+    pub fn is_managed_folder(&self, path: &Path) -> rusqlite::Result<bool> {
         let managed_folders = self.get_managed_folders()?;
         Ok(managed_folders.iter().any(|(_, p)| p == path))
     }
 
     // Get managed folder by name
-    pub fn get_managed_folder_by_name(&self, name: &str) -> io::Result<Option<PathBuf>> {
+    pub fn get_managed_folder_by_name(&self, name: &str) -> rusqlite::Result<Option<PathBuf>> {
         let managed_folders = self.get_managed_folders()?;
         Ok(managed_folders
             .iter()
@@ -373,22 +616,253 @@ impl Config {
             .map(|(_, p)| p.clone()))
     }
 
-    // Get the current active managed folder (for now, just get the default or first)
-    pub fn get_active_managed_folder(&self) -> io::Result<Option<(String, PathBuf)>> {
+    // Get the name of the folder marked as default, if any
+    pub fn get_default_managed_folder(&self) -> rusqlite::Result<Option<String>> {
+        if let Some(conn) = &self.connection {
+            match conn.query_row(
+                "SELECT name FROM managed_folders WHERE is_default = 1 ORDER BY id LIMIT 1",
+                [],
+                |row| row.get(0),
+            ) {
+                Ok(name) => Ok(Some(name)),
+                Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+                Err(e) => Err(e),
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Mark one folder as the default
+    pub fn set_default_managed_folder(&self, name: &str) -> rusqlite::Result<bool> {
+        if let Some(conn) = &self.connection {
+            if self.get_managed_folder_by_name(name)?.is_none() {
+                return Ok(false);
+            }
+            conn.execute("UPDATE managed_folders SET is_default = 0", [])?;
+            conn.execute(
+                "UPDATE managed_folders SET is_default = 1 WHERE name = ?",
+                [name],
+            )?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    // Rename a managed folder, keeping it active if it was
+    pub fn rename_managed_folder(&self, old: &str, new: &str) -> rusqlite::Result<bool> {
+        if let Some(conn) = &self.connection {
+            let affected = conn.execute(
+                "UPDATE managed_folders SET name = ? WHERE name = ?",
+                [new, old],
+            )?;
+            if affected > 0 && self.get_setting(ACTIVE_FOLDER_KEY)?.as_deref() == Some(old) {
+                self.set_setting(ACTIVE_FOLDER_KEY, new)?;
+            }
+            Ok(affected > 0)
+        } else {
+            Ok(false)
+        }
+    }
+
+    // Point a managed folder at a new location. Rows stored relative to the
+    // folder follow it on their own; rows holding absolute paths into the old
+    // location are rewritten.
+    pub fn relocate_managed_folder(&self, name: &str, to: &Path) -> rusqlite::Result<bool> {
+        let Some(conn) = &self.connection else {
+            return Ok(false);
+        };
+        let Some((id, _, from)) = self.folder_rows()?.into_iter().find(|(_, n, _)| n == name)
+        else {
+            return Ok(false);
+        };
+
+        conn.execute(
+            "UPDATE managed_folders SET path = ? WHERE id = ?",
+            rusqlite::params![path_utils::to_portable(to, None), id],
+        )?;
+        history::record(Change::MovedFolder {
+            name: name.to_string(),
+            from: from.clone(),
+            to: to.to_path_buf(),
+        });
+
+        let rows = {
+            let mut stmt = conn.prepare("SELECT id, source, target FROM dotfiles ORDER BY id")?;
+            stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, i64>(0)?,
+                    path_bytes(row, 1)?,
+                    path_bytes(row, 2)?,
+                ))
+            })?
+            .collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        let moved = |stored: &[u8]| {
+            if stored.starts_with(b"$FOLDER") {
+                return None;
+            }
+            let path = path_utils::from_portable(stored, None);
+            path.strip_prefix(&from).ok().map(|rest| to.join(rest))
+        };
+
+        for (row_id, source, target) in rows {
+            let (new_source, new_target) = (moved(&source), moved(&target));
+            if new_source.is_none() && new_target.is_none() {
+                continue;
+            }
+
+            self.snapshot_rows("id = ?1", [row_id])?;
+            let source = match new_source {
+                Some(path) => path_utils::to_portable(path, None),
+                None => source,
+            };
+            match new_target {
+                Some(path) => conn.execute(
+                    "UPDATE dotfiles SET source = ?, target = ?, folder_id = ? WHERE id = ?",
+                    rusqlite::params![source, path_utils::to_portable(path, Some(to)), id, row_id],
+                )?,
+                None => conn.execute(
+                    "UPDATE dotfiles SET source = ? WHERE id = ?",
+                    rusqlite::params![source, row_id],
+                )?,
+            };
+        }
+
+        Ok(true)
+    }
+
+    // Forget a managed folder (its files are left alone)
+    pub fn remove_managed_folder(&self, name: &str) -> rusqlite::Result<bool> {
+        if let Some(conn) = &self.connection {
+            // Rows stored relative to the folder must not depend on it any more
+            let folder = self.get_managed_folder_by_name(name)?;
+            let rows = {
+                let mut stmt = conn.prepare(
+                    "SELECT id, target FROM dotfiles
+                     WHERE folder_id = (SELECT id FROM managed_folders WHERE name = ?)",
+                )?;
+                stmt.query_map([name], |row| {
+                    Ok((row.get::<_, i64>(0)?, path_bytes(row, 1)?))
+                })?
+                .collect::<rusqlite::Result<Vec<_>>>()?
+            };
+            for (id, target) in rows {
+                let target = path_utils::from_portable(&target, folder.as_deref());
+                conn.execute(
+                    "UPDATE dotfiles SET target = ? WHERE id = ?",
+                    rusqlite::params![path_utils::to_portable(&target, None), id],
+                )?;
+            }
+            conn.execute(
+                "UPDATE dotfiles SET folder_id = NULL
+                 WHERE folder_id = (SELECT id FROM managed_folders WHERE name = ?)",
+                [name],
+            )?;
+            let affected = conn.execute("DELETE FROM managed_folders WHERE name = ?", [name])?;
+            if affected > 0 && self.get_setting(ACTIVE_FOLDER_KEY)?.as_deref() == Some(name) {
+                conn.execute("DELETE FROM settings WHERE key = ?", [ACTIVE_FOLDER_KEY])?;
+            }
+            Ok(affected > 0)
+        } else {
+            Ok(false)
+        }
+    }
+
+    // Make a folder the active one for later commands
+    pub fn use_managed_folder(&self, name: &str) -> rusqlite::Result<bool> {
+        if self.get_managed_folder_by_name(name)?.is_none() {
+            return Ok(false);
+        }
+        self.set_setting(ACTIVE_FOLDER_KEY, name)?;
+        Ok(true)
+    }
+
+    // Get the folder commands operate on. In order of precedence: the --folder
+    // override, the folder selected with 'forge folders use', the folder marked
+    // as default, a folder named 'default', then the first folder added.
+    pub fn get_active_managed_folder(&self) -> rusqlite::Result<Option<(String, PathBuf)>> {
         let managed_folders = self.get_managed_folders()?;
+        let find = |name: &str| {
+            managed_folders
+                .iter()
+                .find(|(n, _)| n == name)
+                .map(|(n, p)| (n.clone(), p.clone()))
+        };
 
-        // First look for the default managed folder
-        if let Some(default) = managed_folders
-            .iter()
-            .find(|(name, _)| name == DEFAULT_MANAGED_FOLDER)
-        {
-            return Ok(Some((default.0.clone(), default.1.clone())));
+        if let Ok(name) = std::env::var(FOLDER_OVERRIDE_ENV) {
+            return Ok(find(&name));
+        }
+
+        let candidates = [
+            self.get_setting(ACTIVE_FOLDER_KEY)?,
+            self.get_default_managed_folder()?,
+            Some(DEFAULT_MANAGED_FOLDER.to_string()),
+        ];
+        for name in candidates.into_iter().flatten() {
+            if let Some(folder) = find(&name) {
+                return Ok(Some(folder));
+            }
         }
 
-        // If no default, return the first one
         Ok(managed_folders.first().map(|(n, p)| (n.clone(), p.clone())))
     }
 
+    // ---- Settings ----
+
+    // Get a value from the settings table
+    pub fn get_setting(&self, key: &str) -> rusqlite::Result<Option<String>> {
+        if let Some(conn) = &self.connection {
+            match conn.query_row("SELECT value FROM settings WHERE key = ?", [key], |row| {
+                row.get(0)
+            }) {
+                Ok(value) => Ok(Some(value)),
+                Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+                Err(e) => Err(e),
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Get every setting whose key starts with `prefix`, in key order
+    pub fn get_settings_with_prefix(
+        &self,
+        prefix: &str,
+    ) -> rusqlite::Result<Vec<(String, String)>> {
+        let Some(conn) = &self.connection else {
+            return Ok(Vec::new());
+        };
+        let mut stmt = conn.prepare(
+            "SELECT key, value FROM settings WHERE substr(key, 1, ?1) = ?2 ORDER BY key",
+        )?;
+        let rows = stmt.query_map(rusqlite::params![prefix.len() as i64, prefix], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })?;
+        rows.collect()
+    }
+
+    // Remove a value from the settings table; returns false if it was not set
+    pub fn remove_setting(&self, key: &str) -> rusqlite::Result<bool> {
+        if let Some(conn) = &self.connection {
+            Ok(conn.execute("DELETE FROM settings WHERE key = ?", [key])? > 0)
+        } else {
+            Ok(false)
+        }
+    }
+
+    // Set a value in the settings table
+    pub fn set_setting(&self, key: &str, value: &str) -> rusqlite::Result<()> {
+        if let Some(conn) = &self.connection {
+            conn.execute(
+                "INSERT OR REPLACE INTO settings (key, value) VALUES (?, ?)",
+                [key, value],
+            )?;
+        }
+        Ok(())
+    }
+
     // Helper functions
 
     // Check if file exists and contains item
@@ -484,164 +958,318 @@ impl Config {
 
     // ---- Database operations for dotfiles ----
 
-    // Stage a dotfile in the database
-    pub fn stage_dotfile(
+    // Record the current contents of matching rows in the operation history
+    // before they are changed, so the change can be undone
+    fn snapshot_rows<P: rusqlite::Params>(
         &self,
-        source: &Path,
-        target: &Path,
-        profile: Option<&str>,
+        where_clause: &str,
+        params: P,
     ) -> rusqlite::Result<()> {
-        if let Some(conn) = &self.connection {
-            let source_str = source.to_string_lossy().to_string();
-            let target_str = target.to_string_lossy().to_string();
-
-            conn.execute(
-                "INSERT INTO dotfiles (source, target, profile, status) VALUES (?, ?, ?, 'staged')",
-                rusqlite::params![source_str, target_str, profile],
-            )?;
+        if !history::is_recording() {
+            return Ok(());
+        }
 
-            Ok(())
-        } else {
-            Err(rusqlite::Error::QueryReturnedNoRows)
+        if let Some(conn) = &self.connection {
+            let sql = format!(
+                "SELECT id, source, target, profile, status, active, created_at, folder_id, mode, synced_hash
+                 FROM dotfiles WHERE {}",
+                where_clause
+            );
+            let mut stmt = conn.prepare(&sql)?;
+            let rows = stmt.query_map(params, |row| {
+                Ok(DotfileRow {
+                    id: row.get(0)?,
+                    source: path_column(row, 1)?,
+                    target: path_column(row, 2)?,
+                    profile: row.get(3)?,
+                    status: row.get(4)?,
+                    active: row.get(5)?,
+                    created_at: row.get(6)?,
+                    folder_id: row.get(7)?,
+                    mode: row.get::<_, String>(8)?.parse().unwrap_or_default(),
+                    synced_hash: row.get(9)?,
+                })
+            })?;
+            for row in rows {
+                history::record(Change::RowChanged { row: row? });
+            }
         }
+
+        Ok(())
     }
 
-    // Update a dotfile status to linked
-    pub fn link_dotfile(&self, source: &Path, target: &Path) -> rusqlite::Result<()> {
+    // Put a row back exactly as it was in a snapshot
+    pub fn restore_row(&self, row: &DotfileRow) -> rusqlite::Result<()> {
         if let Some(conn) = &self.connection {
-            let source_str = source.to_string_lossy().to_string();
-            let target_str = target.to_string_lossy().to_string();
-
+            if self.target_by_id(row.id)?.is_some() {
+                self.snapshot_rows("id = ?", [row.id])?;
+            } else {
+                history::record(Change::RowInserted { id: row.id });
+            }
             conn.execute(
-                "UPDATE dotfiles SET status = 'linked' WHERE source = ? AND target = ? AND active = 1",
-                rusqlite::params![source_str, target_str],
+                "INSERT OR REPLACE INTO dotfiles
+                    (id, source, target, profile, status, active, created_at, folder_id, mode, synced_hash)
+                 VALUES (?, ?, ?, ?, ?, ?, COALESCE(?, CURRENT_TIMESTAMP), ?, ?, ?)",
+                rusqlite::params![
+                    row.id,
+                    path_utils::to_bytes(&row.source),
+                    path_utils::to_bytes(&row.target),
+                    row.profile,
+                    row.status,
+                    row.active,
+                    row.created_at,
+                    row.folder_id,
+                    row.mode.as_str(),
+                    row.synced_hash
+                ],
             )?;
-
-            Ok(())
-        } else {
-            Err(rusqlite::Error::QueryReturnedNoRows)
+            if let Some(target) = self.target_by_id(row.id)? {
+                self.sync_manifest(&[target])?;
+            }
         }
+        Ok(())
     }
 
-    // Add a dotfile directly with linked status (for legacy compatibility)
-    pub fn add_dotfile(
-        &self,
-        source: &Path,
-        target: &Path,
-        profile: Option<&str>,
-    ) -> rusqlite::Result<()> {
+    // Delete a single row by id
+    pub fn delete_row(&self, id: i64) -> rusqlite::Result<()> {
         if let Some(conn) = &self.connection {
-            let source_str = source.to_string_lossy().to_string();
-            let target_str = target.to_string_lossy().to_string();
-
-            conn.execute(
-                "INSERT INTO dotfiles (source, target, profile, status) VALUES (?, ?, ?, 'linked')",
-                rusqlite::params![source_str, target_str, profile],
-            )?;
-
-            Ok(())
-        } else {
-            Err(rusqlite::Error::QueryReturnedNoRows)
+            let target = self.target_by_id(id)?;
+            self.snapshot_rows("id = ?", [id])?;
+            conn.execute("DELETE FROM dotfiles WHERE id = ?", [id])?;
+            if let Some(target) = target {
+                self.sync_manifest(&[target])?;
+            }
         }
+        Ok(())
     }
 
-    // Get all dotfiles
-    pub fn get_dotfiles(
-        &self,
-        profile: Option<&str>,
-    ) -> rusqlite::Result<Vec<crate::dotfile::DotFile>> {
-        let mut dotfiles = Vec::new();
-
+    // Resolved managed path of a row, active or not
+    fn target_by_id(&self, id: i64) -> rusqlite::Result<Option<PathBuf>> {
         if let Some(conn) = &self.connection {
-            let query = match profile {
-                Some(_) => {
-                    "SELECT source, target, profile, status FROM dotfiles WHERE active = 1 AND profile = ?1"
+            let result = conn.query_row(
+                &format!("SELECT {} FROM dotfiles WHERE id = ?", DOTFILE_COLUMNS),
+                [id],
+                map_dotfile,
+            );
+            match result {
+                Ok(dotfile) => Ok(Some(dotfile.target)),
+                Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+                Err(e) => Err(e),
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Bring the forge.toml manifest of each target's managed folder in step
+    // with the active rows for that target. A folder without a manifest yet
+    // gets one listing all of its active dotfiles. Failing to write the
+    // manifest does not undo the database change, so it is only reported.
+    fn sync_manifest(&self, targets: &[PathBuf]) -> rusqlite::Result<()> {
+        let folders = self.folder_rows()?;
+        let paths: Vec<(i64, PathBuf)> = folders
+            .iter()
+            .map(|(id, _, path)| (*id, path.clone()))
+            .collect();
+
+        for target in targets {
+            let Some((id, folder)) = innermost_folder(&paths, target) else {
+                continue;
+            };
+            let result = if !manifest::exists(folder) {
+                let name = folders.iter().find(|(f, _, _)| f == id).map(|(_, n, _)| n);
+                manifest::write_all(folder, &self.get_dotfiles(None, name.map(String::as_str))?)
+            } else {
+                match self.find_dotfile_by_target(target)? {
+                    Some(dotfile) => manifest::declare(folder, &dotfile),
+                    None => manifest::forget(folder, target),
                 }
-                None => "SELECT source, target, profile, status FROM dotfiles WHERE active = 1",
             };
+            if let Err(e) = result {
+                eprintln!("Warning: {:#}", e);
+            }
+        }
 
-            let mut stmt = conn.prepare(query)?;
-
-            // This closure is used to extract the dotfile data from a row
-            let map_row = |row: &rusqlite::Row| -> rusqlite::Result<crate::dotfile::DotFile> {
-                let source: String = row.get(0)?;
-                let target: String = row.get(1)?;
-                let profile: Option<String> = row.get(2)?;
-                let status_str: String = row.get(3)?;
-
-                let status = match status_str.as_str() {
-                    "staged" => crate::dotfile::DotFileStatus::Staged,
-                    "linked" => crate::dotfile::DotFileStatus::Linked,
-                    "unlinked" => crate::dotfile::DotFileStatus::Unlinked,
-                    _ => crate::dotfile::DotFileStatus::Staged,
-                };
-
-                Ok(crate::dotfile::DotFile::with_status(
-                    PathBuf::from(source),
-                    PathBuf::from(target),
-                    profile,
-                    status,
-                ))
-            };
+        Ok(())
+    }
 
-            let rows = match profile {
-                Some(p) => stmt.query_map([p], map_row)?,
-                None => stmt.query_map([], map_row)?,
-            };
+    // Stage a dotfile in the database
+    pub fn stage_dotfile(
+        &self,
+        source: &Path,
+        target: &Path,
+        profile: Option<&str>,
+    ) -> rusqlite::Result<()> {
+        self.insert_dotfile(source, target, profile, "staged")
+    }
 
-            for dotfile_result in rows {
-                dotfiles.push(dotfile_result?);
+    // Update a dotfile status to linked
+    pub fn link_dotfile(&self, source: &Path, target: &Path) -> rusqlite::Result<()> {
+        if let Some(conn) = &self.connection {
+            let [source_portable, source_absolute] = source_keys(source);
+            let [target_portable, target_absolute] = self.target_keys(target)?;
+            let params = rusqlite::params![
+                source_portable,
+                source_absolute,
+                target_portable,
+                target_absolute
+            ];
+
+            self.snapshot_rows(
+                "source IN (?1, ?2) AND target IN (?3, ?4) AND active = 1",
+                params,
+            )?;
+            conn.execute(
+                "UPDATE dotfiles SET status = 'linked'
+                 WHERE source IN (?1, ?2) AND target IN (?3, ?4) AND active = 1",
+                params,
+            )?;
+            self.sync_manifest(&[target.to_path_buf()])?;
+
+            Ok(())
+        } else {
+            Err(rusqlite::Error::QueryReturnedNoRows)
+        }
+    }
+
+    // Change how a dotfile is deployed
+    pub fn set_dotfile_mode(&self, target: &Path, mode: DeployMode) -> rusqlite::Result<bool> {
+        if let Some(conn) = &self.connection {
+            let keys = self.target_keys(target)?;
+
+            self.snapshot_rows(
+                "target IN (?1, ?2) AND active = 1",
+                rusqlite::params_from_iter(&keys),
+            )?;
+            let affected = conn.execute(
+                "UPDATE dotfiles SET mode = ?3 WHERE target IN (?1, ?2) AND active = 1",
+                rusqlite::params![keys[0], keys[1], mode.as_str()],
+            )?;
+            self.sync_manifest(&[target.to_path_buf()])?;
+
+            Ok(affected > 0)
+        } else {
+            Ok(false)
+        }
+    }
+
+    // Remember the hash both sides of a copied dotfile had when last in step
+    pub fn set_synced_hash(&self, target: &Path, hash: Option<&str>) -> rusqlite::Result<()> {
+        if let Some(conn) = &self.connection {
+            let keys = self.target_keys(target)?;
+
+            self.snapshot_rows(
+                "target IN (?1, ?2) AND active = 1",
+                rusqlite::params_from_iter(&keys),
+            )?;
+            conn.execute(
+                "UPDATE dotfiles SET synced_hash = ?3 WHERE target IN (?1, ?2) AND active = 1",
+                rusqlite::params![keys[0], keys[1], hash],
+            )?;
+        }
+        Ok(())
+    }
+
+    // The hash recorded at the last link or sync of a dotfile, if any
+    pub fn get_synced_hash(&self, target: &Path) -> rusqlite::Result<Option<String>> {
+        if let Some(conn) = &self.connection {
+            let result = conn.query_row(
+                "SELECT synced_hash FROM dotfiles WHERE target IN (?1, ?2) AND active = 1",
+                self.target_keys(target)?,
+                |row| row.get(0),
+            );
+            match result {
+                Ok(hash) => Ok(hash),
+                Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+                Err(e) => Err(e),
             }
+        } else {
+            Ok(None)
         }
+    }
 
-        Ok(dotfiles)
+    // Add a dotfile directly with linked status (for legacy compatibility)
+    pub fn add_dotfile(
+        &self,
+        source: &Path,
+        target: &Path,
+        profile: Option<&str>,
+    ) -> rusqlite::Result<()> {
+        self.insert_dotfile(source, target, profile, "linked")
+    }
+
+    // Insert a dotfile row, storing its paths relative to their roots
+    fn insert_dotfile(
+        &self,
+        source: &Path,
+        target: &Path,
+        profile: Option<&str>,
+        status: &str,
+    ) -> rusqlite::Result<()> {
+        if let Some(conn) = &self.connection {
+            let folder = self.folder_for(target)?;
+            let folder_path = folder.as_ref().map(|(_, path)| path.as_path());
+
+            conn.execute(
+                "INSERT INTO dotfiles (source, target, profile, status, folder_id)
+                 VALUES (?, ?, ?, ?, ?)",
+                rusqlite::params![
+                    path_utils::to_portable(source, None),
+                    path_utils::to_portable(target, folder_path),
+                    profile,
+                    status,
+                    folder.as_ref().map(|(id, _)| id)
+                ],
+            )?;
+            history::record(Change::RowInserted {
+                id: conn.last_insert_rowid(),
+            });
+            self.sync_manifest(&[target.to_path_buf()])?;
+
+            Ok(())
+        } else {
+            Err(rusqlite::Error::QueryReturnedNoRows)
+        }
     }
 
-    // Get staged dotfiles
+    // Get all dotfiles, optionally only those in one profile and/or managed folder
+    pub fn get_dotfiles(
+        &self,
+        profile: Option<&str>,
+        folder: Option<&str>,
+    ) -> rusqlite::Result<Vec<crate::dotfile::DotFile>> {
+        self.select_dotfiles("active = 1", profile, folder)
+    }
+
+    // Get staged dotfiles, optionally only those in one profile and/or managed folder
     pub fn get_staged_dotfiles(
         &self,
         profile: Option<&str>,
+        folder: Option<&str>,
+    ) -> rusqlite::Result<Vec<crate::dotfile::DotFile>> {
+        self.select_dotfiles("status = 'staged' AND active = 1", profile, folder)
+    }
+
+    fn select_dotfiles(
+        &self,
+        condition: &str,
+        profile: Option<&str>,
+        folder: Option<&str>,
     ) -> rusqlite::Result<Vec<crate::dotfile::DotFile>> {
         let mut dotfiles = Vec::new();
 
         if let Some(conn) = &self.connection {
-            let query = match profile {
-                Some(_) => {
-                    "SELECT source, target, profile, status FROM dotfiles WHERE status = 'staged' AND active = 1 AND profile = ?1"
-                }
-                None => {
-                    "SELECT source, target, profile, status FROM dotfiles WHERE status = 'staged' AND active = 1"
-                }
-            };
+            let query = format!(
+                "SELECT {} FROM dotfiles
+                 WHERE {}
+                 AND (?1 IS NULL OR profile = ?1)
+                 AND (?2 IS NULL OR folder_id = (SELECT id FROM managed_folders WHERE name = ?2))",
+                DOTFILE_COLUMNS, condition
+            );
 
-            let mut stmt = conn.prepare(query)?;
-
-            // This closure is used to extract the dotfile data from a row
-            let map_row = |row: &rusqlite::Row| -> rusqlite::Result<crate::dotfile::DotFile> {
-                let source: String = row.get(0)?;
-                let target: String = row.get(1)?;
-                let profile: Option<String> = row.get(2)?;
-                let status_str: String = row.get(3)?;
-
-                let status = match status_str.as_str() {
-                    "staged" => crate::dotfile::DotFileStatus::Staged,
-                    "linked" => crate::dotfile::DotFileStatus::Linked,
-                    "unlinked" => crate::dotfile::DotFileStatus::Unlinked,
-                    _ => crate::dotfile::DotFileStatus::Staged,
-                };
-
-                Ok(crate::dotfile::DotFile::with_status(
-                    PathBuf::from(source),
-                    PathBuf::from(target),
-                    profile,
-                    status,
-                ))
-            };
+            let mut stmt = conn.prepare(&query)?;
 
-            let rows = match profile {
-                Some(p) => stmt.query_map([p], map_row)?,
-                None => stmt.query_map([], map_row)?,
-            };
+            let rows = stmt.query_map(rusqlite::params![profile, folder], map_dotfile)?;
 
             for dotfile_result in rows {
                 dotfiles.push(dotfile_result?);
@@ -654,12 +1282,17 @@ impl Config {
     // Deactivate (mark as inactive) a dotfile by target path
     pub fn deactivate_dotfile(&self, target: &Path) -> rusqlite::Result<bool> {
         if let Some(conn) = &self.connection {
-            let target_str = target.to_string_lossy().to_string();
+            let keys = self.target_keys(target)?;
 
+            self.snapshot_rows(
+                "target IN (?1, ?2) AND active = 1",
+                rusqlite::params_from_iter(&keys),
+            )?;
             let affected = conn.execute(
-                "UPDATE dotfiles SET active = 0 WHERE target = ?",
-                [target_str],
+                "UPDATE dotfiles SET active = 0 WHERE target IN (?1, ?2)",
+                rusqlite::params_from_iter(&keys),
             )?;
+            self.sync_manifest(&[target.to_path_buf()])?;
 
             Ok(affected > 0)
         } else {
@@ -670,9 +1303,14 @@ impl Config {
     // Completely remove a dotfile from the database
     pub fn remove_dotfile(&self, target: &Path) -> rusqlite::Result<bool> {
         if let Some(conn) = &self.connection {
-            let target_str = target.to_string_lossy().to_string();
+            let keys = self.target_keys(target)?;
 
-            let affected = conn.execute("DELETE FROM dotfiles WHERE target = ?", [target_str])?;
+            self.snapshot_rows("target IN (?1, ?2)", rusqlite::params_from_iter(&keys))?;
+            let affected = conn.execute(
+                "DELETE FROM dotfiles WHERE target IN (?1, ?2)",
+                rusqlite::params_from_iter(&keys),
+            )?;
+            self.sync_manifest(&[target.to_path_buf()])?;
 
             Ok(affected > 0)
         } else {
@@ -686,31 +1324,13 @@ impl Config {
         target: &Path,
     ) -> rusqlite::Result<Option<crate::dotfile::DotFile>> {
         if let Some(conn) = &self.connection {
-            let target_str = target.to_string_lossy().to_string();
-
             let result = conn.query_row(
-                "SELECT source, target, profile, status FROM dotfiles WHERE target = ? AND active = 1",
-                [target_str],
-                |row| {
-                    let source: String = row.get(0)?;
-                    let target: String = row.get(1)?;
-                    let profile: Option<String> = row.get(2)?;
-                    let status_str: String = row.get(3)?;
-
-                    let status = match status_str.as_str() {
-                        "staged" => crate::dotfile::DotFileStatus::Staged,
-                        "linked" => crate::dotfile::DotFileStatus::Linked,
-                        "unlinked" => crate::dotfile::DotFileStatus::Unlinked,
-                        _ => crate::dotfile::DotFileStatus::Staged,
-                    };
-
-                    Ok(crate::dotfile::DotFile::with_status(
-                        PathBuf::from(source),
-                        PathBuf::from(target),
-                        profile,
-                        status,
-                    ))
-                },
+                &format!(
+                    "SELECT {} FROM dotfiles WHERE target IN (?1, ?2) AND active = 1",
+                    DOTFILE_COLUMNS
+                ),
+                self.target_keys(target)?,
+                map_dotfile,
             );
 
             match result {
@@ -723,37 +1343,265 @@ impl Config {
         }
     }
 
+    // Find active targets that are tracked by more than one row, returning row ids newest first.
+    // Rows are compared by resolved path, since the same file may be stored in different forms.
+    pub fn get_duplicate_targets(&self) -> rusqlite::Result<Vec<(PathBuf, Vec<i64>)>> {
+        let mut duplicates: Vec<(PathBuf, Vec<i64>)> = Vec::new();
+
+        if let Some(conn) = &self.connection {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT id, {} FROM dotfiles WHERE active = 1 ORDER BY id DESC",
+                DOTFILE_COLUMNS
+            ))?;
+            let rows = stmt.query_map([], |row| {
+                let dotfile = map_dotfile_at(row, 1)?;
+                Ok((dotfile.target, row.get::<_, i64>(0)?))
+            })?;
+
+            for row in rows {
+                let (target, id) = row?;
+                match duplicates.iter_mut().find(|(t, _)| *t == target) {
+                    Some((_, ids)) => ids.push(id),
+                    None => duplicates.push((target, vec![id])),
+                }
+            }
+            duplicates.retain(|(_, ids)| ids.len() > 1);
+        }
+
+        Ok(duplicates)
+    }
+
+    // Deactivate (mark as inactive) a single dotfile row by id
+    pub fn deactivate_dotfile_by_id(&self, id: i64) -> rusqlite::Result<bool> {
+        if let Some(conn) = &self.connection {
+            self.snapshot_rows("id = ?1 AND active = 1", [id])?;
+            let affected = conn.execute("UPDATE dotfiles SET active = 0 WHERE id = ?", [id])?;
+            if let Some(target) = self.target_by_id(id)? {
+                self.sync_manifest(&[target])?;
+            }
+            Ok(affected > 0)
+        } else {
+            Ok(false)
+        }
+    }
+
+    // ---- Operation history ----
+
+    // Store a completed operation and its serialized changes
+    pub fn record_operation(&self, command: &str, changes: &str) -> rusqlite::Result<i64> {
+        if let Some(conn) = &self.connection {
+            conn.execute(
+                "INSERT INTO operations (command, changes) VALUES (?, ?)",
+                [command, changes],
+            )?;
+            Ok(conn.last_insert_rowid())
+        } else {
+            Err(rusqlite::Error::QueryReturnedNoRows)
+        }
+    }
+
+    // Get recorded operations, newest first
+    pub fn get_operations(&self, limit: usize) -> rusqlite::Result<Vec<OperationRecord>> {
+        let mut operations = Vec::new();
+
+        if let Some(conn) = &self.connection {
+            let mut stmt = conn.prepare(
+                "SELECT id, command, changes, undone, created_at FROM operations ORDER BY id DESC LIMIT ?",
+            )?;
+            let rows = stmt.query_map([limit as i64], map_operation)?;
+            for row in rows {
+                operations.push(row?);
+            }
+        }
+
+        Ok(operations)
+    }
+
+    // Get a single operation by id, or the most recent one that has not been undone
+    pub fn get_operation(&self, id: Option<i64>) -> rusqlite::Result<Option<OperationRecord>> {
+        if let Some(conn) = &self.connection {
+            let result = match id {
+                Some(id) => conn.query_row(
+                    "SELECT id, command, changes, undone, created_at FROM operations WHERE id = ?",
+                    [id],
+                    map_operation,
+                ),
+                None => conn.query_row(
+                    "SELECT id, command, changes, undone, created_at FROM operations WHERE undone = 0 ORDER BY id DESC LIMIT 1",
+                    [],
+                    map_operation,
+                ),
+            };
+
+            match result {
+                Ok(op) => Ok(Some(op)),
+                Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+                Err(e) => Err(e),
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Mark an operation as undone
+    pub fn mark_operation_undone(&self, id: i64) -> rusqlite::Result<()> {
+        if let Some(conn) = &self.connection {
+            conn.execute("UPDATE operations SET undone = 1 WHERE id = ?", [id])?;
+        }
+        Ok(())
+    }
+
+    // Forget a recorded operation
+    pub fn delete_operation(&self, id: i64) -> rusqlite::Result<()> {
+        if let Some(conn) = &self.connection {
+            conn.execute("DELETE FROM operations WHERE id = ?", [id])?;
+        }
+        Ok(())
+    }
+
+    // ---- Backup store ----
+
+    // Index a copy in the backup store; re-indexing the same copy replaces its entry
+    pub fn add_backup(
+        &self,
+        original: &Path,
+        stored: &Path,
+        reason: &str,
+        hash: &str,
+        size: u64,
+    ) -> rusqlite::Result<i64> {
+        if let Some(conn) = &self.connection {
+            conn.execute(
+                "INSERT OR REPLACE INTO backups (original, stored, reason, hash, size)
+                 VALUES (?, ?, ?, ?, ?)",
+                rusqlite::params![
+                    path_utils::to_portable(original, None),
+                    path_utils::to_bytes(stored),
+                    reason,
+                    hash,
+                    size as i64
+                ],
+            )?;
+            Ok(conn.last_insert_rowid())
+        } else {
+            Err(rusqlite::Error::QueryReturnedNoRows)
+        }
+    }
+
+    // Get indexed backups, newest first, optionally only those of one original path
+    pub fn get_backups(&self, original: Option<&Path>) -> rusqlite::Result<Vec<BackupRecord>> {
+        let mut backups = Vec::new();
+
+        if let Some(conn) = &self.connection {
+            let mut stmt = conn.prepare(&format!(
+                "SELECT {} FROM backups ORDER BY id DESC",
+                BACKUP_COLUMNS
+            ))?;
+            let rows = stmt.query_map([], map_backup)?;
+            for row in rows {
+                let backup = row?;
+                if original.is_none_or(|original| backup.original == original) {
+                    backups.push(backup);
+                }
+            }
+        }
+
+        Ok(backups)
+    }
+
+    // Get a single backup by id
+    pub fn get_backup(&self, id: i64) -> rusqlite::Result<Option<BackupRecord>> {
+        if let Some(conn) = &self.connection {
+            match conn.query_row(
+                &format!("SELECT {} FROM backups WHERE id = ?", BACKUP_COLUMNS),
+                [id],
+                map_backup,
+            ) {
+                Ok(backup) => Ok(Some(backup)),
+                Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+                Err(e) => Err(e),
+            }
+        } else {
+            Ok(None)
+        }
+    }
+
+    // Drop a backup from the index
+    pub fn remove_backup(&self, id: i64) -> rusqlite::Result<bool> {
+        if let Some(conn) = &self.connection {
+            let affected = conn.execute("DELETE FROM backups WHERE id = ?", [id])?;
+            Ok(affected > 0)
+        } else {
+            Ok(false)
+        }
+    }
+
+    // ---- Selection rules ----
+
+    // Add a selection rule; returns None if the same rule already exists
+    pub fn add_selection_rule(
+        &self,
+        action: &str,
+        kind: &str,
+        pattern: &str,
+    ) -> rusqlite::Result<Option<i64>> {
+        if let Some(conn) = &self.connection {
+            let added = conn.execute(
+                "INSERT OR IGNORE INTO selection_rules (action, kind, pattern) VALUES (?, ?, ?)",
+                [action, kind, pattern],
+            )?;
+            Ok((added > 0).then(|| conn.last_insert_rowid()))
+        } else {
+            Err(rusqlite::Error::QueryReturnedNoRows)
+        }
+    }
+
+    // Get all selection rules, oldest first
+    pub fn get_selection_rules(&self) -> rusqlite::Result<Vec<SelectionRuleRecord>> {
+        let mut rules = Vec::new();
+
+        if let Some(conn) = &self.connection {
+            let mut stmt =
+                conn.prepare("SELECT id, action, kind, pattern FROM selection_rules ORDER BY id")?;
+            let rows = stmt.query_map([], |row| {
+                Ok(SelectionRuleRecord {
+                    id: row.get(0)?,
+                    action: row.get(1)?,
+                    kind: row.get(2)?,
+                    pattern: row.get(3)?,
+                })
+            })?;
+            for row in rows {
+                rules.push(row?);
+            }
+        }
+
+        Ok(rules)
+    }
+
+    // Remove a selection rule by id
+    pub fn remove_selection_rule(&self, id: i64) -> rusqlite::Result<bool> {
+        if let Some(conn) = &self.connection {
+            let affected = conn.execute("DELETE FROM selection_rules WHERE id = ?", [id])?;
+            Ok(affected > 0)
+        } else {
+            Ok(false)
+        }
+    }
+
     // Find a dotfile by source path
     pub fn find_dotfile_by_source(
         &self,
         source: &Path,
     ) -> rusqlite::Result<Option<crate::dotfile::DotFile>> {
         if let Some(conn) = &self.connection {
-            let source_str = source.to_string_lossy().to_string();
-
             let result = conn.query_row(
-                "SELECT source, target, profile, status FROM dotfiles WHERE source = ? AND active = 1",
-                [source_str],
-                |row| {
-                    let source: String = row.get(0)?;
-                    let target: String = row.get(1)?;
-                    let profile: Option<String> = row.get(2)?;
-                    let status_str: String = row.get(3)?;
-
-                    let status = match status_str.as_str() {
-                        "staged" => crate::dotfile::DotFileStatus::Staged,
-                        "linked" => crate::dotfile::DotFileStatus::Linked,
-                        "unlinked" => crate::dotfile::DotFileStatus::Unlinked,
-                        _ => crate::dotfile::DotFileStatus::Staged,
-                    };
-
-                    Ok(crate::dotfile::DotFile::with_status(
-                        PathBuf::from(source),
-                        PathBuf::from(target),
-                        profile,
-                        status,
-                    ))
-                },
+                &format!(
+                    "SELECT {} FROM dotfiles WHERE source IN (?1, ?2) AND active = 1",
+                    DOTFILE_COLUMNS
+                ),
+                source_keys(source),
+                map_dotfile,
             );
 
             match result {
@@ -767,6 +1615,94 @@ impl Config {
     }
 }
 
+// Read the raw bytes of a path column; rows written before paths were
+// stored as bytes may still hold text
+fn path_bytes(row: &rusqlite::Row, idx: usize) -> rusqlite::Result<Vec<u8>> {
+    match row.get_ref(idx)? {
+        ValueRef::Blob(bytes) | ValueRef::Text(bytes) => Ok(bytes.to_vec()),
+        other => Err(rusqlite::Error::FromSqlConversionFailure(
+            idx,
+            other.data_type(),
+            "expected a path".into(),
+        )),
+    }
+}
+
+// Read a path column exactly as stored, without resolving its root
+fn path_column(row: &rusqlite::Row, idx: usize) -> rusqlite::Result<PathBuf> {
+    Ok(path_utils::from_bytes(&path_bytes(row, idx)?))
+}
+
+// The managed folder (id, path) a path lies in, the innermost if nested
+fn innermost_folder<'a>(folders: &'a [(i64, PathBuf)], path: &Path) -> Option<&'a (i64, PathBuf)> {
+    folders
+        .iter()
+        .filter(|(_, folder)| path.starts_with(folder) && path != folder)
+        .max_by_key(|(_, folder)| folder.components().count())
+}
+
+// Stored forms a source path may have: relative to a root, or absolute for
+// rows written before paths were stored portably. Sources are never stored
+// relative to a managed folder.
+fn source_keys(source: &Path) -> [Vec<u8>; 2] {
+    [
+        path_utils::to_portable(source, None),
+        path_utils::to_bytes(source),
+    ]
+}
+
+// Map a row of DOTFILE_COLUMNS to a DotFile, resolving its paths
+fn map_dotfile(row: &rusqlite::Row) -> rusqlite::Result<crate::dotfile::DotFile> {
+    map_dotfile_at(row, 0)
+}
+
+// Map DOTFILE_COLUMNS starting at column `first`
+fn map_dotfile_at(row: &rusqlite::Row, first: usize) -> rusqlite::Result<crate::dotfile::DotFile> {
+    let folder = row
+        .get::<_, Option<Vec<u8>>>(first + 4)?
+        .map(|folder| path_utils::from_portable(&folder, None));
+    let status_str: String = row.get(first + 3)?;
+    let mode = row.get::<_, String>(first + 5)?.parse().unwrap_or_default();
+    let status = match status_str.as_str() {
+        "staged" => crate::dotfile::DotFileStatus::Staged,
+        "linked" => crate::dotfile::DotFileStatus::Linked,
+        "unlinked" => crate::dotfile::DotFileStatus::Unlinked,
+        _ => crate::dotfile::DotFileStatus::Staged,
+    };
+
+    Ok(crate::dotfile::DotFile::with_status(
+        path_utils::from_portable(&path_bytes(row, first)?, folder.as_deref()),
+        path_utils::from_portable(&path_bytes(row, first + 1)?, folder.as_deref()),
+        row.get(first + 2)?,
+        status,
+    )
+    .with_mode(mode))
+}
+
+const BACKUP_COLUMNS: &str = "id, original, stored, reason, hash, size, created_at";
+
+fn map_backup(row: &rusqlite::Row) -> rusqlite::Result<BackupRecord> {
+    Ok(BackupRecord {
+        id: row.get(0)?,
+        original: path_utils::from_portable(&path_bytes(row, 1)?, None),
+        stored: path_utils::from_bytes(&path_bytes(row, 2)?),
+        reason: row.get(3)?,
+        hash: row.get(4)?,
+        size: row.get::<_, i64>(5)? as u64,
+        created_at: row.get(6)?,
+    })
+}
+
+fn map_operation(row: &rusqlite::Row) -> rusqlite::Result<OperationRecord> {
+    Ok(OperationRecord {
+        id: row.get(0)?,
+        command: row.get(1)?,
+        changes: row.get(2)?,
+        undone: row.get(3)?,
+        created_at: row.get(4)?,
+    })
+}
+
 // Static helper functions to use when a Config instance is not available
 
 // Get default configuration instance
@@ -774,31 +1710,86 @@ fn get_config() -> Config {
     Config::new()
 }
 
+// Get the global config directory
+pub fn get_config_dir() -> PathBuf {
+    get_config().get_config_dir().clone()
+}
+
 // Read default path
 pub fn read_default_path() -> String {
     get_config().read_default_path()
 }
 
-// Get file types
-pub fn get_file_types() -> io::Result<Vec<String>> {
-    get_config().read_lines(get_config().get_filetypes_file())
-}
-
 // Get ignored paths
 pub fn get_ignored_paths() -> io::Result<Vec<String>> {
     get_config().read_lines(get_config().get_ignored_paths_file())
 }
 
+// Get a value from the settings table
+pub fn get_setting(key: &str) -> rusqlite::Result<Option<String>> {
+    get_db_connection()?.get_setting(key)
+}
+
+// Get every setting whose key starts with `prefix`
+pub fn get_settings_with_prefix(prefix: &str) -> rusqlite::Result<Vec<(String, String)>> {
+    get_db_connection()?.get_settings_with_prefix(prefix)
+}
+
+// Remove a value from the settings table
+pub fn remove_setting(key: &str) -> rusqlite::Result<bool> {
+    get_db_connection()?.remove_setting(key)
+}
+
+// Set a value in the settings table
+pub fn set_setting(key: &str, value: &str) -> rusqlite::Result<()> {
+    get_db_connection()?.set_setting(key, value)
+}
+
 // ---- Managed Folders operations ----
 
-// Add a managed folder
-pub fn add_managed_folder(name: &str, path: &Path) -> io::Result<()> {
-    get_config().add_managed_folder(name, path)
+// Add a managed folder; returns false if the name is already taken
+pub fn add_managed_folder(name: &str, path: &Path) -> rusqlite::Result<bool> {
+    get_db_connection()?.add_managed_folder(name, path)
+}
+
+// Get all managed folders (name, path)
+pub fn get_managed_folders() -> rusqlite::Result<Vec<(String, PathBuf)>> {
+    get_db_connection()?.get_managed_folders()
 }
 
 // Get the current active managed folder
-pub fn get_active_managed_folder() -> io::Result<Option<(String, PathBuf)>> {
-    get_config().get_active_managed_folder()
+pub fn get_active_managed_folder() -> rusqlite::Result<Option<(String, PathBuf)>> {
+    get_db_connection()?.get_active_managed_folder()
+}
+
+// Get the folder marked as default
+pub fn get_default_managed_folder() -> rusqlite::Result<Option<String>> {
+    get_db_connection()?.get_default_managed_folder()
+}
+
+// Mark a folder as the default
+pub fn set_default_managed_folder(name: &str) -> rusqlite::Result<bool> {
+    get_db_connection()?.set_default_managed_folder(name)
+}
+
+// Rename a managed folder
+pub fn rename_managed_folder(old: &str, new: &str) -> rusqlite::Result<bool> {
+    get_db_connection()?.rename_managed_folder(old, new)
+}
+
+// Point a managed folder at a new location
+pub fn relocate_managed_folder(name: &str, to: &Path) -> rusqlite::Result<bool> {
+    get_db_connection()?.relocate_managed_folder(name, to)
+}
+
+// Forget a managed folder
+pub fn remove_managed_folder(name: &str) -> rusqlite::Result<bool> {
+    get_db_connection()?.remove_managed_folder(name)
+}
+
+// Make a folder the active one
+pub fn use_managed_folder(name: &str) -> rusqlite::Result<bool> {
+    get_db_connection()?.use_managed_folder(name)
 }
 
 // ---- Database operations for dotfiles ----
@@ -810,6 +1801,29 @@ pub fn get_db_connection() -> rusqlite::Result<Config> {
     Ok(config)
 }
 
+// Get the schema version and pending migrations without applying them
+pub fn pending_migrations() -> rusqlite::Result<(u32, Vec<&'static Migration>)> {
+    let mut config = get_config();
+    config.open()?;
+    config.pending_migrations()
+}
+
+// Apply pending migrations, returning those applied
+pub fn migrate_database() -> rusqlite::Result<Vec<&'static Migration>> {
+    let mut config = get_config();
+    config.open()?;
+    config.migrate()
+}
+
+// Rewrite stored paths in their portable form
+pub fn rebase_paths(
+    from_home: Option<&Path>,
+    dry_run: bool,
+) -> rusqlite::Result<Vec<(PathBuf, PathBuf)>> {
+    let config = get_db_connection()?;
+    config.rebase_paths(from_home, dry_run)
+}
+
 // Stage a dotfile
 pub fn stage_dotfile(source: &Path, target: &Path, profile: Option<&str>) -> rusqlite::Result<()> {
     let config = get_db_connection()?;
@@ -828,18 +1842,22 @@ pub fn add_dotfile(source: &Path, target: &Path, profile: Option<&str>) -> rusql
     config.add_dotfile(source, target, profile)
 }
 
-// Get all dotfiles
-pub fn get_dotfiles(profile: Option<&str>) -> rusqlite::Result<Vec<crate::dotfile::DotFile>> {
+// Get all dotfiles, optionally filtered by profile and managed folder name
+pub fn get_dotfiles(
+    profile: Option<&str>,
+    folder: Option<&str>,
+) -> rusqlite::Result<Vec<crate::dotfile::DotFile>> {
     let config = get_db_connection()?;
-    config.get_dotfiles(profile)
+    config.get_dotfiles(profile, folder)
 }
 
-// Get staged dotfiles
+// Get staged dotfiles, optionally filtered by profile and managed folder name
 pub fn get_staged_dotfiles(
     profile: Option<&str>,
+    folder: Option<&str>,
 ) -> rusqlite::Result<Vec<crate::dotfile::DotFile>> {
     let config = get_db_connection()?;
-    config.get_staged_dotfiles(profile)
+    config.get_staged_dotfiles(profile, folder)
 }
 
 // Deactivate a dotfile
@@ -854,6 +1872,24 @@ pub fn remove_dotfile(target: &Path) -> rusqlite::Result<bool> {
     config.remove_dotfile(target)
 }
 
+// Change how a dotfile is deployed
+pub fn set_dotfile_mode(target: &Path, mode: DeployMode) -> rusqlite::Result<bool> {
+    let config = get_db_connection()?;
+    config.set_dotfile_mode(target, mode)
+}
+
+// Remember the hash of a copied dotfile at its last link or sync
+pub fn set_synced_hash(target: &Path, hash: Option<&str>) -> rusqlite::Result<()> {
+    let config = get_db_connection()?;
+    config.set_synced_hash(target, hash)
+}
+
+// The hash of a copied dotfile at its last link or sync
+pub fn get_synced_hash(target: &Path) -> rusqlite::Result<Option<String>> {
+    let config = get_db_connection()?;
+    config.get_synced_hash(target)
+}
+
 // Find a dotfile by target path
 pub fn find_dotfile_by_target(target: &Path) -> rusqlite::Result<Option<crate::dotfile::DotFile>> {
     let config = get_db_connection()?;
@@ -866,6 +1902,112 @@ pub fn find_dotfile_by_source(source: &Path) -> rusqlite::Result<Option<crate::d
     config.find_dotfile_by_source(source)
 }
 
+// Find active targets tracked by more than one row
+pub fn get_duplicate_targets() -> rusqlite::Result<Vec<(PathBuf, Vec<i64>)>> {
+    let config = get_db_connection()?;
+    config.get_duplicate_targets()
+}
+
+// Deactivate a single dotfile row by id
+pub fn deactivate_dotfile_by_id(id: i64) -> rusqlite::Result<bool> {
+    let config = get_db_connection()?;
+    config.deactivate_dotfile_by_id(id)
+}
+
+// Restore a dotfiles row from a snapshot
+pub fn restore_row(row: &DotfileRow) -> rusqlite::Result<()> {
+    let config = get_db_connection()?;
+    config.restore_row(row)
+}
+
+// Delete a dotfiles row by id
+pub fn delete_row(id: i64) -> rusqlite::Result<()> {
+    let config = get_db_connection()?;
+    config.delete_row(id)
+}
+
+// Store a completed operation in the history
+pub fn record_operation(command: &str, changes: &str) -> rusqlite::Result<i64> {
+    let config = get_db_connection()?;
+    config.record_operation(command, changes)
+}
+
+// Get recorded operations, newest first
+pub fn get_operations(limit: usize) -> rusqlite::Result<Vec<OperationRecord>> {
+    let config = get_db_connection()?;
+    config.get_operations(limit)
+}
+
+// Get an operation by id, or the latest one not yet undone
+pub fn get_operation(id: Option<i64>) -> rusqlite::Result<Option<OperationRecord>> {
+    let config = get_db_connection()?;
+    config.get_operation(id)
+}
+
+// Mark an operation as undone
+pub fn mark_operation_undone(id: i64) -> rusqlite::Result<()> {
+    let config = get_db_connection()?;
+    config.mark_operation_undone(id)
+}
+
+// Forget a recorded operation
+pub fn delete_operation(id: i64) -> rusqlite::Result<()> {
+    let config = get_db_connection()?;
+    config.delete_operation(id)
+}
+
+// Index a copy in the backup store
+pub fn add_backup(
+    original: &Path,
+    stored: &Path,
+    reason: &str,
+    hash: &str,
+    size: u64,
+) -> rusqlite::Result<i64> {
+    let config = get_db_connection()?;
+    config.add_backup(original, stored, reason, hash, size)
+}
+
+// Get indexed backups, newest first, optionally only those of one original path
+pub fn get_backups(original: Option<&Path>) -> rusqlite::Result<Vec<BackupRecord>> {
+    let config = get_db_connection()?;
+    config.get_backups(original)
+}
+
+// Get a single backup by id
+pub fn get_backup(id: i64) -> rusqlite::Result<Option<BackupRecord>> {
+    let config = get_db_connection()?;
+    config.get_backup(id)
+}
+
+// Drop a backup from the index
+pub fn remove_backup(id: i64) -> rusqlite::Result<bool> {
+    let config = get_db_connection()?;
+    config.remove_backup(id)
+}
+
+// Add a selection rule; returns None if the same rule already exists
+pub fn add_selection_rule(
+    action: &str,
+    kind: &str,
+    pattern: &str,
+) -> rusqlite::Result<Option<i64>> {
+    let config = get_db_connection()?;
+    config.add_selection_rule(action, kind, pattern)
+}
+
+// Get all selection rules, oldest first
+pub fn get_selection_rules() -> rusqlite::Result<Vec<SelectionRuleRecord>> {
+    let config = get_db_connection()?;
+    config.get_selection_rules()
+}
+
+// Remove a selection rule by id
+pub fn remove_selection_rule(id: i64) -> rusqlite::Result<bool> {
+    let config = get_db_connection()?;
+    config.remove_selection_rule(id)
+}
+
 /// Batch deactivate (mark as inactive) dotfiles by target paths
 pub fn deactivate_dotfiles(targets: &[std::path::PathBuf]) -> rusqlite::Result<usize> {
     if targets.is_empty() {
@@ -876,64 +2018,90 @@ pub fn deactivate_dotfiles(targets: &[std::path::PathBuf]) -> rusqlite::Result<u
         if targets.is_empty() {
             return Ok(0);
         }
-        // Build a parameterized query for all targets
+        // Build a parameterized query for all stored forms of all targets
+        let mut keys = Vec::new();
+        for target in targets {
+            let target_keys = config.target_keys(target)?;
+            config.snapshot_rows(
+                "active = 1 AND target IN (?1, ?2)",
+                rusqlite::params_from_iter(&target_keys),
+            )?;
+            keys.extend(target_keys);
+        }
         let mut sql =
             String::from("UPDATE dotfiles SET active = 0 WHERE active = 1 AND target IN (");
-        let params: Vec<String> = (0..targets.len()).map(|i| format!("?{}", i + 1)).collect();
+        let params: Vec<String> = (0..keys.len()).map(|i| format!("?{}", i + 1)).collect();
         sql.push_str(&params.join(","));
         sql.push(')');
-        let target_strs: Vec<_> = targets
-            .iter()
-            .map(|p| p.to_string_lossy().to_string())
-            .collect();
-        let updated = conn.execute(&sql, rusqlite::params_from_iter(target_strs))?;
+        let updated = conn.execute(&sql, rusqlite::params_from_iter(keys))?;
+        config.sync_manifest(targets)?;
         Ok(updated)
     } else {
         Ok(0)
     }
 }
 
+/// Rows (id, dotfile) whose source or target is the given folder, or lies under it if recursive
+fn dotfile_rows_in_folder(
+    config: &Config,
+    folder: &std::path::Path,
+    recursive: bool,
+) -> rusqlite::Result<Vec<(i64, crate::dotfile::DotFile)>> {
+    let mut matching = Vec::new();
+    if let Some(conn) = &config.connection {
+        let mut stmt = conn.prepare(&format!("SELECT id, {} FROM dotfiles", DOTFILE_COLUMNS))?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, map_dotfile_at(row, 1)?)))?;
+        for row in rows {
+            let (id, dotfile): (i64, crate::dotfile::DotFile) = row?;
+            let in_folder = |path: &std::path::Path| {
+                if recursive {
+                    path.starts_with(folder) && path != folder
+                } else {
+                    path == folder
+                }
+            };
+            if in_folder(&dotfile.source) || in_folder(&dotfile.target) {
+                matching.push((id, dotfile));
+            }
+        }
+    }
+    Ok(matching)
+}
+
 /// Purge all dotfile records (staged or managed) for a specified folder (and subfolders if recursive)
 pub fn purge_dotfiles_in_folder(
     folder: &std::path::Path,
     recursive: bool,
+    keep: &[PathBuf],
 ) -> rusqlite::Result<usize> {
     let config = get_db_connection()?;
     if let Some(conn) = &config.connection {
-        let folder_str = folder.to_string_lossy();
-        let like_pattern = if recursive {
-            format!("{}%", folder_str)
-        } else {
-            format!("{}", folder_str)
-        };
         println!(
-            "purge: folder_str = '{}', like_pattern = '{}'",
-            folder_str, like_pattern
+            "purge: folder = '{}', recursive = {}",
+            folder.display(),
+            recursive
         );
+        let rows = dotfile_rows_in_folder(&config, folder, recursive)?;
         // Print a few sample source/target paths for debugging
-        let mut stmt = conn.prepare("SELECT source, target FROM dotfiles LIMIT 5")?;
-        let rows = stmt.query_map([], |row| {
-            let source: String = row.get(0)?;
-            let target: String = row.get(1)?;
-            Ok((source, target))
-        })?;
-        for (i, row) in rows.enumerate() {
-            if let Ok((source, target)) = row {
-                println!(
-                    "purge: sample {}: source='{}', target='{}'",
-                    i + 1,
-                    source,
-                    target
-                );
-            }
-        }
-        // Delete where source or target is under the folder
-        let sql = if recursive {
-            "DELETE FROM dotfiles WHERE (source LIKE ?1 OR target LIKE ?1)"
-        } else {
-            "DELETE FROM dotfiles WHERE (source = ?1 OR target = ?1)"
-        };
-        let deleted = conn.execute(sql, rusqlite::params![like_pattern])?;
+        for (i, (_, dotfile)) in rows.iter().take(5).enumerate() {
+            println!(
+                "purge: sample {}: source='{}', target='{}'",
+                i + 1,
+                dotfile.source.display(),
+                dotfile.target.display()
+            );
+        }
+        let mut deleted = 0;
+        let mut targets = Vec::new();
+        for (id, dotfile) in rows {
+            if keep.contains(&dotfile.target) {
+                continue;
+            }
+            config.snapshot_rows("id = ?1", [id])?;
+            deleted += conn.execute("DELETE FROM dotfiles WHERE id = ?", [id])?;
+            targets.push(dotfile.target);
+        }
+        config.sync_manifest(&targets)?;
         Ok(deleted)
     } else {
         Ok(0)
@@ -946,39 +2114,8 @@ pub fn get_dotfiles_in_folder(
     recursive: bool,
 ) -> rusqlite::Result<Vec<crate::dotfile::DotFile>> {
     let config = get_db_connection()?;
-    let mut dotfiles = Vec::new();
-    if let Some(conn) = &config.connection {
-        let folder_str = folder.to_string_lossy();
-        let like_pattern = if recursive {
-            let mut s = folder_str.to_string();
-            if !s.ends_with('/') {
-                s.push('/');
-            }
-            format!("{}%", s)
-        } else {
-            format!("{}", folder_str)
-        };
-        let sql = if recursive {
-            "SELECT source, target, profile, status FROM dotfiles WHERE (source LIKE ?1 OR target LIKE ?1)"
-        } else {
-            "SELECT source, target, profile, status FROM dotfiles WHERE (source = ?1 OR target = ?1)"
-        };
-        let mut stmt = conn.prepare(sql)?;
-        let rows = stmt.query_map(rusqlite::params![like_pattern], |row| {
-            Ok(crate::dotfile::DotFile {
-                source: std::path::PathBuf::from(row.get::<_, String>(0)?),
-                target: std::path::PathBuf::from(row.get::<_, String>(1)?),
-                profile: row.get::<_, Option<String>>(2)?,
-                status: match row.get::<_, String>(3)?.as_str() {
-                    "staged" => crate::dotfile::DotFileStatus::Staged,
-                    "linked" => crate::dotfile::DotFileStatus::Linked,
-                    _ => crate::dotfile::DotFileStatus::Unlinked,
-                },
-            })
-        })?;
-        for df in rows {
-            dotfiles.push(df?);
-        }
-    }
-    Ok(dotfiles)
+    Ok(dotfile_rows_in_folder(&config, folder, recursive)?
+        .into_iter()
+        .map(|(_, dotfile)| dotfile)
+        .collect())
 }
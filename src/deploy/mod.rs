@@ -0,0 +1,357 @@
+// Deploying managed files and keeping copies in step
+//
+// A linked dotfile is deployed at its original location in one of three
+// modes: a symlink to the managed copy, a hard link to it, or a separate copy
+// for applications that refuse symlinks or replace them when saving. Hard
+// links and copies can drift away from the managed copy, so their hash at the
+// last link or sync is recorded; `forge sync` compares both sides with it to
+// tell which one changed and copies that side over the other. A template is
+// deployed as its rendered output (see the template module) and is brought up
+// to date with `forge render` instead, and an encrypted file as its
+// decrypted content (see the secret module). Where a managed file has
+// alternates, the variant selected for this machine is what gets deployed.
+use crate::alternate;
+use crate::config;
+use crate::dotfile::{DeployMode, DotFile, backup};
+use crate::history;
+use crate::secret;
+use crate::symlink;
+use crate::template;
+use crate::utils::hash;
+use anyhow::{Result, bail};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Deploy the managed copy at `at`, which must not exist yet
+pub fn deploy(mode: DeployMode, managed: &Path, at: &Path) -> io::Result<()> {
+    match mode {
+        DeployMode::Symlink => history::create_link(managed, at),
+        DeployMode::Hardlink => history::hard_link(managed, at),
+        DeployMode::Copy if managed.is_dir() => history::copy_tree(managed, at),
+        DeployMode::Copy => history::copy(managed, at).map(|_| ()),
+        DeployMode::Template => {
+            let rendered = template::render_file(managed)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:#}", e)))?;
+            history::write(at, rendered.as_bytes())
+        }
+        DeployMode::Encrypted => {
+            let plain = secret::decrypt_file(managed)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("{:#}", e)))?;
+            history::write_private(at, &plain)
+        }
+    }
+}
+
+/// Whether `at` is already the managed copy deployed in `mode`
+pub fn is_deployed(mode: DeployMode, managed: &Path, at: &Path) -> bool {
+    match mode {
+        DeployMode::Symlink => symlink::resolve_link(at).as_deref() == Some(managed),
+        DeployMode::Hardlink => same_file(managed, at),
+        DeployMode::Copy => {
+            !symlink::is_symlink(at)
+                && fs::symlink_metadata(at).is_ok()
+                && matches!(
+                    (hash::path_hash(at), hash::path_hash(managed)),
+                    (Ok(a), Ok(b)) if a == b
+                )
+        }
+        DeployMode::Template => {
+            !symlink::is_symlink(at)
+                && matches!(
+                    (fs::read(at), template::render_file(managed)),
+                    (Ok(deployed), Ok(rendered)) if deployed == rendered.as_bytes()
+                )
+        }
+        DeployMode::Encrypted => {
+            !symlink::is_symlink(at)
+                && matches!(
+                    (fs::read(at), secret::decrypt_file(managed)),
+                    (Ok(deployed), Ok(plain)) if deployed == plain
+                )
+        }
+    }
+}
+
+/// Whether two paths are names of the same file (neither followed if a symlink)
+#[cfg(unix)]
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (fs::symlink_metadata(a), fs::symlink_metadata(b)) {
+        (Ok(a), Ok(b)) => a.is_file() && a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+/// Whether two paths are names of the same file; hard links are only told
+/// apart on Unix
+#[cfg(not(unix))]
+pub fn same_file(_a: &Path, _b: &Path) -> bool {
+    false
+}
+
+/// Which side of a hard-linked, copied or rendered dotfile changed since the
+/// last sync or render
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Changed {
+    /// The file at the original location
+    Deployed,
+    /// The managed copy in the forge folder (for a template, the template or
+    /// the values it uses)
+    Managed,
+    /// Both, or there is no record of the last sync to tell them apart
+    Both,
+}
+
+impl Changed {
+    pub fn describe(self) -> &'static str {
+        match self {
+            Changed::Deployed => "deployed file changed",
+            Changed::Managed => "managed copy changed",
+            Changed::Both => "both sides changed",
+        }
+    }
+}
+
+/// The managed file deployed for a dotfile on this machine: the variant
+/// selected among its alternates, or the managed copy itself
+pub fn managed_path(dotfile: &DotFile) -> PathBuf {
+    alternate::resolve(&dotfile.target)
+}
+
+/// Compare both sides of a deployed dotfile, None if their content matches
+pub fn changed_side(dotfile: &DotFile) -> Result<Option<Changed>> {
+    if dotfile.mode == DeployMode::Encrypted {
+        return changed_secret(dotfile);
+    }
+    let deployed = hash::path_hash(&dotfile.source)?;
+    let managed = match dotfile.mode {
+        DeployMode::Template => rendered_hash(&template::render_file(&managed_path(dotfile))?),
+        _ => hash::path_hash(managed_path(dotfile))?,
+    };
+    if deployed == managed {
+        return Ok(None);
+    }
+
+    let synced = config::get_synced_hash(&dotfile.target)?;
+    Ok(Some(match synced {
+        Some(synced) if synced == managed => Changed::Deployed,
+        Some(synced) if synced == deployed => Changed::Managed,
+        _ => Changed::Both,
+    }))
+}
+
+// Without decrypting, an encrypted file is compared with the hashes of both
+// sides recorded when it was last decrypted
+fn changed_secret(dotfile: &DotFile) -> Result<Option<Changed>> {
+    let current = synced_hash(dotfile.mode, &dotfile.source, &managed_path(dotfile))?;
+    let synced = config::get_synced_hash(&dotfile.target)?;
+    let Some((deployed, managed)) = synced.as_deref().and_then(|s| s.split_once(':')) else {
+        return Ok(Some(Changed::Both));
+    };
+    let (now_deployed, now_managed) = current.split_once(':').unwrap_or_default();
+
+    Ok(match (now_deployed == deployed, now_managed == managed) {
+        (true, true) => None,
+        (false, true) => Some(Changed::Deployed),
+        (true, false) => Some(Changed::Managed),
+        (false, false) => Some(Changed::Both),
+    })
+}
+
+/// The hash recorded for a deployed dotfile when both sides are in step. For
+/// an encrypted file that is the hash of the decrypted file and of the
+/// ciphertext, so either side changing can be told without the passphrase.
+pub fn synced_hash(mode: DeployMode, deployed: &Path, managed: &Path) -> Result<String> {
+    let hash = hash::path_hash(deployed)?;
+    Ok(match mode {
+        DeployMode::Encrypted => format!("{}:{}", hash, hash::path_hash(managed)?),
+        _ => hash,
+    })
+}
+
+/// What `forge sync` does for one dotfile
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncAction {
+    /// Nothing to do
+    InSync,
+    /// Copy the deployed file into the managed folder
+    Absorb,
+    /// Deploy the managed copy again, replacing the deployed file
+    Deploy,
+    /// Both sides changed; left for the user to resolve
+    Conflict,
+}
+
+/// Work out what syncing a hard-linked or copied dotfile would do
+pub fn plan_sync(dotfile: &DotFile) -> Result<SyncAction> {
+    match dotfile.mode {
+        DeployMode::Template => bail!(
+            "{} is a template; use 'forge render'",
+            dotfile.target.display()
+        ),
+        DeployMode::Encrypted => bail!(
+            "{} is encrypted; use 'forge secret add' or 'forge link'",
+            dotfile.target.display()
+        ),
+        _ => {}
+    }
+    let managed = managed_path(dotfile);
+    if fs::symlink_metadata(&managed).is_err() {
+        bail!("Managed copy {} is missing", managed.display());
+    }
+    if symlink::is_symlink(&dotfile.source) {
+        bail!(
+            "{} is a symlink, not a {}",
+            dotfile.source.display(),
+            dotfile.mode
+        );
+    }
+    if fs::symlink_metadata(&dotfile.source).is_err() {
+        return Ok(SyncAction::Deploy);
+    }
+    if dotfile.mode == DeployMode::Hardlink && same_file(&managed, &dotfile.source) {
+        return Ok(SyncAction::InSync);
+    }
+
+    Ok(match changed_side(dotfile)? {
+        // A broken hard link with the same content only needs linking again
+        None if dotfile.mode == DeployMode::Hardlink => SyncAction::Deploy,
+        None => SyncAction::InSync,
+        Some(Changed::Deployed) => SyncAction::Absorb,
+        Some(Changed::Managed) => SyncAction::Deploy,
+        Some(Changed::Both) => SyncAction::Conflict,
+    })
+}
+
+/// Carry out a planned sync and record the new common hash
+pub fn sync(dotfile: &DotFile, action: SyncAction) -> Result<()> {
+    let (deployed, managed) = (&dotfile.source, managed_path(dotfile));
+    match action {
+        SyncAction::InSync | SyncAction::Conflict => {}
+        SyncAction::Absorb => {
+            replace(deployed, &managed)?;
+            if dotfile.mode == DeployMode::Hardlink {
+                redeploy(dotfile)?;
+            }
+        }
+        SyncAction::Deploy => redeploy(dotfile)?,
+    }
+
+    if action != SyncAction::Conflict {
+        let hash = hash::path_hash(&managed)?;
+        if config::get_synced_hash(&dotfile.target)?.as_deref() != Some(hash.as_str()) {
+            config::set_synced_hash(&dotfile.target, Some(&hash))?;
+        }
+    }
+    Ok(())
+}
+
+/// Hash of rendered template output, comparable with the hash of the file it
+/// was written to
+pub fn rendered_hash(rendered: &str) -> String {
+    hash::content_hash(rendered.as_bytes())
+}
+
+/// Write a template's rendered output at its original location and record it
+/// as the last render. A file there that was edited since the last render is
+/// saved in the backup store first; the id of that backup is returned.
+pub fn render(dotfile: &DotFile, rendered: &str) -> Result<Option<i64>> {
+    let deployed = &dotfile.source;
+    let synced = config::get_synced_hash(&dotfile.target)?;
+    let hash = rendered_hash(rendered);
+    let mut backup = None;
+
+    if deployed.is_file() && !symlink::is_symlink(deployed) {
+        let current = hash::file_hash(deployed)?;
+        if current != hash {
+            if synced.as_deref() != Some(current.as_str()) {
+                backup = Some(backup::save(deployed, "render")?);
+            }
+            history::write(deployed, rendered.as_bytes())?;
+        }
+    } else {
+        remove(deployed)?;
+        if let Some(parent) = deployed.parent() {
+            history::create_dir_all(parent)?;
+        }
+        history::write(deployed, rendered.as_bytes())?;
+    }
+
+    if synced.as_deref() != Some(hash.as_str()) {
+        config::set_synced_hash(&dotfile.target, Some(&hash))?;
+    }
+    Ok(backup)
+}
+
+/// Write an encrypted file's decrypted content at its original location and
+/// record both sides as in step. Returns false if it was already there.
+pub fn decrypt(dotfile: &DotFile) -> Result<bool> {
+    let (deployed, managed) = (&dotfile.source, managed_path(dotfile));
+    let plain = secret::decrypt_file(&managed)?;
+    let written = if deployed.is_file() && !symlink::is_symlink(deployed) {
+        let changed = fs::read(deployed)? != plain;
+        if changed {
+            history::write_private(deployed, &plain)?;
+        }
+        changed
+    } else {
+        remove(deployed)?;
+        if let Some(parent) = deployed.parent() {
+            history::create_dir_all(parent)?;
+        }
+        history::write_private(deployed, &plain)?;
+        true
+    };
+
+    let hash = synced_hash(dotfile.mode, deployed, &managed)?;
+    config::set_synced_hash(&dotfile.target, Some(&hash))?;
+    Ok(written)
+}
+
+/// Encrypt the decrypted file at an encrypted dotfile's original location into
+/// its managed copy, e.g. after it was edited there
+pub fn encrypt(dotfile: &DotFile) -> Result<()> {
+    let (deployed, managed) = (&dotfile.source, managed_path(dotfile));
+    if !deployed.is_file() || symlink::is_symlink(deployed) {
+        bail!("{} is not a regular file", deployed.display());
+    }
+    let sealed = secret::encrypt(&fs::read(deployed)?)?;
+    history::write(&managed, &sealed)?;
+
+    let hash = synced_hash(dotfile.mode, deployed, &managed)?;
+    config::set_synced_hash(&dotfile.target, Some(&hash))?;
+    Ok(())
+}
+
+// Remove whatever is at the original location and deploy the managed copy there
+fn redeploy(dotfile: &DotFile) -> Result<()> {
+    remove(&dotfile.source)?;
+    if let Some(parent) = dotfile.source.parent() {
+        history::create_dir_all(parent)?;
+    }
+    deploy(dotfile.mode, &managed_path(dotfile), &dotfile.source)?;
+    Ok(())
+}
+
+// Overwrite `to` with a copy of `from`
+fn replace(from: &Path, to: &Path) -> Result<()> {
+    if from.is_dir() || to.is_dir() {
+        remove(to)?;
+        history::copy_tree(from, to)?;
+    } else {
+        history::copy(from, to)?;
+    }
+    Ok(())
+}
+
+fn remove(path: &Path) -> io::Result<()> {
+    if path.is_dir() && !symlink::is_symlink(path) {
+        history::remove_tree(path)
+    } else if fs::symlink_metadata(path).is_ok() {
+        history::remove_file(path)
+    } else {
+        Ok(())
+    }
+}
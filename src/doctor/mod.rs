@@ -0,0 +1,322 @@
+// Consistency checks and repairs for the database and managed folders
+use crate::config;
+use crate::dotfile::{DeployMode, DotFile, DotFileStatus};
+use crate::symlink;
+use anyhow::{Result, anyhow};
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory inside a managed folder's .forge where untracked files are moved by --fix
+const ORPHANS_DIR: &str = "orphans";
+
+/// A problem found by the doctor
+#[derive(Debug)]
+pub enum Issue {
+    /// An untracked symlink pointing into a managed folder at a file that no longer exists
+    DanglingLink { link: PathBuf, dest: PathBuf },
+    /// A database row whose files are gone (or whose staging symlink is gone)
+    OrphanedRecord { dotfile: DotFile },
+    /// The managed copy exists but the symlink at the original location was never created
+    IncompleteLink { dotfile: DotFile },
+    /// A file in a managed folder that no database row refers to
+    UntrackedManagedFile { folder: PathBuf, path: PathBuf },
+    /// Several active rows for the same managed target; ids are newest first
+    DuplicateRecords { target: PathBuf, ids: Vec<i64> },
+}
+
+impl Issue {
+    /// Describe the problem for the report
+    pub fn describe(&self) -> String {
+        match self {
+            Issue::DanglingLink { link, dest } => format!(
+                "Dangling symlink {} → {} (target no longer exists)",
+                link.display(),
+                dest.display()
+            ),
+            Issue::OrphanedRecord { dotfile } => format!(
+                "Record with no files: {} → {}",
+                dotfile.source.display(),
+                dotfile.target.display()
+            ),
+            Issue::IncompleteLink { dotfile } => format!(
+                "Interrupted link: {} exists but {} is missing",
+                dotfile.target.display(),
+                dotfile.source.display()
+            ),
+            Issue::UntrackedManagedFile { path, .. } => {
+                format!("Managed file with no record: {}", path.display())
+            }
+            Issue::DuplicateRecords { target, ids } => {
+                format!("{} records for one target: {}", ids.len(), target.display())
+            }
+        }
+    }
+
+    /// Describe what --fix will do about the problem
+    pub fn fix_action(&self) -> &'static str {
+        match self {
+            Issue::DanglingLink { .. } => "remove the dangling symlink",
+            Issue::OrphanedRecord { .. } => "deactivate the record",
+            Issue::IncompleteLink { dotfile } => match dotfile.mode {
+                DeployMode::Symlink => "create the symlink and mark the record linked",
+                DeployMode::Hardlink => "create the hard link and mark the record linked",
+                DeployMode::Copy => "copy the file back and mark the record linked",
+                DeployMode::Template => "render the template and mark the record linked",
+                DeployMode::Encrypted => "decrypt the file and mark the record linked",
+            },
+            Issue::UntrackedManagedFile { .. } => "move the file to .forge/orphans",
+            Issue::DuplicateRecords { .. } => "keep the newest record and deactivate the rest",
+        }
+    }
+
+    /// Apply the safe default repair for this problem
+    pub fn fix(&self) -> Result<()> {
+        match self {
+            Issue::DanglingLink { link, .. } => {
+                fs::remove_file(link)?;
+            }
+            Issue::OrphanedRecord { dotfile } => {
+                // Clear out any leftover dangling links before dropping the record
+                for path in [&dotfile.target, &dotfile.source] {
+                    if symlink::is_symlink(path) && !path.exists() {
+                        fs::remove_file(path)?;
+                    }
+                }
+                config::deactivate_dotfile(&dotfile.target)?;
+            }
+            Issue::IncompleteLink { dotfile } => {
+                if let Some(parent) = dotfile.source.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                let managed = crate::deploy::managed_path(dotfile);
+                crate::deploy::deploy(dotfile.mode, &managed, &dotfile.source)?;
+                config::link_dotfile(&dotfile.source, &dotfile.target)?;
+                if !dotfile.mode.is_symlink() {
+                    let hash = crate::deploy::synced_hash(dotfile.mode, &dotfile.source, &managed)?;
+                    config::set_synced_hash(&dotfile.target, Some(&hash))?;
+                }
+            }
+            Issue::UntrackedManagedFile { folder, path } => {
+                if symlink::is_symlink(path) {
+                    // A leftover staging symlink holds no data of its own
+                    fs::remove_file(path)?;
+                } else {
+                    let relative = path.strip_prefix(folder)?;
+                    let dest = folder.join(".forge").join(ORPHANS_DIR).join(relative);
+                    if dest.exists() {
+                        return Err(anyhow!("{} already exists", dest.display()));
+                    }
+                    if let Some(parent) = dest.parent() {
+                        fs::create_dir_all(parent)?;
+                    }
+                    fs::rename(path, &dest)?;
+                }
+            }
+            Issue::DuplicateRecords { ids, .. } => {
+                for id in ids.iter().skip(1) {
+                    config::deactivate_dotfile_by_id(*id)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check a single database row for problems
+fn check_record(dotfile: &DotFile) -> Option<Issue> {
+    let source_present = dotfile.source.exists() || symlink::is_symlink(&dotfile.source);
+    let managed_present = dotfile.target.exists() && !symlink::is_symlink(&dotfile.target);
+
+    match dotfile.status {
+        DotFileStatus::Staged => {
+            if managed_present && !source_present && !dotfile.target.is_dir() {
+                // Copied into the forge folder but interrupted before the symlink was made
+                Some(Issue::IncompleteLink {
+                    dotfile: dotfile.clone(),
+                })
+            } else if !dotfile.target.exists() {
+                // Staging symlink is gone or dangles
+                Some(Issue::OrphanedRecord {
+                    dotfile: dotfile.clone(),
+                })
+            } else {
+                None
+            }
+        }
+        DotFileStatus::Linked => {
+            let managed = crate::deploy::managed_path(dotfile);
+            let managed_present = managed.exists() && !symlink::is_symlink(&managed);
+            if managed_present && !source_present && !managed.is_dir() {
+                Some(Issue::IncompleteLink {
+                    dotfile: dotfile.clone(),
+                })
+            } else if !managed_present && !dotfile.source.exists() {
+                Some(Issue::OrphanedRecord {
+                    dotfile: dotfile.clone(),
+                })
+            } else {
+                None
+            }
+        }
+        DotFileStatus::Unlinked => {
+            if !dotfile.target.exists() && !source_present {
+                Some(Issue::OrphanedRecord {
+                    dotfile: dotfile.clone(),
+                })
+            } else {
+                None
+            }
+        }
+    }
+}
+
+/// Find untracked files in a managed folder
+fn scan_managed_folder(folder: &Path, tracked: &HashSet<PathBuf>, issues: &mut Vec<Issue>) {
+    let walker = walkdir::WalkDir::new(folder)
+        .min_depth(1)
+        .follow_links(false)
+        .into_iter()
+        .filter_entry(|e| {
+            let name = e.file_name();
+            name != ".forge" && name != ".git"
+        });
+
+    for entry in walker.filter_map(|e| e.ok()) {
+        let path = entry.path();
+        if entry.file_type().is_dir() || path == crate::manifest::path(folder) {
+            continue;
+        }
+
+        // Files inside a tracked directory belong to that directory's record,
+        // and alternates to the record of the file they stand in for
+        if path.ancestors().any(|a| {
+            tracked.contains(a) || crate::alternate::base(a).is_some_and(|b| tracked.contains(&b))
+        }) {
+            continue;
+        }
+
+        issues.push(Issue::UntrackedManagedFile {
+            folder: folder.to_path_buf(),
+            path: path.to_path_buf(),
+        });
+    }
+}
+
+/// Find untracked symlinks next to tracked files that point into a managed folder at nothing
+fn scan_dangling_links(
+    dirs: &HashSet<PathBuf>,
+    folders: &[PathBuf],
+    tracked_sources: &HashSet<PathBuf>,
+    issues: &mut Vec<Issue>,
+) {
+    for dir in dirs {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if tracked_sources.contains(&path) || !symlink::is_symlink(&path) || path.exists() {
+                continue;
+            }
+
+            if let Some(dest) = symlink::resolve_link(&path)
+                && folders.iter().any(|f| dest.starts_with(f))
+            {
+                issues.push(Issue::DanglingLink { link: path, dest });
+            }
+        }
+    }
+}
+
+/// Inspect the database and every managed folder for inconsistencies
+pub fn diagnose() -> Result<Vec<Issue>> {
+    let dotfiles = config::get_dotfiles(None, None)?;
+    let folders: Vec<PathBuf> = config::get_managed_folders()?
+        .into_iter()
+        .map(|(_, path)| path)
+        .collect();
+
+    let mut issues = Vec::new();
+
+    // Fixing an orphaned record deactivates every row for its target, so
+    // duplicates of it are reported once, as orphaned
+    let mut records = Vec::new();
+    let mut orphaned = HashSet::new();
+    for dotfile in &dotfiles {
+        match check_record(dotfile) {
+            Some(Issue::OrphanedRecord { .. }) if !orphaned.insert(dotfile.target.clone()) => {}
+            Some(issue) => records.push(issue),
+            None => {}
+        }
+    }
+
+    for (target, ids) in config::get_duplicate_targets()? {
+        if !orphaned.contains(&target) {
+            issues.push(Issue::DuplicateRecords { target, ids });
+        }
+    }
+    issues.extend(records);
+
+    let tracked: HashSet<PathBuf> = dotfiles.iter().map(|d| d.target.clone()).collect();
+    for folder in &folders {
+        if folder.is_dir() {
+            scan_managed_folder(folder, &tracked, &mut issues);
+        }
+    }
+
+    let tracked_sources: HashSet<PathBuf> = dotfiles.iter().map(|d| d.source.clone()).collect();
+    let source_dirs: HashSet<PathBuf> = dotfiles
+        .iter()
+        .filter_map(|d| d.source.parent().map(Path::to_path_buf))
+        .collect();
+    scan_dangling_links(&source_dirs, &folders, &tracked_sources, &mut issues);
+
+    Ok(issues)
+}
+
+/// Report problems and optionally repair them. Returns true if nothing is left broken.
+pub fn run(fix: bool) -> bool {
+    let issues = match diagnose() {
+        Ok(issues) => issues,
+        Err(e) => {
+            eprintln!("Failed to run checks: {}", e);
+            return false;
+        }
+    };
+
+    if issues.is_empty() {
+        println!("No problems found.");
+        return true;
+    }
+
+    println!("Found {} problem(s):", issues.len());
+    let mut remaining = 0;
+
+    for issue in &issues {
+        println!("  - {}", issue.describe());
+        if !fix {
+            println!("    fix: {}", issue.fix_action());
+            remaining += 1;
+            continue;
+        }
+
+        match issue.fix() {
+            Ok(_) => println!("    fixed: {}", issue.fix_action()),
+            Err(e) => {
+                eprintln!("    failed to fix: {}", e);
+                remaining += 1;
+            }
+        }
+    }
+
+    if !fix {
+        println!("\nRun 'forge doctor --fix' to repair these problems.");
+    } else {
+        println!("\n{} fixed, {} failed", issues.len() - remaining, remaining);
+    }
+
+    remaining == 0
+}
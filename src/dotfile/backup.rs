@@ -1,16 +1,218 @@
+// Central backup store
+//
+// Before forge overwrites or deletes content on the user's behalf it copies
+// that content into ~/.forge/backups. Every backup gets a directory of its own,
+// named after the time it was taken, and keeps the original file name inside
+// it, so backups of config.toml and config.json, or two backups of the same
+// file, never collide. The backups table indexes them by original path.
+use crate::config::{self, BackupRecord};
+use crate::history;
+use crate::symlink;
+use crate::utils::hash;
+use anyhow::{Context, Result};
 use std::fs;
 use std::io;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
+const BACKUPS_DIR: &str = "backups";
+
+/// Root of the backup store
+pub fn store_dir() -> PathBuf {
+    config::get_config_dir().join(BACKUPS_DIR)
+}
+
+/// Absolute location of a backup's copy
+pub fn location(backup: &BackupRecord) -> PathBuf {
+    store_dir().join(&backup.stored)
+}
+
+/// A fresh location in the store for a backup of `original`
+pub fn new_location(original: &Path) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let slot = format!(
+        "{}-{}-{}",
+        chrono::Utc::now().format("%Y%m%d%H%M%S%6f"),
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    );
+    let name = original
+        .file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_else(|| "backup".into());
+    store_dir().join(slot).join(name)
+}
+
+/// Copy a file or directory into the store at `location` (see new_location)
+/// and index it. Copying again to the same location replaces the backup.
+pub fn save_at(original: &Path, location: &Path, reason: &str) -> Result<i64> {
+    store(original, original, location, reason)
+}
+
+/// Back up a file or directory, returning the id of the new backup
+pub fn save(original: &Path, reason: &str) -> Result<i64> {
+    save_at(original, &new_location(original), reason)
+}
+
+/// Back up `content` as a backup of `original`, for content that lives
+/// somewhere else than where it belongs (a managed copy of a deployed file)
+pub fn save_as(content: &Path, original: &Path, reason: &str) -> Result<i64> {
+    store(content, original, &new_location(original), reason)
+}
+
+fn store(content: &Path, original: &Path, location: &Path, reason: &str) -> Result<i64> {
+    if let Some(parent) = location.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let size = copy_into_store(content, location)
+        .with_context(|| format!("Failed to back up {}", content.display()))?;
+    let hash = hash::path_hash(location)?;
+
+    let stored = location.strip_prefix(store_dir()).unwrap_or(location);
+    Ok(config::add_backup(original, stored, reason, &hash, size)?)
+}
+
+// Copy without recording history: undoing a command must not take its
+// backups away. Symlinks inside a directory are copied as links.
+fn copy_into_store(from: &Path, to: &Path) -> io::Result<u64> {
+    if !from.is_dir() || symlink::is_symlink(from) {
+        return fs::copy(from, to);
+    }
+
+    let mut size = 0;
+    for entry in walkdir::WalkDir::new(from).sort_by_file_name() {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(from).unwrap_or(entry.path());
+        let dest = to.join(relative);
+
+        let file_type = entry.file_type();
+        if file_type.is_symlink() {
+            if !symlink::is_symlink(&dest) {
+                symlink::create_symlink(fs::read_link(entry.path())?, &dest)?;
+            }
+        } else if file_type.is_dir() {
+            fs::create_dir_all(&dest)?;
+        } else {
+            size += fs::copy(entry.path(), &dest)?;
+        }
+    }
+    Ok(size)
+}
+
+/// How a backup compares with what is at its original path now
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Identical,
+    Differs,
+    Missing,
+}
+
+pub fn compare(backup: &BackupRecord) -> Comparison {
+    if fs::symlink_metadata(&backup.original).is_err() {
+        return Comparison::Missing;
+    }
+    match hash::path_hash(&backup.original) {
+        Ok(hash) if hash == backup.hash => Comparison::Identical,
+        _ => Comparison::Differs,
+    }
+}
+
+/// Copy a backup back to `dest`. Whatever `dest` holds is backed up first;
+/// its backup id is returned. Changes go through history so they can be undone.
+pub fn restore(backup: &BackupRecord, dest: &Path) -> Result<Option<i64>> {
+    let from = location(backup);
+    if fs::symlink_metadata(&from).is_err() {
+        anyhow::bail!(
+            "Backup {} is missing from the store ({})",
+            backup.id,
+            from.display()
+        );
+    }
+
+    let replaced = if fs::symlink_metadata(dest).is_ok() {
+        let id = if symlink::is_symlink(dest) {
+            None
+        } else {
+            Some(save(dest, "restore")?)
+        };
+        if dest.is_dir() && !symlink::is_symlink(dest) {
+            history::remove_tree(dest)?;
+        } else {
+            history::remove_file(dest)?;
+        }
+        id
+    } else {
+        None
+    };
+
+    if let Some(parent) = dest.parent() {
+        history::create_dir_all(parent)?;
+    }
+    if from.is_dir() {
+        history::copy_tree(&from, dest)?;
+    } else {
+        history::copy(&from, dest)?;
+    }
+    Ok(replaced)
+}
+
+/// Backups to prune: all but the newest `keep` of each original path, and
+/// any taken more than `older_than_days` days ago
+pub fn prunable(keep: Option<usize>, older_than_days: Option<u32>) -> Result<Vec<BackupRecord>> {
+    let cutoff = older_than_days.map(|days| {
+        (chrono::Utc::now() - chrono::Duration::days(days.into()))
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string()
+    });
+
+    let mut seen: Vec<(PathBuf, usize)> = Vec::new();
+    let mut prunable = Vec::new();
+    for backup in config::get_backups(None)? {
+        let newer = match seen.iter_mut().find(|(path, _)| *path == backup.original) {
+            Some((_, count)) => {
+                *count += 1;
+                *count - 1
+            }
+            None => {
+                seen.push((backup.original.clone(), 1));
+                0
+            }
+        };
+        let surplus = keep.is_some_and(|keep| newer >= keep);
+        let expired = cutoff
+            .as_deref()
+            .is_some_and(|cutoff| *backup.created_at < *cutoff);
+        if surplus || expired {
+            prunable.push(backup);
+        }
+    }
+    Ok(prunable)
+}
+
+/// Delete a backup's copy and drop it from the index
+pub fn remove(backup: &BackupRecord) -> Result<()> {
+    let copy = location(backup);
+    let store = store_dir();
+    match copy.parent() {
+        Some(slot) if slot.starts_with(&store) && slot != store && slot.is_dir() => {
+            fs::remove_dir_all(slot)?
+        }
+        _ if copy.is_dir() && !symlink::is_symlink(&copy) => fs::remove_dir_all(&copy)?,
+        _ if fs::symlink_metadata(&copy).is_ok() => fs::remove_file(&copy)?,
+        _ => {}
+    }
+    config::remove_backup(backup.id)?;
+    Ok(())
+}
+
+/// Back up a file before it is replaced by a link
 #[allow(dead_code)]
 pub fn backup_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
-    // Implementation of backup_file
     let path = path.as_ref();
     if !path.exists() {
         return Ok(());
     }
 
-    let backup_path = path.with_extension("bak");
-    fs::copy(path, backup_path)?;
+    save(path, "link").map_err(io::Error::other)?;
     Ok(())
 }
@@ -1,9 +1,9 @@
 use crate::config;
 use crate::dotfile::DotFile;
 
-pub fn list_dotfiles(profile: Option<&str>) -> Vec<DotFile> {
+pub fn list_dotfiles(profile: Option<&str>, folder: Option<&str>) -> Vec<DotFile> {
     // Fetch dotfiles from database
-    match config::get_dotfiles(profile) {
+    match config::get_dotfiles(profile, folder) {
         Ok(dotfiles) => dotfiles,
         Err(e) => {
             eprintln!("Error fetching dotfiles: {}", e);
@@ -12,11 +12,14 @@ pub fn list_dotfiles(profile: Option<&str>) -> Vec<DotFile> {
     }
 }
 
-pub fn print_dotfiles(profile: Option<&str>) {
-    let dotfiles = list_dotfiles(profile);
+pub fn print_dotfiles(profile: Option<&str>, folder: Option<&str>) {
+    let dotfiles = list_dotfiles(profile, folder);
 
     let profile_str = profile.unwrap_or("all profiles");
-    println!("\nDotfiles ({})", profile_str);
+    match folder {
+        Some(folder) => println!("\nDotfiles ({}) in '{}'", profile_str, folder),
+        None => println!("\nDotfiles ({})", profile_str),
+    }
 
     if dotfiles.is_empty() {
         println!("  No dotfiles found");
@@ -30,12 +33,18 @@ pub fn print_dotfiles(profile: Option<&str>) {
         };
 
         let status = crate::utils::ui::format_dotfile_status(dotfile.status);
+        let mode = if dotfile.mode.is_symlink() {
+            String::new()
+        } else {
+            format!(" [{}]", dotfile.mode)
+        };
 
         println!(
-            "  [{}] {} → {}{}",
+            "  [{}] {} → {}{}{}",
             status,
             dotfile.source.display(),
             dotfile.target.display(),
+            mode,
             profile_info
         );
     }
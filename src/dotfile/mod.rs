@@ -1,15 +1,21 @@
 pub mod backup;
 pub mod link;
 pub mod list;
+pub mod status;
 pub mod unlink;
 
+use serde::{Deserialize, Serialize};
+use std::fmt;
 use std::path::PathBuf;
+use std::str::FromStr;
 
+#[derive(Debug, Clone)]
 pub struct DotFile {
     pub source: PathBuf,
     pub target: PathBuf,
     pub profile: Option<String>,
     pub status: DotFileStatus,
+    pub mode: DeployMode,
 }
 
 #[derive(Debug, PartialEq, Clone, Copy)]
@@ -19,6 +25,60 @@ pub enum DotFileStatus {
     Unlinked,
 }
 
+/// How a linked dotfile is deployed at its original location
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum DeployMode {
+    /// A symlink to the managed copy
+    #[default]
+    Symlink,
+    /// A hard link to the managed copy (same file, two names)
+    Hardlink,
+    /// A separate copy, kept in step with `forge sync`
+    Copy,
+    /// The managed file rendered with machine facts and user-defined values
+    Template,
+    /// Decrypted from an encrypted managed copy, readable only by the owner
+    Encrypted,
+}
+
+impl DeployMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeployMode::Symlink => "symlink",
+            DeployMode::Hardlink => "hardlink",
+            DeployMode::Copy => "copy",
+            DeployMode::Template => "template",
+            DeployMode::Encrypted => "encrypted",
+        }
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        *self == DeployMode::Symlink
+    }
+}
+
+impl fmt::Display for DeployMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for DeployMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "symlink" => Ok(DeployMode::Symlink),
+            "hardlink" => Ok(DeployMode::Hardlink),
+            "copy" => Ok(DeployMode::Copy),
+            "template" => Ok(DeployMode::Template),
+            "encrypted" => Ok(DeployMode::Encrypted),
+            other => Err(format!("unknown deploy mode '{}'", other)),
+        }
+    }
+}
+
 #[allow(dead_code)]
 impl DotFile {
     pub fn new(source: PathBuf, target: PathBuf, profile: Option<String>) -> Self {
@@ -27,6 +87,7 @@ impl DotFile {
             target,
             profile,
             status: DotFileStatus::Staged,
+            mode: DeployMode::Symlink,
         }
     }
 
@@ -41,9 +102,15 @@ impl DotFile {
             target,
             profile,
             status,
+            mode: DeployMode::Symlink,
         }
     }
 
+    pub fn with_mode(mut self, mode: DeployMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
     pub fn set_status(&mut self, status: DotFileStatus) {
         self.status = status;
     }
@@ -0,0 +1,279 @@
+use crate::alternate::{self, Selection};
+use crate::config;
+use crate::deploy::{self, Changed};
+use crate::dotfile::{DeployMode, DotFile, DotFileStatus};
+use crate::forgeignore::{IgnoreRules, Verdict};
+use crate::symlink;
+use anyhow::{Result, anyhow};
+use std::path::PathBuf;
+
+/// The actual on-disk state of a tracked dotfile, compared with its database record
+#[derive(Debug, PartialEq, Clone)]
+pub enum LinkState {
+    /// Everything on disk matches the database record
+    Ok,
+    /// The symlink at the original location is gone
+    LinkMissing,
+    /// The original location is a symlink, but to somewhere other than the managed copy
+    PointsElsewhere(PathBuf),
+    /// The symlink leads to another alternate of the managed file than the one
+    /// selected for this machine
+    OtherVariant(PathBuf),
+    /// The symlink at the original location was replaced by a regular file or directory
+    ReplacedByFile,
+    /// The managed copy in the forge folder is gone
+    ManagedCopyMissing,
+    /// The staging symlink in the forge folder is missing or points at nothing
+    StagingDangling,
+    /// A hard link that has become a separate file, e.g. after an editor
+    /// saved by replacing it
+    HardLinkBroken,
+    /// A hard link or copy whose content differs from the managed copy
+    OutOfSync(Changed),
+    /// A rendered template that differs from what its template renders to now
+    RenderOutdated(Changed),
+    /// A template that cannot be rendered, e.g. for an undefined value
+    RenderFailed(String),
+    /// A decrypted file or its encrypted copy changed since it was decrypted
+    SecretOutdated(Changed),
+}
+
+impl LinkState {
+    pub fn is_ok(&self) -> bool {
+        *self == LinkState::Ok
+    }
+
+    /// Short human-readable description of the state
+    pub fn describe(&self) -> String {
+        match self {
+            LinkState::Ok => "OK".to_string(),
+            LinkState::LinkMissing => "link missing".to_string(),
+            LinkState::PointsElsewhere(dest) => format!("points elsewhere ({})", dest.display()),
+            LinkState::OtherVariant(dest) => format!(
+                "links to another variant ({}), run 'forge link'",
+                dest.display()
+            ),
+            LinkState::ReplacedByFile => {
+                "replaced by regular file, run 'forge reconcile'".to_string()
+            }
+            LinkState::ManagedCopyMissing => "managed copy missing".to_string(),
+            LinkState::StagingDangling => "staging symlink dangling".to_string(),
+            LinkState::HardLinkBroken => "hard link broken, run 'forge sync'".to_string(),
+            LinkState::OutOfSync(Changed::Both) => {
+                "both sides changed since the last sync".to_string()
+            }
+            LinkState::OutOfSync(changed) => format!("{}, run 'forge sync'", changed.describe()),
+            LinkState::RenderOutdated(Changed::Managed) => {
+                "template changed, run 'forge render'".to_string()
+            }
+            LinkState::RenderOutdated(_) => {
+                "rendered file edited; move the change into the template".to_string()
+            }
+            LinkState::RenderFailed(e) => format!("cannot render: {}", e),
+            LinkState::SecretOutdated(Changed::Managed) => {
+                "encrypted copy changed, run 'forge link'".to_string()
+            }
+            LinkState::SecretOutdated(Changed::Deployed) => {
+                "decrypted file edited, run 'forge secret add' to encrypt it".to_string()
+            }
+            LinkState::SecretOutdated(Changed::Both) => {
+                "decrypted file and encrypted copy both changed".to_string()
+            }
+        }
+    }
+}
+
+/// Inspect the filesystem and work out the real state of a tracked dotfile
+pub fn check_dotfile(dotfile: &DotFile) -> LinkState {
+    match dotfile.status {
+        DotFileStatus::Staged => {
+            // While staged, the forge folder holds a symlink back to the original file
+            if !symlink::is_symlink(&dotfile.target) {
+                if dotfile.target.is_dir() && dotfile.source.is_dir() {
+                    // Directories are staged as a plain folder in the forge folder
+                    return LinkState::Ok;
+                }
+                return LinkState::StagingDangling;
+            }
+            if !dotfile.target.exists() {
+                return LinkState::StagingDangling;
+            }
+            LinkState::Ok
+        }
+        DotFileStatus::Linked if !dotfile.mode.is_symlink() => check_deployed_file(dotfile),
+        DotFileStatus::Linked => {
+            let managed = deploy::managed_path(dotfile);
+            let managed_exists = managed.exists() && !symlink::is_symlink(&managed);
+
+            if symlink::is_symlink(&dotfile.source) {
+                match symlink::resolve_link(&dotfile.source) {
+                    Some(dest) if dest == managed => {
+                        if managed_exists {
+                            LinkState::Ok
+                        } else {
+                            LinkState::ManagedCopyMissing
+                        }
+                    }
+                    Some(dest)
+                        if dest == dotfile.target
+                            || alternate::base(&dest).as_deref() == Some(&dotfile.target) =>
+                    {
+                        LinkState::OtherVariant(dest)
+                    }
+                    Some(dest) => LinkState::PointsElsewhere(dest),
+                    None => LinkState::LinkMissing,
+                }
+            } else if dotfile.source.exists() {
+                LinkState::ReplacedByFile
+            } else if !managed_exists {
+                LinkState::ManagedCopyMissing
+            } else {
+                LinkState::LinkMissing
+            }
+        }
+        DotFileStatus::Unlinked => {
+            if dotfile.target.exists() {
+                LinkState::Ok
+            } else {
+                LinkState::ManagedCopyMissing
+            }
+        }
+    }
+}
+
+// A linked dotfile deployed as a hard link, copy, rendered template or
+// decrypted file: both sides must exist and the deployed file must hold the
+// managed content
+fn check_deployed_file(dotfile: &DotFile) -> LinkState {
+    let managed = deploy::managed_path(dotfile);
+    if !managed.exists() || symlink::is_symlink(&managed) {
+        return LinkState::ManagedCopyMissing;
+    }
+    if symlink::is_symlink(&dotfile.source) {
+        return match symlink::resolve_link(&dotfile.source) {
+            Some(dest) => LinkState::PointsElsewhere(dest),
+            None => LinkState::LinkMissing,
+        };
+    }
+    if !dotfile.source.exists() {
+        return LinkState::LinkMissing;
+    }
+    if dotfile.mode == DeployMode::Hardlink && deploy::same_file(&dotfile.source, &managed) {
+        return LinkState::Ok;
+    }
+
+    if dotfile.mode == DeployMode::Template {
+        return match deploy::changed_side(dotfile) {
+            Ok(None) => LinkState::Ok,
+            Ok(Some(changed)) => LinkState::RenderOutdated(changed),
+            Err(e) => LinkState::RenderFailed(format!("{:#}", e)),
+        };
+    }
+
+    if dotfile.mode == DeployMode::Encrypted {
+        return match deploy::changed_side(dotfile) {
+            Ok(None) => LinkState::Ok,
+            Ok(Some(changed)) => LinkState::SecretOutdated(changed),
+            Err(_) => LinkState::SecretOutdated(Changed::Both),
+        };
+    }
+
+    match deploy::changed_side(dotfile) {
+        Ok(None) if dotfile.mode == DeployMode::Hardlink => LinkState::HardLinkBroken,
+        Ok(None) => LinkState::Ok,
+        Ok(Some(changed)) => LinkState::OutOfSync(changed),
+        Err(_) => LinkState::OutOfSync(Changed::Both),
+    }
+}
+
+/// Check every active dotfile against the filesystem and print a report.
+/// Returns the number of dotfiles that have drifted from their records, or
+/// an error if they could not be read from the database.
+pub fn print_status(profile: Option<&str>, folder: Option<&str>) -> Result<usize> {
+    let dotfiles = config::get_dotfiles(profile, folder)
+        .map_err(|e| anyhow!("Error fetching dotfiles: {}", e))?;
+
+    let profile_str = profile.unwrap_or("all profiles");
+    match folder {
+        Some(folder) => println!("\nStatus ({}) in '{}'", profile_str, folder),
+        None => println!("\nStatus ({})", profile_str),
+    }
+
+    if dotfiles.is_empty() {
+        println!("  No dotfiles found");
+        return Ok(0);
+    }
+
+    // Tracked files that the rules of their managed folder now exclude are
+    // not checked
+    let rules: Vec<IgnoreRules> = config::get_managed_folders()
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|(_, path)| match IgnoreRules::for_folder(path) {
+            Ok(rules) => Some(rules),
+            Err(e) => {
+                eprintln!("Invalid ignore rules: {}", e);
+                None
+            }
+        })
+        .collect();
+
+    let machine = alternate::Machine::current();
+    let mut drifted = 0;
+    let mut ignored = 0;
+    for dotfile in &dotfiles {
+        let verdict = rules
+            .iter()
+            .find(|rules| dotfile.target.starts_with(rules.root()))
+            .map(|rules| rules.check(&dotfile.target, dotfile.target.is_dir()));
+        if let Some(Verdict::Ignored(rule)) = verdict {
+            println!("  [ignored] {} ({})", dotfile.source.display(), rule);
+            ignored += 1;
+            continue;
+        }
+
+        let state = check_dotfile(dotfile);
+        if !state.is_ok() {
+            drifted += 1;
+        }
+
+        let mode = if dotfile.is_linked() && !dotfile.mode.is_symlink() {
+            format!(" ({})", dotfile.mode)
+        } else {
+            String::new()
+        };
+        println!(
+            "  [{}] {} → {}{}: {}",
+            crate::utils::ui::format_dotfile_status(dotfile.status),
+            dotfile.source.display(),
+            dotfile.target.display(),
+            mode,
+            state.describe()
+        );
+        match alternate::select(&dotfile.target, &machine) {
+            Selection::Plain => {}
+            Selection::Variant { path, reason } => println!(
+                "      variant: {} ({})",
+                path.file_name().unwrap_or_default().to_string_lossy(),
+                reason
+            ),
+            Selection::Fallback => {
+                println!("      variant: none matches this machine, using the plain file")
+            }
+        }
+    }
+
+    let checked = dotfiles.len() - ignored;
+    print!(
+        "\n{} dotfiles checked: {} ok, {} drifted",
+        checked,
+        checked - drifted,
+        drifted
+    );
+    if ignored > 0 {
+        print!(", {} ignored", ignored);
+    }
+    println!();
+
+    Ok(drifted)
+}
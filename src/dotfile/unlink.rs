@@ -20,10 +20,13 @@ pub fn unlink_file<P: AsRef<Path>>(target: P) -> io::Result<()> {
 
     fs::remove_file(target)?;
 
-    // Restore backup if it exists
-    let backup_path = target.with_extension("bak");
-    if backup_path.exists() {
-        fs::rename(backup_path, target)?;
+    // Restore the newest backup of the file, if any
+    let backups = crate::config::get_backups(Some(target)).map_err(io::Error::other)?;
+    if let Some(backup) = backups.first() {
+        let stored = crate::dotfile::backup::location(backup);
+        if stored.is_file() {
+            fs::copy(stored, target)?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,168 @@
+// Ignore rules
+//
+// Files can be kept out of a managed folder with gitignore syntax: globs,
+// `**`, `!` to re-include, a trailing `/` for directories only and a leading
+// `/` to anchor a pattern to the folder. Rules come from the `forgeignore`
+// file in the config directory, which applies to every folder, and then from
+// the folder's own `.forgeignore`, so the folder's rules win. Like git's
+// global excludes file, both are matched against paths relative to the
+// managed folder, i.e. where a file lives or would live once staged. As in
+// git, nothing inside an ignored directory can be re-included.
+use crate::config;
+use anyhow::{Result, anyhow};
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Name of the ignore file in a managed folder
+pub const FILE_NAME: &str = ".forgeignore";
+
+/// Name of the ignore file in the config directory that applies everywhere
+pub const GLOBAL_FILE_NAME: &str = "forgeignore";
+
+/// Ignored in every folder: forge's own metadata and the folder's ignore file
+const BUILT_IN: &[&str] = &[".forge/", "/.forgeignore"];
+
+/// The rule that decided whether a path is ignored
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    /// Ignore file the rule is in, or None for a built-in rule
+    pub file: Option<PathBuf>,
+    /// Line number in that file
+    pub line: usize,
+    pub pattern: String,
+}
+
+impl std::fmt::Display for Rule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.file {
+            Some(file) => write!(f, "{}:{}: {}", file.display(), self.line, self.pattern),
+            None => write!(f, "built-in: {}", self.pattern),
+        }
+    }
+}
+
+/// What the rules say about a path
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    /// No rule matches
+    NotIgnored,
+    /// Ignored by this rule
+    Ignored(Rule),
+    /// Matched by an ignore rule, then re-included by this `!` rule
+    Reincluded(Rule),
+}
+
+impl Verdict {
+    pub fn is_ignored(&self) -> bool {
+        matches!(self, Verdict::Ignored(_))
+    }
+}
+
+/// The ignore rules of one managed folder
+pub struct IgnoreRules {
+    root: PathBuf,
+    matcher: Gitignore,
+    /// Lines of each ignore file, to tell which line a match came from
+    lines: HashMap<PathBuf, Vec<String>>,
+}
+
+impl IgnoreRules {
+    /// The global rules followed by those of the folder at `root`
+    pub fn for_folder(root: &Path) -> Result<Self> {
+        let mut builder = GitignoreBuilder::new(root);
+        for line in BUILT_IN {
+            builder.add_line(None, line)?;
+        }
+
+        let mut lines = HashMap::new();
+        let files = [
+            config::get_config_dir().join(GLOBAL_FILE_NAME),
+            root.join(FILE_NAME),
+        ];
+        for file in files {
+            let content = match fs::read_to_string(&file) {
+                Ok(content) => content,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(anyhow!("Failed to read {}: {}", file.display(), e)),
+            };
+            for line in content.lines() {
+                builder
+                    .add_line(Some(file.clone()), line)
+                    .map_err(|e| anyhow!("{}: {}", file.display(), e))?;
+            }
+            lines.insert(file, content.lines().map(String::from).collect());
+        }
+
+        Ok(IgnoreRules {
+            root: root.to_path_buf(),
+            matcher: builder.build()?,
+            lines,
+        })
+    }
+
+    /// The rules of the active managed folder, or None without one
+    pub fn for_active_folder() -> Result<Option<Self>> {
+        match config::get_active_managed_folder()? {
+            Some((_, path)) => Ok(Some(Self::for_folder(&path)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The folder the rules are relative to
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Decide about a path in the folder, given absolute or relative to it
+    pub fn check(&self, path: &Path, is_dir: bool) -> Verdict {
+        let relative = path.strip_prefix(&self.root).unwrap_or(path);
+        if relative.as_os_str().is_empty() || relative.has_root() {
+            return Verdict::NotIgnored;
+        }
+
+        // An ignored directory hides everything inside it, whatever later
+        // rules say, so look at the parents first
+        let parents: Vec<&Path> = relative
+            .ancestors()
+            .skip(1)
+            .filter(|p| !p.as_os_str().is_empty())
+            .collect();
+        for parent in parents.into_iter().rev() {
+            if let Match::Ignore(glob) = self.matcher.matched(parent, true) {
+                return Verdict::Ignored(self.rule(glob));
+            }
+        }
+        match self.matcher.matched(relative, is_dir) {
+            Match::None => Verdict::NotIgnored,
+            Match::Ignore(glob) => Verdict::Ignored(self.rule(glob)),
+            Match::Whitelist(glob) => Verdict::Reincluded(self.rule(glob)),
+        }
+    }
+
+    /// Whether a path in the folder is ignored, given absolute or relative to it
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        self.check(path, is_dir).is_ignored()
+    }
+
+    fn rule(&self, glob: &ignore::gitignore::Glob) -> Rule {
+        let file = glob.from().map(Path::to_path_buf);
+        // Of identical lines the last one takes effect
+        let line = file
+            .as_ref()
+            .and_then(|file| self.lines.get(file))
+            .and_then(|lines| {
+                lines
+                    .iter()
+                    .rposition(|line| line.trim_end() == glob.original())
+            })
+            .map_or(0, |i| i + 1);
+        Rule {
+            file,
+            line,
+            pattern: glob.original().to_string(),
+        }
+    }
+}
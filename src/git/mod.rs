@@ -0,0 +1,151 @@
+// Git integration for managed folders
+//
+// A managed folder kept in git can be committed after the commands that
+// change it, and `forge sync` commits, pulls with rebase and pushes against a
+// remote. Everything goes through the git command line run in the folder, so
+// the user's own git configuration applies. Forge's metadata under .forge/ is
+// never committed.
+use anyhow::{Context, Result, anyhow, bail};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Everything in the managed folder except forge's own metadata
+const PATHSPEC: &[&str] = &["--", ".", ":(exclude).forge"];
+
+/// How many changed files a generated commit message names
+const NAMED_FILES: usize = 3;
+
+/// Run git in `folder` and return its output, or an error carrying what git
+/// printed on failure
+pub fn run(folder: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(folder)
+        .args(args)
+        .output()
+        .map_err(|e| anyhow!("Failed to run git: {}", e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("git {} failed: {}", args[0], stderr.trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .trim_end()
+        .to_string())
+}
+
+fn run_on_folder(folder: &Path, args: &[&str]) -> Result<String> {
+    let args: Vec<&str> = args.iter().chain(PATHSPEC).copied().collect();
+    run(folder, &args)
+}
+
+/// Whether `folder` is inside a git work tree
+pub fn is_repo(folder: &Path) -> bool {
+    run(folder, &["rev-parse", "--is-inside-work-tree"]).is_ok_and(|out| out == "true")
+}
+
+/// Whether the repository has a remote with this name
+pub fn has_remote(folder: &Path, remote: &str) -> bool {
+    run(folder, &["remote", "get-url", remote]).is_ok()
+}
+
+/// Make `folder` a git repository, unless it is inside one already, and make
+/// sure .forge/ is ignored. Returns whether a repository was created.
+pub fn init(folder: &Path) -> Result<bool> {
+    let created = !is_repo(folder);
+    if created {
+        run(folder, &["init", "--quiet"])?;
+    }
+
+    let ignore = folder.join(".gitignore");
+    let mut content = match fs::read_to_string(&ignore) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", ignore.display())),
+    };
+    let ignored = content
+        .lines()
+        .any(|line| matches!(line.trim(), ".forge" | ".forge/" | "/.forge" | "/.forge/"));
+    if !ignored {
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(".forge/\n");
+        fs::write(&ignore, content)
+            .with_context(|| format!("Failed to write {}", ignore.display()))?;
+    }
+    Ok(created)
+}
+
+/// Uncommitted changes in the managed folder, one `git status --short` line each
+pub fn uncommitted(folder: &Path) -> Result<Vec<String>> {
+    let status = run_on_folder(folder, &["status", "--porcelain", "--untracked-files=all"])?;
+    Ok(status.lines().map(String::from).collect())
+}
+
+/// Commit every change in the managed folder with a message naming the
+/// operation and the files it touched. Returns the message, or None if there
+/// was nothing to commit.
+pub fn commit_all(folder: &Path, operation: &str) -> Result<Option<String>> {
+    // Naming .forge in the pathspec makes `git add` fail when it is ignored,
+    // so it is added with the rest and taken out again
+    run(folder, &["add", "--all", "--", "."])?;
+    run(folder, &["reset", "--quiet", "--", ".forge"])?;
+    let changed = run_on_folder(folder, &["diff", "--cached", "--name-only", "--relative"])?;
+    let names: Vec<&str> = changed.lines().collect();
+    if names.is_empty() {
+        return Ok(None);
+    }
+
+    let message = commit_message(operation, &names);
+    run_on_folder(folder, &["commit", "--quiet", "--message", &message])?;
+    Ok(Some(message))
+}
+
+fn commit_message(operation: &str, names: &[&str]) -> String {
+    let mut message = format!(
+        "forge {}: {}",
+        operation,
+        names[..names.len().min(NAMED_FILES)].join(", ")
+    );
+    if names.len() > NAMED_FILES {
+        message.push_str(&format!(" and {} more", names.len() - NAMED_FILES));
+    }
+    message
+}
+
+/// Commit local changes, pull with rebase from `remote` and push back to it.
+/// A remote without the current branch yet, such as a new bare repository,
+/// is pushed to without pulling. Returns whether the pull brought in commits.
+pub fn sync(folder: &Path, remote: &str) -> Result<bool> {
+    if let Some(message) = commit_all(folder, "sync")? {
+        println!("Committed: {}", message);
+    }
+    let branch = run(folder, &["symbolic-ref", "--short", "HEAD"])?;
+    let head = || run(folder, &["rev-parse", "--verify", "--quiet", "HEAD"]).ok();
+    let before = head();
+
+    let remote_branch = run(folder, &["ls-remote", "--heads", remote, &branch])?;
+    if !remote_branch.is_empty() {
+        run(
+            folder,
+            &["pull", "--rebase", "--autostash", "--quiet", remote, &branch],
+        )
+        .map_err(|e| {
+            anyhow!(
+                "{:#}\nResolve the conflict with 'forge git status' and 'forge git rebase --continue', then sync again",
+                e
+            )
+        })?;
+        println!("Pulled from {} {}", remote, branch);
+    }
+
+    if head().is_some() {
+        run(
+            folder,
+            &["push", "--quiet", "--set-upstream", remote, &branch],
+        )?;
+        println!("Pushed to {} {}", remote, branch);
+    }
+    Ok(!remote_branch.is_empty() && head() != before)
+}
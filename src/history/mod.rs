@@ -0,0 +1,727 @@
+// Operation history for mutating commands, used by `forge undo`
+//
+// A command opens a recording with begin() and every filesystem and database
+// change made through this module (or through the Config mutators) is
+// appended to it. Files that are deleted or overwritten are first copied to a
+// per-operation backup directory so they can be put back. When the command
+// finishes, commit() stores the list of changes in the operations table.
+// Paths that are not valid UTF-8 are stored base64-encoded so undo acts on
+// exactly the same files.
+use crate::config::{self, DotfileRow};
+use crate::symlink;
+use crate::utils::{hash, path_utils};
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+const HISTORY_DIR: &str = "history";
+
+/// A single recorded change
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Change {
+    CreatedSymlink {
+        #[serde(with = "path_utils::serde_path")]
+        path: PathBuf,
+        #[serde(with = "path_utils::serde_path")]
+        dest: PathBuf,
+    },
+    RemovedSymlink {
+        #[serde(with = "path_utils::serde_path")]
+        path: PathBuf,
+        #[serde(with = "path_utils::serde_path")]
+        dest: PathBuf,
+    },
+    CreatedFile {
+        #[serde(with = "path_utils::serde_path")]
+        path: PathBuf,
+        hash: String,
+    },
+    RemovedFile {
+        #[serde(with = "path_utils::serde_path")]
+        path: PathBuf,
+        #[serde(with = "path_utils::serde_path")]
+        backup: PathBuf,
+    },
+    OverwroteFile {
+        #[serde(with = "path_utils::serde_path")]
+        path: PathBuf,
+        #[serde(with = "path_utils::serde_path")]
+        backup: PathBuf,
+        hash: String,
+    },
+    Renamed {
+        #[serde(with = "path_utils::serde_path")]
+        from: PathBuf,
+        #[serde(with = "path_utils::serde_path")]
+        to: PathBuf,
+    },
+    CreatedDir {
+        #[serde(with = "path_utils::serde_path")]
+        path: PathBuf,
+    },
+    RemovedDir {
+        #[serde(with = "path_utils::serde_path")]
+        path: PathBuf,
+    },
+    RowInserted {
+        id: i64,
+    },
+    RowChanged {
+        row: DotfileRow,
+    },
+    MovedFolder {
+        name: String,
+        #[serde(with = "path_utils::serde_path")]
+        from: PathBuf,
+        #[serde(with = "path_utils::serde_path")]
+        to: PathBuf,
+    },
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Changes {
+    changes: Vec<Change>,
+}
+
+struct Recorder {
+    command: String,
+    backup_dir: PathBuf,
+    changes: Vec<Change>,
+}
+
+static RECORDER: Mutex<Option<Recorder>> = Mutex::new(None);
+
+/// Start recording changes for a command
+pub fn begin(command: &str) {
+    let stamp = format!(
+        "{}-{}",
+        chrono::Utc::now().format("%Y%m%d%H%M%S%f"),
+        std::process::id()
+    );
+    let backup_dir = config::get_config_dir().join(HISTORY_DIR).join(stamp);
+
+    if let Ok(mut recorder) = RECORDER.lock() {
+        *recorder = Some(Recorder {
+            command: command.to_string(),
+            backup_dir,
+            changes: Vec::new(),
+        });
+    }
+}
+
+/// Stop recording and store the operation if anything changed.
+/// Returns the id of the stored operation.
+pub fn commit() -> Option<i64> {
+    let recorder = RECORDER.lock().ok()?.take()?;
+    if recorder.changes.is_empty() {
+        return None;
+    }
+
+    let changes = Changes {
+        changes: recorder.changes,
+    };
+    let serialized = match toml::to_string(&changes) {
+        Ok(s) => s,
+        Err(e) => {
+            eprintln!("Failed to record operation history: {}", e);
+            return None;
+        }
+    };
+
+    match config::record_operation(&recorder.command, &serialized) {
+        Ok(id) => Some(id),
+        Err(e) => {
+            eprintln!("Failed to record operation history: {}", e);
+            None
+        }
+    }
+}
+
+/// Undo the changes recorded so far by the current command, newest first, and
+/// drop them from the recording, to back out of a command that failed part-way.
+/// Keeps going past changes that cannot be undone and reports them at the end.
+pub fn rollback() -> Result<()> {
+    // Recording is paused meanwhile, so undoing is not itself recorded
+    let mut recorder = RECORDER
+        .lock()
+        .map_err(|_| anyhow!("history lock poisoned"))?
+        .take();
+    let changes = recorder
+        .as_mut()
+        .map(|r| std::mem::take(&mut r.changes))
+        .unwrap_or_default();
+
+    let failed: Vec<String> = changes
+        .iter()
+        .rev()
+        .filter_map(|change| {
+            change
+                .undo()
+                .err()
+                .map(|e| format!("{}: {}", change.describe(), e))
+        })
+        .collect();
+
+    if let Ok(mut current) = RECORDER.lock() {
+        *current = recorder;
+    }
+    if failed.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("Could not roll back: {}", failed.join("; ")))
+    }
+}
+
+/// Whether a command is currently being recorded
+pub fn is_recording() -> bool {
+    RECORDER.lock().map(|r| r.is_some()).unwrap_or(false)
+}
+
+/// Append a change to the current recording, if any
+pub fn record(change: Change) {
+    if let Ok(mut recorder) = RECORDER.lock()
+        && let Some(recorder) = recorder.as_mut()
+    {
+        recorder.changes.push(change);
+    }
+}
+
+/// Copy a file into the current operation's backup directory
+fn backup(path: &Path) -> io::Result<Option<PathBuf>> {
+    let dest = {
+        let recorder = RECORDER
+            .lock()
+            .map_err(|_| io::Error::other("history lock poisoned"))?;
+        match recorder.as_ref() {
+            Some(r) => r.backup_dir.join(r.changes.len().to_string()),
+            None => return Ok(None),
+        }
+    };
+
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::copy(path, &dest)?;
+    Ok(Some(dest))
+}
+
+// ---- Tracked filesystem operations ----
+
+/// Create a symlink at `link` pointing to `dest`
+pub fn create_symlink<P: AsRef<Path>, Q: AsRef<Path>>(dest: P, link: Q) -> io::Result<()> {
+    symlink::create_symlink(&dest, &link)?;
+    record(Change::CreatedSymlink {
+        path: link.as_ref().to_path_buf(),
+        dest: dest.as_ref().to_path_buf(),
+    });
+    Ok(())
+}
+
+/// Create a deployed symlink at `link` leading to `dest`, relative to the
+/// link's directory when relative links are on (see symlink::link_text)
+pub fn create_link<P: AsRef<Path>, Q: AsRef<Path>>(dest: P, link: Q) -> io::Result<()> {
+    create_symlink(symlink::link_text(dest, &link), link)
+}
+
+/// Create a hard link at `link` to the existing file `original`
+pub fn hard_link<P: AsRef<Path>, Q: AsRef<Path>>(original: P, link: Q) -> io::Result<()> {
+    let link = link.as_ref();
+    fs::hard_link(original, link)?;
+    if is_recording() {
+        record(Change::CreatedFile {
+            path: link.to_path_buf(),
+            hash: hash::file_hash(link)?,
+        });
+    }
+    Ok(())
+}
+
+/// Remove a file or symlink, backing up regular files first
+pub fn remove_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+
+    if symlink::is_symlink(path) {
+        let dest = fs::read_link(path)?;
+        fs::remove_file(path)?;
+        record(Change::RemovedSymlink {
+            path: path.to_path_buf(),
+            dest,
+        });
+        return Ok(());
+    }
+
+    let saved = if path.is_file() { backup(path)? } else { None };
+    fs::remove_file(path)?;
+    if let Some(backup) = saved {
+        record(Change::RemovedFile {
+            path: path.to_path_buf(),
+            backup,
+        });
+    }
+    Ok(())
+}
+
+/// Copy a file, backing up whatever it overwrites
+pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<u64> {
+    let to = to.as_ref();
+    let saved = if to.is_file() { backup(to)? } else { None };
+    let bytes = fs::copy(from, to)?;
+
+    if is_recording() {
+        let hash = hash::file_hash(to)?;
+        record(match saved {
+            Some(backup) => Change::OverwroteFile {
+                path: to.to_path_buf(),
+                backup,
+                hash,
+            },
+            None => Change::CreatedFile {
+                path: to.to_path_buf(),
+                hash,
+            },
+        });
+    }
+    Ok(bytes)
+}
+
+/// Write a file, backing up whatever it overwrites; a symlink in the way is
+/// replaced rather than followed
+pub fn write<P: AsRef<Path>>(path: P, contents: &[u8]) -> io::Result<()> {
+    write_file(path.as_ref(), contents, false)
+}
+
+/// Write a file that only its owner can read, like write(); an existing file
+/// is made private before anything is written to it
+pub fn write_private<P: AsRef<Path>>(path: P, contents: &[u8]) -> io::Result<()> {
+    write_file(path.as_ref(), contents, true)
+}
+
+fn write_file(path: &Path, contents: &[u8], private: bool) -> io::Result<()> {
+    if symlink::is_symlink(path) {
+        remove_file(path)?;
+    }
+    let saved = if path.is_file() { backup(path)? } else { None };
+    if private {
+        write_owner_only(path, contents)?;
+    } else {
+        fs::write(path, contents)?;
+    }
+
+    if is_recording() {
+        let hash = hash::file_hash(path)?;
+        record(match saved {
+            Some(backup) => Change::OverwroteFile {
+                path: path.to_path_buf(),
+                backup,
+                hash,
+            },
+            None => Change::CreatedFile {
+                path: path.to_path_buf(),
+                hash,
+            },
+        });
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn write_owner_only(path: &Path, contents: &[u8]) -> io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    if path.exists() {
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))?;
+    }
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?
+        .write_all(contents)
+}
+
+#[cfg(not(unix))]
+fn write_owner_only(path: &Path, contents: &[u8]) -> io::Result<()> {
+    fs::write(path, contents)
+}
+
+/// Rename a file or directory, backing up a file it would replace
+pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    if to.is_file() && !symlink::is_symlink(to) {
+        remove_file(to)?;
+    }
+    fs::rename(from, to)?;
+    record(Change::Renamed {
+        from: from.to_path_buf(),
+        to: to.to_path_buf(),
+    });
+    Ok(())
+}
+
+/// Create a directory and any missing parents
+pub fn create_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    let missing: Vec<PathBuf> = path
+        .ancestors()
+        .take_while(|a| !a.as_os_str().is_empty() && !a.exists())
+        .map(Path::to_path_buf)
+        .collect();
+
+    fs::create_dir_all(path)?;
+    for dir in missing.into_iter().rev() {
+        record(Change::CreatedDir { path: dir });
+    }
+    Ok(())
+}
+
+/// Remove an empty directory
+pub fn remove_dir<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    let path = path.as_ref();
+    fs::remove_dir(path)?;
+    record(Change::RemovedDir {
+        path: path.to_path_buf(),
+    });
+    Ok(())
+}
+
+/// Copy a directory tree, recording every directory, file and symlink created.
+/// Symlinks are copied as links. Entries already present are overwritten.
+pub fn copy_tree<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    create_dir_all(to)?;
+
+    for entry in walkdir::WalkDir::new(from).min_depth(1).sort_by_file_name() {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(from).unwrap_or(entry.path());
+        let dest = to.join(relative);
+
+        let file_type = entry.file_type();
+        if file_type.is_symlink() {
+            if !symlink::is_symlink(&dest) {
+                create_symlink(fs::read_link(entry.path())?, &dest)?;
+            }
+        } else if file_type.is_dir() {
+            create_dir_all(&dest)?;
+        } else {
+            copy(entry.path(), &dest)?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove a directory tree, backing up its files first
+pub fn remove_tree<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    for entry in walkdir::WalkDir::new(path.as_ref()).contents_first(true) {
+        let entry = entry?;
+        if entry.file_type().is_dir() {
+            remove_dir(entry.path())?;
+        } else {
+            remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+// ---- Undo ----
+
+impl Change {
+    /// Describe the change for history output
+    pub fn describe(&self) -> String {
+        match self {
+            Change::CreatedSymlink { path, dest } => {
+                format!("created symlink {} → {}", path.display(), dest.display())
+            }
+            Change::RemovedSymlink { path, dest } => {
+                format!("removed symlink {} → {}", path.display(), dest.display())
+            }
+            Change::CreatedFile { path, .. } => format!("created {}", path.display()),
+            Change::RemovedFile { path, .. } => format!("removed {}", path.display()),
+            Change::OverwroteFile { path, .. } => format!("overwrote {}", path.display()),
+            Change::Renamed { from, to } => {
+                format!("renamed {} → {}", from.display(), to.display())
+            }
+            Change::CreatedDir { path } => format!("created directory {}", path.display()),
+            Change::RemovedDir { path } => format!("removed directory {}", path.display()),
+            Change::RowInserted { id } => format!("inserted record {}", id),
+            Change::RowChanged { row } => {
+                format!("changed record {} ({})", row.id, row.target.display())
+            }
+            Change::MovedFolder { name, from, to } => format!(
+                "moved managed folder '{}' from {} to {}",
+                name,
+                from.display(),
+                to.display()
+            ),
+        }
+    }
+
+    /// Reverse this change, refusing if the path has changed since. The
+    /// reversal is itself recorded when a recording is open.
+    pub fn undo(&self) -> Result<()> {
+        match self {
+            Change::CreatedSymlink { path, dest } => {
+                if symlink::is_symlink(path) {
+                    if fs::read_link(path)? != *dest {
+                        return Err(anyhow!("{} now points elsewhere", path.display()));
+                    }
+                    remove_file(path)?;
+                }
+            }
+            Change::RemovedSymlink { path, dest } => {
+                if path.exists() || symlink::is_symlink(path) {
+                    return Err(anyhow!("{} already exists", path.display()));
+                }
+                ensure_parent(path)?;
+                create_symlink(dest, path)?;
+            }
+            Change::CreatedFile { path, hash } => {
+                if path.is_file() {
+                    if hash::file_hash(path)? != *hash {
+                        return Err(anyhow!("{} was modified since", path.display()));
+                    }
+                    remove_file(path)?;
+                }
+            }
+            Change::RemovedFile { path, backup } => {
+                if path.exists() || symlink::is_symlink(path) {
+                    return Err(anyhow!("{} already exists", path.display()));
+                }
+                ensure_parent(path)?;
+                copy(backup, path)?;
+            }
+            Change::OverwroteFile { path, backup, hash } => {
+                if path.is_file() && hash::file_hash(path)? != *hash {
+                    return Err(anyhow!("{} was modified since", path.display()));
+                }
+                ensure_parent(path)?;
+                copy(backup, path)?;
+            }
+            Change::Renamed { from, to } => {
+                if from.exists() || symlink::is_symlink(from) {
+                    return Err(anyhow!("{} already exists", from.display()));
+                }
+                rename(to, from)?;
+            }
+            Change::CreatedDir { path } => {
+                if path.is_dir() {
+                    remove_dir(path)?;
+                }
+            }
+            Change::RemovedDir { path } => {
+                create_dir_all(path)?;
+            }
+            Change::RowInserted { id } => config::delete_row(*id)?,
+            Change::RowChanged { row } => config::restore_row(row)?,
+            Change::MovedFolder { name, from, .. } => {
+                config::relocate_managed_folder(name, from)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The copy this change keeps of a file it deleted or overwrote
+    fn backup(&self) -> Option<&Path> {
+        match self {
+            Change::RemovedFile { backup, .. } | Change::OverwroteFile { backup, .. } => {
+                Some(backup)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn ensure_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => create_dir_all(parent),
+        None => Ok(()),
+    }
+}
+
+/// Parse the stored changes of an operation
+pub fn parse_changes(serialized: &str) -> Result<Vec<Change>> {
+    let changes: Changes = toml::from_str(serialized)?;
+    Ok(changes.changes)
+}
+
+/// Reverse an operation (the most recent one if `id` is None). The reversal
+/// is recorded as it goes, so if a change cannot be reversed, the ones
+/// already reversed are redone and the operation is left as it was.
+pub fn undo(id: Option<i64>) -> Result<()> {
+    let operation = config::get_operation(id)?.ok_or_else(|| anyhow!("No operation to undo"))?;
+    if operation.undone {
+        return Err(anyhow!(
+            "Operation {} has already been undone",
+            operation.id
+        ));
+    }
+
+    println!("Undoing operation {}: {}", operation.id, operation.command);
+    let changes = parse_changes(&operation.changes)?;
+
+    begin(&format!("undo {}", operation.id));
+    for change in changes.iter().rev() {
+        if let Err(e) = change.undo() {
+            let failed = format!("Could not undo '{}': {}", change.describe(), e);
+            let result = match rollback() {
+                Ok(()) => Err(anyhow!(
+                    "{}; operation {} is left as it was",
+                    failed,
+                    operation.id
+                )),
+                Err(redo) => Err(anyhow!(
+                    "{}; redoing the changes already reverted also failed: {:#}",
+                    failed,
+                    redo
+                )),
+            };
+            discard();
+            return result;
+        }
+        println!("  reverted: {}", change.describe());
+    }
+    discard();
+
+    config::mark_operation_undone(operation.id)?;
+    println!("Operation {} undone.", operation.id);
+    Ok(())
+}
+
+// Stop recording without storing anything, deleting the copies the
+// recording kept
+fn discard() {
+    let recorder = RECORDER.lock().ok().and_then(|mut r| r.take());
+    if let Some(recorder) = recorder
+        && recorder.backup_dir.is_dir()
+    {
+        let _ = fs::remove_dir_all(&recorder.backup_dir);
+    }
+}
+
+/// Operations to prune: all but the newest `keep`, and any recorded more
+/// than `older_than_days` days ago
+pub fn prunable(
+    keep: Option<usize>,
+    older_than_days: Option<u32>,
+) -> Result<Vec<config::OperationRecord>> {
+    let cutoff = older_than_days.map(|days| {
+        (chrono::Utc::now() - chrono::Duration::days(days.into()))
+            .format("%Y-%m-%d %H:%M:%S")
+            .to_string()
+    });
+
+    Ok(config::get_operations(usize::MAX)?
+        .into_iter()
+        .enumerate()
+        .filter(|(newer, operation)| {
+            keep.is_some_and(|keep| *newer >= keep)
+                || cutoff
+                    .as_deref()
+                    .is_some_and(|cutoff| *operation.created_at < *cutoff)
+        })
+        .map(|(_, operation)| operation)
+        .collect())
+}
+
+/// Forget an operation and delete the copies kept to undo it
+pub fn remove(operation: &config::OperationRecord) -> Result<()> {
+    let history_dir = config::get_config_dir().join(HISTORY_DIR);
+    let changes = parse_changes(&operation.changes)?;
+    let mut dirs: Vec<&Path> = changes
+        .iter()
+        .filter_map(|change| change.backup()?.parent())
+        .filter(|dir| dir.starts_with(&history_dir) && *dir != history_dir)
+        .collect();
+    dirs.dedup();
+    for dir in dirs {
+        if dir.is_dir() {
+            fs::remove_dir_all(dir)?;
+        }
+    }
+    config::delete_operation(operation.id)?;
+    Ok(())
+}
+
+/// Delete surplus and old operations. Returns false if anything failed.
+pub fn prune_history(keep: Option<usize>, older_than: Option<u32>, dry_run: bool) -> bool {
+    if keep.is_none() && older_than.is_none() {
+        eprintln!("Nothing to prune by: give --keep, --older-than or both");
+        return false;
+    }
+
+    let prunable = match prunable(keep, older_than) {
+        Ok(prunable) => prunable,
+        Err(e) => {
+            eprintln!("Failed to read operation history: {:#}", e);
+            return false;
+        }
+    };
+
+    let mut ok = true;
+    let mut pruned = 0;
+    for operation in &prunable {
+        if dry_run {
+            println!(
+                "Would prune: {} ({}, {})",
+                operation.id, operation.command, operation.created_at
+            );
+            continue;
+        }
+        match remove(operation) {
+            Ok(()) => {
+                println!(
+                    "Pruned: {} ({}, {})",
+                    operation.id, operation.command, operation.created_at
+                );
+                pruned += 1;
+            }
+            Err(e) => {
+                eprintln!("Failed to prune operation {}: {:#}", operation.id, e);
+                ok = false;
+            }
+        }
+    }
+
+    if dry_run {
+        println!("\nWould prune {} operation(s)", prunable.len());
+    } else {
+        println!("\nPruned {} operation(s)", pruned);
+    }
+    ok
+}
+
+/// Print recorded operations, newest first
+pub fn print_history(limit: usize, verbose: bool) {
+    let operations = match config::get_operations(limit) {
+        Ok(ops) => ops,
+        Err(e) => {
+            eprintln!("Failed to read operation history: {}", e);
+            return;
+        }
+    };
+
+    if operations.is_empty() {
+        println!("No operations recorded.");
+        return;
+    }
+
+    for op in operations {
+        println!(
+            "{:>4}  {}  {}{}",
+            op.id,
+            op.created_at,
+            op.command,
+            if op.undone { "  (undone)" } else { "" }
+        );
+        if verbose && let Ok(changes) = parse_changes(&op.changes) {
+            for change in changes {
+                println!("        {}", change.describe());
+            }
+        }
+    }
+}
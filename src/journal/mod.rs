@@ -0,0 +1,585 @@
+// Write-ahead journal for multi-step link/unlink operations
+//
+// Each operation is planned as a fixed list of steps and recorded under the
+// managed folder's .forge/journal/ before anything is touched. The journal is
+// rewritten after every completed step, so if forge is killed part-way through,
+// the next run can tell exactly how far it got. Once the content has been
+// safely copied and verified the operation is rolled forward, otherwise the
+// completed steps are undone.
+use crate::alternate;
+use crate::config;
+use crate::deploy;
+use crate::dotfile::{DeployMode, DotFile, backup};
+use crate::history;
+use crate::secret;
+use crate::symlink;
+use crate::template;
+use crate::utils::{hash, path_utils};
+use anyhow::{Result, anyhow};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const JOURNAL_DIR: &str = "journal";
+const TEMP_SUFFIX: &str = ".forge-tmp";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Operation {
+    Link,
+    Unlink,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    // link
+    RemoveStagingLink,
+    BackupManaged,
+    CopyToManaged,
+    RemoveOriginal,
+    CreateLink,
+    MarkLinked,
+    // unlink
+    CopyToTemp,
+    RemoveLink,
+    RenameTemp,
+    Deactivate,
+}
+
+const LINK_STEPS: &[Step] = &[
+    Step::RemoveStagingLink,
+    Step::BackupManaged,
+    Step::CopyToManaged,
+    Step::RemoveOriginal,
+    Step::CreateLink,
+    Step::MarkLinked,
+];
+
+const UNLINK_STEPS: &[Step] = &[
+    Step::CopyToTemp,
+    Step::RemoveLink,
+    Step::RenameTemp,
+    Step::Deactivate,
+];
+
+impl Operation {
+    pub fn name(self) -> &'static str {
+        match self {
+            Operation::Link => "link",
+            Operation::Unlink => "unlink",
+        }
+    }
+
+    fn steps(self) -> &'static [Step] {
+        match self {
+            Operation::Link => LINK_STEPS,
+            Operation::Unlink => UNLINK_STEPS,
+        }
+    }
+
+    /// Number of completed steps after which the data is safe in its new home
+    /// and an interrupted operation should be rolled forward rather than back
+    fn commit_point(self) -> usize {
+        match self {
+            Operation::Link => 3,
+            Operation::Unlink => 1,
+        }
+    }
+}
+
+/// A single journaled operation on one dotfile
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Journal {
+    pub operation: Operation,
+    /// Original location of the dotfile (where it is deployed once linked)
+    #[serde(with = "path_utils::serde_path")]
+    pub source: PathBuf,
+    /// Managed copy in the forge folder
+    #[serde(with = "path_utils::serde_path")]
+    pub target: PathBuf,
+    /// BLAKE3 hash of the content being moved, used to verify copies
+    pub hash: String,
+    /// How the dotfile is deployed at its original location
+    #[serde(default)]
+    pub mode: DeployMode,
+    /// Where in the backup store a pre-existing managed copy is saved before
+    /// being overwritten
+    #[serde(default, with = "path_utils::serde_path::option")]
+    pub backup: Option<PathBuf>,
+    /// Number of steps that have completed
+    pub completed: usize,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+/// Directory holding pending journals for a managed folder
+pub fn journal_dir(forge_path: &Path) -> PathBuf {
+    forge_path.join(".forge").join(JOURNAL_DIR)
+}
+
+// A directory is staged as an empty directory in the forge folder
+fn is_staging_dir(target: &Path) -> bool {
+    !symlink::is_symlink(target)
+        && fs::read_dir(target).is_ok_and(|mut entries| entries.next().is_none())
+}
+
+// Copy a file or directory tree, flushing a file to disk
+fn copy_path(from: &Path, to: &Path) -> Result<()> {
+    if from.is_dir() {
+        history::copy_tree(from, to)?;
+    } else {
+        history::copy(from, to)?;
+        fs::File::open(to)?.sync_all()?;
+    }
+    Ok(())
+}
+
+// Remove a file or directory tree
+fn remove_path(path: &Path) -> Result<()> {
+    if path.is_dir() && !symlink::is_symlink(path) {
+        history::remove_tree(path)?;
+    } else {
+        history::remove_file(path)?;
+    }
+    Ok(())
+}
+
+fn temp_path(source: &Path) -> PathBuf {
+    let mut name = source.as_os_str().to_owned();
+    name.push(TEMP_SUFFIX);
+    PathBuf::from(name)
+}
+
+impl Journal {
+    /// Plan an operation and persist it before any step runs
+    fn begin(forge_path: &Path, operation: Operation, dotfile: &DotFile) -> Result<Self> {
+        let (hash, backup) = match operation {
+            Operation::Link => {
+                if symlink::is_symlink(&dotfile.source)
+                    || !(dotfile.source.is_file() || dotfile.source.is_dir())
+                {
+                    return Err(anyhow!(
+                        "{} is not a regular file or directory",
+                        dotfile.source.display()
+                    ));
+                }
+                if dotfile.mode == DeployMode::Hardlink && dotfile.source.is_dir() {
+                    return Err(anyhow!(
+                        "{} is a directory and cannot be hard linked",
+                        dotfile.source.display()
+                    ));
+                }
+                if dotfile.mode == DeployMode::Template {
+                    // Fails for a directory or a placeholder that cannot be filled
+                    template::render_file(&dotfile.source)?;
+                }
+                if dotfile.mode == DeployMode::Encrypted {
+                    if dotfile.source.is_dir() {
+                        return Err(anyhow!(
+                            "{} is a directory and cannot be encrypted",
+                            dotfile.source.display()
+                        ));
+                    }
+                    // Ask for the passphrase before anything is moved
+                    secret::with_passphrase(|_| Ok(()))?;
+                }
+                let backup = if dotfile.target.exists()
+                    && !symlink::is_symlink(&dotfile.target)
+                    && !is_staging_dir(&dotfile.target)
+                {
+                    Some(backup::new_location(&dotfile.target))
+                } else {
+                    None
+                };
+                (hash::path_hash(&dotfile.source)?, backup)
+            }
+            Operation::Unlink => {
+                // What is restored is the variant deployed on this machine
+                let managed = alternate::resolve(&dotfile.target);
+                if !(managed.is_file() || managed.is_dir()) {
+                    return Err(anyhow!("Managed copy {} is missing", managed.display()));
+                }
+                // Removing the link comes after the commit point, so a file
+                // that replaced it must be caught before anything is copied
+                if dotfile.mode.is_symlink()
+                    && fs::symlink_metadata(&dotfile.source).is_ok()
+                    && !symlink::is_symlink(&dotfile.source)
+                {
+                    return Err(anyhow!(
+                        "{} is not a symlink (it was replaced by a regular file); run 'forge reconcile' first",
+                        dotfile.source.display()
+                    ));
+                }
+                if dotfile.mode == DeployMode::Template {
+                    // The rendered output is restored, not the template
+                    let hash = deploy::rendered_hash(&template::render_file(&managed)?);
+                    if dotfile.source.is_file()
+                        && !symlink::is_symlink(&dotfile.source)
+                        && hash::file_hash(&dotfile.source)? != hash
+                    {
+                        return Err(anyhow!(
+                            "{} has changes that are not in the template; move them into {} or run 'forge render' first",
+                            dotfile.source.display(),
+                            managed.display()
+                        ));
+                    }
+                    (hash, None)
+                } else if dotfile.mode == DeployMode::Encrypted {
+                    let hash = hash::content_hash(&secret::decrypt_file(&managed)?);
+                    if dotfile.source.is_file()
+                        && !symlink::is_symlink(&dotfile.source)
+                        && hash::file_hash(&dotfile.source)? != hash
+                    {
+                        return Err(anyhow!(
+                            "{} has changes that are not in the encrypted copy; run 'forge secret add {}' first",
+                            dotfile.source.display(),
+                            dotfile.source.display()
+                        ));
+                    }
+                    (hash, None)
+                } else {
+                    let hash = hash::path_hash(&managed)?;
+                    // A hard link or copy is replaced by the managed content, so
+                    // it must not hold changes of its own
+                    if !dotfile.mode.is_symlink()
+                        && fs::symlink_metadata(&dotfile.source).is_ok()
+                        && !symlink::is_symlink(&dotfile.source)
+                        && !deploy::same_file(&dotfile.source, &managed)
+                        && hash::path_hash(&dotfile.source)? != hash
+                    {
+                        return Err(anyhow!(
+                            "{} has changes that are not in the managed copy; run 'forge sync' first",
+                            dotfile.source.display()
+                        ));
+                    }
+                    (hash, None)
+                }
+            }
+        };
+
+        let dir = journal_dir(forge_path);
+        fs::create_dir_all(&dir)?;
+        let name = format!(
+            "{}-{}.toml",
+            chrono::Utc::now().format("%Y%m%d%H%M%S%f"),
+            std::process::id()
+        );
+
+        let journal = Journal {
+            operation,
+            source: dotfile.source.clone(),
+            target: dotfile.target.clone(),
+            hash,
+            mode: dotfile.mode,
+            backup,
+            completed: 0,
+            path: dir.join(name),
+        };
+        journal.save()?;
+        Ok(journal)
+    }
+
+    /// Load a pending journal from disk
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = fs::read_to_string(path)?;
+        let mut journal: Journal = toml::from_str(&content)?;
+        journal.path = path.to_path_buf();
+        Ok(journal)
+    }
+
+    /// Atomically write the journal and flush it to disk
+    fn save(&self) -> Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(toml::to_string_pretty(self)?.as_bytes())?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<()> {
+        fs::remove_file(&self.path)?;
+        Ok(())
+    }
+
+    /// Run the remaining steps; on failure undo whatever was done
+    fn run(&mut self) -> Result<()> {
+        let steps = self.operation.steps();
+        while self.completed < steps.len() {
+            if let Err(e) = self.apply(steps[self.completed]) {
+                if self.completed >= self.operation.commit_point() {
+                    // Data is already safe in its new home; leave the journal so the
+                    // next run can finish the job
+                    return Err(e);
+                }
+                return match self.roll_back() {
+                    Ok(_) => Err(e),
+                    Err(undo) => Err(anyhow!("{} (rollback also failed: {})", e, undo)),
+                };
+            }
+            self.completed += 1;
+            self.save()?;
+        }
+        self.finish()
+    }
+
+    /// Undo every completed step, plus the one that may have been in progress
+    fn roll_back(&mut self) -> Result<()> {
+        let steps = self.operation.steps();
+        let last = self.completed.min(steps.len() - 1);
+        for step in steps[..=last].iter().rev() {
+            self.undo(*step)?;
+        }
+        self.finish()
+    }
+
+    /// Bring an interrupted operation to a consistent state. Returns whether
+    /// it was rolled forward. An operation that cannot be finished, because
+    /// the files changed since it was interrupted, is rolled back instead of
+    /// being retried on every run.
+    pub fn recover(&mut self) -> Result<bool> {
+        if self.completed < self.operation.commit_point() {
+            self.roll_back()?;
+            return Ok(false);
+        }
+        match self.run() {
+            Ok(()) => Ok(true),
+            Err(e) => match self.roll_back() {
+                Ok(()) => {
+                    eprintln!(
+                        "Cannot finish the interrupted {}: {}",
+                        self.operation.name(),
+                        e
+                    );
+                    Ok(false)
+                }
+                Err(undo) => Err(anyhow!("{} (rollback also failed: {})", e, undo)),
+            },
+        }
+    }
+
+    fn verify(&self, path: &Path) -> Result<()> {
+        let actual = hash::path_hash(path)?;
+        if actual != self.hash {
+            return Err(anyhow!("Content of {} does not match", path.display()));
+        }
+        Ok(())
+    }
+
+    // Verify the managed copy, which for an encrypted file is what it decrypts to
+    fn verify_managed(&self, path: &Path) -> Result<()> {
+        if self.mode != DeployMode::Encrypted {
+            return self.verify(path);
+        }
+        if hash::content_hash(&secret::decrypt_file(path)?) != self.hash {
+            return Err(anyhow!("Content of {} does not match", path.display()));
+        }
+        Ok(())
+    }
+
+    // Every step is idempotent so it can be safely re-run during recovery
+    fn apply(&self, step: Step) -> Result<()> {
+        let source = &self.source;
+        let target = &self.target;
+        match step {
+            Step::RemoveStagingLink => {
+                if symlink::is_symlink(target) {
+                    history::remove_file(target)?;
+                } else if source.is_dir() && is_staging_dir(target) {
+                    history::remove_dir(target)?;
+                }
+            }
+            Step::BackupManaged => {
+                if let Some(backup) = &self.backup
+                    && target.exists()
+                    && !symlink::is_symlink(target)
+                {
+                    backup::save_at(target, backup, "link")?;
+                    remove_path(target)?;
+                }
+            }
+            Step::CopyToManaged => {
+                if let Some(parent) = target.parent() {
+                    history::create_dir_all(parent)?;
+                }
+                if self.mode == DeployMode::Encrypted {
+                    history::write(target, &secret::encrypt(&fs::read(source)?)?)?;
+                } else {
+                    copy_path(source, target)?;
+                }
+                self.verify_managed(target)?;
+            }
+            Step::RemoveOriginal => {
+                if source.exists() && !symlink::is_symlink(source) {
+                    self.verify_managed(target)?;
+                    remove_path(source)?;
+                }
+            }
+            Step::CreateLink => {
+                let managed = alternate::resolve(target);
+                if !deploy::is_deployed(self.mode, &managed, source) {
+                    // Anything left here is a partial deployment from an
+                    // interrupted run; the original is safe in the managed copy
+                    if fs::symlink_metadata(source).is_ok() {
+                        remove_path(source)?;
+                    }
+                    deploy::deploy(self.mode, &managed, source)?;
+                }
+            }
+            Step::MarkLinked => {
+                config::link_dotfile(source, target)?;
+                if !self.mode.is_symlink() {
+                    let managed = alternate::resolve(target);
+                    let hash = deploy::synced_hash(self.mode, source, &managed)?;
+                    config::set_synced_hash(target, Some(&hash))?;
+                }
+            }
+            Step::CopyToTemp => {
+                let tmp = temp_path(source);
+                let managed = alternate::resolve(target);
+                if self.mode == DeployMode::Template {
+                    history::write(&tmp, template::render_file(&managed)?.as_bytes())?;
+                } else if self.mode == DeployMode::Encrypted {
+                    history::write_private(&tmp, &secret::decrypt_file(&managed)?)?;
+                } else {
+                    copy_path(&managed, &tmp)?;
+                }
+                self.verify(&tmp)?;
+            }
+            Step::RemoveLink => {
+                if symlink::is_symlink(source) {
+                    history::remove_file(source)?;
+                } else if source.exists() {
+                    if self.mode.is_symlink() {
+                        return Err(anyhow!("{} is not a symlink", source.display()));
+                    }
+                    // The hard link or copy holds nothing the temporary copy lacks
+                    remove_path(source)?;
+                }
+            }
+            Step::RenameTemp => {
+                let tmp = temp_path(source);
+                if tmp.exists() {
+                    history::rename(&tmp, source)?;
+                }
+            }
+            Step::Deactivate => {
+                config::deactivate_dotfile(target)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn undo(&self, step: Step) -> Result<()> {
+        let source = &self.source;
+        let target = &self.target;
+        match step {
+            Step::RemoveStagingLink => {
+                if !target.exists() && !symlink::is_symlink(target) && source.exists() {
+                    if source.is_dir() {
+                        history::create_dir_all(target)?;
+                    } else {
+                        history::create_symlink(source, target)?;
+                    }
+                }
+            }
+            Step::BackupManaged => {
+                if let Some(backup) = &self.backup
+                    && backup.exists()
+                    && !target.exists()
+                {
+                    copy_path(backup, target)?;
+                }
+            }
+            Step::CopyToManaged => {
+                let copied =
+                    (target.is_file() && source.is_file()) || (target.is_dir() && source.is_dir());
+                if copied && !symlink::is_symlink(target) && !symlink::is_symlink(source) {
+                    remove_path(target)?;
+                }
+            }
+            Step::RemoveOriginal => {
+                if !source.exists() && !symlink::is_symlink(source) {
+                    if self.mode == DeployMode::Encrypted {
+                        history::write_private(source, &secret::decrypt_file(target)?)?;
+                    } else {
+                        copy_path(target, source)?;
+                    }
+                }
+            }
+            Step::CreateLink => {
+                if deploy::is_deployed(self.mode, &alternate::resolve(target), source) {
+                    remove_path(source)?;
+                }
+            }
+            Step::CopyToTemp => {
+                let tmp = temp_path(source);
+                if tmp.exists() {
+                    remove_path(&tmp)?;
+                }
+            }
+            Step::RemoveLink => {
+                if !source.exists() && !symlink::is_symlink(source) {
+                    deploy::deploy(self.mode, &alternate::resolve(target), source)?;
+                }
+            }
+            // Database updates are the last step and never need undoing
+            Step::MarkLinked | Step::RenameTemp | Step::Deactivate => {}
+        }
+        Ok(())
+    }
+}
+
+/// Move a staged file or directory into the forge folder and deploy it in its
+/// place as a symlink, hard link or copy
+pub fn link_file(forge_path: &Path, dotfile: &DotFile) -> Result<()> {
+    Journal::begin(forge_path, Operation::Link, dotfile)?.run()
+}
+
+/// Replace a deployed file with an independent copy of the managed file or
+/// directory and stop tracking it
+pub fn unlink_file(forge_path: &Path, dotfile: &DotFile) -> Result<()> {
+    Journal::begin(forge_path, Operation::Unlink, dotfile)?.run()
+}
+
+/// Finish or undo any operation left behind by an interrupted run
+pub fn recover_pending() {
+    let folders = match config::get_managed_folders() {
+        Ok(folders) => folders,
+        Err(_) => return,
+    };
+
+    for (_, folder) in folders {
+        let entries = match fs::read_dir(journal_dir(&folder)) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_none_or(|ext| ext != "toml") {
+                continue;
+            }
+
+            let result = Journal::load(&path).and_then(|mut journal| {
+                let forward = journal.recover()?;
+                Ok((journal, forward))
+            });
+
+            match result {
+                Ok((journal, forward)) => println!(
+                    "Recovered interrupted {} of {}: rolled {}",
+                    journal.operation.name(),
+                    journal.source.display(),
+                    if forward { "forward" } else { "back" }
+                ),
+                Err(e) => eprintln!(
+                    "Failed to recover interrupted operation {}: {}",
+                    path.display(),
+                    e
+                ),
+            }
+        }
+    }
+}
@@ -1,9 +1,24 @@
 // Library exports for forge-rs
 // This allows unit tests to import modules directly
 
+pub mod alternate;
 pub mod cli;
 pub mod config;
+pub mod deploy;
+pub mod doctor;
 pub mod dotfile;
+pub mod forgeignore;
+pub mod git;
+pub mod history;
+pub mod journal;
+pub mod manifest;
+pub mod reconcile;
+pub mod relocate;
 pub mod scanner;
+pub mod secret;
+pub mod selection;
+pub mod stow;
 pub mod symlink;
+pub mod template;
 pub mod utils;
+pub mod watch;
@@ -1,12 +1,30 @@
 use clap::{Parser, Subcommand};
+use dotfile::DeployMode;
+use selection::Kind;
 use std::path::PathBuf;
+use watch::Policy;
 
+mod alternate;
 mod cli;
 mod config;
+mod deploy;
+mod doctor;
 mod dotfile;
+mod forgeignore;
+mod git;
+mod history;
+mod journal;
+mod manifest;
+mod reconcile;
+mod relocate;
 mod scanner;
+mod secret;
+mod selection;
+mod stow;
 mod symlink;
+mod template;
 mod utils;
+mod watch;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -19,6 +37,10 @@ struct Cli {
     #[arg(short = 'v', long)]
     verbose: bool,
 
+    /// Use this managed folder instead of the active one for this command
+    #[arg(long, global = true, value_name = "NAME")]
+    folder: Option<String>,
+
     #[command(subcommand)]
     command: Option<Commands>,
 }
@@ -34,6 +56,10 @@ enum Commands {
         /// Directory to initialize (defaults to current directory)
         #[arg(short, long)]
         dir: Option<PathBuf>,
+
+        /// Also make the directory a git repository (forge's metadata is ignored)
+        #[arg(long)]
+        git: bool,
     },
     /// Unstage staged files by target path
     Unstage {
@@ -48,7 +74,8 @@ enum Commands {
     },
     Purge {
         /// Folder to purge (if omitted, purges everything)
-        folder: Option<PathBuf>,
+        #[arg(value_name = "FOLDER")]
+        path: Option<PathBuf>,
         /// Process subfolders recursively
         #[arg(short, long, default_value_t = true)]
         recursive: bool,
@@ -66,11 +93,118 @@ enum Commands {
         /// Maximum recursion depth for directories (overrides --recursive)
         #[arg(long)]
         depth: Option<usize>,
+
+        /// How the files will be deployed once linked
+        #[arg(long, value_enum)]
+        mode: Option<DeployMode>,
     },
     /// Create symlinks for staged/tracked files
     Link {
         /// Files to link (if not specified, links all staged files)
         files: Vec<PathBuf>,
+
+        /// Create symlinks relative to the directory they are in
+        #[arg(long)]
+        relative: bool,
+
+        /// Deploy as a symlink, hard link or copy (overrides the mode set when staging)
+        #[arg(long, value_enum)]
+        mode: Option<DeployMode>,
+    },
+    /// Copy changes between hard-linked or copied files and their managed copies, and
+    /// commit, pull and push the managed folder when it is a git repository
+    Sync {
+        /// Files to sync (defaults to every hard-linked or copied file)
+        files: Vec<PathBuf>,
+
+        /// Show what would be copied without touching anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Render linked templates again with the current values
+    Render {
+        /// Templates to render (defaults to every linked template)
+        files: Vec<PathBuf>,
+
+        /// Print what each template renders to without writing anything
+        #[arg(long)]
+        check: bool,
+    },
+    /// Encrypt files in the managed folder and edit or re-encrypt them
+    Secret {
+        #[command(subcommand)]
+        action: SecretActions,
+    },
+    /// Link everything declared in the managed folder's forge.toml
+    Apply {
+        /// Remove links to managed files that are no longer declared
+        #[arg(long)]
+        prune: bool,
+
+        /// Show what would change without touching anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Create symlinks relative to the directory they are in
+        #[arg(long)]
+        relative: bool,
+    },
+    /// Move the active managed folder and rewrite everything pointing at it
+    Relocate {
+        /// New location of the folder
+        new_path: PathBuf,
+
+        /// Show what would change without touching anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Track the links GNU Stow made for packages in a stow directory
+    AdoptStow {
+        /// The stow directory
+        stow_dir: PathBuf,
+
+        /// Packages to adopt (defaults to every package in the stow directory)
+        packages: Vec<String>,
+
+        /// Directory the packages are stowed into (defaults to the stow directory's parent)
+        #[arg(short, long)]
+        target: Option<PathBuf>,
+
+        /// Name for the managed folder (defaults to the stow directory name)
+        #[arg(short, long)]
+        name: Option<String>,
+
+        /// Show what would be adopted without changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Link a package (a top-level directory of the managed folder) into place as GNU Stow does
+    Stow {
+        /// Packages to stow
+        #[arg(required = true)]
+        packages: Vec<String>,
+
+        /// Directory to stow into (defaults to the managed folder's parent)
+        #[arg(short, long)]
+        target: Option<PathBuf>,
+
+        /// Show what would be linked without changing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Create symlinks relative to the directory they are in
+        #[arg(long)]
+        relative: bool,
+    },
+    /// Remove the links of stowed packages
+    Unstow {
+        /// Packages to unstow
+        #[arg(required = true)]
+        packages: Vec<String>,
+
+        /// Directory the packages were stowed into (defaults to the managed folder's parent)
+        #[arg(short, long)]
+        target: Option<PathBuf>,
     },
     /// Remove symlinks but keep files in forge folder
     Unlink {
@@ -109,10 +243,59 @@ enum Commands {
         #[arg(short, long)]
         profile: Option<String>,
     },
+    /// Check tracked files against the filesystem (exits non-zero on drift)
+    Status {
+        /// Filter by profile name
+        #[arg(short, long)]
+        profile: Option<String>,
+    },
+    /// Tell whether the ignore rules exclude a path, and which rule decides
+    /// (exits 0 if ignored, 1 if not)
+    CheckIgnore {
+        /// Path to check
+        path: PathBuf,
+    },
+    /// Resolve symlinks that applications replaced with regular files, and link them again
+    Reconcile {
+        /// Files to reconcile (defaults to every symlink replaced by a regular file)
+        files: Vec<PathBuf>,
+
+        /// Make the new content the managed copy, backing up the old one
+        #[arg(long, group = "resolution")]
+        absorb: bool,
+
+        /// Throw the new content away (it is backed up)
+        #[arg(long, group = "resolution")]
+        discard: bool,
+
+        /// Keep the new content next to the link as NAME.local
+        #[arg(long, group = "resolution")]
+        keep_both: bool,
+    },
+    /// Watch linked files and report, notify or repair drift as it happens
+    Watch {
+        /// What to do when a file drifts
+        #[arg(long, value_enum, default_value_t = Policy::Log)]
+        policy: Policy,
+    },
+    /// Find and optionally repair broken links and orphaned records
+    Doctor {
+        /// Repair each problem using a safe default action
+        #[arg(long)]
+        fix: bool,
+    },
     /// Switch to a profile
     Switch {
         /// Profile name
         name: String,
+
+        /// Create symlinks relative to the directory they are in
+        #[arg(long)]
+        relative: bool,
+
+        /// Deploy the profile's files as symlinks, hard links or copies
+        #[arg(long, value_enum, default_value_t)]
+        mode: DeployMode,
     },
     /// Create a new profile at a specific location
     New {
@@ -209,6 +392,68 @@ enum Commands {
         #[arg(short, long)]
         scope: Option<String>,
     },
+    /// Database maintenance
+    Db {
+        #[command(subcommand)]
+        action: DbActions,
+    },
+    /// Choose which files profile switching links, and test the choice on a directory
+    Rules {
+        #[command(subcommand)]
+        action: RuleActions,
+    },
+    /// Manage the registered managed folders
+    Folders {
+        #[command(subcommand)]
+        action: FolderActions,
+    },
+    /// Inspect and restore the copies forge keeps of content it overwrites or deletes
+    Backups {
+        #[command(subcommand)]
+        action: BackupActions,
+    },
+    /// Run git in the active managed folder
+    Git {
+        /// Arguments passed to git
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+    /// Show or change settings
+    Config {
+        /// Setting to show or change (all settings are listed without one)
+        key: Option<String>,
+        /// New value
+        value: Option<String>,
+        /// Remove a template value (var.NAME)
+        #[arg(long, requires = "key", conflicts_with = "value")]
+        unset: bool,
+    },
+    /// Reverse the last operation, or the operation with the given id
+    Undo {
+        /// Operation id (see 'forge history')
+        id: Option<i64>,
+    },
+    /// Show recorded operations, newest first
+    History {
+        /// Number of operations to show
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+        /// List the individual changes of each operation
+        #[arg(long)]
+        changes: bool,
+        /// Instead of listing, delete all but the newest N operations and
+        /// the copies kept to undo them
+        #[arg(long, value_name = "N")]
+        keep: Option<usize>,
+        /// Instead of listing, delete operations recorded more than DAYS days
+        /// ago and the copies kept to undo them
+        #[arg(long, value_name = "DAYS")]
+        older_than: Option<u32>,
+        /// With --keep or --older-than, show what would be deleted without
+        /// deleting anything
+        #[arg(long)]
+        dry_run: bool,
+    },
     /// Explain pack contents and installation plan
     Explain {
         /// Path to the pack archive (.zip file)
@@ -234,6 +479,144 @@ enum StartActions {
     },
 }
 
+#[derive(Subcommand)]
+enum DbActions {
+    /// Apply pending schema migrations (a backup of the database is taken first)
+    Migrate {
+        /// List pending migrations without applying them
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Store paths relative to $HOME, $XDG_CONFIG_HOME or their managed folder
+    Rebase {
+        /// Home directory of the machine the database came from; absolute paths
+        /// under it are moved under the current home directory
+        #[arg(long, value_name = "DIR")]
+        from_home: Option<PathBuf>,
+        /// Show the paths that would be rewritten without changing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum FolderActions {
+    /// List managed folders (* marks the active one)
+    List,
+    /// Make a folder the active one for later commands
+    Use {
+        /// Folder name
+        name: String,
+    },
+    /// Rename a managed folder
+    Rename {
+        /// Current folder name
+        old: String,
+        /// New folder name
+        new: String,
+    },
+    /// Forget a managed folder (its files are left in place)
+    Remove {
+        /// Folder name
+        name: String,
+    },
+    /// Mark a folder as the default, used when none has been selected
+    SetDefault {
+        /// Folder name
+        name: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum RuleActions {
+    /// List the selection rules
+    List,
+    /// Select the files that match a pattern
+    Include {
+        /// What the pattern is matched against
+        #[arg(value_enum)]
+        kind: Kind,
+        /// Glob, extension, regular expression or size limit (e.g. '>1M')
+        #[arg(allow_hyphen_values = true)]
+        pattern: String,
+    },
+    /// Never select the files that match a pattern (takes precedence over include rules)
+    Exclude {
+        /// What the pattern is matched against
+        #[arg(value_enum)]
+        kind: Kind,
+        /// Glob, extension, regular expression or size limit (e.g. '>1M')
+        #[arg(allow_hyphen_values = true)]
+        pattern: String,
+    },
+    /// Remove a rule
+    Remove {
+        /// Rule id (see 'forge rules list')
+        id: i64,
+    },
+    /// Show which files of a directory would be selected, and by which rule
+    Test {
+        /// Directory to test, such as a profile directory
+        dir: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum BackupActions {
+    /// List backups, newest first
+    List {
+        /// Only list backups of this path
+        path: Option<PathBuf>,
+    },
+    /// Show a backup and how it compares with its original path now
+    Show {
+        /// Backup id (see 'forge backups list')
+        id: i64,
+    },
+    /// Copy a backup back to its original path (what is there now is backed up first)
+    Restore {
+        /// Backup id (see 'forge backups list')
+        id: i64,
+        /// Restore to this path instead
+        #[arg(long, value_name = "PATH")]
+        to: Option<PathBuf>,
+    },
+    /// Delete old backups
+    Prune {
+        /// Keep only the newest N backups of each path
+        #[arg(long, value_name = "N")]
+        keep: Option<usize>,
+        /// Delete backups taken more than DAYS days ago
+        #[arg(long, value_name = "DAYS")]
+        older_than: Option<u32>,
+        /// Show what would be deleted without deleting anything
+        #[arg(long)]
+        dry_run: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum SecretActions {
+    /// Encrypt files: untracked ones are staged and linked in encrypted mode,
+    /// and the edited content of linked ones is encrypted again
+    Add {
+        /// Files to encrypt
+        #[arg(required = true)]
+        files: Vec<PathBuf>,
+    },
+    /// Decrypt a file into $EDITOR and encrypt it again when the editor exits
+    Edit {
+        /// Encrypted file (its original location or managed copy)
+        file: PathBuf,
+    },
+    /// Encrypt every encrypted file in the managed folder with a new passphrase or key file
+    Rekey {
+        /// Use this key file from now on instead of a passphrase
+        #[arg(long, value_name = "PATH")]
+        key_file: Option<PathBuf>,
+    },
+}
+
 #[derive(Subcommand)]
 enum ProfileActions {
     /// Create a new profile
@@ -260,19 +643,170 @@ fn main() {
         }
     }
 
+    // Override the active managed folder for this command only
+    // (db commands use no folder, and looking folders up would migrate the schema)
+    if let Some(folder) = &cli.folder
+        && !matches!(cli.command, Some(Commands::Db { .. }))
+    {
+        match config::get_managed_folders() {
+            Ok(folders) if folders.iter().any(|(name, _)| name == folder) => unsafe {
+                std::env::set_var(config::FOLDER_OVERRIDE_ENV, folder);
+            },
+            Ok(_) => {
+                eprintln!("No managed folder named '{}'", folder);
+                std::process::exit(1);
+            }
+            Err(e) => {
+                eprintln!("Failed to read managed folders: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Create relative symlinks for this command only
+    if matches!(
+        cli.command,
+        Some(
+            Commands::Link { relative: true, .. }
+                | Commands::Apply { relative: true, .. }
+                | Commands::Stow { relative: true, .. }
+                | Commands::Switch { relative: true, .. }
+        )
+    ) {
+        unsafe {
+            std::env::set_var(config::RELATIVE_LINKS_ENV, "1");
+        }
+    }
+
+    // Finish or undo anything a previous run left half-done
+    // (skipped for db commands, which must not migrate the schema behind the user's back)
+    if cli.command.is_some() && !matches!(cli.command, Some(Commands::Db { .. })) {
+        journal::recover_pending();
+    }
+
+    // Record what mutating commands change so they can be undone
+    let recorded = matches!(
+        cli.command,
+        Some(
+            Commands::Stage { .. }
+                | Commands::Link { .. }
+                | Commands::Sync { .. }
+                | Commands::Render { check: false, .. }
+                | Commands::Secret { .. }
+                | Commands::Apply { .. }
+                | Commands::Reconcile { .. }
+                | Commands::Relocate { .. }
+                | Commands::AdoptStow { .. }
+                | Commands::Stow { .. }
+                | Commands::Unstow { .. }
+                | Commands::Unlink { .. }
+                | Commands::Remove { .. }
+                | Commands::Delete { .. }
+                | Commands::Unstage { .. }
+                | Commands::Purge { .. }
+                | Commands::Switch { .. }
+                | Commands::Install { .. }
+                | Commands::Restore { .. }
+                | Commands::Backups {
+                    action: BackupActions::Restore { .. }
+                }
+        )
+    );
+    if recorded {
+        let args: Vec<String> = std::env::args_os()
+            .skip(1)
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        history::begin(&format!("forge {}", args.join(" ")));
+    }
+
     match &cli.command {
-        Some(Commands::Init { name, dir }) => {
-            cli::commands::init_command(name.as_deref(), dir.as_deref());
+        Some(Commands::Init { name, dir, git }) => {
+            cli::commands::init_command(name.as_deref(), dir.as_deref(), *git);
         }
         Some(Commands::Stage {
             files,
             recursive,
             depth,
+            mode,
+        }) => {
+            cli::commands::stage_command(files, *recursive, *depth, *mode);
+        }
+        Some(Commands::Link { files, mode, .. }) => {
+            cli::commands::link_command(files, *mode);
+        }
+        Some(Commands::Sync { files, dry_run }) => {
+            if !cli::commands::sync_command(files, *dry_run) {
+                history::commit();
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Render { files, check }) => {
+            if !cli::commands::render_command(files, *check) {
+                history::commit();
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Secret { action }) => {
+            let ok = match action {
+                SecretActions::Add { files } => cli::commands::secret::add(files),
+                SecretActions::Edit { file } => cli::commands::secret::edit(file),
+                SecretActions::Rekey { key_file } => {
+                    cli::commands::secret::rekey(key_file.as_deref())
+                }
+            };
+            if !ok {
+                history::commit();
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Apply { prune, dry_run, .. }) => {
+            if !cli::commands::apply_command(*prune, *dry_run) {
+                // Keep whatever was applied undoable before reporting the failure
+                history::commit();
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Relocate { new_path, dry_run }) => {
+            if !cli::commands::relocate_command(new_path, *dry_run) {
+                history::commit();
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::AdoptStow {
+            stow_dir,
+            packages,
+            target,
+            name,
+            dry_run,
+        }) => {
+            if !cli::commands::adopt_stow_command(
+                stow_dir,
+                packages,
+                target.as_deref(),
+                name.as_deref(),
+                *dry_run,
+            ) {
+                history::commit();
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Stow {
+            packages,
+            target,
+            dry_run,
+            ..
         }) => {
-            cli::commands::stage_command(files, *recursive, *depth);
+            if !cli::commands::stow_command(packages, target.as_deref(), *dry_run) {
+                history::commit();
+                std::process::exit(1);
+            }
         }
-        Some(Commands::Link { files }) => {
-            cli::commands::link_command(files);
+        Some(Commands::Unstow { packages, target }) => {
+            if !cli::commands::unstow_command(packages, target.as_deref()) {
+                history::commit();
+                std::process::exit(1);
+            }
         }
         Some(Commands::Unlink { files, yes }) => {
             cli::commands::unlink_command(files, *yes);
@@ -290,12 +824,22 @@ fn main() {
                 cli::commands::list_command(profile.as_deref());
             }
         }
-        Some(Commands::Switch { name }) => {
-            cli::commands::profile::switch(name);
+        Some(Commands::Status { profile }) => {
+            if !cli::commands::status_command(profile.as_deref()) {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Doctor { fix }) => {
+            if !cli::commands::doctor_command(*fix) {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Switch { name, mode, .. }) => {
+            cli::commands::profile::switch(name, *mode);
         }
         Some(Commands::New { profile, path }) => {
             // Initialize the directory as a forge managed folder with the profile name
-            cli::commands::init_command(Some(profile), Some(path.as_path()));
+            cli::commands::init_command(Some(profile), Some(path.as_path()), false);
         }
         Some(Commands::Profile { action }) => match action {
             ProfileActions::Create { name } => {
@@ -316,7 +860,7 @@ fn main() {
                     "Note: This command is deprecated, please use 'forge switch {}' instead",
                     name
                 );
-                cli::commands::profile::switch(name);
+                cli::commands::profile::switch(name, DeployMode::Symlink);
             }
         },
         Some(Commands::Start { action }) => match action {
@@ -368,6 +912,131 @@ fn main() {
         Some(Commands::Unpack { files, scope }) => {
             cli::commands::pack::unpack_files(files, scope.as_deref());
         }
+        Some(Commands::Db { action }) => match action {
+            DbActions::Migrate { dry_run } => {
+                if !cli::commands::db_migrate_command(*dry_run) {
+                    std::process::exit(1);
+                }
+            }
+            DbActions::Rebase { from_home, dry_run } => {
+                if !cli::commands::db_rebase_command(from_home.as_deref(), *dry_run) {
+                    std::process::exit(1);
+                }
+            }
+        },
+        Some(Commands::Rules { action }) => {
+            use selection::Action;
+            let ok = match action {
+                RuleActions::List => cli::commands::rules::list(),
+                RuleActions::Include { kind, pattern } => {
+                    cli::commands::rules::add(Action::Include, *kind, pattern)
+                }
+                RuleActions::Exclude { kind, pattern } => {
+                    cli::commands::rules::add(Action::Exclude, *kind, pattern)
+                }
+                RuleActions::Remove { id } => cli::commands::rules::remove(*id),
+                RuleActions::Test { dir } => cli::commands::rules::test(dir),
+            };
+            if !ok {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Folders { action }) => {
+            let ok = match action {
+                FolderActions::List => {
+                    cli::commands::folders::list();
+                    true
+                }
+                FolderActions::Use { name } => cli::commands::folders::use_folder(name),
+                FolderActions::Rename { old, new } => cli::commands::folders::rename(old, new),
+                FolderActions::Remove { name } => cli::commands::folders::remove(name),
+                FolderActions::SetDefault { name } => cli::commands::folders::set_default(name),
+            };
+            if !ok {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Backups { action }) => {
+            let ok = match action {
+                BackupActions::List { path } => cli::commands::backups::list(path.as_deref()),
+                BackupActions::Show { id } => cli::commands::backups::show(*id),
+                BackupActions::Restore { id, to } => {
+                    cli::commands::backups::restore(*id, to.as_deref())
+                }
+                BackupActions::Prune {
+                    keep,
+                    older_than,
+                    dry_run,
+                } => cli::commands::backups::prune(*keep, *older_than, *dry_run),
+            };
+            if !ok {
+                history::commit();
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::CheckIgnore { path }) => {
+            if !cli::commands::check_ignore_command(path) {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Reconcile {
+            files,
+            absorb,
+            discard,
+            keep_both,
+        }) => {
+            use reconcile::Resolution;
+            let resolution = if *absorb {
+                Some(Resolution::Absorb)
+            } else if *discard {
+                Some(Resolution::Discard)
+            } else if *keep_both {
+                Some(Resolution::KeepBoth)
+            } else {
+                None
+            };
+            if !cli::commands::reconcile_command(files, resolution) {
+                history::commit();
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Watch { policy }) => {
+            if !cli::commands::watch_command(*policy) {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Git { args }) => {
+            let code = cli::commands::git_command(args);
+            if code != 0 {
+                std::process::exit(code);
+            }
+        }
+        Some(Commands::Config { key, value, unset }) => {
+            if !cli::commands::config_command(key.as_deref(), value.as_deref(), *unset) {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::Undo { id }) => {
+            if let Err(e) = history::undo(*id) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::History {
+            limit,
+            changes,
+            keep,
+            older_than,
+            dry_run,
+        }) => {
+            if keep.is_some() || older_than.is_some() || *dry_run {
+                if !history::prune_history(*keep, *older_than, *dry_run) {
+                    std::process::exit(1);
+                }
+            } else {
+                history::print_history(*limit, *changes);
+            }
+        }
         Some(Commands::Explain {
             archive,
             install,
@@ -391,20 +1060,39 @@ fn main() {
         }) => {
             cli::commands::unstage_command(files, *recursive, *depth);
         }
-        Some(Commands::Purge { folder, recursive }) => {
-            let folder_path = match folder {
-                Some(f) => f.clone(),
+        Some(Commands::Purge { path, recursive }) => {
+            let folder_path = match path {
+                Some(f) => Some(f.clone()),
                 None => match config::get_active_managed_folder() {
-                    Ok(Some((_name, path))) => path,
-                    _ => {
-                        eprintln!("No folder specified and no active managed folder found.");
-                        return;
-                    }
+                    Ok(Some((_name, path))) => Some(path),
+                    _ => None,
                 },
             };
-            cli::commands::purge_command_safe(&folder_path, *recursive);
+            match folder_path {
+                Some(folder_path) => cli::commands::purge_command_safe(&folder_path, *recursive),
+                None => eprintln!("No folder specified and no active managed folder found."),
+            }
         }
     }
+
+    if recorded
+        && let Some(id) = history::commit()
+        && cli.verbose
+    {
+        println!("Recorded as operation {} (use 'forge undo' to reverse)", id);
+    }
+
+    // Commit what the command changed when the folder is kept in git
+    let operation = match cli.command {
+        Some(Commands::Stage { .. }) => Some("stage"),
+        Some(Commands::Link { .. }) => Some("link"),
+        Some(Commands::Unlink { .. }) => Some("unlink"),
+        Some(Commands::Remove { .. }) => Some("remove"),
+        _ => None,
+    };
+    if let Some(operation) = operation {
+        cli::commands::auto_commit(operation);
+    }
 }
 
 #[cfg(test)]
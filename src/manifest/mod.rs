@@ -0,0 +1,155 @@
+// Declarative manifest of a managed folder
+//
+// Every managed folder carries a forge.toml listing the files it manages and
+// where each one is deployed. Unlike the database it travels with the folder,
+// so a clone of the folder on another machine can be deployed with
+// `forge apply`. The database layer keeps it in step with the dotfile records,
+// so stage, link, unlink and undo all update it.
+use crate::dotfile::{DeployMode, DotFile};
+use crate::utils::path_utils;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// File name of the manifest, at the top of the managed folder
+pub const MANIFEST_FILE: &str = "forge.toml";
+
+const HEADER: &str = "# Files managed in this folder and where they are deployed.\n\
+                      # Kept up to date by forge; deploy with 'forge apply'.\n";
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(default, rename = "file")]
+    pub files: Vec<Entry>,
+}
+
+/// One managed file
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entry {
+    /// The managed copy, relative to the managed folder
+    #[serde(with = "path_utils::serde_path")]
+    pub path: PathBuf,
+    /// Where the file is deployed, relative to $HOME or $XDG_CONFIG_HOME where possible
+    #[serde(with = "path_utils::serde_path")]
+    pub target: PathBuf,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    /// How the file is deployed; symlinks are the default and left out
+    #[serde(default, skip_serializing_if = "DeployMode::is_symlink")]
+    pub mode: DeployMode,
+}
+
+impl Entry {
+    /// Absolute path of the managed copy
+    pub fn managed(&self, folder: &Path) -> PathBuf {
+        folder.join(&self.path)
+    }
+
+    /// Absolute deploy location on this machine. Hand-written entries may
+    /// also use `~/`.
+    pub fn deploy(&self) -> PathBuf {
+        path_utils::expand_tilde(path_utils::from_portable(
+            &path_utils::to_bytes(&self.target),
+            None,
+        ))
+    }
+}
+
+/// Location of the manifest of a managed folder
+pub fn path(folder: &Path) -> PathBuf {
+    folder.join(MANIFEST_FILE)
+}
+
+/// Whether a managed folder has a manifest yet
+pub fn exists(folder: &Path) -> bool {
+    path(folder).exists()
+}
+
+/// Read the manifest of a managed folder; a folder without one declares nothing
+pub fn load(folder: &Path) -> Result<Manifest> {
+    let file = path(folder);
+    if !file.exists() {
+        return Ok(Manifest::default());
+    }
+
+    let content =
+        fs::read_to_string(&file).with_context(|| format!("Failed to read {}", file.display()))?;
+    toml::from_str(&content).with_context(|| format!("Failed to parse {}", file.display()))
+}
+
+impl Manifest {
+    pub fn save(&self, folder: &Path) -> Result<()> {
+        let file = path(folder);
+        let content = format!("{}\n{}", HEADER, toml::to_string(self)?);
+        fs::write(&file, content).with_context(|| format!("Failed to write {}", file.display()))
+    }
+
+    /// The entry for a managed copy, given relative to the managed folder
+    pub fn find(&self, relative: &Path) -> Option<&Entry> {
+        self.files.iter().find(|entry| entry.path == relative)
+    }
+
+    // Add or replace the entry for a dotfile; returns whether anything changed
+    fn declare(&mut self, folder: &Path, dotfile: &DotFile) -> bool {
+        let Some(entry) = entry_for(folder, dotfile) else {
+            return false;
+        };
+
+        match self.files.iter_mut().find(|e| e.path == entry.path) {
+            Some(existing) if *existing == entry => false,
+            Some(existing) => {
+                *existing = entry;
+                true
+            }
+            None => {
+                self.files.push(entry);
+                true
+            }
+        }
+    }
+}
+
+// Manifest entry for a dotfile whose managed copy lies in `folder`
+fn entry_for(folder: &Path, dotfile: &DotFile) -> Option<Entry> {
+    let relative = dotfile.target.strip_prefix(folder).ok()?;
+    Some(Entry {
+        path: relative.to_path_buf(),
+        target: path_utils::from_bytes(&path_utils::to_portable(&dotfile.source, None)),
+        profile: dotfile.profile.clone(),
+        mode: dotfile.mode,
+    })
+}
+
+/// Write a manifest declaring exactly the given dotfiles
+pub fn write_all(folder: &Path, dotfiles: &[DotFile]) -> Result<()> {
+    let mut manifest = Manifest::default();
+    for dotfile in dotfiles {
+        manifest.declare(folder, dotfile);
+    }
+    manifest.save(folder)
+}
+
+/// Declare a dotfile in the manifest of its managed folder
+pub fn declare(folder: &Path, dotfile: &DotFile) -> Result<()> {
+    let mut manifest = load(folder)?;
+    if manifest.declare(folder, dotfile) {
+        manifest.save(folder)?;
+    }
+    Ok(())
+}
+
+/// Drop the entry for a managed copy from the manifest of its managed folder
+pub fn forget(folder: &Path, managed: &Path) -> Result<()> {
+    let Ok(relative) = managed.strip_prefix(folder) else {
+        return Ok(());
+    };
+
+    let mut manifest = load(folder)?;
+    let before = manifest.files.len();
+    manifest.files.retain(|entry| entry.path != relative);
+    if manifest.files.len() != before {
+        manifest.save(folder)?;
+    }
+    Ok(())
+}
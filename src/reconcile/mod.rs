@@ -0,0 +1,108 @@
+// Reconciling clobbered symlinks
+//
+// Some applications save their settings by writing a new file over the
+// symlink forge deployed, so later edits land outside the managed folder.
+// Reconciling compares the regular file with the managed copy, resolves the
+// difference one of three ways, then links the file again and marks its
+// record linked. Whatever is overwritten or removed is kept in the backup
+// store first.
+use crate::config;
+use crate::deploy;
+use crate::dotfile::status::{self, LinkState};
+use crate::dotfile::{DeployMode, DotFile, backup};
+use crate::history;
+use anyhow::{Result, bail};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// How to resolve a file that replaced its symlink
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Resolution {
+    /// Make its content the managed copy
+    Absorb,
+    /// Throw it away in favour of the managed copy
+    Discard,
+    /// Keep it next to the link under another name
+    KeepBoth,
+}
+
+/// Whether a linked dotfile's symlink was replaced by a regular file
+pub fn is_clobbered(dotfile: &DotFile) -> bool {
+    dotfile.is_linked()
+        && dotfile.mode == DeployMode::Symlink
+        && status::check_dotfile(dotfile) == LinkState::ReplacedByFile
+}
+
+/// A unified diff from the managed copy to the file that replaced its
+/// symlink, or a note when either is not text
+pub fn diff(dotfile: &DotFile) -> Result<String> {
+    let managed = deploy::managed_path(dotfile);
+    if !managed.exists() {
+        return Ok("The managed copy is missing\n".to_string());
+    }
+    let (old, new) = (fs::read(&managed)?, fs::read(&dotfile.source)?);
+    let (Ok(old), Ok(new)) = (String::from_utf8(old), String::from_utf8(new)) else {
+        return Ok("Binary files differ\n".to_string());
+    };
+    if old == new {
+        return Ok("Same content as the managed copy\n".to_string());
+    }
+    Ok(similar::TextDiff::from_lines(&old, &new)
+        .unified_diff()
+        .header(
+            &managed.display().to_string(),
+            &dotfile.source.display().to_string(),
+        )
+        .to_string())
+}
+
+/// Resolve a clobbered symlink, then link it again. Returns where the
+/// replacing file was kept with KeepBoth.
+pub fn resolve(dotfile: &DotFile, resolution: Resolution) -> Result<Option<PathBuf>> {
+    let managed = deploy::managed_path(dotfile);
+    if dotfile.source.is_dir() || managed.is_dir() {
+        bail!(
+            "{} is a directory; reconcile it by hand",
+            dotfile.source.display()
+        );
+    }
+    let managed_exists = managed.exists();
+    if !managed_exists && resolution != Resolution::Absorb {
+        bail!("the managed copy is missing; it can only be absorbed");
+    }
+
+    let mut kept = None;
+    match resolution {
+        Resolution::Absorb => {
+            if managed_exists {
+                backup::save_as(&managed, &dotfile.source, "reconcile")?;
+            }
+            history::copy(&dotfile.source, &managed)?;
+            history::remove_file(&dotfile.source)?;
+        }
+        Resolution::Discard => {
+            backup::save(&dotfile.source, "reconcile")?;
+            history::remove_file(&dotfile.source)?;
+        }
+        Resolution::KeepBoth => {
+            let side = side_path(&dotfile.source);
+            history::rename(&dotfile.source, &side)?;
+            kept = Some(side);
+        }
+    }
+    deploy::deploy(DeployMode::Symlink, &managed, &dotfile.source)?;
+    config::link_dotfile(&dotfile.source, &dotfile.target)?;
+    Ok(kept)
+}
+
+/// A free name next to `path` for the content that replaced its symlink
+fn side_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut side = path.with_file_name(format!("{}.local", name));
+    let mut n = 1;
+    while fs::symlink_metadata(&side).is_ok() {
+        side = path.with_file_name(format!("{}.local.{}", name, n));
+        n += 1;
+    }
+    side
+}
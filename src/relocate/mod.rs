@@ -0,0 +1,234 @@
+// Moving a managed folder
+//
+// Relocating a folder moves it on disk (copying across filesystems when a
+// rename is not possible), points its database entry at the new location,
+// rewrites rows that hold absolute paths into it, and replaces every symlink
+// that led into the old location, tracked or not, under the home directory
+// and the default deploy target. Every change goes through history, so if a
+// step or the final check fails, everything done so far is rolled back.
+use crate::alternate;
+use crate::config;
+use crate::deploy;
+use crate::dotfile::DotFile;
+use crate::history;
+use crate::symlink;
+use crate::utils::{hash, path_utils};
+use anyhow::{Result, anyhow, bail};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A symlink that leads into the folder being moved
+#[derive(Debug, Clone)]
+pub struct Relink {
+    pub link: PathBuf,
+    /// Where it leads now, in the old location
+    pub from: PathBuf,
+    /// Where it will lead, in the new location
+    pub to: PathBuf,
+}
+
+/// Everything relocating a folder will change
+#[derive(Debug)]
+pub struct Plan {
+    pub name: String,
+    pub from: PathBuf,
+    pub to: PathBuf,
+    /// Tracked files of the folder whose managed copy is present, as recorded
+    /// before the move
+    pub dotfiles: Vec<DotFile>,
+    pub relinks: Vec<Relink>,
+}
+
+/// Work out a relocation without touching anything
+pub fn plan(name: &str, from: &Path, to: &Path) -> Result<Plan> {
+    if !from.is_dir() {
+        bail!("Managed folder {} does not exist", from.display());
+    }
+    if fs::symlink_metadata(to).is_ok() {
+        bail!("{} already exists", to.display());
+    }
+    if to.starts_with(from) {
+        bail!("Cannot move {} into itself", from.display());
+    }
+
+    let dotfiles: Vec<DotFile> = config::get_dotfiles_in_folder(from, true)?
+        .into_iter()
+        .filter(|dotfile| dotfile.target.starts_with(from))
+        .filter(|dotfile| fs::symlink_metadata(deploy::managed_path(dotfile)).is_ok())
+        .collect();
+
+    let mut relinks = Vec::new();
+    for dotfile in &dotfiles {
+        if let Some(dest) = symlink::resolve_link(&dotfile.source)
+            && dest.starts_with(from)
+        {
+            relinks.push(Relink {
+                link: dotfile.source.clone(),
+                to: moved(&dest, from, to),
+                from: dest,
+            });
+        }
+    }
+
+    // Links nobody tracks, such as ones made by hand or by another tool,
+    // lead into the folder just the same
+    for root in deploy_roots() {
+        if root.starts_with(from) {
+            continue;
+        }
+        let walk = walkdir::WalkDir::new(&root)
+            .min_depth(1)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|entry| !entry.path().starts_with(from));
+        for entry in walk.filter_map(|entry| entry.ok()) {
+            if !entry.file_type().is_symlink()
+                || relinks.iter().any(|relink| relink.link == entry.path())
+            {
+                continue;
+            }
+            if let Some(dest) = symlink::resolve_link(entry.path())
+                && dest.starts_with(from)
+            {
+                relinks.push(Relink {
+                    link: entry.path().to_path_buf(),
+                    to: moved(&dest, from, to),
+                    from: dest,
+                });
+            }
+        }
+    }
+
+    // Links inside the folder with absolute text into it would dangle too
+    for entry in walkdir::WalkDir::new(from).min_depth(1).sort_by_file_name() {
+        let entry = entry?;
+        if !entry.file_type().is_symlink() {
+            continue;
+        }
+        let text = fs::read_link(entry.path())?;
+        if text.is_absolute() && text.starts_with(from) {
+            relinks.push(Relink {
+                link: moved(entry.path(), from, to),
+                to: moved(&text, from, to),
+                from: text,
+            });
+        }
+    }
+
+    Ok(Plan {
+        name: name.to_string(),
+        from: from.to_path_buf(),
+        to: to.to_path_buf(),
+        dotfiles,
+        relinks,
+    })
+}
+
+// Where dotfiles are deployed: the home directory and the default target
+// of bulk linking
+fn deploy_roots() -> Vec<PathBuf> {
+    let mut roots: Vec<PathBuf> = dirs::home_dir().into_iter().collect();
+    let default_path = path_utils::expand_tilde(config::read_default_path());
+    if default_path.is_dir() && !roots.iter().any(|root| default_path.starts_with(root)) {
+        roots.push(default_path);
+    }
+    roots
+}
+
+fn moved(path: &Path, from: &Path, to: &Path) -> PathBuf {
+    match path.strip_prefix(from) {
+        Ok(rest) => to.join(rest),
+        Err(_) => path.to_path_buf(),
+    }
+}
+
+/// Carry out a plan. On failure, whatever was done is rolled back before the
+/// error is returned.
+pub fn relocate(plan: &Plan) -> Result<()> {
+    match apply(plan) {
+        Ok(()) => Ok(()),
+        Err(e) => match history::rollback() {
+            Ok(()) => Err(e.context("Relocation rolled back")),
+            Err(rollback) => Err(anyhow!("{:#}; rolling back also failed: {:#}", e, rollback)),
+        },
+    }
+}
+
+fn apply(plan: &Plan) -> Result<()> {
+    move_folder(&plan.from, &plan.to)?;
+
+    if !config::relocate_managed_folder(&plan.name, &plan.to)? {
+        bail!("No managed folder named '{}'", plan.name);
+    }
+
+    for relink in &plan.relinks {
+        // Relative links stay relative
+        let relative = fs::read_link(&relink.link)?.is_relative();
+        history::remove_file(&relink.link)?;
+        let text = if relative {
+            symlink::relative_link_text(&relink.to, &relink.link)
+        } else {
+            relink.to.clone()
+        };
+        history::create_symlink(text, &relink.link)?;
+    }
+
+    verify(plan)
+}
+
+// Rename the folder, or copy it and remove the original across filesystems
+fn move_folder(from: &Path, to: &Path) -> Result<()> {
+    if let Some(parent) = to.parent() {
+        history::create_dir_all(parent)?;
+    }
+
+    match history::rename(from, to) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            let before = hash::path_hash(from)?;
+            history::copy_tree(from, to)?;
+            if hash::path_hash(to)? != before {
+                bail!(
+                    "Copy of {} at {} does not match",
+                    from.display(),
+                    to.display()
+                );
+            }
+            history::remove_tree(from)?;
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Every managed copy is at its new place, every rewritten link leads to it,
+// and the database resolves the folder's files to the new location
+fn verify(plan: &Plan) -> Result<()> {
+    if fs::symlink_metadata(&plan.from).is_ok() {
+        bail!("{} is still there after the move", plan.from.display());
+    }
+
+    for relink in &plan.relinks {
+        if symlink::resolve_link(&relink.link).as_deref() != Some(relink.to.as_path()) {
+            bail!(
+                "{} does not lead to {} after the move",
+                relink.link.display(),
+                relink.to.display()
+            );
+        }
+    }
+
+    let recorded = config::get_dotfiles_in_folder(&plan.to, true)?;
+    for dotfile in &plan.dotfiles {
+        let target = moved(&dotfile.target, &plan.from, &plan.to);
+        let managed = alternate::resolve(&target);
+        if fs::symlink_metadata(&managed).is_err() {
+            bail!("{} is missing after the move", managed.display());
+        }
+        if !recorded.iter().any(|d| d.target == target) {
+            bail!("The record for {} was not moved", target.display());
+        }
+    }
+    Ok(())
+}
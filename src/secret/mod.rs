@@ -0,0 +1,258 @@
+// Encrypted dotfiles
+//
+// A dotfile deployed in encrypted mode is kept in the managed folder only as
+// ciphertext, so the folder can be committed or shared without exposing it,
+// and is decrypted at its original location with mode 0600. Files are
+// sealed with AES-256-GCM under a key derived from a passphrase (or the
+// content of a key file) with Argon2id:
+//
+//   magic (14) | memory KiB, passes, lanes (3 x u32 LE) | salt (16)
+//   | nonce (12) | ciphertext + tag
+//
+// The passphrase is taken from the file named by the `secret_key_file`
+// setting, else from FORGE_PASSPHRASE, else asked for on the terminal. It is
+// checked against the files already encrypted before it encrypts anything.
+use crate::config;
+use crate::dotfile::DeployMode;
+use crate::utils::ui;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, rand_core::RngCore};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use anyhow::{Context, Result, anyhow, bail};
+use argon2::{Algorithm, Argon2, Params, Version};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Environment variable holding the passphrase
+pub const PASSPHRASE_ENV: &str = "FORGE_PASSPHRASE";
+
+const MAGIC: &[u8] = b"FORGE-SECRET\0\x01";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const HEADER_LEN: usize = MAGIC.len() + 12 + SALT_LEN + NONCE_LEN;
+
+/// Argon2 cost: memory in KiB, passes and lanes
+type Cost = [u32; 3];
+
+const COST: Cost = [
+    Params::DEFAULT_M_COST,
+    Params::DEFAULT_T_COST,
+    Params::DEFAULT_P_COST,
+];
+
+/// What unlocks encrypted files
+pub struct Passphrase {
+    secret: Vec<u8>,
+    /// Derived keys by salt and cost, as derivation is deliberately expensive
+    keys: HashMap<([u8; SALT_LEN], Cost), [u8; 32]>,
+    /// Salt used for everything this passphrase encrypts in one run
+    salt: [u8; SALT_LEN],
+    /// Whether it was typed on the terminal
+    typed: bool,
+    /// Whether it was checked before anything was encrypted with it
+    confirmed: bool,
+}
+
+impl Passphrase {
+    pub fn new(secret: impl Into<Vec<u8>>) -> Result<Self> {
+        let secret = secret.into();
+        if secret.is_empty() {
+            bail!("The passphrase must not be empty");
+        }
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        Ok(Passphrase {
+            secret,
+            keys: HashMap::new(),
+            salt,
+            typed: false,
+            confirmed: false,
+        })
+    }
+
+    /// The content of a key file, without a trailing newline
+    pub fn from_key_file(path: &Path) -> Result<Self> {
+        let mut secret = fs::read(path)
+            .with_context(|| format!("Failed to read key file {}", path.display()))?;
+        while secret.last().is_some_and(|b| *b == b'\n' || *b == b'\r') {
+            secret.pop();
+        }
+        if secret.is_empty() {
+            bail!("Key file {} is empty", path.display());
+        }
+        Passphrase::new(secret)
+    }
+
+    fn key(&mut self, salt: [u8; SALT_LEN], cost: Cost) -> Result<[u8; 32]> {
+        if let Some(key) = self.keys.get(&(salt, cost)) {
+            return Ok(*key);
+        }
+        let [memory, passes, lanes] = cost;
+        // Not more than 1 GiB, or 16 times the passes and lanes new files use,
+        // whatever a file's header asks for
+        if memory > 1 << 20 {
+            bail!("key derivation would need {} KiB of memory", memory);
+        }
+        if passes > COST[1] * 16 || lanes > COST[2] * 16 {
+            bail!(
+                "key derivation would need {} passes over {} lanes",
+                passes,
+                lanes
+            );
+        }
+        let params = Params::new(memory, passes, lanes, Some(32))
+            .map_err(|e| anyhow!("invalid key derivation cost: {}", e))?;
+        let mut key = [0; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(&self.secret, &salt, &mut key)
+            .map_err(|e| anyhow!("Key derivation failed: {}", e))?;
+        self.keys.insert((salt, cost), key);
+        Ok(key)
+    }
+
+    /// Encrypt `plain` into the file format above
+    pub fn encrypt(&mut self, plain: &[u8]) -> Result<Vec<u8>> {
+        let salt = self.salt;
+        let key = self.key(salt, COST)?;
+        let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let sealed = cipher
+            .encrypt(&nonce, plain)
+            .map_err(|_| anyhow!("Encryption failed"))?;
+
+        let mut out = Vec::with_capacity(HEADER_LEN + sealed.len());
+        out.extend_from_slice(MAGIC);
+        for value in COST {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.extend_from_slice(&salt);
+        out.extend_from_slice(&nonce);
+        out.extend_from_slice(&sealed);
+        Ok(out)
+    }
+
+    /// Decrypt the content of an encrypted file; `path` is only for messages
+    pub fn decrypt(&mut self, data: &[u8], path: &Path) -> Result<Vec<u8>> {
+        if !is_encrypted(data) || data.len() < HEADER_LEN {
+            bail!("{} is not an encrypted forge file", path.display());
+        }
+        let (header, sealed) = data.split_at(HEADER_LEN);
+        let (cost, rest) = header[MAGIC.len()..].split_at(12);
+        let cost: Cost = std::array::from_fn(|i| {
+            u32::from_le_bytes(cost[i * 4..i * 4 + 4].try_into().expect("4 bytes"))
+        });
+        let (salt, nonce) = rest.split_at(SALT_LEN);
+        let salt: [u8; SALT_LEN] = salt.try_into()?;
+
+        let key = self
+            .key(salt, cost)
+            .with_context(|| format!("{} has a damaged header", path.display()))?;
+        Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key))
+            .decrypt(Nonce::from_slice(nonce), sealed)
+            .map_err(|_| {
+                anyhow!(
+                    "Cannot decrypt {}: wrong passphrase or key file, or the file is damaged",
+                    path.display()
+                )
+            })
+    }
+}
+
+/// Whether `data` looks like an encrypted forge file
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Whether the file at `path` is an encrypted forge file
+pub fn is_encrypted_file(path: &Path) -> bool {
+    use std::io::Read;
+
+    let mut magic = [0; MAGIC.len()];
+    fs::File::open(path)
+        .and_then(|mut file| file.read_exact(&mut magic))
+        .is_ok_and(|()| magic == MAGIC)
+}
+
+/// Where the passphrase for this run comes from: the configured key file, if any
+pub fn key_file() -> Result<Option<PathBuf>> {
+    Ok(config::get_setting(config::SECRET_KEY_FILE_KEY)?
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from))
+}
+
+// Asked for or read once per run
+static CURRENT: Mutex<Option<Passphrase>> = Mutex::new(None);
+
+/// Run `f` with the passphrase for this run, reading or asking for it first
+pub fn with_passphrase<T>(f: impl FnOnce(&mut Passphrase) -> Result<T>) -> Result<T> {
+    let mut current = CURRENT
+        .lock()
+        .map_err(|_| anyhow!("passphrase lock poisoned"))?;
+    if current.is_none() {
+        *current = Some(read_passphrase()?);
+    }
+    f(current.as_mut().expect("passphrase was just set"))
+}
+
+fn read_passphrase() -> Result<Passphrase> {
+    if let Some(path) = key_file()? {
+        return Passphrase::from_key_file(&path);
+    }
+    if let Ok(passphrase) = std::env::var(PASSPHRASE_ENV) {
+        return Passphrase::new(passphrase);
+    }
+    match ui::prompt_hidden("Passphrase for encrypted files") {
+        Some(passphrase) => Ok(Passphrase {
+            typed: true,
+            ..Passphrase::new(passphrase)?
+        }),
+        None => bail!(
+            "No passphrase for encrypted files: set {} or 'forge config {} PATH'",
+            PASSPHRASE_ENV,
+            config::SECRET_KEY_FILE_KEY
+        ),
+    }
+}
+
+/// Encrypt with the passphrase for this run
+pub fn encrypt(plain: &[u8]) -> Result<Vec<u8>> {
+    with_passphrase(|passphrase| {
+        if !passphrase.confirmed {
+            confirm(passphrase)?;
+        }
+        passphrase.encrypt(plain)
+    })
+}
+
+// A mistyped passphrase would seal files that nothing opens, so before the
+// first encryption the passphrase must open a file encrypted earlier, or,
+// when there is none yet and it was typed, be typed again
+fn confirm(passphrase: &mut Passphrase) -> Result<()> {
+    let existing = config::get_dotfiles(None, None)?
+        .into_iter()
+        .filter(|dotfile| dotfile.mode == DeployMode::Encrypted)
+        .map(|dotfile| dotfile.target)
+        .find(|target| is_encrypted_file(target));
+    if let Some(path) = existing {
+        let data = fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        passphrase
+            .decrypt(&data, &path)
+            .map_err(|e| anyhow!("{}; not encrypting with a different passphrase", e))?;
+    } else if passphrase.typed
+        && ui::prompt_hidden("Repeat the passphrase")
+            .as_deref()
+            .map(str::as_bytes)
+            != Some(passphrase.secret.as_slice())
+    {
+        bail!("The passphrases do not match");
+    }
+    passphrase.confirmed = true;
+    Ok(())
+}
+
+/// Read and decrypt an encrypted file with the passphrase for this run
+pub fn decrypt_file(path: &Path) -> Result<Vec<u8>> {
+    let data = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    with_passphrase(|passphrase| passphrase.decrypt(&data, path))
+}
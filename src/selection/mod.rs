@@ -0,0 +1,351 @@
+// Selection rules
+//
+// Bulk linking (switching to a profile) does not link every file it finds in
+// a directory, only those the selection rules pick. A rule includes or
+// excludes the files that match a glob, an extension, a regular expression
+// or a size limit. Exclude rules take precedence: a file matched by any of
+// them is never selected. Otherwise a file is selected when an include rule
+// matches it, or when there are no include rules at all. Rules are matched
+// against the name a file is deployed under, so an alternate such as
+// `.bashrc##os.linux` is matched as `.bashrc`. Files the directory's ignore
+// rules exclude are left out before any selection rule is consulted.
+use crate::alternate;
+use crate::config;
+use crate::forgeignore::{IgnoreRules, Verdict};
+use anyhow::{Result, anyhow, bail};
+use globset::{GlobBuilder, GlobMatcher};
+use regex::Regex;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Whether a rule adds or removes the files it matches
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Action {
+    Include,
+    Exclude,
+}
+
+impl Action {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Include => "include",
+            Action::Exclude => "exclude",
+        }
+    }
+}
+
+/// What a rule's pattern is matched against
+#[derive(Debug, PartialEq, Eq, Clone, Copy, clap::ValueEnum)]
+pub enum Kind {
+    /// A glob matched against the file name, or against the path relative
+    /// to the directory when it contains a '/'
+    Glob,
+    /// A file extension, with or without the leading dot
+    Ext,
+    /// A regular expression searched for in the relative path
+    Regex,
+    /// A size limit such as <100K or >=1M
+    Size,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Glob => "glob",
+            Kind::Ext => "ext",
+            Kind::Regex => "regex",
+            Kind::Size => "size",
+        }
+    }
+}
+
+enum Matcher {
+    /// Whether the glob is matched against the whole relative path
+    Glob(GlobMatcher, bool),
+    Ext(String),
+    Regex(Regex),
+    Size(Comparison, u64),
+}
+
+#[derive(Clone, Copy)]
+enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+/// A parsed selection rule
+pub struct Rule {
+    pub id: i64,
+    pub action: Action,
+    pub kind: Kind,
+    pub pattern: String,
+    matcher: Matcher,
+}
+
+impl Rule {
+    /// Parse a rule, failing if its pattern is invalid for its kind
+    pub fn new(id: i64, action: Action, kind: Kind, pattern: &str) -> Result<Self> {
+        let matcher = match kind {
+            Kind::Glob => {
+                let glob = GlobBuilder::new(pattern)
+                    .literal_separator(true)
+                    .build()
+                    .map_err(|e| anyhow!("invalid glob '{}': {}", pattern, e))?;
+                Matcher::Glob(glob.compile_matcher(), pattern.contains('/'))
+            }
+            Kind::Ext => {
+                let ext = pattern.strip_prefix('.').unwrap_or(pattern);
+                if ext.is_empty() || ext.contains('/') {
+                    bail!("invalid extension '{}'", pattern);
+                }
+                Matcher::Ext(ext.to_string())
+            }
+            Kind::Regex => Matcher::Regex(
+                Regex::new(pattern).map_err(|e| anyhow!("invalid regex '{}': {}", pattern, e))?,
+            ),
+            Kind::Size => {
+                let (comparison, limit) = parse_size(pattern).ok_or_else(|| {
+                    anyhow!(
+                        "invalid size '{}': expected a comparison such as <100K or >1M",
+                        pattern
+                    )
+                })?;
+                Matcher::Size(comparison, limit)
+            }
+        };
+        Ok(Rule {
+            id,
+            action,
+            kind,
+            pattern: pattern.to_string(),
+            matcher,
+        })
+    }
+
+    /// Whether the rule matches a file, given by its path relative to the
+    /// directory being linked and its size
+    fn matches(&self, relative: &Path, size: u64) -> bool {
+        match &self.matcher {
+            Matcher::Glob(glob, true) => glob.is_match(relative),
+            Matcher::Glob(glob, false) => relative.file_name().is_some_and(|n| glob.is_match(n)),
+            Matcher::Ext(ext) => relative.extension().is_some_and(|e| e == ext.as_str()),
+            Matcher::Regex(regex) => regex.is_match(&relative.to_string_lossy()),
+            Matcher::Size(comparison, limit) => match comparison {
+                Comparison::Less => size < *limit,
+                Comparison::LessOrEqual => size <= *limit,
+                Comparison::Greater => size > *limit,
+                Comparison::GreaterOrEqual => size >= *limit,
+            },
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{} {} {} {}",
+            self.id,
+            self.action.as_str(),
+            self.kind.as_str(),
+            self.pattern
+        )
+    }
+}
+
+/// Parse a size limit: a comparison, a number and an optional K, M or G
+/// (powers of 1024, a trailing B is allowed)
+fn parse_size(pattern: &str) -> Option<(Comparison, u64)> {
+    let pattern = pattern.trim();
+    let (comparison, rest) = if let Some(rest) = pattern.strip_prefix("<=") {
+        (Comparison::LessOrEqual, rest)
+    } else if let Some(rest) = pattern.strip_prefix(">=") {
+        (Comparison::GreaterOrEqual, rest)
+    } else if let Some(rest) = pattern.strip_prefix('<') {
+        (Comparison::Less, rest)
+    } else if let Some(rest) = pattern.strip_prefix('>') {
+        (Comparison::Greater, rest)
+    } else {
+        return None;
+    };
+
+    let rest = rest.trim().to_ascii_uppercase();
+    let rest = rest.strip_suffix('B').unwrap_or(&rest);
+    let digits = rest.trim_end_matches(['K', 'M', 'G']);
+    let unit: u64 = match &rest[digits.len()..] {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        _ => return None,
+    };
+    let number: u64 = digits.trim().parse().ok()?;
+    Some((comparison, number.checked_mul(unit)?))
+}
+
+/// How a file fared against the rules
+#[derive(Debug, PartialEq, Clone)]
+pub enum Outcome {
+    /// Selected by this include rule, or because there are no include rules
+    Selected(Option<String>),
+    /// Excluded by this rule
+    Excluded(String),
+    /// No include rule matches
+    NotIncluded,
+    /// Left out by this ignore rule or ignored path
+    Ignored(String),
+    /// Another alternate of the file is selected for this machine
+    OtherVariant,
+}
+
+impl Outcome {
+    pub fn is_selected(&self) -> bool {
+        matches!(self, Outcome::Selected(_))
+    }
+}
+
+/// All selection rules, in the order they were added
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+impl Rules {
+    pub fn new(rules: Vec<Rule>) -> Self {
+        Rules { rules }
+    }
+
+    /// The rules stored in the database
+    pub fn load() -> Result<Self> {
+        let mut rules = Vec::new();
+        for record in config::get_selection_rules()? {
+            let action = match record.action.as_str() {
+                "include" => Action::Include,
+                "exclude" => Action::Exclude,
+                other => bail!(
+                    "selection rule #{} has unknown action '{}'",
+                    record.id,
+                    other
+                ),
+            };
+            let kind = <Kind as clap::ValueEnum>::from_str(&record.kind, false)
+                .map_err(|_| anyhow!("selection rule #{} has unknown kind", record.id))?;
+            let rule = Rule::new(record.id, action, kind, &record.pattern)
+                .map_err(|e| anyhow!("selection rule #{}: {}", record.id, e))?;
+            rules.push(rule);
+        }
+        Ok(Rules::new(rules))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Rule> {
+        self.rules.iter()
+    }
+
+    /// Decide about a file, given by its path relative to the directory
+    /// being linked and its size
+    pub fn decide(&self, relative: &Path, size: u64) -> Outcome {
+        let matching = |action| self.rules.iter().filter(move |rule| rule.action == action);
+        if let Some(rule) = matching(Action::Exclude).find(|rule| rule.matches(relative, size)) {
+            return Outcome::Excluded(rule.to_string());
+        }
+        let mut includes = matching(Action::Include).peekable();
+        if includes.peek().is_none() {
+            return Outcome::Selected(None);
+        }
+        match includes.find(|rule| rule.matches(relative, size)) {
+            Some(rule) => Outcome::Selected(Some(rule.to_string())),
+            None => Outcome::NotIncluded,
+        }
+    }
+}
+
+/// A file found in a directory being linked
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    /// The file, or the ignored directory
+    pub path: PathBuf,
+    /// Where it deploys, relative to the directory: its own path, or the
+    /// plain name of an alternate
+    pub deployed: PathBuf,
+    pub outcome: Outcome,
+}
+
+/// Every file under `dir`, sorted by path, with what the ignore rules and
+/// `rules` make of it. An ignored directory is listed instead of its contents.
+pub fn scan(dir: &Path, rules: &Rules) -> Result<Vec<Candidate>> {
+    if !dir.is_dir() {
+        bail!("{} is not a directory", dir.display());
+    }
+    let ignore_rules = IgnoreRules::for_folder(dir)?;
+    let ignored_paths: Vec<PathBuf> = config::get_ignored_paths()?
+        .iter()
+        .map(PathBuf::from)
+        .collect();
+    let machine = alternate::Machine::current();
+
+    let mut candidates = Vec::new();
+    let mut walk = WalkDir::new(dir)
+        .min_depth(1)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter();
+    while let Some(entry) = walk.next() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let path = entry.path();
+        let relative = path.strip_prefix(dir).unwrap_or(path).to_path_buf();
+        let is_dir = entry.file_type().is_dir();
+
+        let ignored = match ignored_paths.iter().find(|p| path.starts_with(p)) {
+            Some(ignored) => Some(Some(format!("ignored path {}", ignored.display()))),
+            None => match ignore_rules.check(path, is_dir) {
+                // forge's own files are not worth a mention
+                Verdict::Ignored(rule) if rule.file.is_none() => Some(None),
+                Verdict::Ignored(rule) => Some(Some(rule.to_string())),
+                _ => None,
+            },
+        };
+        if let Some(reason) = ignored {
+            if is_dir {
+                walk.skip_current_dir();
+            }
+            let Some(reason) = reason else {
+                continue;
+            };
+            candidates.push(Candidate {
+                path: path.to_path_buf(),
+                deployed: relative,
+                outcome: Outcome::Ignored(reason),
+            });
+            continue;
+        }
+        if !path.is_file() {
+            continue;
+        }
+
+        let Some(deployed) = alternate::stands_for(path, &machine) else {
+            candidates.push(Candidate {
+                path: path.to_path_buf(),
+                deployed: relative,
+                outcome: Outcome::OtherVariant,
+            });
+            continue;
+        };
+        let deployed = deployed
+            .strip_prefix(dir)
+            .unwrap_or(&deployed)
+            .to_path_buf();
+        let size = fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+        let outcome = rules.decide(&deployed, size);
+        candidates.push(Candidate {
+            path: path.to_path_buf(),
+            deployed,
+            outcome,
+        });
+    }
+    Ok(candidates)
+}
@@ -0,0 +1,361 @@
+// GNU Stow compatibility
+//
+// A stow directory holds one subdirectory per package, each mirroring the
+// layout of the target directory (by default the stow directory's parent).
+// Stow deploys a package by symlinking its entries into the target, folding
+// a whole directory into a single link when nothing else lives there.
+use crate::config;
+use crate::history;
+use anyhow::Result;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// A symlink in the target directory that stow created for a package entry
+#[derive(Debug, Clone)]
+pub struct StowLink {
+    /// The symlink in the target directory
+    pub link: PathBuf,
+    /// The package entry it points at
+    pub entry: PathBuf,
+}
+
+/// What was found for a package
+#[derive(Debug, Default)]
+pub struct PackageLinks {
+    pub links: Vec<StowLink>,
+    /// Package entries with no stow link for them in the target directory
+    pub unstowed: Vec<PathBuf>,
+}
+
+/// Packages in a stow directory, sorted by name. Hidden directories are not
+/// packages.
+pub fn packages(stow_dir: &Path) -> io::Result<Vec<String>> {
+    let mut packages = Vec::new();
+    for entry in fs::read_dir(stow_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_dir() && !name.starts_with('.') {
+            packages.push(name);
+        }
+    }
+    packages.sort();
+    Ok(packages)
+}
+
+/// Find the links stow made for a package in the target directory. A folded
+/// directory is one link; an unfolded one is searched for links to its entries.
+pub fn find_links(package_dir: &Path, target_dir: &Path) -> io::Result<PackageLinks> {
+    let mut found = PackageLinks::default();
+    walk(package_dir, target_dir, &mut found)?;
+    Ok(found)
+}
+
+fn walk(dir: &Path, target_dir: &Path, found: &mut PackageLinks) -> io::Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<io::Result<_>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let deployed = target_dir.join(entry.file_name());
+
+        if points_at(&deployed, &path) {
+            found.links.push(StowLink {
+                link: deployed,
+                entry: path,
+            });
+        } else if entry.file_type()?.is_dir() && !deployed.is_symlink() && deployed.is_dir() {
+            walk(&path, &deployed, found)?;
+        } else {
+            found.unstowed.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+// Whether `link` is a symlink that leads to `entry`, however it is spelled
+fn points_at(link: &Path, entry: &Path) -> bool {
+    if !link.is_symlink() {
+        return false;
+    }
+    match (fs::canonicalize(link), fs::canonicalize(entry)) {
+        (Ok(link), Ok(entry)) => link == entry,
+        _ => false,
+    }
+}
+
+// ---- Package mode ----
+//
+// `forge stow` treats each top-level directory of a managed folder as a
+// package and mirrors it into the target directory as stow does. A directory
+// that only one package needs is deployed as a single link (folded); when a
+// second package needs the same directory, the link is replaced by a real
+// directory holding one link per entry (unfolded). Unstowing removes the
+// package's links, deletes directories that become empty and folds
+// directories that are left with entries of a single package only.
+
+/// A change `forge stow` makes in the target directory
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    /// Create a symlink to a package entry
+    Link { link: PathBuf, entry: PathBuf },
+    /// Replace a folded directory link with a real directory holding one link
+    /// per entry of the package directory it pointed at
+    Unfold { link: PathBuf, dir: PathBuf },
+}
+
+/// Everything needed to stow a package, worked out before touching anything
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub actions: Vec<Action>,
+    /// Target paths in the way that forge does not own
+    pub conflicts: Vec<PathBuf>,
+}
+
+// What a target path holds, as far as stowing is concerned
+enum Node {
+    Missing,
+    /// A symlink, with the canonical path it leads to if it resolves
+    Link(Option<PathBuf>),
+    Dir,
+    Other,
+}
+
+/// Plan stowing a package directory of `folder` into `target_dir`
+pub fn plan_stow(package_dir: &Path, target_dir: &Path, folder: &Path) -> io::Result<Plan> {
+    let mut plan = Plan::default();
+    let folder = Folder::new(folder);
+    plan_dir(package_dir, target_dir, None, &folder, &mut plan)?;
+    Ok(plan)
+}
+
+// A managed folder, with its canonical path for comparing link destinations
+struct Folder {
+    path: PathBuf,
+    canonical: PathBuf,
+}
+
+impl Folder {
+    fn new(path: &Path) -> Self {
+        Folder {
+            path: path.to_path_buf(),
+            canonical: fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()),
+        }
+    }
+
+    /// The path in the managed folder a canonical path corresponds to, if it is in it
+    fn managed(&self, canonical: &Path) -> Option<PathBuf> {
+        let relative = canonical.strip_prefix(&self.canonical).ok()?;
+        Some(self.path.join(relative))
+    }
+}
+
+// Plan the entries of `src` into `target`. When `unfolded` is set, `target`
+// is a folded link that an earlier action replaces by a directory holding
+// links to the entries of `unfolded`, so that is what it is taken to contain.
+fn plan_dir(
+    src: &Path,
+    target: &Path,
+    unfolded: Option<&Path>,
+    folder: &Folder,
+    plan: &mut Plan,
+) -> io::Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(src)?.collect::<io::Result<_>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let entry_path = entry.path();
+        let link = target.join(entry.file_name());
+        let is_dir = entry.file_type()?.is_dir();
+
+        let node = match unfolded {
+            Some(other) => {
+                let theirs = other.join(entry.file_name());
+                match fs::symlink_metadata(&theirs) {
+                    Ok(_) => Node::Link(fs::canonicalize(&theirs).ok()),
+                    Err(_) => Node::Missing,
+                }
+            }
+            None => match fs::symlink_metadata(&link) {
+                Err(_) => Node::Missing,
+                Ok(meta) if meta.file_type().is_symlink() => {
+                    Node::Link(fs::canonicalize(&link).ok())
+                }
+                Ok(meta) if meta.is_dir() => Node::Dir,
+                Ok(_) => Node::Other,
+            },
+        };
+
+        match node {
+            Node::Missing => plan.actions.push(Action::Link {
+                link,
+                entry: entry_path,
+            }),
+            Node::Link(Some(dest)) if fs::canonicalize(&entry_path).ok() == Some(dest.clone()) => {}
+            Node::Link(Some(dest)) if is_dir && dest.is_dir() => match folder.managed(&dest) {
+                // A directory folded into another package: unfold it, then
+                // stow this package's entries alongside
+                Some(dir) => {
+                    plan.actions.push(Action::Unfold {
+                        link: link.clone(),
+                        dir: dir.clone(),
+                    });
+                    plan_dir(&entry_path, &link, Some(&dir), folder, plan)?;
+                }
+                None => plan.conflicts.push(link),
+            },
+            Node::Dir if is_dir && unfolded.is_none() => {
+                plan_dir(&entry_path, &link, None, folder, plan)?;
+            }
+            _ => plan.conflicts.push(link),
+        }
+    }
+
+    Ok(())
+}
+
+/// A directory that can be folded back into a single link: every entry is a
+/// symlink into the same managed directory, and that directory has no other
+/// entries. Returns the managed directory.
+pub fn foldable(dir: &Path, folder: &Path) -> io::Result<Option<PathBuf>> {
+    if dir.is_symlink() || !dir.is_dir() {
+        return Ok(None);
+    }
+    let folder = Folder::new(folder);
+
+    let mut names = Vec::new();
+    let mut parent: Option<PathBuf> = None;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_symlink() {
+            return Ok(None);
+        }
+        let Ok(dest) = fs::canonicalize(entry.path()) else {
+            return Ok(None);
+        };
+        if dest.file_name() != Some(entry.file_name().as_os_str()) {
+            return Ok(None);
+        }
+        let dest_parent = dest.parent().map(Path::to_path_buf);
+        if parent.is_some() && parent != dest_parent {
+            return Ok(None);
+        }
+        parent = dest_parent;
+        names.push(entry.file_name());
+    }
+
+    let Some(parent) = parent else {
+        return Ok(None);
+    };
+    let Some(managed) = folder.managed(&parent) else {
+        return Ok(None);
+    };
+    // A package's top-level directory is never folded into
+    if managed.parent() == Some(folder.path.as_path()) {
+        return Ok(None);
+    }
+
+    let mut theirs: Vec<_> = fs::read_dir(&managed)?
+        .map(|entry| entry.map(|e| e.file_name()))
+        .collect::<io::Result<_>>()?;
+    theirs.sort();
+    names.sort();
+    Ok((theirs == names).then_some(managed))
+}
+
+/// Carry out a planned action, recording each link it creates as a linked dotfile
+pub fn perform(action: &Action) -> Result<()> {
+    match action {
+        Action::Link { link, entry } => {
+            history::create_link(entry, link)?;
+            config::add_dotfile(link, entry, None)?;
+        }
+        Action::Unfold { link, dir } => {
+            history::remove_file(link)?;
+            config::deactivate_dotfile(dir)?;
+            history::create_dir_all(link)?;
+
+            let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<io::Result<_>>()?;
+            entries.sort_by_key(|entry| entry.file_name());
+            for entry in entries {
+                let entry_link = link.join(entry.file_name());
+                history::create_link(entry.path(), &entry_link)?;
+                config::add_dotfile(&entry_link, &entry.path(), None)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// What unstowing a package changed in the target directory
+#[derive(Debug, Default)]
+pub struct Unstowed {
+    pub removed_links: Vec<PathBuf>,
+    pub removed_dirs: Vec<PathBuf>,
+    pub folded: Vec<PathBuf>,
+}
+
+/// Remove the links to a package's entries, then tidy the directories they
+/// were in: those left empty are removed, those left holding entries of a
+/// single package directory are folded back into one link to it
+pub fn unstow(package_dir: &Path, target_dir: &Path, folder: &Path) -> Result<Unstowed> {
+    let mut unstowed = Unstowed::default();
+    let mut pending: Vec<PathBuf> = Vec::new();
+
+    let dotfiles = config::get_dotfiles(None, None)?;
+    for dotfile in dotfiles
+        .iter()
+        .filter(|d| d.is_linked() && d.target.starts_with(package_dir))
+    {
+        if points_at(&dotfile.source, &dotfile.target) {
+            history::remove_file(&dotfile.source)?;
+            unstowed.removed_links.push(dotfile.source.clone());
+            if let Some(parent) = dotfile.source.parent() {
+                pending.push(parent.to_path_buf());
+            }
+        }
+        config::deactivate_dotfile(&dotfile.target)?;
+    }
+
+    // Deepest first, so a directory is tidied after everything inside it
+    loop {
+        pending.sort_by_key(|dir| dir.components().count());
+        pending.dedup();
+        let Some(dir) = pending.pop() else {
+            break;
+        };
+        if dir == target_dir || !dir.starts_with(target_dir) || dir.is_symlink() {
+            continue;
+        }
+
+        if fs::read_dir(&dir)?.next().is_none() {
+            history::remove_dir(&dir)?;
+            unstowed.removed_dirs.push(dir.clone());
+            if let Some(parent) = dir.parent() {
+                pending.push(parent.to_path_buf());
+            }
+        } else if let Some(managed) = foldable(&dir, folder)? {
+            fold(&dir, &managed)?;
+            unstowed.folded.push(dir.clone());
+            if let Some(parent) = dir.parent() {
+                pending.push(parent.to_path_buf());
+            }
+        }
+    }
+
+    Ok(unstowed)
+}
+
+// Replace a directory of links to the entries of `managed` by one link to it
+fn fold(dir: &Path, managed: &Path) -> Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        history::remove_file(entry.path())?;
+        config::deactivate_dotfile(&managed.join(entry.file_name()))?;
+    }
+    history::remove_dir(dir)?;
+    history::create_link(managed, dir)?;
+    config::add_dotfile(dir, managed, None)?;
+    Ok(())
+}
@@ -1,9 +1,10 @@
-use std::collections::HashMap;
 use std::io::{self, Error, ErrorKind};
 use std::path::{Path, PathBuf};
-use walkdir::WalkDir;
+use std::sync::OnceLock;
 
 use crate::config;
+use crate::dotfile::DeployMode;
+use crate::selection::{self, Outcome};
 use crate::utils::path_utils;
 
 /// Create a symlink from source to target
@@ -22,14 +23,94 @@ pub fn create_symlink<P: AsRef<Path>, Q: AsRef<Path>>(source: P, target: Q) -> i
     }
 }
 
+/// Whether deployed symlinks are created relative to the directory they are
+/// in: for this command with --relative, or always with the relative_links setting
+pub fn relative_links() -> bool {
+    static RELATIVE: OnceLock<bool> = OnceLock::new();
+    *RELATIVE.get_or_init(|| {
+        std::env::var_os(config::RELATIVE_LINKS_ENV).is_some()
+            || config::get_setting(config::RELATIVE_LINKS_KEY)
+                .ok()
+                .flatten()
+                .is_some_and(|value| value == "true")
+    })
+}
+
+/// The text of a symlink at `link` leading to `dest`: `dest` itself, or the
+/// path from the link's directory to it when links are relative
+pub fn link_text<P: AsRef<Path>, Q: AsRef<Path>>(dest: P, link: Q) -> PathBuf {
+    if relative_links() {
+        relative_link_text(dest, link)
+    } else {
+        path_utils::normalize(dest.as_ref())
+    }
+}
+
+/// The path from the directory of `link` to `dest`. Both are resolved through
+/// existing symlinks first, so the `..` steps hold on disk.
+pub fn relative_link_text<P: AsRef<Path>, Q: AsRef<Path>>(dest: P, link: Q) -> PathBuf {
+    let dest = path_utils::normalize(dest.as_ref());
+    let link = path_utils::normalize(link.as_ref());
+    let Some(dir) = link.parent() else {
+        return dest;
+    };
+    let dir = std::fs::canonicalize(dir).unwrap_or_else(|_| dir.to_path_buf());
+    let dest = match (dest.parent(), dest.file_name()) {
+        (Some(parent), Some(name)) => std::fs::canonicalize(parent)
+            .map(|parent| parent.join(name))
+            .unwrap_or(dest),
+        _ => dest,
+    };
+    relative_path(&dest, &dir)
+}
+
+/// Path from directory `from` to `to`, both absolute and normalized
+pub fn relative_path(to: &Path, from: &Path) -> PathBuf {
+    let to: Vec<_> = to.components().collect();
+    let from: Vec<_> = from.components().collect();
+    let common = to.iter().zip(&from).take_while(|(a, b)| a == b).count();
+
+    let mut relative = PathBuf::new();
+    for _ in common..from.len() {
+        relative.push("..");
+    }
+    for component in &to[common..] {
+        relative.push(component);
+    }
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative
+}
+
 /// Check if path is a symlink
 pub fn is_symlink<P: AsRef<Path>>(path: P) -> bool {
     path.as_ref().is_symlink()
 }
 
-/// Creates symlinks from files in source directory to target directory
-/// based on file types and ignored paths from configuration
-pub fn create_symlinks<P: AsRef<Path>, Q: AsRef<Path>>(source: P, target: Q) -> io::Result<()> {
+/// Read where a symlink points, resolving relative link text against the
+/// link's parent directory. Returns None if the path is not a symlink.
+pub fn resolve_link<P: AsRef<Path>>(link: P) -> Option<PathBuf> {
+    let link = link.as_ref();
+    let dest = std::fs::read_link(link).ok()?;
+
+    if dest.is_absolute() {
+        return Some(path_utils::normalize(dest));
+    }
+
+    let parent = link.parent().unwrap_or_else(|| Path::new("/"));
+    Some(path_utils::normalize(parent.join(dest)))
+}
+
+/// Creates symlinks (or hard links or copies, per `mode`) in the target directory for
+/// the files of the source directory that the selection rules pick, leaving out what
+/// the ignore rules and ignored paths exclude. Returns the source and deployed path of
+/// each file deployed.
+pub fn create_symlinks<P: AsRef<Path>, Q: AsRef<Path>>(
+    source: P,
+    target: Q,
+    mode: DeployMode,
+) -> io::Result<Vec<(PathBuf, PathBuf)>> {
     // Resolve and normalize source directory
     let abs_source = path_utils::normalize(source.as_ref());
 
@@ -52,62 +133,40 @@ pub fn create_symlinks<P: AsRef<Path>, Q: AsRef<Path>>(source: P, target: Q) ->
         ));
     }
 
-    // Read registered file types and ignored paths
-    let filetypes = config::get_file_types()?;
-    let ignored_paths = config::get_ignored_paths()?;
-
-    // Create HashMaps for faster lookup
-    let filetypes_map: HashMap<String, bool> =
-        filetypes.iter().map(|ft| (ft.to_string(), true)).collect();
-
-    let ignored_paths_map: HashMap<PathBuf, bool> = ignored_paths
-        .iter()
-        .map(|path| (PathBuf::from(path), true))
-        .collect();
+    let rules = selection::Rules::load().map_err(io::Error::other)?;
+    let candidates = selection::scan(&abs_source, &rules).map_err(io::Error::other)?;
 
-    // Walk through source directory and create symlinks in target
-    for entry in WalkDir::new(&abs_source)
-        .follow_links(false)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let path = entry.path();
-
-        // Skip if path is in ignored paths
-        if ignored_paths_map.contains_key(&PathBuf::from(path)) {
-            if path.is_dir() {
-                continue; // Skip entire directory
-            }
+    let mut deployed = Vec::new();
+    for candidate in candidates {
+        let path = &candidate.path;
+        if let Outcome::Ignored(reason) = &candidate.outcome {
+            println!("Ignored: {:?} ({})", path, reason);
+        }
+        if !candidate.outcome.is_selected() {
             continue;
         }
 
-        // Only process files, not directories
-        if path.is_file() {
-            // Check if the file extension is in the approved list
-            if let Some(ext) = path.extension() {
-                let ext_str = format!(".{}", ext.to_string_lossy());
-                if filetypes_map.contains_key(&ext_str) {
-                    // Create the symlink in the target directory
-                    let file_name = match path.file_name() {
-                        Some(name) => name,
-                        None => {
-                            println!(
-                                "Warning: Invalid path with no filename component: {:?}",
-                                path
-                            );
-                            continue;
-                        }
-                    };
-                    let target_path = abs_target.join(file_name);
-
-                    match create_symlink(path, &target_path) {
-                        Ok(_) => println!("Created symlink for {:?} -> {:?}", path, target_path),
-                        Err(e) => println!("Failed to create symlink for {:?}: {}", path, e),
-                    }
-                }
+        // Create the link in the target directory
+        let file_name = match candidate.deployed.file_name() {
+            Some(name) => name,
+            None => {
+                println!(
+                    "Warning: Invalid path with no filename component: {:?}",
+                    path
+                );
+                continue;
+            }
+        };
+        let target_path = abs_target.join(file_name);
+
+        match crate::deploy::deploy(mode, path, &target_path) {
+            Ok(_) => {
+                println!("Created {} for {:?} -> {:?}", mode, path, target_path);
+                deployed.push((path.clone(), target_path));
             }
+            Err(e) => println!("Failed to create {} for {:?}: {}", mode, path, e),
         }
     }
 
-    Ok(())
+    Ok(deployed)
 }
@@ -0,0 +1,157 @@
+// Rendering template dotfiles
+//
+// A managed file deployed in template mode is not linked: its content is
+// rendered with facts about the machine and user-defined values, and the
+// result is written at the original location. Placeholders look like
+// `{{ name }}`; a literal `{{` is written as `\{{`.
+//
+//   {{ hostname }}  {{ os }}  {{ arch }}  {{ user }}  {{ home }}
+//   {{ env.NAME }}  an environment variable, which must be set
+//   {{ var.NAME }}  a value set with `forge config var.NAME VALUE`
+use crate::config;
+use anyhow::{Context as _, Result, anyhow, bail};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+/// Prefix of the settings that hold user-defined template values
+pub const VAR_PREFIX: &str = "var.";
+
+/// Everything a template can refer to, gathered once per command
+#[derive(Debug, Clone)]
+pub struct Context {
+    pub hostname: String,
+    pub os: String,
+    pub arch: String,
+    pub user: String,
+    pub home: String,
+    /// User-defined values, without the `var.` prefix
+    pub vars: BTreeMap<String, String>,
+}
+
+impl Context {
+    /// Facts about this machine plus the values set with `forge config`
+    pub fn current() -> Result<Self> {
+        let vars = config::get_settings_with_prefix(VAR_PREFIX)?
+            .into_iter()
+            .map(|(key, value)| (key[VAR_PREFIX.len()..].to_string(), value))
+            .collect();
+        Ok(Context {
+            hostname: hostname(),
+            os: std::env::consts::OS.to_string(),
+            arch: std::env::consts::ARCH.to_string(),
+            user: username(),
+            home: dirs::home_dir()
+                .map(|home| home.display().to_string())
+                .unwrap_or_default(),
+            vars,
+        })
+    }
+
+    /// The value of one placeholder name
+    pub fn lookup(&self, name: &str) -> Result<String> {
+        if let Some(var) = name.strip_prefix("env.") {
+            return std::env::var(var)
+                .map_err(|_| anyhow!("environment variable {} is not set", var));
+        }
+        if let Some(var) = name.strip_prefix(VAR_PREFIX) {
+            return match self.vars.get(var) {
+                Some(value) => Ok(value.clone()),
+                None => bail!(
+                    "'{}' is not defined; set it with 'forge config {} VALUE'",
+                    name,
+                    name
+                ),
+            };
+        }
+        Ok(match name {
+            "hostname" => self.hostname.clone(),
+            "os" => self.os.clone(),
+            "arch" => self.arch.clone(),
+            "user" => self.user.clone(),
+            "home" => self.home.clone(),
+            _ => bail!("unknown template variable '{}'", name),
+        })
+    }
+
+    /// Replace every placeholder in `text`
+    pub fn render(&self, text: &str) -> Result<String> {
+        let mut out = String::with_capacity(text.len());
+        for (number, line) in text.split_inclusive('\n').enumerate() {
+            self.render_line(line, &mut out)
+                .map_err(|e| anyhow!("line {}: {}", number + 1, e))?;
+        }
+        Ok(out)
+    }
+
+    fn render_line(&self, mut line: &str, out: &mut String) -> Result<()> {
+        while let Some(start) = line.find("{{") {
+            if line[..start].ends_with('\\') {
+                out.push_str(&line[..start - 1]);
+                out.push_str("{{");
+                line = &line[start + 2..];
+                continue;
+            }
+            out.push_str(&line[..start]);
+            let Some(end) = line[start..].find("}}") else {
+                bail!("'{{{{' without a closing '}}}}'");
+            };
+            let name = line[start + 2..start + end].trim();
+            if name.is_empty() {
+                bail!("empty placeholder");
+            }
+            out.push_str(&self.lookup(name)?);
+            line = &line[start + end + 2..];
+        }
+        out.push_str(line);
+        Ok(())
+    }
+
+    /// Render a managed template file
+    pub fn render_file(&self, path: &Path) -> Result<String> {
+        if path.is_dir() {
+            bail!("{} is a directory and cannot be a template", path.display());
+        }
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read template {}", path.display()))?;
+        self.render(&text)
+            .map_err(|e| anyhow!("Cannot render {}: {}", path.display(), e))
+    }
+}
+
+/// Render a managed template file with the current context
+pub fn render_file(path: &Path) -> Result<String> {
+    Context::current()?.render_file(path)
+}
+
+/// Short name of this machine, without any domain
+pub fn hostname() -> String {
+    let name = fs::read_to_string("/proc/sys/kernel/hostname")
+        .or_else(|_| fs::read_to_string("/etc/hostname"))
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .or_else(|| {
+            Command::new("hostname")
+                .output()
+                .ok()
+                .and_then(|output| String::from_utf8(output.stdout).ok())
+        })
+        .unwrap_or_default();
+    let name = name.trim();
+    name.split('.').next().unwrap_or(name).to_string()
+}
+
+/// Name of the user running forge
+pub fn username() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("LOGNAME"))
+        .or_else(|_| std::env::var("USERNAME"))
+        .ok()
+        .or_else(|| {
+            dirs::home_dir()
+                .and_then(|home| home.file_name().map(|name| name.to_string_lossy().into()))
+        })
+        .unwrap_or_default()
+}
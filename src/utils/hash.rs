@@ -0,0 +1,46 @@
+use crate::utils::path_utils;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// Calculate the BLAKE3 hash of a file's contents as a hex string
+pub fn file_hash<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let content = fs::read(path)?;
+    Ok(content_hash(&content))
+}
+
+/// Hash of content in memory, comparable with the file_hash of a file holding it
+pub fn content_hash(content: &[u8]) -> String {
+    blake3::hash(content).to_hex().to_string()
+}
+
+/// Hash of a file's contents, or of a whole directory tree: the relative path,
+/// kind and contents of every entry in name order. Symlinks inside a tree are
+/// hashed by the text they point to, not followed.
+pub fn path_hash<P: AsRef<Path>>(path: P) -> io::Result<String> {
+    let path = path.as_ref();
+    if !fs::symlink_metadata(path)?.is_dir() {
+        return file_hash(path);
+    }
+
+    let mut hasher = blake3::Hasher::new();
+    for entry in walkdir::WalkDir::new(path).min_depth(1).sort_by_file_name() {
+        let entry = entry?;
+        let relative = entry.path().strip_prefix(path).unwrap_or(entry.path());
+        hasher.update(&path_utils::to_bytes(relative));
+        hasher.update(b"\0");
+
+        let file_type = entry.file_type();
+        if file_type.is_symlink() {
+            hasher.update(b"l");
+            hasher.update(&path_utils::to_bytes(fs::read_link(entry.path())?));
+        } else if file_type.is_dir() {
+            hasher.update(b"d");
+        } else {
+            hasher.update(b"f");
+            hasher.update(file_hash(entry.path())?.as_bytes());
+        }
+        hasher.update(b"\0");
+    }
+    Ok(hasher.finalize().to_hex().to_string())
+}
@@ -1,2 +1,3 @@
+pub mod hash;
 pub mod path_utils;
 pub mod ui;
@@ -1,3 +1,7 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use std::ffi::OsString;
+use std::io;
 use std::path::{Component, Path, PathBuf};
 
 pub fn expand_tilde<P: AsRef<Path>>(path: P) -> PathBuf {
@@ -63,3 +67,187 @@ fn clean_path_components(path: &Path) -> PathBuf {
 
     components.iter().collect()
 }
+
+// ---- Byte-exact path encoding ----
+//
+// Paths are stored as the raw bytes the OS uses for them, so a filename that
+// is not valid UTF-8 comes back as exactly the same file.
+
+/// Raw OS bytes of a path
+#[cfg(unix)]
+pub fn to_bytes<P: AsRef<Path>>(path: P) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    path.as_ref().as_os_str().as_bytes().to_vec()
+}
+
+#[cfg(not(unix))]
+pub fn to_bytes<P: AsRef<Path>>(path: P) -> Vec<u8> {
+    path.as_ref().to_string_lossy().into_owned().into_bytes()
+}
+
+/// Path from raw OS bytes
+#[cfg(unix)]
+pub fn from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(OsString::from_vec(bytes.to_vec()))
+}
+
+#[cfg(not(unix))]
+pub fn from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(OsString::from(String::from_utf8_lossy(bytes).into_owned()))
+}
+
+/// Base64 encoding of a path's raw bytes
+pub fn encode_base64<P: AsRef<Path>>(path: P) -> String {
+    BASE64.encode(to_bytes(path))
+}
+
+/// Path from the base64 encoding of its raw bytes
+pub fn decode_base64(encoded: &str) -> io::Result<PathBuf> {
+    BASE64
+        .decode(encoded)
+        .map(|bytes| from_bytes(&bytes))
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Serde helpers for path fields in TOML files. Paths that are valid UTF-8 are
+/// written as plain strings; any other path is written as `{ base64 = "..." }`.
+pub mod serde_path {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::path::{Path, PathBuf};
+
+    #[derive(Serialize, Deserialize)]
+    #[serde(untagged)]
+    enum Repr {
+        Text(String),
+        Bytes { base64: String },
+    }
+
+    pub fn serialize<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+        match path.to_str() {
+            Some(text) => Repr::Text(text.to_string()),
+            None => Repr::Bytes {
+                base64: super::encode_base64(path),
+            },
+        }
+        .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<PathBuf, D::Error> {
+        match Repr::deserialize(deserializer)? {
+            Repr::Text(text) => Ok(PathBuf::from(text)),
+            Repr::Bytes { base64 } => {
+                super::decode_base64(&base64).map_err(serde::de::Error::custom)
+            }
+        }
+    }
+
+    /// The same encoding for `Option<PathBuf>` fields
+    pub mod option {
+        use serde::{Deserialize, Deserializer, Serializer};
+        use std::path::PathBuf;
+
+        #[derive(Deserialize)]
+        struct Wrapper(#[serde(with = "super")] PathBuf);
+
+        pub fn serialize<S: Serializer>(
+            path: &Option<PathBuf>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match path {
+                Some(path) => super::serialize(path, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<PathBuf>, D::Error> {
+            Ok(Option::<Wrapper>::deserialize(deserializer)?.map(|Wrapper(path)| path))
+        }
+    }
+}
+
+// ---- Portable paths ----
+//
+// Stored paths are written relative to a logical root where possible, so the
+// database and managed folders can move to a machine with a different user
+// name or home layout. A stored path is either absolute, or a root token
+// followed by an optional relative part, e.g. `$HOME/.vimrc`. Absolute paths
+// always start with '/', so the two forms cannot be confused.
+
+/// The user's home directory
+pub const HOME_ROOT: &str = "$HOME";
+/// `$XDG_CONFIG_HOME`, or `~/.config` when it is not set
+pub const XDG_CONFIG_ROOT: &str = "$XDG_CONFIG_HOME";
+/// The managed folder a dotfile belongs to
+pub const FOLDER_ROOT: &str = "$FOLDER";
+
+/// Current location of `$XDG_CONFIG_HOME`
+pub fn xdg_config_home() -> Option<PathBuf> {
+    match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if Path::new(&dir).is_absolute() => Some(PathBuf::from(dir)),
+        _ => dirs::home_dir().map(|home| home.join(".config")),
+    }
+}
+
+fn roots(folder: Option<&Path>) -> Vec<(&'static str, PathBuf)> {
+    let mut roots = Vec::new();
+    if let Some(folder) = folder {
+        roots.push((FOLDER_ROOT, folder.to_path_buf()));
+    }
+    if let Some(config) = xdg_config_home() {
+        roots.push((XDG_CONFIG_ROOT, config));
+    }
+    if let Some(home) = dirs::home_dir() {
+        roots.push((HOME_ROOT, home));
+    }
+    roots
+}
+
+/// Encode a path for storage, relative to the most specific root it is under.
+/// `folder` is the managed folder the path belongs to, if any.
+pub fn to_portable<P: AsRef<Path>>(path: P, folder: Option<&Path>) -> Vec<u8> {
+    let path = path.as_ref();
+    let best = roots(folder)
+        .into_iter()
+        .filter_map(|(token, root)| {
+            let relative = path.strip_prefix(&root).ok()?;
+            Some((token, root.components().count(), relative.to_path_buf()))
+        })
+        .max_by_key(|(_, depth, _)| *depth);
+
+    match best {
+        Some((token, _, relative)) => {
+            let mut stored = token.as_bytes().to_vec();
+            if !relative.as_os_str().is_empty() {
+                stored.push(b'/');
+                stored.extend(to_bytes(relative));
+            }
+            stored
+        }
+        None => to_bytes(path),
+    }
+}
+
+/// Resolve a stored path against the current roots. A root that cannot be
+/// resolved here (no home directory, or no managed folder) is left as is.
+pub fn from_portable(stored: &[u8], folder: Option<&Path>) -> PathBuf {
+    if stored.first() != Some(&b'$') {
+        return from_bytes(stored);
+    }
+
+    let (token, relative) = match stored.iter().position(|&b| b == b'/') {
+        Some(slash) => (&stored[..slash], Some(&stored[slash + 1..])),
+        None => (stored, None),
+    };
+    let root = roots(folder)
+        .into_iter()
+        .find(|(name, _)| name.as_bytes() == token);
+
+    match (root, relative) {
+        (Some((_, root)), Some(relative)) => root.join(from_bytes(relative)),
+        (Some((_, root)), None) => root,
+        (None, _) => from_bytes(stored),
+    }
+}
@@ -1,4 +1,5 @@
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, Stdio};
 
 /// Prompt the user for confirmation, returning true if they answer yes
 pub fn confirm(message: &str) -> bool {
@@ -32,6 +33,64 @@ pub fn confirm_with_text(message: &str, required_text: &str) -> bool {
     input.trim() == required_text
 }
 
+/// Ask the user to pick one of `choices`, by its first letter or in full.
+/// Returns its index, or None if input ends first.
+pub fn choose(message: &str, choices: &[&str]) -> Option<usize> {
+    let letters: Vec<String> = choices
+        .iter()
+        .map(|choice| choice.chars().take(1).collect())
+        .collect();
+    loop {
+        print!("{} [{}]: ", message, letters.join("/"));
+        if io::stdout().flush().is_err() {
+            eprintln!("Warning: Failed to flush stdout");
+        }
+
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input).ok()? == 0 {
+            println!();
+            return None;
+        }
+        let input = input.trim().to_lowercase();
+        let picked = choices
+            .iter()
+            .zip(&letters)
+            .position(|(choice, letter)| input == *letter || input == choice.to_lowercase());
+        if picked.is_some() {
+            return picked;
+        }
+        println!("Please answer {}", choices.join(", "));
+    }
+}
+
+/// Prompt for a line of input without echoing it, e.g. a passphrase. Returns
+/// None when stdin is not a terminal.
+pub fn prompt_hidden(message: &str) -> Option<String> {
+    if !io::stdin().is_terminal() {
+        return None;
+    }
+    eprint!("{}: ", message);
+    let _ = io::stderr().flush();
+
+    let echo_off = Command::new("stty")
+        .arg("-echo")
+        .stdin(Stdio::inherit())
+        .status()
+        .is_ok_and(|status| status.success());
+    let mut input = String::new();
+    let read = io::stdin().read_line(&mut input);
+    if echo_off {
+        let _ = Command::new("stty")
+            .arg("echo")
+            .stdin(Stdio::inherit())
+            .status();
+    }
+    eprintln!();
+
+    read.ok()?;
+    Some(input.trim_end_matches(['\r', '\n']).to_string())
+}
+
 /// Format a status message for a dotfile
 pub fn format_dotfile_status(status: crate::dotfile::DotFileStatus) -> String {
     match status {
@@ -0,0 +1,337 @@
+// Live drift detection
+//
+// `forge watch` keeps an inotify watch on the directory holding each linked
+// file and on every directory of the managed folder. Editors that save by
+// writing a temporary file and renaming it over the original replace a
+// symlink with a regular file; a watch on the symlink itself would follow it
+// into the managed folder and miss that, so the parent directories are
+// watched instead. After each burst of events the dotfiles it touched are
+// checked again, and every change of state is handled by the policy.
+use crate::config;
+use crate::deploy::{self, Changed};
+use crate::dotfile::status::{self, LinkState};
+use crate::dotfile::{DeployMode, DotFile, backup};
+use crate::history;
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// File in the config directory every report is appended to
+const LOG_FILE: &str = "watch.log";
+
+/// What to do when a linked file drifts
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, clap::ValueEnum)]
+pub enum Policy {
+    /// Print and log the change
+    #[default]
+    Log,
+    /// Also show a desktop notification
+    Notify,
+    /// Also put the file back in place where that is safe
+    Repair,
+}
+
+impl Policy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Policy::Log => "log",
+            Policy::Notify => "notify",
+            Policy::Repair => "repair",
+        }
+    }
+}
+
+/// Watches the linked files of one managed folder
+struct Watcher {
+    folder_name: String,
+    forge_path: PathBuf,
+    policy: Policy,
+    log: Option<fs::File>,
+    /// Last known state of each linked dotfile, by managed path
+    states: HashMap<PathBuf, LinkState>,
+}
+
+impl Watcher {
+    fn new(folder_name: &str, forge_path: &Path, policy: Policy) -> Self {
+        let log = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(config::get_config_dir().join(LOG_FILE))
+            .map_err(|e| eprintln!("Cannot open the watch log: {}", e))
+            .ok();
+        Watcher {
+            folder_name: folder_name.to_string(),
+            forge_path: forge_path.to_path_buf(),
+            policy,
+            log,
+            states: HashMap::new(),
+        }
+    }
+
+    /// Linked dotfiles of the folder, read again each time so that files
+    /// linked while watching are picked up
+    fn dotfiles(&self) -> Vec<DotFile> {
+        match config::get_dotfiles(None, Some(&self.folder_name)) {
+            Ok(dotfiles) => dotfiles.into_iter().filter(|d| d.is_linked()).collect(),
+            Err(e) => {
+                eprintln!("Error fetching tracked files: {}", e);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Directories to watch: where each linked file is deployed, and the
+    /// managed folder without forge's and git's metadata
+    fn directories(&self, dotfiles: &[DotFile]) -> Vec<PathBuf> {
+        let mut dirs: Vec<PathBuf> = dotfiles
+            .iter()
+            .filter_map(|d| d.source.parent().map(Path::to_path_buf))
+            .collect();
+        let managed = walkdir::WalkDir::new(&self.forge_path)
+            .into_iter()
+            .filter_entry(|e| !matches!(e.file_name().to_str(), Some(".forge" | ".git")))
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_dir())
+            .map(|e| e.into_path());
+        dirs.extend(managed);
+        dirs.sort();
+        dirs.dedup();
+        dirs
+    }
+
+    /// Check `dotfiles` again and handle those whose state changed
+    fn check(&mut self, dotfiles: &[DotFile]) {
+        for dotfile in dotfiles {
+            let state = status::check_dotfile(dotfile);
+            let previous = self
+                .states
+                .insert(dotfile.target.clone(), state.clone())
+                .unwrap_or(LinkState::Ok);
+            if state == previous {
+                continue;
+            }
+            if state.is_ok() {
+                self.report(&format!("Back in place: {}", dotfile.source.display()));
+                continue;
+            }
+            self.report(&format!(
+                "Drifted: {}: {}",
+                dotfile.source.display(),
+                state.describe()
+            ));
+            if self.policy == Policy::Repair {
+                self.repair(dotfile, &state);
+            }
+        }
+    }
+
+    fn repair(&mut self, dotfile: &DotFile, state: &LinkState) {
+        history::begin(&format!("forge watch: repair {}", dotfile.source.display()));
+        let result = repair(dotfile, state);
+        if result.is_err() {
+            let _ = history::rollback();
+        }
+        history::commit();
+        match result {
+            Ok(()) => {
+                self.states
+                    .insert(dotfile.target.clone(), status::check_dotfile(dotfile));
+                self.report(&format!("Repaired: {}", dotfile.source.display()));
+            }
+            Err(e) => self.report(&format!(
+                "Cannot repair {}: {:#}",
+                dotfile.source.display(),
+                e
+            )),
+        }
+    }
+
+    /// Report a file created inside a linked directory
+    fn new_file(&mut self, dotfiles: &[DotFile], path: &Path) {
+        let Some(dotfile) = dotfiles.iter().find(|d| {
+            let managed = deploy::managed_path(d);
+            path != managed && path.starts_with(&managed) && managed.is_dir()
+        }) else {
+            return;
+        };
+        // Editors create and remove scratch files while saving
+        if fs::symlink_metadata(path).is_err() {
+            return;
+        }
+        let managed = deploy::managed_path(dotfile);
+        let relative = path.strip_prefix(&managed).unwrap_or(path);
+        self.report(&format!(
+            "New file in tracked directory {}: {}",
+            dotfile.source.display(),
+            relative.display()
+        ));
+    }
+
+    fn report(&mut self, message: &str) {
+        let line = format!(
+            "{} {}",
+            chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+            message
+        );
+        println!("{}", line);
+        if let Some(log) = self.log.as_mut() {
+            let _ = writeln!(log, "{}", line);
+        }
+        if self.policy == Policy::Notify {
+            notify(message);
+        }
+    }
+}
+
+/// Put a drifted file back in place, where that loses nothing
+fn repair(dotfile: &DotFile, state: &LinkState) -> Result<()> {
+    let managed = deploy::managed_path(dotfile);
+    match state {
+        LinkState::ReplacedByFile if dotfile.source.is_file() && managed.is_file() => {
+            // What the editor saved is the newest content: keep it as the
+            // managed copy, after backing up the one it replaces
+            backup::save_as(&managed, &dotfile.source, "watch")?;
+            history::copy(&dotfile.source, &managed)?;
+            history::remove_file(&dotfile.source)?;
+            deploy::deploy(DeployMode::Symlink, &managed, &dotfile.source)?;
+        }
+        LinkState::LinkMissing => {
+            if let Some(parent) = dotfile.source.parent() {
+                history::create_dir_all(parent)?;
+            }
+            deploy::deploy(dotfile.mode, &managed, &dotfile.source)?;
+            if !dotfile.mode.is_symlink() {
+                let hash = deploy::synced_hash(dotfile.mode, &dotfile.source, &managed)?;
+                config::set_synced_hash(&dotfile.target, Some(&hash))?;
+            }
+        }
+        LinkState::HardLinkBroken | LinkState::OutOfSync(Changed::Deployed | Changed::Managed) => {
+            let action = deploy::plan_sync(dotfile)?;
+            deploy::sync(dotfile, action)?;
+        }
+        LinkState::ManagedCopyMissing => {
+            bail!("the managed copy is gone; restore it with 'forge backups' or from git")
+        }
+        _ => bail!("repair it by hand"),
+    }
+    Ok(())
+}
+
+/// Show a desktop notification, if a notifier is installed
+fn notify(message: &str) {
+    let shown = std::process::Command::new("notify-send")
+        .args(["--app-name=forge", "forge", message])
+        .status()
+        .is_ok_and(|status| status.success());
+    if !shown {
+        eprintln!("Could not show a notification (is notify-send installed?)");
+    }
+}
+
+/// Watch the linked files of a managed folder until interrupted
+#[cfg(target_os = "linux")]
+pub fn run(folder_name: &str, forge_path: &Path, policy: Policy) -> Result<()> {
+    use inotify::{EventMask, Inotify, WatchMask};
+    use std::time::Duration;
+
+    // Editors save in several steps; let them finish before looking
+    const SETTLE: Duration = Duration::from_millis(200);
+
+    let mask = WatchMask::CREATE
+        | WatchMask::DELETE
+        | WatchMask::MOVED_FROM
+        | WatchMask::MOVED_TO
+        | WatchMask::CLOSE_WRITE
+        | WatchMask::ATTRIB
+        | WatchMask::DONT_FOLLOW;
+    let mut inotify = Inotify::init()?;
+    let mut watcher = Watcher::new(folder_name, forge_path, policy);
+    let mut watched = HashMap::new();
+    let mut buffer = vec![0; 64 * 1024];
+
+    let mut dotfiles = watcher.dotfiles();
+    let mut started = false;
+    loop {
+        for dir in watcher.directories(&dotfiles) {
+            match inotify.watches().add(&dir, mask) {
+                Ok(wd) => {
+                    watched.insert(wd, dir);
+                }
+                // A directory that does not exist yet has nothing to report
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                Err(e) => eprintln!("Cannot watch {}: {}", dir.display(), e),
+            }
+        }
+        if !started {
+            println!(
+                "Watching {} linked file(s) in '{}' ({} policy, Ctrl-C to stop)",
+                dotfiles.len(),
+                folder_name,
+                policy.as_str()
+            );
+            // Drift from before the watch started is handled like any other
+            watcher.check(&dotfiles);
+            started = true;
+        }
+
+        let mut changed = Vec::new();
+        let mut created = Vec::new();
+        let mut record = |events: inotify::Events| {
+            for event in events {
+                if event.mask.contains(EventMask::IGNORED) {
+                    watched.remove(&event.wd);
+                    continue;
+                }
+                let (Some(dir), Some(name)) = (watched.get(&event.wd), event.name) else {
+                    continue;
+                };
+                let path = dir.join(name);
+                if event
+                    .mask
+                    .intersects(EventMask::CREATE | EventMask::MOVED_TO)
+                {
+                    created.push(path.clone());
+                }
+                changed.push(path);
+            }
+        };
+        record(inotify.read_events_blocking(&mut buffer)?);
+        std::thread::sleep(SETTLE);
+        loop {
+            match inotify.read_events(&mut buffer) {
+                Ok(events) => record(events),
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        dotfiles = watcher.dotfiles();
+        let touched: Vec<DotFile> = dotfiles
+            .iter()
+            .filter(|d| {
+                let managed = deploy::managed_path(d);
+                changed.iter().any(|path| {
+                    *path == d.source
+                        || path.starts_with(&managed)
+                        || managed.starts_with(path)
+                        || *path == d.target
+                })
+            })
+            .cloned()
+            .collect();
+        watcher.check(&touched);
+        created.sort();
+        created.dedup();
+        for path in &created {
+            watcher.new_file(&dotfiles, path);
+        }
+    }
+}
+
+/// Watch the linked files of a managed folder until interrupted
+#[cfg(not(target_os = "linux"))]
+pub fn run(_folder_name: &str, _forge_path: &Path, _policy: Policy) -> Result<()> {
+    bail!("forge watch needs inotify, which is only available on Linux")
+}
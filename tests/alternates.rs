@@ -0,0 +1,201 @@
+// Tests for host- and OS-specific alternate files
+#![cfg(unix)]
+
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+/// Short hostname of this machine, as forge sees it
+fn hostname() -> String {
+    let name = fs::read_to_string("/proc/sys/kernel/hostname").unwrap();
+    name.trim().split('.').next().unwrap().to_string()
+}
+
+#[test]
+fn test_link_selects_and_switches_alternates() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    let dots = home.join("dots");
+    fs::create_dir_all(&dots).unwrap();
+    let deployed = home.join(".bashrc");
+    fs::write(&deployed, "plain\n").unwrap();
+
+    ctx.forge_in(&home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .arg("stage")
+        .arg(&deployed)
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .arg("link")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    assert_eq!(fs::read_link(&deployed).unwrap(), dots.join(".bashrc"));
+
+    // Variants added later are picked up by the next link
+    let host = format!(".bashrc##host.{}", hostname());
+    fs::write(dots.join(&host), "host\n").unwrap();
+    fs::write(
+        dots.join(format!(".bashrc##os.{}", std::env::consts::OS)),
+        "os\n",
+    )
+    .unwrap();
+    fs::write(dots.join(".bashrc##host.no-such-host"), "elsewhere\n").unwrap();
+    fs::write(dots.join(".bashrc##planet.mars"), "not a variant\n").unwrap();
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("links to another variant"))
+        .stdout(predicate::str::contains(format!(
+            "variant: {} (host is {})",
+            host,
+            hostname()
+        )));
+    ctx.forge_in(&home)
+        .arg("link")
+        .current_dir(&dots)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Switched variant: "));
+    assert_eq!(fs::read_link(&deployed).unwrap(), dots.join(&host));
+    assert_eq!(fs::read_to_string(&deployed).unwrap(), "host\n");
+    ctx.forge_in(&home).arg("status").assert().success();
+
+    // Variants are not untracked files
+    ctx.forge_in(&home)
+        .arg("doctor")
+        .assert()
+        .stdout(predicate::str::contains(".bashrc##host").not());
+
+    // A class and user together outrank the host
+    ctx.forge_in(&home)
+        .args(["config", "class", "work,gaming"])
+        .assert()
+        .success();
+    fs::write(dots.join(".bashrc##class.work,user.alice"), "work\n").unwrap();
+    ctx.forge_in(&home)
+        .arg("link")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(&deployed).unwrap(), "work\n");
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "variant: .bashrc##class.work,user.alice (class work is set, user is alice)",
+        ));
+
+    // Switching can be undone
+    ctx.forge_in(&home).arg("undo").assert().success();
+    assert_eq!(fs::read_to_string(&deployed).unwrap(), "host\n");
+
+    // Unlinking restores the content of the selected variant
+    ctx.forge_in(&home)
+        .arg("link")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .args(["unlink", "--yes"])
+        .arg(dots.join(".bashrc"))
+        .assert()
+        .success();
+    assert!(!fs::symlink_metadata(&deployed).unwrap().is_symlink());
+    assert_eq!(fs::read_to_string(&deployed).unwrap(), "work\n");
+}
+
+#[test]
+fn test_apply_deploys_the_selected_alternate() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    let dots = home.join("dots");
+    fs::create_dir_all(&dots).unwrap();
+    // Only variants, no plain file
+    fs::write(dots.join(".tmux.conf##default"), "default\n").unwrap();
+    fs::write(
+        dots.join(format!(".tmux.conf##os.{}", std::env::consts::OS)),
+        "os\n",
+    )
+    .unwrap();
+    fs::write(
+        dots.join("forge.toml"),
+        "[[file]]\npath = \".tmux.conf\"\ntarget = \"$HOME/.tmux.conf\"\n",
+    )
+    .unwrap();
+    ctx.forge_in(&home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+
+    ctx.forge_in(&home).arg("apply").assert().success();
+    let deployed = home.join(".tmux.conf");
+    assert_eq!(fs::read_to_string(&deployed).unwrap(), "os\n");
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "(os is {})",
+            std::env::consts::OS
+        )));
+
+    // A more specific variant added later replaces the link on the next apply
+    fs::write(dots.join(".tmux.conf##user.alice"), "alice\n").unwrap();
+    ctx.forge_in(&home)
+        .arg("apply")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Linked 1, 0 already in place"));
+    assert_eq!(fs::read_to_string(&deployed).unwrap(), "alice\n");
+}
+
+#[test]
+fn test_switch_links_the_selected_alternate() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    let profile = home.join(".forge/profiles/work");
+    let target = home.join("config");
+    fs::create_dir_all(&profile).unwrap();
+    fs::create_dir_all(&target).unwrap();
+    fs::create_dir_all(ctx.config_path()).unwrap();
+    fs::write(Path::new(ctx.config_path()).join("filetypes"), ".conf\n").unwrap();
+    fs::write(
+        Path::new(ctx.config_path()).join("default_path"),
+        target.to_str().unwrap(),
+    )
+    .unwrap();
+    fs::write(profile.join("app.conf"), "plain\n").unwrap();
+    fs::write(profile.join("app.conf##class.work"), "work\n").unwrap();
+    fs::write(profile.join("app.conf##class.home"), "home\n").unwrap();
+
+    ctx.forge_in(&home)
+        .args(["config", "class", "work"])
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .args(["switch", "work"])
+        .assert()
+        .success();
+    let deployed = target.join("app.conf");
+    assert_eq!(
+        fs::read_link(&deployed).unwrap(),
+        profile.join("app.conf##class.work")
+    );
+    assert!(!target.join("app.conf##class.home").exists());
+}
@@ -0,0 +1,199 @@
+// Tests for the backup store and forge backups
+#![cfg(unix)]
+
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+/// Stored copies in the backup store, relative to it, sorted
+fn stored(ctx: &common::TestContext) -> Vec<String> {
+    let store = Path::new(ctx.config_path()).join("backups");
+    let mut files: Vec<String> = walkdir::WalkDir::new(&store)
+        .into_iter()
+        .map(Result::unwrap)
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| {
+            let relative = entry.path().strip_prefix(&store).unwrap();
+            relative.to_string_lossy().into_owned()
+        })
+        .collect();
+    files.sort();
+    files
+}
+
+#[test]
+fn test_backups_of_similar_names_do_not_collide() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    let dots = home.join("dots");
+    fs::create_dir_all(&dots).unwrap();
+    fs::write(home.join("config.toml"), "toml").unwrap();
+    fs::write(home.join("config.json"), "json").unwrap();
+    ctx.forge_in(&home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    for file in ["config.toml", "config.json"] {
+        ctx.forge_in(&home)
+            .arg("stage")
+            .arg(home.join(file))
+            .current_dir(&dots)
+            .assert()
+            .success();
+    }
+    ctx.forge_in(&home)
+        .arg("link")
+        .current_dir(&dots)
+        .assert()
+        .success();
+
+    ctx.forge_in(&home)
+        .args(["delete", "--yes"])
+        .arg(dots.join("config.toml"))
+        .arg(dots.join("config.json"))
+        .current_dir(&dots)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Backed up "));
+    assert!(fs::symlink_metadata(home.join("config.toml")).is_err());
+
+    let files = stored(&ctx);
+    assert_eq!(files.len(), 2);
+    assert!(files.iter().any(|f| f.ends_with("/config.toml")));
+    assert!(files.iter().any(|f| f.ends_with("/config.json")));
+
+    // Backups are listed under the path the file was deployed at
+    ctx.forge_in(&home)
+        .args(["backups", "list"])
+        .arg(home.join("config.toml"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("delete"))
+        .stdout(predicate::str::contains("config.json").not());
+
+    ctx.forge_in(&home)
+        .args(["backups", "restore", "1"])
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(home.join("config.toml")).unwrap(),
+        "toml"
+    );
+
+    // Restoring can be undone
+    ctx.forge_in(&home).arg("undo").assert().success();
+    assert!(!home.join("config.toml").exists());
+
+    // A path that is a symlink now is not overwritten without --to
+    std::os::unix::fs::symlink(dots.join("elsewhere"), home.join("config.json")).unwrap();
+    ctx.forge_in(&home)
+        .args(["backups", "restore", "2"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("is a symlink now"));
+}
+
+#[test]
+fn test_link_backs_up_the_managed_copy_it_replaces() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    let dots = home.join("dots");
+    fs::create_dir_all(&dots).unwrap();
+    fs::write(home.join(".vimrc"), "new").unwrap();
+    ctx.forge_in(&home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .arg("stage")
+        .arg(home.join(".vimrc"))
+        .current_dir(&dots)
+        .assert()
+        .success();
+
+    // Something else put a real file where the staging link was
+    fs::remove_file(dots.join(".vimrc")).unwrap();
+    fs::write(dots.join(".vimrc"), "old").unwrap();
+
+    ctx.forge_in(&home)
+        .arg("link")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(dots.join(".vimrc")).unwrap(), "new");
+    assert!(!dots.join(".vimrc.bak").exists());
+
+    ctx.forge_in(&home)
+        .args(["backups", "show", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(link)"))
+        .stdout(predicate::str::contains("differs from the backup"));
+    ctx.forge_in(&home)
+        .args(["backups", "restore", "1", "--to"])
+        .arg(home.join("vimrc.old"))
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(home.join("vimrc.old")).unwrap(), "old");
+}
+
+#[test]
+fn test_prune_keeps_the_newest_backups() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    fs::create_dir_all(home.join("dots")).unwrap();
+    ctx.forge_in(&home)
+        .arg("init")
+        .current_dir(home.join("dots"))
+        .assert()
+        .success();
+    let notes = home.join("notes.txt");
+
+    // Two backups of the same path are kept side by side
+    for content in ["first", "second"] {
+        fs::write(&notes, content).unwrap();
+        ctx.forge_in(&home)
+            .args(["delete", "--yes"])
+            .arg(&notes)
+            .assert()
+            .success();
+    }
+    assert_eq!(stored(&ctx).len(), 2);
+
+    ctx.forge_in(&home)
+        .args(["backups", "prune"])
+        .assert()
+        .failure();
+    ctx.forge_in(&home)
+        .args(["backups", "prune", "--keep", "1", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Would prune: 1 "));
+    assert_eq!(stored(&ctx).len(), 2);
+
+    ctx.forge_in(&home)
+        .args(["backups", "prune", "--keep", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Pruned 1 backup(s)"));
+    assert_eq!(stored(&ctx).len(), 1);
+
+    ctx.forge_in(&home)
+        .args(["backups", "restore", "2"])
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(&notes).unwrap(), "second");
+    ctx.forge_in(&home)
+        .args(["backups", "show", "2"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("identical to the backup"));
+}
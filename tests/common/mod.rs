@@ -1,4 +1,5 @@
 // Common test utilities shared across all tests
+#![allow(dead_code)]
 
 use assert_cmd::Command;
 use assert_fs::TempDir;
@@ -8,6 +9,15 @@ use std::sync::Mutex;
 /// Global counter for test database isolation
 static TEST_DB_COUNTER: Mutex<u32> = Mutex::new(0);
 
+/// Identity for commits made in tests
+pub const GIT_ENV: &[(&str, &str)] = &[
+    ("GIT_AUTHOR_NAME", "Alice"),
+    ("GIT_AUTHOR_EMAIL", "alice@example.com"),
+    ("GIT_COMMITTER_NAME", "Alice"),
+    ("GIT_COMMITTER_EMAIL", "alice@example.com"),
+    ("GIT_CONFIG_NOSYSTEM", "1"),
+];
+
 /// Test context that maintains database paths for a single test
 pub struct TestContext {
     db_path: String,
@@ -53,6 +63,28 @@ impl TestContext {
         cmd
     }
 
+    /// Create a forge command with this test's isolated database, run as
+    /// alice with `home` as the home directory
+    pub fn forge_in(&self, home: &Path) -> Command {
+        Command::from_std(self.process_in(home))
+    }
+
+    /// Like `forge_in`, as a plain process for commands that are spawned and
+    /// stopped rather than run to completion
+    pub fn process_in(&self, home: &Path) -> std::process::Command {
+        let mut cmd = std::process::Command::new(assert_cmd::cargo::cargo_bin("forge"));
+        cmd.env("FORGE_TEST_DB", &self.db_path)
+            .env("FORGE_TEST_CONFIG_DIR", &self.config_path)
+            .env("HOME", home)
+            .env("USER", "alice")
+            .env_remove("XDG_CONFIG_HOME")
+            .env("FORGE_PASSPHRASE", "correct horse")
+            .env_remove("FORGE_NEW_PASSPHRASE")
+            .env_remove("VISUAL")
+            .envs(GIT_ENV.iter().copied());
+        cmd
+    }
+
     /// Initialize a forge repository in a temporary directory with isolated database
     pub fn init_forge_repo(&self, temp: &TempDir) -> Result<(), Box<dyn std::error::Error>> {
         self.forge_cmd()
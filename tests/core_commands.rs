@@ -1,10 +1,8 @@
 // Integration tests for core forge commands: init, stage, link, unlink, remove, delete
 
-use assert_cmd::prelude::*;
 use assert_fs::TempDir;
 use assert_fs::prelude::*;
 use predicates::prelude::*;
-use std::fs;
 
 mod common;
 
@@ -0,0 +1,224 @@
+// Tests for hard-linked and copied dotfiles and forge sync
+#![cfg(unix)]
+
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+mod common;
+
+/// A home with ~/dots initialized and `file` staged in it
+fn setup(ctx: &common::TestContext, home: &Path, file: &str, stage_args: &[&str]) {
+    let dots = home.join("dots");
+    fs::create_dir_all(&dots).unwrap();
+    ctx.forge_in(home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(home)
+        .arg("stage")
+        .args(stage_args)
+        .arg(home.join(file))
+        .current_dir(&dots)
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_copy_mode_link_sync_and_unlink() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    fs::create_dir_all(&home).unwrap();
+    fs::write(home.join(".gitconfig"), "[user]\n").unwrap();
+    setup(&ctx, &home, ".gitconfig", &["--mode", "copy"]);
+    let (deployed, managed) = (home.join(".gitconfig"), home.join("dots/.gitconfig"));
+
+    ctx.forge_in(&home)
+        .arg("link")
+        .current_dir(home.join("dots"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Created copy: "));
+    assert!(!fs::symlink_metadata(&deployed).unwrap().is_symlink());
+    assert_eq!(fs::read_to_string(&managed).unwrap(), "[user]\n");
+    assert!(
+        fs::read_to_string(home.join("dots/forge.toml"))
+            .unwrap()
+            .contains("mode = \"copy\"")
+    );
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(copy): OK"));
+
+    // An edit of the deployed copy is carried into the managed folder
+    fs::write(&deployed, "[user]\nemail = a@b\n").unwrap();
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("deployed file changed"));
+    ctx.forge_in(&home)
+        .args(["sync", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Would copy: "));
+    assert_eq!(fs::read_to_string(&managed).unwrap(), "[user]\n");
+    ctx.forge_in(&home)
+        .arg("sync")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Synced 1, 0 already in sync"));
+    assert_eq!(
+        fs::read_to_string(&managed).unwrap(),
+        "[user]\nemail = a@b\n"
+    );
+
+    // ...and an edit of the managed copy the other way
+    fs::write(&managed, "[user]\nemail = c@d\n").unwrap();
+    ctx.forge_in(&home).arg("sync").assert().success();
+    assert_eq!(
+        fs::read_to_string(&deployed).unwrap(),
+        "[user]\nemail = c@d\n"
+    );
+
+    // Both sides changed: nothing is overwritten
+    fs::write(&deployed, "deployed").unwrap();
+    fs::write(&managed, "managed").unwrap();
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("both sides changed"));
+    ctx.forge_in(&home)
+        .arg("sync")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Conflict: "));
+    assert_eq!(fs::read_to_string(&deployed).unwrap(), "deployed");
+    assert_eq!(fs::read_to_string(&managed).unwrap(), "managed");
+
+    // Unlinking would lose the deployed changes until they are synced
+    ctx.forge_in(&home)
+        .args(["unlink", "--yes"])
+        .arg(&managed)
+        .assert()
+        .stderr(predicate::str::contains("run 'forge sync' first"));
+    fs::write(&deployed, "managed").unwrap();
+    ctx.forge_in(&home).arg("sync").assert().success();
+    ctx.forge_in(&home)
+        .args(["unlink", "--yes"])
+        .arg(&managed)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Removed copy and restored file"));
+    assert_eq!(fs::read_to_string(&deployed).unwrap(), "managed");
+    ctx.forge_in(&home)
+        .arg("sync")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No hard-linked or copied files"));
+}
+
+#[test]
+fn test_hardlink_mode_and_repair() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    fs::create_dir_all(home.join(".config/app")).unwrap();
+    fs::write(home.join(".tmux.conf"), "set -g mouse on\n").unwrap();
+    setup(&ctx, &home, ".tmux.conf", &[]);
+    let (deployed, managed) = (home.join(".tmux.conf"), home.join("dots/.tmux.conf"));
+
+    ctx.forge_in(&home)
+        .args(["link", "--mode", "hardlink"])
+        .current_dir(home.join("dots"))
+        .assert()
+        .success();
+    assert_eq!(
+        fs::metadata(&deployed).unwrap().ino(),
+        fs::metadata(&managed).unwrap().ino()
+    );
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(hardlink): OK"));
+
+    // An editor that saves by replacing the file breaks the hard link
+    fs::remove_file(&deployed).unwrap();
+    fs::write(&deployed, "set -g mouse off\n").unwrap();
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("deployed file changed"));
+    ctx.forge_in(&home).arg("sync").assert().success();
+    assert_eq!(fs::read_to_string(&managed).unwrap(), "set -g mouse off\n");
+    assert_eq!(
+        fs::metadata(&deployed).unwrap().ino(),
+        fs::metadata(&managed).unwrap().ino()
+    );
+
+    // Syncing can be undone like any other command
+    ctx.forge_in(&home).arg("undo").assert().success();
+    assert_eq!(fs::read_to_string(&managed).unwrap(), "set -g mouse on\n");
+    assert_eq!(fs::read_to_string(&deployed).unwrap(), "set -g mouse off\n");
+
+    // Directories cannot be hard linked
+    ctx.forge_in(&home)
+        .args(["stage", "--mode", "hardlink"])
+        .arg(home.join(".config/app"))
+        .current_dir(home.join("dots"))
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .arg("link")
+        .current_dir(home.join("dots"))
+        .assert()
+        .stderr(predicate::str::contains("cannot be hard linked"));
+    assert!(home.join(".config/app").is_dir());
+}
+
+#[test]
+fn test_apply_deploys_copies_declared_in_the_manifest() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    let dots = home.join("dots");
+    fs::create_dir_all(&dots).unwrap();
+    fs::write(dots.join("settings.json"), "{}").unwrap();
+    fs::write(
+        dots.join("forge.toml"),
+        "[[file]]\npath = \"settings.json\"\ntarget = \"$HOME/settings.json\"\nmode = \"copy\"\n",
+    )
+    .unwrap();
+    ctx.forge_in(&home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+
+    ctx.forge_in(&home).arg("apply").assert().success();
+    let deployed = home.join("settings.json");
+    assert!(!fs::symlink_metadata(&deployed).unwrap().is_symlink());
+    assert_eq!(fs::read_to_string(&deployed).unwrap(), "{}");
+
+    // Applying again finds the copy in place
+    ctx.forge_in(&home)
+        .arg("apply")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Linked 0, 1 already in place"));
+    fs::write(&deployed, "{\"a\": 1}").unwrap();
+    ctx.forge_in(&home).arg("sync").assert().success();
+    assert_eq!(
+        fs::read_to_string(dots.join("settings.json")).unwrap(),
+        "{\"a\": 1}"
+    );
+}
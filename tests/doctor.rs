@@ -0,0 +1,173 @@
+// Integration tests for forge doctor
+
+use assert_fs::TempDir;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+
+mod common;
+
+fn stage_and_link(ctx: &common::TestContext, managed: &TempDir, file: &std::path::Path) {
+    ctx.forge_cmd()
+        .arg("stage")
+        .arg(file)
+        .current_dir(managed.path())
+        .assert()
+        .success();
+    ctx.forge_cmd()
+        .arg("link")
+        .current_dir(managed.path())
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_doctor_reports_healthy_setup() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let source = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+
+    let file = source.child("app.conf");
+    file.write_str("content").unwrap();
+    stage_and_link(&ctx, &managed, file.path());
+
+    ctx.forge_cmd()
+        .arg("doctor")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No problems found"));
+}
+
+#[test]
+fn test_doctor_fix_completes_interrupted_link() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let source = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+
+    let file = source.child("app.conf");
+    file.write_str("content").unwrap();
+    stage_and_link(&ctx, &managed, file.path());
+
+    // Simulate a link that died after removing the original but before symlinking
+    fs::remove_file(file.path()).unwrap();
+
+    ctx.forge_cmd()
+        .arg("doctor")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("Interrupted link"));
+
+    ctx.forge_cmd()
+        .arg("doctor")
+        .arg("--fix")
+        .assert()
+        .success();
+
+    assert!(file.path().is_symlink());
+    assert_eq!(fs::read_to_string(file.path()).unwrap(), "content");
+    ctx.forge_cmd().arg("doctor").assert().success();
+}
+
+#[test]
+fn test_doctor_fix_deactivates_record_with_dangling_staging_symlink() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let source = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+
+    let file = source.child("app.conf");
+    file.write_str("content").unwrap();
+    ctx.forge_cmd()
+        .arg("stage")
+        .arg(file.path())
+        .current_dir(managed.path())
+        .assert()
+        .success();
+
+    fs::remove_file(file.path()).unwrap();
+
+    ctx.forge_cmd()
+        .arg("doctor")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("Record with no files"));
+
+    ctx.forge_cmd()
+        .arg("doctor")
+        .arg("--fix")
+        .assert()
+        .success();
+
+    assert!(!managed.path().join("app.conf").is_symlink());
+    ctx.forge_cmd().arg("doctor").assert().success();
+}
+
+#[test]
+fn test_doctor_fix_moves_untracked_managed_file_aside() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+
+    let stray = managed.child("stray.conf");
+    stray.write_str("stray").unwrap();
+
+    ctx.forge_cmd()
+        .arg("doctor")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("Managed file with no record"));
+
+    ctx.forge_cmd()
+        .arg("doctor")
+        .arg("--fix")
+        .assert()
+        .success();
+
+    assert!(!stray.path().exists());
+    let moved = managed.path().join(".forge/orphans/stray.conf");
+    assert_eq!(fs::read_to_string(moved).unwrap(), "stray");
+}
+
+#[test]
+fn test_doctor_reports_orphaned_duplicate_records_once() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let source = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+
+    let file = source.child("app.conf");
+    file.write_str("content").unwrap();
+    ctx.forge_cmd()
+        .arg("stage")
+        .arg(file.path())
+        .current_dir(managed.path())
+        .assert()
+        .success();
+
+    // A second row for the same file, whose staging symlink then dangles
+    let conn = rusqlite::Connection::open(ctx.db_path()).unwrap();
+    conn.execute_batch(
+        "CREATE TEMP TABLE copy AS SELECT * FROM dotfiles;
+        UPDATE copy SET id = NULL;
+        INSERT INTO dotfiles SELECT * FROM copy;",
+    )
+    .unwrap();
+    drop(conn);
+    fs::remove_file(file.path()).unwrap();
+
+    let output = ctx.forge_cmd().arg("doctor").output().unwrap();
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(!output.status.success());
+    assert_eq!(stdout.matches("Record with no files").count(), 1);
+    assert!(!stdout.contains("records for one target"));
+
+    ctx.forge_cmd()
+        .arg("doctor")
+        .arg("--fix")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 fixed, 0 failed"));
+    ctx.forge_cmd().arg("doctor").assert().success();
+}
@@ -0,0 +1,282 @@
+// Tests for managed folder selection: forge folders and --folder
+
+use assert_fs::TempDir;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+
+mod common;
+
+fn init_named(ctx: &common::TestContext, dir: &TempDir, name: &str) {
+    ctx.forge_cmd()
+        .arg("init")
+        .arg("--name")
+        .arg(name)
+        .current_dir(dir.path())
+        .assert()
+        .success();
+}
+
+fn stage(ctx: &common::TestContext, file: &std::path::Path) -> assert_cmd::assert::Assert {
+    ctx.forge_cmd().arg("stage").arg(file).assert().success()
+}
+
+#[test]
+fn test_folders_use_switches_where_files_are_staged() {
+    let ctx = common::TestContext::new();
+    let first = TempDir::new().unwrap();
+    let second = TempDir::new().unwrap();
+    let source = TempDir::new().unwrap();
+    init_named(&ctx, &first, "first");
+    init_named(&ctx, &second, "second");
+
+    let a = source.child("a.conf");
+    a.write_str("a").unwrap();
+    stage(&ctx, a.path());
+    assert!(first.path().join("a.conf").is_symlink());
+
+    ctx.forge_cmd()
+        .args(["folders", "use", "second"])
+        .assert()
+        .success();
+    ctx.forge_cmd()
+        .args(["folders", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("* second"));
+
+    let b = source.child("b.conf");
+    b.write_str("b").unwrap();
+    stage(&ctx, b.path());
+    assert!(second.path().join("b.conf").is_symlink());
+    assert!(!first.path().join("b.conf").exists());
+}
+
+#[test]
+fn test_folder_flag_overrides_active_folder_for_one_command() {
+    let ctx = common::TestContext::new();
+    let first = TempDir::new().unwrap();
+    let second = TempDir::new().unwrap();
+    let source = TempDir::new().unwrap();
+    init_named(&ctx, &first, "first");
+    init_named(&ctx, &second, "second");
+
+    let a = source.child("a.conf");
+    a.write_str("a").unwrap();
+    ctx.forge_cmd()
+        .arg("stage")
+        .arg(a.path())
+        .arg("--folder")
+        .arg("second")
+        .assert()
+        .success();
+    assert!(second.path().join("a.conf").is_symlink());
+
+    // The override does not stick
+    let b = source.child("b.conf");
+    b.write_str("b").unwrap();
+    stage(&ctx, b.path());
+    assert!(first.path().join("b.conf").is_symlink());
+
+    // A folder path given to purge is not mistaken for the global flag
+    ctx.forge_cmd()
+        .arg("purge")
+        .arg(second.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Purged 1 database records."));
+    assert!(a.path().is_file());
+    assert!(first.path().join("b.conf").is_symlink());
+
+    ctx.forge_cmd()
+        .args(["--folder", "missing", "list"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "No managed folder named 'missing'",
+        ));
+}
+
+#[test]
+fn test_folders_rename_remove_and_set_default() {
+    let ctx = common::TestContext::new();
+    let first = TempDir::new().unwrap();
+    let second = TempDir::new().unwrap();
+    let source = TempDir::new().unwrap();
+    init_named(&ctx, &first, "first");
+    init_named(&ctx, &second, "second");
+
+    ctx.forge_cmd()
+        .args(["folders", "use", "first"])
+        .assert()
+        .success();
+    ctx.forge_cmd()
+        .args(["folders", "rename", "first", "main"])
+        .assert()
+        .success();
+    ctx.forge_cmd()
+        .args(["folders", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("* main"));
+
+    // A folder that still tracks files cannot be forgotten
+    let a = source.child("a.conf");
+    a.write_str("a").unwrap();
+    stage(&ctx, a.path());
+    ctx.forge_cmd()
+        .args(["folders", "remove", "main"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("still tracks 1 file"));
+
+    ctx.forge_cmd()
+        .args(["folders", "set-default", "second"])
+        .assert()
+        .success();
+    ctx.forge_cmd().arg("unstage").assert().success();
+    ctx.forge_cmd()
+        .args(["folders", "remove", "main"])
+        .assert()
+        .success();
+
+    // With the active folder gone, the default takes over
+    ctx.forge_cmd()
+        .args(["folders", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("* second"))
+        .stdout(predicate::str::contains("(default)"))
+        .stdout(predicate::str::contains("main").not());
+    assert!(first.path().exists());
+}
+
+#[test]
+fn test_legacy_managed_folders_file_is_imported() {
+    let ctx = common::TestContext::new();
+    let legacy = TempDir::new().unwrap();
+    fs::create_dir_all(ctx.config_path()).unwrap();
+    let file = std::path::Path::new(ctx.config_path()).join("managed_folders");
+    fs::write(&file, format!("dots:{}\n", legacy.path().display())).unwrap();
+
+    ctx.forge_cmd()
+        .args(["folders", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("* dots"));
+    assert!(!file.exists());
+
+    // Removing the folder must not bring it back on the next run
+    ctx.forge_cmd()
+        .args(["folders", "remove", "dots"])
+        .assert()
+        .success();
+    ctx.forge_cmd()
+        .args(["folders", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No managed folders"));
+}
+
+#[test]
+fn test_list_link_and_unstage_only_touch_the_active_folder() {
+    let ctx = common::TestContext::new();
+    let first = TempDir::new().unwrap();
+    let second = TempDir::new().unwrap();
+    let source = TempDir::new().unwrap();
+    init_named(&ctx, &first, "first");
+    init_named(&ctx, &second, "second");
+
+    let a = source.child("a.conf");
+    a.write_str("a").unwrap();
+    stage(&ctx, a.path());
+    ctx.forge_cmd()
+        .args(["folders", "use", "second"])
+        .assert()
+        .success();
+    let b = source.child("b.conf");
+    b.write_str("b").unwrap();
+    stage(&ctx, b.path());
+
+    ctx.forge_cmd()
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("b.conf"))
+        .stdout(predicate::str::contains("a.conf").not());
+
+    // Linking everything staged only links the active folder's files
+    ctx.forge_cmd().arg("link").assert().success();
+    assert!(b.path().is_symlink());
+    assert!(!a.path().is_symlink());
+
+    // Unstaging everything in the other folder leaves the active folder alone
+    ctx.forge_cmd()
+        .args(["--folder", "first", "unstage"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Unstaged 1 files"));
+    assert!(!first.path().join("a.conf").exists());
+    ctx.forge_cmd()
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("b.conf"));
+}
+
+#[test]
+fn test_existing_rows_are_assigned_to_folders_by_target_path() {
+    let ctx = common::TestContext::new();
+    let dots = TempDir::new().unwrap();
+    let work = TempDir::new().unwrap();
+
+    // A database and folder list written before rows recorded their folder
+    let conn = rusqlite::Connection::open(ctx.db_path()).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE dotfiles (
+            id INTEGER PRIMARY KEY,
+            source TEXT NOT NULL,
+            target TEXT NOT NULL,
+            profile TEXT,
+            status TEXT NOT NULL DEFAULT 'staged',
+            active BOOLEAN NOT NULL DEFAULT 1,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        );",
+    )
+    .unwrap();
+    for (source, target) in [
+        ("/home/u/.vimrc", dots.path().join(".vimrc")),
+        ("/home/u/.gitconfig", work.path().join(".gitconfig")),
+    ] {
+        conn.execute(
+            "INSERT INTO dotfiles (source, target) VALUES (?, ?)",
+            [source, &target.to_string_lossy()],
+        )
+        .unwrap();
+    }
+    drop(conn);
+
+    fs::create_dir_all(ctx.config_path()).unwrap();
+    fs::write(
+        std::path::Path::new(ctx.config_path()).join("managed_folders"),
+        format!(
+            "dots:{}\nwork:{}\n",
+            dots.path().display(),
+            work.path().display()
+        ),
+    )
+    .unwrap();
+
+    ctx.forge_cmd()
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(".vimrc"))
+        .stdout(predicate::str::contains(".gitconfig").not());
+    ctx.forge_cmd()
+        .args(["--folder", "work", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(".gitconfig"))
+        .stdout(predicate::str::contains(".vimrc").not());
+}
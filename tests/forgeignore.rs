@@ -0,0 +1,154 @@
+// Tests for .forgeignore rules and forge check-ignore
+#![cfg(unix)]
+
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+mod common;
+
+/// An nvim config with logs and a cache, a managed folder that ignores them
+/// and a global rule for lock files
+fn setup(ctx: &common::TestContext, root: &Path) -> (PathBuf, PathBuf) {
+    let home = root.join("home");
+    let dots = home.join("dots");
+    let nvim = home.join(".config/nvim");
+    fs::create_dir_all(nvim.join("cache")).unwrap();
+    fs::write(nvim.join("init.lua"), "-- nvim\n").unwrap();
+    fs::write(nvim.join("lazy-lock.json"), "{}\n").unwrap();
+    fs::write(nvim.join("debug.log"), "noise\n").unwrap();
+    fs::write(nvim.join("keep.log"), "wanted\n").unwrap();
+    fs::write(nvim.join("cache/state"), "x\n").unwrap();
+
+    ctx.forge_in(&home)
+        .args(["init", "--dir"])
+        .arg(&dots)
+        .assert()
+        .success();
+    fs::write(
+        dots.join(".forgeignore"),
+        "# noise\n*.log\n!keep.log\ncache/\n",
+    )
+    .unwrap();
+    fs::write(
+        Path::new(ctx.config_path()).join("forgeignore"),
+        "lazy-lock.json\n",
+    )
+    .unwrap();
+    (home, dots)
+}
+
+#[test]
+fn test_stage_skips_ignored_files_and_check_ignore_explains() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let (home, dots) = setup(&ctx, root.path());
+    let nvim = home.join(".config/nvim");
+
+    ctx.forge_in(&home)
+        .args(["stage", "-r"])
+        .arg(&nvim)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Ignored: "));
+    assert!(dots.join("nvim/init.lua").exists());
+    assert!(dots.join("nvim/keep.log").exists());
+    assert!(!dots.join("nvim/debug.log").exists());
+    assert!(!dots.join("nvim/cache").exists());
+    assert!(!dots.join("nvim/lazy-lock.json").exists());
+
+    // A file outside the folder is checked where staging would put it
+    let ignore_file = dots.join(".forgeignore");
+    ctx.forge_in(&home)
+        .arg("check-ignore")
+        .arg(home.join("trace.log"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "is ignored by {}:2: *.log",
+            ignore_file.display()
+        )));
+    ctx.forge_in(&home)
+        .arg("check-ignore")
+        .arg(dots.join("nvim/keep.log"))
+        .assert()
+        .code(1)
+        .stdout(predicate::str::contains(format!(
+            "is not ignored: {}:3: !keep.log re-includes it",
+            ignore_file.display()
+        )));
+    ctx.forge_in(&home)
+        .arg("check-ignore")
+        .arg(dots.join("nvim/lazy-lock.json"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("forgeignore:1: lazy-lock.json"));
+    ctx.forge_in(&home)
+        .arg("check-ignore")
+        .arg(dots.join(".forge"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("built-in: .forge/"));
+    ctx.forge_in(&home)
+        .arg("check-ignore")
+        .arg(dots.join("nvim/init.lua"))
+        .assert()
+        .code(1)
+        .stdout(predicate::str::ends_with("is not ignored\n"));
+
+    // Nothing inside an ignored directory can be re-included
+    fs::write(dots.join(".forgeignore"), "cache/\n!cache/state\n").unwrap();
+    ctx.forge_in(&home)
+        .arg("check-ignore")
+        .arg(dots.join("nvim/cache/state"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(":1: cache/"));
+}
+
+#[test]
+fn test_pack_and_status_follow_ignore_rules() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let (home, dots) = setup(&ctx, root.path());
+    let nvim = home.join(".config/nvim");
+
+    ctx.forge_in(&home)
+        .args(["start", "packing", "editor"])
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .args(["pack", "--scope", "editor", "-r"])
+        .arg(&nvim)
+        .current_dir(&dots)
+        .assert()
+        .success();
+    let files = dots.join(".forge/tmp/pack/editor/files/nvim");
+    assert!(files.join("init.lua").exists());
+    assert!(files.join("keep.log").exists());
+    assert!(!files.join("debug.log").exists());
+    assert!(!files.join("cache").exists());
+    assert!(!files.join("lazy-lock.json").exists());
+
+    // A file tracked before a rule excluded it is left out of the check
+    let vimrc = home.join(".vimrc");
+    fs::write(&vimrc, "set number\n").unwrap();
+    ctx.forge_in(&home)
+        .arg("stage")
+        .arg(&vimrc)
+        .assert()
+        .success();
+    ctx.forge_in(&home).arg("link").assert().success();
+    fs::remove_file(&vimrc).unwrap();
+    ctx.forge_in(&home).arg("status").assert().failure();
+
+    fs::write(dots.join(".forgeignore"), ".vimrc\n").unwrap();
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[ignored] "))
+        .stdout(predicate::str::contains("0 drifted, 1 ignored"));
+}
@@ -0,0 +1,183 @@
+// Tests for managed folders kept in git
+#![cfg(unix)]
+
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+fn git(dir: &Path, home: &Path, args: &[&str]) -> String {
+    let output = std::process::Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .env("HOME", home)
+        .envs(common::GIT_ENV.iter().copied())
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "git {:?} failed: {:?}",
+        args,
+        output
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn test_init_git_auto_commit_and_status_warning() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    let dots = home.join("dots");
+    fs::create_dir_all(&home).unwrap();
+    fs::write(home.join(".bashrc"), "alias ll='ls -l'\n").unwrap();
+
+    ctx.forge_in(&home)
+        .args(["init", "--git", "--dir"])
+        .arg(&dots)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Initialized git repository"));
+    assert!(dots.join(".git").is_dir());
+    assert_eq!(
+        fs::read_to_string(dots.join(".gitignore")).unwrap(),
+        ".forge/\n"
+    );
+
+    ctx.forge_in(&home)
+        .args(["config", "git_auto_commit", "true"])
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .arg("stage")
+        .arg(home.join(".bashrc"))
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .arg("link")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Committed: forge link: "));
+    let log = git(&dots, &home, &["log", "--format=%s", "--name-only"]);
+    assert!(log.contains(".bashrc"), "{}", log);
+    assert!(!log.contains(".forge/"), "{}", log);
+
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("uncommitted").not());
+
+    // Edits through the symlink show up as uncommitted, without failing status
+    fs::write(home.join(".bashrc"), "alias la='ls -a'\n").unwrap();
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Warning: 1 uncommitted change(s) in managed folder 'dots'",
+        ))
+        .stdout(predicate::str::contains(" M .bashrc"));
+
+    // Passthrough keeps git's exit code
+    ctx.forge_in(&home)
+        .args(["git", "commit", "-qam", "Edit bashrc"])
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .args(["git", "rev-parse", "--verify", "no-such-branch"])
+        .assert()
+        .code(128);
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("uncommitted").not());
+}
+
+#[test]
+fn test_sync_with_bare_remote() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    let dots = home.join("dots");
+    let remote = root.path().join("remote.git");
+    fs::create_dir_all(&home).unwrap();
+    fs::write(home.join(".vimrc"), "set number\n").unwrap();
+    fs::write(home.join(".gitconfig"), "[core]\n").unwrap();
+    git(
+        root.path(),
+        &home,
+        &["init", "--quiet", "--bare", "remote.git"],
+    );
+
+    ctx.forge_in(&home)
+        .args(["init", "--git", "--dir"])
+        .arg(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .args(["git", "remote", "add", "origin"])
+        .arg(&remote)
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .arg("stage")
+        .arg(home.join(".vimrc"))
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .args(["stage", "--mode", "copy"])
+        .arg(home.join(".gitconfig"))
+        .assert()
+        .success();
+    ctx.forge_in(&home).arg("link").assert().success();
+
+    ctx.forge_in(&home)
+        .args(["sync", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Would commit changes"));
+    ctx.forge_in(&home)
+        .arg("sync")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Committed: forge sync: "))
+        .stdout(predicate::str::contains("Pulled").not())
+        .stdout(predicate::str::contains("Pushed to origin"));
+
+    // Another machine pushes a change
+    let other = root.path().join("other");
+    git(
+        root.path(),
+        &home,
+        &["clone", "--quiet", remote.to_str().unwrap(), "other"],
+    );
+    fs::write(other.join(".vimrc"), "set number\nset hlsearch\n").unwrap();
+    fs::write(other.join(".gitconfig"), "[core]\n\teditor = vim\n").unwrap();
+    git(&other, &home, &["commit", "--quiet", "-am", "Tweak"]);
+    git(&other, &home, &["push", "--quiet"]);
+
+    // Local changes are committed and rebased onto it
+    fs::write(dots.join("notes"), "local\n").unwrap();
+    ctx.forge_in(&home)
+        .arg("sync")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Pulled from origin"))
+        .stdout(predicate::str::contains("run 'forge link'"));
+    assert_eq!(
+        fs::read_to_string(home.join(".vimrc")).unwrap(),
+        "set number\nset hlsearch\n"
+    );
+    // The pulled copy is deployed again
+    assert_eq!(
+        fs::read_to_string(home.join(".gitconfig")).unwrap(),
+        "[core]\n\teditor = vim\n"
+    );
+    let log = git(&remote, &home, &["log", "--format=%s"]);
+    assert_eq!(log.lines().next(), Some("forge sync: notes"), "{}", log);
+}
@@ -0,0 +1,232 @@
+// Tests for the operation history and forge undo
+
+use assert_fs::TempDir;
+use assert_fs::prelude::*;
+use predicates::prelude::*;
+use std::fs;
+
+mod common;
+
+fn stage(ctx: &common::TestContext, managed: &TempDir, file: &std::path::Path) {
+    ctx.forge_cmd()
+        .arg("stage")
+        .arg(file)
+        .current_dir(managed.path())
+        .assert()
+        .success();
+}
+
+fn link(ctx: &common::TestContext, managed: &TempDir) {
+    ctx.forge_cmd()
+        .arg("link")
+        .current_dir(managed.path())
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_undo_link_then_stage() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let source_dir = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+
+    let source = source_dir.child("app.conf");
+    source.write_str("content").unwrap();
+    let target = managed.path().join("app.conf");
+    stage(&ctx, &managed, source.path());
+    link(&ctx, &managed);
+    assert!(source.path().is_symlink());
+
+    // Undoing the link puts the original file back and restores the staging symlink
+    ctx.forge_cmd()
+        .arg("undo")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("undone"));
+    assert!(!source.path().is_symlink());
+    assert_eq!(fs::read_to_string(source.path()).unwrap(), "content");
+    assert!(target.is_symlink());
+    ctx.forge_cmd()
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[Staged]"));
+
+    // Undoing the stage removes the staging symlink and the record
+    ctx.forge_cmd().arg("undo").assert().success();
+    assert!(!target.exists() && !target.is_symlink());
+    assert_eq!(fs::read_to_string(source.path()).unwrap(), "content");
+    ctx.forge_cmd()
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("app.conf").not());
+}
+
+#[test]
+fn test_undo_delete_restores_files_from_backup() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let source_dir = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+
+    let source = source_dir.child("app.conf");
+    source.write_str("precious").unwrap();
+    let target = managed.path().join("app.conf");
+    stage(&ctx, &managed, source.path());
+    link(&ctx, &managed);
+
+    ctx.forge_cmd()
+        .arg("delete")
+        .arg("--yes")
+        .arg(&target)
+        .current_dir(managed.path())
+        .assert()
+        .success();
+    assert!(!target.exists());
+    assert!(!source.path().is_symlink());
+
+    ctx.forge_cmd().arg("undo").assert().success();
+    assert_eq!(fs::read_to_string(&target).unwrap(), "precious");
+    assert!(source.path().is_symlink());
+    assert_eq!(fs::read_to_string(source.path()).unwrap(), "precious");
+    ctx.forge_cmd().arg("status").assert().success();
+}
+
+#[test]
+fn test_undo_refuses_to_discard_later_edits() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let source_dir = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+
+    let source = source_dir.child("app.conf");
+    source.write_str("content").unwrap();
+    stage(&ctx, &managed, source.path());
+    link(&ctx, &managed);
+
+    // Edit the managed file through the symlink after linking
+    fs::write(source.path(), "edited").unwrap();
+
+    ctx.forge_cmd()
+        .arg("undo")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("modified since"))
+        .stderr(predicate::str::contains("left as it was"));
+    assert_eq!(
+        fs::read_to_string(managed.path().join("app.conf")).unwrap(),
+        "edited"
+    );
+
+    // What was reverted before the refusal is redone, so the link is intact
+    assert!(source.path().is_symlink());
+    ctx.forge_cmd()
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[Linked]"));
+    ctx.forge_cmd().arg("status").assert().success();
+    ctx.forge_cmd()
+        .arg("history")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(undone)").not());
+}
+
+#[test]
+fn test_history_lists_operations_and_undo_by_id() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let source_dir = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+
+    let first = source_dir.child("first.conf");
+    first.write_str("1").unwrap();
+    let second = source_dir.child("second.conf");
+    second.write_str("2").unwrap();
+    stage(&ctx, &managed, first.path());
+    stage(&ctx, &managed, second.path());
+
+    ctx.forge_cmd()
+        .arg("history")
+        .arg("--changes")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("forge stage"))
+        .stdout(predicate::str::contains("created symlink"));
+
+    // Undo the older of the two operations
+    ctx.forge_cmd().arg("undo").arg("1").assert().success();
+    assert!(!managed.path().join("first.conf").is_symlink());
+    assert!(managed.path().join("second.conf").is_symlink());
+
+    ctx.forge_cmd()
+        .arg("undo")
+        .arg("1")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("already been undone"));
+    ctx.forge_cmd()
+        .arg("history")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(undone)"));
+}
+
+#[test]
+fn test_history_prune_deletes_old_operations_and_their_copies() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let source_dir = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+
+    let source = source_dir.child("app.conf");
+    source.write_str("precious").unwrap();
+    let target = managed.path().join("app.conf");
+    stage(&ctx, &managed, source.path());
+    link(&ctx, &managed);
+    ctx.forge_cmd()
+        .arg("delete")
+        .arg("--yes")
+        .arg(&target)
+        .current_dir(managed.path())
+        .assert()
+        .success();
+    let copies = std::path::Path::new(ctx.config_path()).join("history");
+    assert_eq!(fs::read_dir(&copies).unwrap().count(), 2);
+
+    ctx.forge_cmd()
+        .args(["history", "--keep", "1", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Would prune: 1 (forge stage"))
+        .stdout(predicate::str::contains("Would prune 2 operation(s)"));
+    ctx.forge_cmd()
+        .args(["history", "--dry-run"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Nothing to prune by"));
+    ctx.forge_cmd()
+        .args(["history", "--keep", "1"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Pruned 2 operation(s)"));
+    assert_eq!(fs::read_dir(&copies).unwrap().count(), 1);
+    ctx.forge_cmd()
+        .arg("history")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("forge delete"))
+        .stdout(predicate::str::contains("forge link").not());
+
+    // The kept operation can still be undone from its copies
+    ctx.forge_cmd().arg("undo").assert().success();
+    assert_eq!(fs::read_to_string(&target).unwrap(), "precious");
+    ctx.forge_cmd()
+        .arg("undo")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No operation to undo"));
+}
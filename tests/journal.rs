@@ -0,0 +1,412 @@
+// Tests for crash recovery of journaled link/unlink operations
+
+use assert_fs::TempDir;
+use assert_fs::prelude::*;
+use forge_rs::journal::Journal;
+use forge_rs::utils::hash;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+fn write_journal(
+    forge_path: &Path,
+    operation: &str,
+    source: &Path,
+    target: &Path,
+    completed: usize,
+) {
+    let dir = forge_path.join(".forge/journal");
+    fs::create_dir_all(&dir).unwrap();
+    let hash_of = if operation == "link" && source.exists() {
+        hash::path_hash(source).unwrap()
+    } else {
+        hash::path_hash(target).unwrap()
+    };
+    let content = format!(
+        "operation = \"{}\"\nsource = \"{}\"\ntarget = \"{}\"\nhash = \"{}\"\ncompleted = {}\n",
+        operation,
+        source.display(),
+        target.display(),
+        hash_of,
+        completed
+    );
+    fs::write(dir.join("20250101000000000000000-1.toml"), content).unwrap();
+}
+
+#[test]
+fn test_interrupted_link_before_copy_is_rolled_back() {
+    let temp = TempDir::new().unwrap();
+    let source = temp.child("app.conf");
+    source.write_str("original").unwrap();
+    let forge = temp.child("forge");
+    forge.create_dir_all().unwrap();
+    let target = forge.path().join("app.conf");
+
+    // Staging symlink already removed, copy was cut off half way
+    write_journal(forge.path(), "link", source.path(), &target, 2);
+    fs::write(&target, "orig").unwrap();
+
+    let journal_file = forge
+        .path()
+        .join(".forge/journal/20250101000000000000000-1.toml");
+    let mut journal = Journal::load(&journal_file).unwrap();
+    journal.recover().unwrap();
+
+    assert_eq!(fs::read_to_string(source.path()).unwrap(), "original");
+    assert!(target.is_symlink(), "staging symlink should be restored");
+    assert!(!journal_file.exists());
+}
+
+#[test]
+fn test_interrupted_link_after_copy_is_rolled_forward() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let source_dir = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+
+    let source = source_dir.child("app.conf");
+    source.write_str("content").unwrap();
+    ctx.forge_cmd()
+        .arg("stage")
+        .arg(source.path())
+        .current_dir(managed.path())
+        .assert()
+        .success();
+
+    // Simulate a link killed after removing the original but before symlinking
+    let target = managed.path().join("app.conf");
+    fs::remove_file(&target).unwrap();
+    fs::copy(source.path(), &target).unwrap();
+    write_journal(managed.path(), "link", source.path(), &target, 3);
+    fs::remove_file(source.path()).unwrap();
+
+    ctx.forge_cmd()
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("rolled forward"));
+
+    assert!(source.path().is_symlink());
+    assert_eq!(fs::read_to_string(source.path()).unwrap(), "content");
+    ctx.forge_cmd().arg("status").assert().success();
+}
+
+#[test]
+fn test_unlink_restores_file_content() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let source_dir = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+
+    let source = source_dir.child("app.conf");
+    source.write_str("keep me").unwrap();
+    ctx.forge_cmd()
+        .arg("stage")
+        .arg(source.path())
+        .current_dir(managed.path())
+        .assert()
+        .success();
+    ctx.forge_cmd()
+        .arg("link")
+        .current_dir(managed.path())
+        .assert()
+        .success();
+    assert!(source.path().is_symlink());
+
+    ctx.forge_cmd()
+        .arg("unlink")
+        .arg("--yes")
+        .arg(managed.path().join("app.conf"))
+        .current_dir(managed.path())
+        .assert()
+        .success();
+
+    assert!(!source.path().is_symlink());
+    assert_eq!(fs::read_to_string(source.path()).unwrap(), "keep me");
+    assert_eq!(
+        fs::read_to_string(managed.path().join("app.conf")).unwrap(),
+        "keep me"
+    );
+    assert!(
+        !managed
+            .path()
+            .join(".forge/journal")
+            .read_dir()
+            .unwrap()
+            .any(|_| true)
+    );
+}
+
+#[test]
+fn test_unlink_of_link_replaced_by_a_file_leaves_no_journal() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let home = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+
+    let app = home.child(".app");
+    app.write_str("linked").unwrap();
+    ctx.forge_cmd()
+        .arg("stage")
+        .arg(app.path())
+        .current_dir(managed.path())
+        .assert()
+        .success();
+    ctx.forge_cmd()
+        .arg("link")
+        .current_dir(managed.path())
+        .assert()
+        .success();
+
+    // An application saves over the symlink
+    fs::remove_file(app.path()).unwrap();
+    fs::write(app.path(), "saved by the app").unwrap();
+
+    ctx.forge_cmd()
+        .args(["unlink", ".app", "-y"])
+        .current_dir(home.path())
+        .assert()
+        .stderr(predicate::str::contains("is not a symlink"));
+
+    assert_eq!(fs::read_to_string(app.path()).unwrap(), "saved by the app");
+    assert!(!home.path().join(".app.forge-tmp").exists());
+    assert!(
+        !managed
+            .path()
+            .join(".forge/journal")
+            .read_dir()
+            .is_ok_and(|mut entries| entries.next().is_some())
+    );
+    ctx.forge_cmd()
+        .arg("status")
+        .current_dir(managed.path())
+        .assert()
+        .stderr(predicate::str::contains("Failed to recover").not());
+}
+
+#[test]
+fn test_unfinishable_interrupted_unlink_is_rolled_back() {
+    let temp = TempDir::new().unwrap();
+    let forge = temp.child("forge");
+    forge.create_dir_all().unwrap();
+    let target = forge.path().join("app.conf");
+    fs::write(&target, "managed").unwrap();
+
+    // Copied to the temporary file, then the link was replaced by a file, so
+    // removing the link can never succeed
+    let source = temp.child("app.conf");
+    source.write_str("replaced").unwrap();
+    let tmp = temp.path().join("app.conf.forge-tmp");
+    fs::write(&tmp, "managed").unwrap();
+    write_journal(forge.path(), "unlink", source.path(), &target, 1);
+
+    let journal_file = forge
+        .path()
+        .join(".forge/journal/20250101000000000000000-1.toml");
+    let mut journal = Journal::load(&journal_file).unwrap();
+    assert!(!journal.recover().unwrap(), "should be rolled back");
+
+    assert_eq!(fs::read_to_string(source.path()).unwrap(), "replaced");
+    assert_eq!(fs::read_to_string(&target).unwrap(), "managed");
+    assert!(!tmp.exists());
+    assert!(!journal_file.exists());
+}
+
+/// A directory holding a file and a nested directory
+fn config_dir(parent: &TempDir) -> std::path::PathBuf {
+    let dir = parent.path().join("app");
+    fs::create_dir_all(dir.join("themes")).unwrap();
+    fs::write(dir.join("app.conf"), "setting").unwrap();
+    fs::write(dir.join("themes/dark.toml"), "dark").unwrap();
+    dir
+}
+
+#[test]
+fn test_link_and_unlink_directory() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let source_dir = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+    let source = config_dir(&source_dir);
+    let target = managed.path().join("app");
+
+    ctx.forge_cmd()
+        .arg("stage")
+        .arg(&source)
+        .current_dir(managed.path())
+        .assert()
+        .success();
+    ctx.forge_cmd()
+        .arg("link")
+        .current_dir(managed.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 succeeded, 0 failed"));
+
+    // The contents moved, and the original is one directory symlink
+    assert_eq!(fs::read_link(&source).unwrap(), target);
+    assert_eq!(
+        fs::read_to_string(target.join("themes/dark.toml")).unwrap(),
+        "dark"
+    );
+    assert_eq!(
+        fs::read_to_string(source.join("app.conf")).unwrap(),
+        "setting"
+    );
+    ctx.forge_cmd().arg("status").assert().success();
+
+    ctx.forge_cmd()
+        .arg("unlink")
+        .arg("--yes")
+        .arg(&target)
+        .current_dir(managed.path())
+        .assert()
+        .success();
+    assert!(!source.is_symlink());
+    assert_eq!(
+        fs::read_to_string(source.join("themes/dark.toml")).unwrap(),
+        "dark"
+    );
+    assert!(target.join("app.conf").is_file());
+
+    // Undoing the unlink puts the directory symlink back
+    ctx.forge_cmd().arg("undo").assert().success();
+    assert_eq!(fs::read_link(&source).unwrap(), target);
+}
+
+/// Stage and link `config_dir`, returning the original and managed paths
+fn link_config_dir(
+    ctx: &common::TestContext,
+    managed: &TempDir,
+    source_dir: &TempDir,
+) -> (std::path::PathBuf, std::path::PathBuf) {
+    ctx.init_forge_repo(managed).unwrap();
+    let source = config_dir(source_dir);
+    ctx.forge_cmd()
+        .arg("stage")
+        .arg(&source)
+        .current_dir(managed.path())
+        .assert()
+        .success();
+    ctx.forge_cmd()
+        .arg("link")
+        .current_dir(managed.path())
+        .assert()
+        .success();
+    let target = managed.path().join("app");
+    assert_eq!(fs::read_link(&source).unwrap(), target);
+    (source, target)
+}
+
+#[test]
+fn test_purge_linked_directory() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let source_dir = TempDir::new().unwrap();
+    let (source, target) = link_config_dir(&ctx, &managed, &source_dir);
+
+    ctx.forge_cmd()
+        .arg("purge")
+        .arg(managed.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Purged 1 database records."));
+
+    // The directory is back in place, and nothing is left under management
+    assert!(!source.is_symlink());
+    assert_eq!(
+        fs::read_to_string(source.join("themes/dark.toml")).unwrap(),
+        "dark"
+    );
+    assert!(!target.exists());
+    ctx.forge_cmd()
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("app.conf").not());
+}
+
+#[test]
+fn test_remove_linked_directory() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let source_dir = TempDir::new().unwrap();
+    let (source, target) = link_config_dir(&ctx, &managed, &source_dir);
+
+    ctx.forge_cmd()
+        .arg("remove")
+        .arg("--yes")
+        .arg(&target)
+        .current_dir(managed.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Removed file from forge folder"));
+
+    // The original is a real directory again and the managed copy is gone
+    assert!(!source.is_symlink());
+    assert_eq!(
+        fs::read_to_string(source.join("themes/dark.toml")).unwrap(),
+        "dark"
+    );
+    assert!(!target.exists());
+    ctx.forge_cmd().arg("doctor").assert().success();
+}
+
+#[test]
+fn test_delete_linked_directory() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let source_dir = TempDir::new().unwrap();
+    let (source, target) = link_config_dir(&ctx, &managed, &source_dir);
+
+    ctx.forge_cmd()
+        .arg("delete")
+        .arg("--yes")
+        .arg(&target)
+        .current_dir(managed.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Deleted file from forge folder"));
+    assert!(fs::symlink_metadata(&source).is_err());
+    assert!(!target.exists());
+
+    // Undo brings back the whole tree and the symlink to it
+    ctx.forge_cmd().arg("undo").assert().success();
+    assert_eq!(fs::read_link(&source).unwrap(), target);
+    assert_eq!(
+        fs::read_to_string(target.join("themes/dark.toml")).unwrap(),
+        "dark"
+    );
+}
+
+#[test]
+fn test_interrupted_directory_link_is_rolled_back() {
+    let temp = TempDir::new().unwrap();
+    let source = config_dir(&temp);
+    let forge = temp.child("forge");
+    forge.create_dir_all().unwrap();
+    let target = forge.path().join("app");
+
+    // The staging directory was removed and the copy cut off half way
+    write_journal(forge.path(), "link", &source, &target, 2);
+    fs::create_dir_all(target.join("themes")).unwrap();
+    fs::write(target.join("app.conf"), "setting").unwrap();
+
+    let journal_file = forge
+        .path()
+        .join(".forge/journal/20250101000000000000000-1.toml");
+    let mut journal = Journal::load(&journal_file).unwrap();
+    journal.recover().unwrap();
+
+    assert!(!source.is_symlink());
+    assert_eq!(
+        fs::read_to_string(source.join("themes/dark.toml")).unwrap(),
+        "dark"
+    );
+    // Back to an empty staging directory
+    assert!(target.is_dir());
+    assert_eq!(fs::read_dir(&target).unwrap().count(), 0);
+    assert!(!journal_file.exists());
+}
@@ -0,0 +1,198 @@
+// Tests for the forge.toml manifest and forge apply
+
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+fn manifest(home: &Path) -> String {
+    fs::read_to_string(home.join("dots/forge.toml")).unwrap()
+}
+
+/// Create a home directory with a managed folder `dots` and link the given files into it
+fn link_in_home(ctx: &common::TestContext, home: &Path, files: &[&str]) {
+    let dots = home.join("dots");
+    fs::create_dir_all(&dots).unwrap();
+    ctx.forge_in(home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    for file in files {
+        fs::write(home.join(file), format!("contents of {}", file)).unwrap();
+        ctx.forge_in(home)
+            .arg("stage")
+            .arg(home.join(file))
+            .current_dir(&dots)
+            .assert()
+            .success();
+    }
+    ctx.forge_in(home)
+        .arg("link")
+        .current_dir(&dots)
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_manifest_follows_stage_link_and_unlink() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("alice");
+    let dots = home.join("dots");
+    fs::create_dir_all(&dots).unwrap();
+    fs::write(home.join(".vimrc"), "set nu").unwrap();
+    ctx.forge_in(&home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+
+    ctx.forge_in(&home)
+        .arg("stage")
+        .arg(home.join(".vimrc"))
+        .current_dir(&dots)
+        .assert()
+        .success();
+    let staged = manifest(&home);
+    assert!(staged.contains("path = \".vimrc\""));
+    assert!(staged.contains("target = \"$HOME/.vimrc\""));
+
+    ctx.forge_in(&home)
+        .arg("link")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    assert_eq!(manifest(&home), staged);
+
+    ctx.forge_in(&home)
+        .args(["unlink", "--yes"])
+        .arg(dots.join(".vimrc"))
+        .current_dir(&dots)
+        .assert()
+        .success();
+    assert!(!manifest(&home).contains(".vimrc"));
+
+    // Undoing the unlink declares the file again
+    ctx.forge_in(&home).arg("undo").assert().success();
+    assert_eq!(manifest(&home), staged);
+
+    // The manifest is not an untracked file
+    ctx.forge_in(&home)
+        .arg("doctor")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("forge.toml").not());
+}
+
+#[test]
+fn test_apply_deploys_a_fresh_clone() {
+    let root = TempDir::new().unwrap();
+    let alice = root.path().join("alice");
+    link_in_home(&common::TestContext::new(), &alice, &[".vimrc", ".bashrc"]);
+
+    // Another machine: the managed folder is cloned, the database is empty
+    // and one deploy location is already taken
+    let ctx = common::TestContext::new();
+    let bob = root.path().join("bob");
+    fs::create_dir_all(&bob).unwrap();
+    fs::create_dir(bob.join("dots")).unwrap();
+    for file in ["forge.toml", ".vimrc", ".bashrc"] {
+        fs::copy(alice.join("dots").join(file), bob.join("dots").join(file)).unwrap();
+    }
+    fs::write(bob.join(".bashrc"), "bob's own").unwrap();
+    ctx.forge_in(&bob)
+        .arg("init")
+        .current_dir(bob.join("dots"))
+        .assert()
+        .success();
+
+    ctx.forge_in(&bob)
+        .args(["apply", "--dry-run"])
+        .current_dir(bob.join("dots"))
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("Would link: "))
+        .stderr(predicate::str::contains("Conflict: "));
+    assert!(!bob.join(".vimrc").exists());
+
+    ctx.forge_in(&bob)
+        .arg("apply")
+        .current_dir(bob.join("dots"))
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "Linked 1, 0 already in place, 1 failed",
+        ));
+    assert_eq!(
+        fs::read_link(bob.join(".vimrc")).unwrap(),
+        bob.join("dots/.vimrc")
+    );
+    assert_eq!(
+        fs::read_to_string(bob.join(".bashrc")).unwrap(),
+        "bob's own"
+    );
+
+    // The link is now tracked, and applying again changes nothing
+    ctx.forge_in(&bob)
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            bob.join(".vimrc").display().to_string(),
+        ));
+    fs::remove_file(bob.join(".bashrc")).unwrap();
+    ctx.forge_in(&bob)
+        .arg("apply")
+        .current_dir(bob.join("dots"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Linked 1, 1 already in place, 0 failed",
+        ));
+}
+
+#[test]
+fn test_apply_prunes_undeclared_links() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("alice");
+    link_in_home(&ctx, &home, &[".vimrc", ".bashrc"]);
+
+    // Drop .bashrc from the manifest by hand, as a pulled commit might
+    let declared: String = manifest(&home)
+        .split("[[file]]")
+        .filter(|section| !section.contains(".bashrc"))
+        .collect::<Vec<_>>()
+        .join("[[file]]");
+    fs::write(home.join("dots/forge.toml"), declared).unwrap();
+
+    ctx.forge_in(&home)
+        .arg("apply")
+        .current_dir(home.join("dots"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Not declared: "));
+    ctx.forge_in(&home)
+        .args(["apply", "--prune", "--dry-run"])
+        .current_dir(home.join("dots"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Would prune: "));
+    assert!(home.join(".bashrc").is_symlink());
+
+    ctx.forge_in(&home)
+        .args(["apply", "--prune"])
+        .current_dir(home.join("dots"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 pruned"));
+    assert!(!home.join(".bashrc").exists());
+    assert!(home.join(".vimrc").is_symlink());
+    assert!(home.join("dots/.bashrc").exists());
+
+    ctx.forge_in(&home).arg("undo").assert().success();
+    assert!(home.join(".bashrc").is_symlink());
+}
@@ -0,0 +1,268 @@
+// Tests for versioned schema migrations and forge db migrate
+
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use rusqlite::Connection;
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+const LATEST: u32 = 8;
+
+fn schema_version(db: impl AsRef<Path>) -> Option<String> {
+    let db = db.as_ref();
+    let conn = Connection::open(db).unwrap();
+    conn.query_row(
+        "SELECT value FROM settings WHERE key = 'schema_version'",
+        [],
+        |row| row.get(0),
+    )
+    .ok()
+}
+
+/// Create a database as written by forge before schema versioning
+fn create_unversioned_db(db: impl AsRef<Path>) {
+    let db = db.as_ref();
+    clear_backups(db);
+    let conn = Connection::open(db).unwrap();
+    conn.execute_batch(
+        "CREATE TABLE dotfiles (
+            id INTEGER PRIMARY KEY,
+            source TEXT NOT NULL,
+            target TEXT NOT NULL,
+            profile TEXT,
+            status TEXT NOT NULL DEFAULT 'staged',
+            active BOOLEAN NOT NULL DEFAULT 1,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE TABLE settings (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+        CREATE TABLE filetypes (extension TEXT PRIMARY KEY, active BOOLEAN NOT NULL DEFAULT 1);
+        INSERT INTO filetypes (extension) VALUES ('.conf');
+        INSERT INTO dotfiles (source, target, status) VALUES ('/home/u/.vimrc', '/dots/.vimrc', 'linked');",
+    )
+    .unwrap();
+}
+
+/// Test database paths are reused between runs, so clear out old backups
+fn clear_backups(db: impl AsRef<Path>) {
+    let db = db.as_ref();
+    for name in backups(db) {
+        fs::remove_file(db.with_file_name(name)).unwrap();
+    }
+}
+
+fn backups(db: impl AsRef<Path>) -> Vec<String> {
+    let db = db.as_ref();
+    let prefix = db.file_name().unwrap().to_string_lossy().to_string();
+    fs::read_dir(db.parent().unwrap())
+        .unwrap()
+        .flatten()
+        .map(|e| e.file_name().to_string_lossy().to_string())
+        .filter(|name| name.starts_with(&prefix) && name.ends_with(".bak"))
+        .collect()
+}
+
+#[test]
+fn test_new_database_starts_at_latest_version_without_backup() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    clear_backups(ctx.db_path());
+    ctx.init_forge_repo(&managed).unwrap();
+
+    assert_eq!(schema_version(ctx.db_path()), Some(LATEST.to_string()));
+    assert!(backups(ctx.db_path()).is_empty());
+
+    ctx.forge_cmd()
+        .args(["db", "migrate", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("up to date"));
+}
+
+#[test]
+fn test_dry_run_lists_pending_migrations_without_applying() {
+    let ctx = common::TestContext::new();
+    create_unversioned_db(ctx.db_path());
+
+    ctx.forge_cmd()
+        .args(["db", "migrate", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("version 0"))
+        .stdout(predicate::str::contains("create operations table"))
+        .stdout(predicate::str::contains("add folder_id to dotfiles"));
+
+    assert_eq!(schema_version(ctx.db_path()), None);
+    assert!(backups(ctx.db_path()).is_empty());
+
+    // Nor does picking a folder for the command
+    ctx.forge_cmd()
+        .args(["--folder", "dots", "db", "migrate", "--dry-run"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("version 0"));
+    assert_eq!(schema_version(ctx.db_path()), None);
+    assert!(backups(ctx.db_path()).is_empty());
+
+    ctx.forge_cmd()
+        .args(["db", "migrate"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "now at version {}",
+            LATEST
+        )));
+    assert_eq!(schema_version(ctx.db_path()), Some(LATEST.to_string()));
+
+    // Text paths from before byte-exact storage were converted to raw bytes
+    let conn = Connection::open(ctx.db_path()).unwrap();
+    let (kind, source): (String, Vec<u8>) = conn
+        .query_row("SELECT typeof(source), source FROM dotfiles", [], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .unwrap();
+    assert_eq!(kind, "blob");
+    assert_eq!(source, b"/home/u/.vimrc");
+
+    // File types became include globs, which also match names like .bashrc
+    let rule: (String, String, String) = conn
+        .query_row(
+            "SELECT action, kind, pattern FROM selection_rules",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .unwrap();
+    assert_eq!(rule, ("include".into(), "glob".into(), "*.conf".into()));
+    let filetypes: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE name = 'filetypes'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(filetypes, 0);
+}
+
+fn selection_patterns(db: &str) -> Vec<String> {
+    let conn = Connection::open(db).unwrap();
+    let mut stmt = conn
+        .prepare("SELECT pattern FROM selection_rules ORDER BY pattern")
+        .unwrap();
+    stmt.query_map([], |row| row.get(0))
+        .unwrap()
+        .map(|pattern| pattern.unwrap())
+        .collect()
+}
+
+#[test]
+fn test_default_file_types_migrate_to_the_new_database_selection() {
+    let fresh = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    fresh.init_forge_repo(&managed).unwrap();
+
+    // The file types every old database was seeded with
+    let ctx = common::TestContext::new();
+    create_unversioned_db(ctx.db_path());
+    let conn = Connection::open(ctx.db_path()).unwrap();
+    conn.execute_batch(
+        "DELETE FROM filetypes;
+        INSERT INTO filetypes (extension) VALUES ('.bashrc'), ('.zshrc'), ('.vimrc'), ('.tmux.conf');",
+    )
+    .unwrap();
+    drop(conn);
+    ctx.forge_cmd().args(["db", "migrate"]).assert().success();
+
+    assert_eq!(
+        selection_patterns(ctx.db_path()),
+        selection_patterns(fresh.db_path())
+    );
+    assert_eq!(
+        selection_patterns(ctx.db_path()),
+        [".bashrc", ".tmux.conf", ".vimrc", ".zshrc"]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_database_at_non_utf8_path_is_not_backed_up_lossily() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let config = root
+        .path()
+        .join(std::ffi::OsStr::from_bytes(b"config-\xff"));
+    fs::create_dir_all(config.join("forge")).unwrap();
+    let db = config.join("forge/forge.db");
+    create_unversioned_db(&db);
+
+    // Without the test override, the database lives under the config directory
+    ctx.forge_cmd()
+        .env_remove("FORGE_TEST_DB")
+        .env("XDG_CONFIG_HOME", &config)
+        .args(["db", "migrate"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("not valid UTF-8"));
+
+    assert_eq!(schema_version(&db), None);
+    assert!(backups(&db).is_empty());
+}
+
+#[test]
+fn test_old_database_is_backed_up_and_upgraded_on_connect() {
+    let ctx = common::TestContext::new();
+    create_unversioned_db(ctx.db_path());
+
+    ctx.forge_cmd()
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Upgrading database schema"))
+        .stdout(predicate::str::contains(".vimrc"));
+
+    assert_eq!(schema_version(ctx.db_path()), Some(LATEST.to_string()));
+
+    // The backup is the database exactly as it was before the upgrade
+    let backups = backups(ctx.db_path());
+    assert_eq!(backups.len(), 1);
+    let backup = Path::new(ctx.db_path()).with_file_name(&backups[0]);
+    let conn = Connection::open(backup).unwrap();
+    let columns: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM pragma_table_info('dotfiles') WHERE name = 'folder_id'",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(columns, 0);
+
+    // Nothing further to do on the next run
+    ctx.forge_cmd()
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Upgrading").not());
+}
+
+#[test]
+fn test_newer_schema_is_refused() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+
+    let conn = Connection::open(ctx.db_path()).unwrap();
+    conn.execute(
+        "UPDATE settings SET value = '999' WHERE key = 'schema_version'",
+        [],
+    )
+    .unwrap();
+    drop(conn);
+
+    ctx.forge_cmd()
+        .args(["db", "migrate"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("newer than this forge supports"));
+}
@@ -0,0 +1,126 @@
+// Tests for paths that are not valid UTF-8, which must be stored byte for byte
+#![cfg(unix)]
+
+use assert_fs::TempDir;
+use std::ffi::OsStr;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+mod common;
+
+/// Two names that differ only in invalid bytes, so their lossy forms are equal
+fn colliding_names(dir: &std::path::Path) -> (PathBuf, PathBuf) {
+    (
+        dir.join(OsStr::from_bytes(b"conf\xff")),
+        dir.join(OsStr::from_bytes(b"conf\xfe")),
+    )
+}
+
+#[test]
+fn test_link_and_unlink_non_utf8_file() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let source_dir = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+
+    let (source, sibling) = colliding_names(source_dir.path());
+    fs::write(&source, "mine").unwrap();
+    fs::write(&sibling, "sibling").unwrap();
+
+    ctx.forge_cmd()
+        .arg("stage")
+        .arg(&source)
+        .current_dir(managed.path())
+        .assert()
+        .success();
+    ctx.forge_cmd()
+        .arg("link")
+        .current_dir(managed.path())
+        .assert()
+        .success();
+
+    let target = managed.path().join(source.file_name().unwrap());
+    assert!(source.is_symlink());
+    assert_eq!(fs::read_link(&source).unwrap(), target);
+    assert_eq!(fs::read_to_string(&target).unwrap(), "mine");
+    assert!(!sibling.is_symlink());
+    assert_eq!(fs::read_to_string(&sibling).unwrap(), "sibling");
+
+    // The database holds the exact bytes
+    let conn = rusqlite::Connection::open(ctx.db_path()).unwrap();
+    let stored: Vec<u8> = conn
+        .query_row("SELECT source FROM dotfiles", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(stored, source.as_os_str().as_bytes());
+    drop(conn);
+
+    ctx.forge_cmd()
+        .arg("unlink")
+        .arg("--yes")
+        .arg(&target)
+        .current_dir(managed.path())
+        .assert()
+        .success();
+    assert!(!source.is_symlink());
+    assert_eq!(fs::read_to_string(&source).unwrap(), "mine");
+    assert_eq!(fs::read_to_string(&sibling).unwrap(), "sibling");
+
+    // The operation history round-trips the path too
+    ctx.forge_cmd().arg("undo").assert().success();
+    assert_eq!(fs::read_link(&source).unwrap(), target);
+    assert_eq!(fs::read_to_string(&sibling).unwrap(), "sibling");
+}
+
+#[test]
+fn test_pack_and_restore_non_utf8_file() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let source_dir = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+
+    let (first, second) = colliding_names(source_dir.path());
+    fs::write(&first, "first").unwrap();
+    fs::write(&second, "second").unwrap();
+
+    ctx.forge_cmd()
+        .args(["start", "packing", "bytes"])
+        .current_dir(managed.path())
+        .assert()
+        .success();
+    ctx.forge_cmd()
+        .args(["pack", "--scope", "bytes"])
+        .arg(&first)
+        .arg(&second)
+        .current_dir(managed.path())
+        .assert()
+        .success();
+
+    let manifest = managed.path().join(".forge/tmp/pack/bytes/manifest.toml");
+    let manifest = fs::read_to_string(manifest).unwrap();
+    assert_eq!(manifest.matches("target_path_raw").count(), 2);
+
+    ctx.forge_cmd()
+        .args(["seal", "--scope", "bytes"])
+        .current_dir(managed.path())
+        .assert()
+        .success();
+    let archive = fs::read_dir(managed.path().join(".forge/archives"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+
+    fs::remove_file(&first).unwrap();
+    fs::remove_file(&second).unwrap();
+    ctx.forge_cmd()
+        .arg("restore")
+        .arg(&archive)
+        .current_dir(managed.path())
+        .assert()
+        .success();
+
+    assert_eq!(fs::read_to_string(&first).unwrap(), "first");
+    assert_eq!(fs::read_to_string(&second).unwrap(), "second");
+}
@@ -1,6 +1,5 @@
 // Integration tests for pack-and-go system: pack, seal, install, restore, explain, repack, unpack
 
-use assert_cmd::prelude::*;
 use assert_fs::TempDir;
 use assert_fs::prelude::*;
 use predicates::prelude::*;
@@ -0,0 +1,176 @@
+// Tests for home-relative path storage and forge db rebase
+
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use rusqlite::Connection;
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+/// Stored (source, target) of every dotfile row, as text
+fn stored_rows(ctx: &common::TestContext) -> Vec<(String, String)> {
+    let conn = Connection::open(ctx.db_path()).unwrap();
+    let mut stmt = conn
+        .prepare("SELECT source, target FROM dotfiles ORDER BY id")
+        .unwrap();
+    stmt.query_map([], |row| {
+        let source: Vec<u8> = row.get(0)?;
+        let target: Vec<u8> = row.get(1)?;
+        Ok((
+            String::from_utf8(source).unwrap(),
+            String::from_utf8(target).unwrap(),
+        ))
+    })
+    .unwrap()
+    .map(Result::unwrap)
+    .collect()
+}
+
+fn stored_folder(ctx: &common::TestContext) -> String {
+    let conn = Connection::open(ctx.db_path()).unwrap();
+    let path: Vec<u8> = conn
+        .query_row("SELECT path FROM managed_folders", [], |row| row.get(0))
+        .unwrap();
+    String::from_utf8(path).unwrap()
+}
+
+/// Create a home directory with a managed folder and two staged files
+fn stage_in_home(ctx: &common::TestContext, home: &Path) {
+    let dots = home.join("dots");
+    fs::create_dir_all(&dots).unwrap();
+    fs::create_dir_all(home.join(".config/nvim")).unwrap();
+    fs::write(home.join(".vimrc"), "set nu").unwrap();
+    fs::write(home.join(".config/nvim/init.lua"), "-- nvim").unwrap();
+
+    ctx.forge_in(home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    for file in [".vimrc", ".config/nvim/init.lua"] {
+        ctx.forge_in(home)
+            .arg("stage")
+            .arg(home.join(file))
+            .current_dir(&dots)
+            .assert()
+            .success();
+    }
+}
+
+#[test]
+fn test_paths_are_stored_relative_to_their_roots() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("alice");
+    stage_in_home(&ctx, &home);
+
+    assert_eq!(stored_folder(&ctx), "$HOME/dots");
+    assert_eq!(
+        stored_rows(&ctx),
+        vec![
+            ("$HOME/.vimrc".to_string(), "$FOLDER/.vimrc".to_string()),
+            (
+                "$XDG_CONFIG_HOME/nvim/init.lua".to_string(),
+                "$FOLDER/init.lua".to_string()
+            ),
+        ]
+    );
+
+    // The same database resolves against a different home directory
+    let moved = root.path().join("bob");
+    fs::rename(&home, &moved).unwrap();
+    ctx.forge_in(&moved)
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            moved.join(".vimrc").display().to_string(),
+        ))
+        .stdout(predicate::str::contains(
+            moved.join("dots/init.lua").display().to_string(),
+        ))
+        .stdout(predicate::str::contains("alice").not());
+
+    // Lookups by the new absolute path find the stored rows
+    ctx.forge_in(&moved)
+        .arg("unstage")
+        .arg(moved.join("dots/.vimrc"))
+        .current_dir(moved.join("dots"))
+        .assert()
+        .success();
+    let conn = Connection::open(ctx.db_path()).unwrap();
+    let active: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM dotfiles WHERE active = 1",
+            [],
+            |row| row.get(0),
+        )
+        .unwrap();
+    assert_eq!(active, 1);
+}
+
+#[test]
+fn test_db_rebase_rewrites_absolute_paths() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("alice");
+    stage_in_home(&ctx, &home);
+
+    // Rows and folder as written before paths were stored portably, with the
+    // second row carried over from another machine
+    let conn = Connection::open(ctx.db_path()).unwrap();
+    conn.execute(
+        "UPDATE managed_folders SET path = ?",
+        [home.join("dots").to_string_lossy().as_bytes()],
+    )
+    .unwrap();
+    conn.execute(
+        "UPDATE dotfiles SET source = ?, target = ? WHERE id = 1",
+        [
+            home.join(".vimrc").to_string_lossy().as_bytes(),
+            home.join("dots/.vimrc").to_string_lossy().as_bytes(),
+        ],
+    )
+    .unwrap();
+    conn.execute(
+        "UPDATE dotfiles SET source = ? WHERE id = 2",
+        [b"/home/olduser/.config/nvim/init.lua".as_slice()],
+    )
+    .unwrap();
+    drop(conn);
+
+    ctx.forge_in(&home)
+        .args(["db", "rebase", "--dry-run", "--from-home", "/home/olduser"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("→ $HOME/dots"))
+        .stdout(predicate::str::contains("Would rebase 4 stored path(s)"));
+    assert_eq!(
+        stored_rows(&ctx)[0].0,
+        home.join(".vimrc").to_string_lossy()
+    );
+
+    ctx.forge_in(&home)
+        .args(["db", "rebase", "--from-home", "/home/olduser"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Rebased 4 stored path(s)"));
+    assert_eq!(stored_folder(&ctx), "$HOME/dots");
+    assert_eq!(
+        stored_rows(&ctx),
+        vec![
+            ("$HOME/.vimrc".to_string(), "$FOLDER/.vimrc".to_string()),
+            (
+                "$XDG_CONFIG_HOME/nvim/init.lua".to_string(),
+                "$FOLDER/init.lua".to_string()
+            ),
+        ]
+    );
+
+    ctx.forge_in(&home)
+        .args(["db", "rebase"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("already portable"));
+}
@@ -0,0 +1,126 @@
+// Tests for forge reconcile
+#![cfg(unix)]
+
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+mod common;
+
+/// Link settings.json and let an application replace the symlink with its own file
+fn clobbered(ctx: &common::TestContext, root: &Path) -> (PathBuf, PathBuf, PathBuf) {
+    let home = root.join("home");
+    let dots = home.join("dots");
+    let settings = home.join(".config/foo/settings.json");
+    fs::create_dir_all(settings.parent().unwrap()).unwrap();
+    fs::write(&settings, "{\n  \"theme\": \"dark\",\n  \"font\": 12\n}\n").unwrap();
+
+    ctx.forge_in(&home)
+        .args(["init", "--dir"])
+        .arg(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .arg("stage")
+        .arg(&settings)
+        .assert()
+        .success();
+    ctx.forge_in(&home).arg("link").assert().success();
+
+    let tmp = settings.with_extension("json.tmp");
+    fs::write(&tmp, "{\n  \"theme\": \"light\",\n  \"font\": 12\n}\n").unwrap();
+    fs::rename(&tmp, &settings).unwrap();
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("run 'forge reconcile'"));
+
+    let managed = dots.join("settings.json");
+    (home, settings, managed)
+}
+
+#[test]
+fn test_reconcile_absorb_shows_diff_and_relinks() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let (home, settings, managed) = clobbered(&ctx, root.path());
+
+    // Nothing is decided without an answer
+    ctx.forge_in(&home)
+        .arg("reconcile")
+        .write_stdin("")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("-  \"theme\": \"dark\","))
+        .stdout(predicate::str::contains("+  \"theme\": \"light\","))
+        .stdout(predicate::str::contains("Reconciled 0, 1 skipped"));
+    assert!(!fs::symlink_metadata(&settings).unwrap().is_symlink());
+
+    ctx.forge_in(&home)
+        .arg("reconcile")
+        .write_stdin("maybe\na\n")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Please answer absorb, discard"))
+        .stdout(predicate::str::contains("Absorbed into "))
+        .stdout(predicate::str::contains(
+            "Reconciled 1, 0 skipped, 0 failed",
+        ));
+    assert!(fs::symlink_metadata(&settings).unwrap().is_symlink());
+    assert!(fs::read_to_string(&managed).unwrap().contains("light"));
+    ctx.forge_in(&home).arg("status").assert().success();
+
+    // The old managed copy was backed up, and the whole thing can be undone
+    ctx.forge_in(&home)
+        .args(["backups", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("reconcile"));
+    ctx.forge_in(&home).arg("undo").assert().success();
+    assert!(!fs::symlink_metadata(&settings).unwrap().is_symlink());
+    assert!(fs::read_to_string(&managed).unwrap().contains("dark"));
+}
+
+#[test]
+fn test_reconcile_discard_and_keep_both() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let (home, settings, managed) = clobbered(&ctx, root.path());
+
+    ctx.forge_in(&home)
+        .args(["reconcile", "--keep-both"])
+        .arg(&settings)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Kept the new content as "));
+    let local = settings.with_file_name("settings.json.local");
+    assert!(fs::read_to_string(&local).unwrap().contains("light"));
+    assert!(fs::symlink_metadata(&settings).unwrap().is_symlink());
+    assert!(fs::read_to_string(&managed).unwrap().contains("dark"));
+
+    // Replaced again, and this time thrown away
+    fs::remove_file(&settings).unwrap();
+    fs::write(&settings, "{}\n").unwrap();
+    ctx.forge_in(&home)
+        .args(["reconcile", "--discard"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Discarded the new content"));
+    assert!(fs::symlink_metadata(&settings).unwrap().is_symlink());
+    assert!(fs::read_to_string(&settings).unwrap().contains("dark"));
+    ctx.forge_in(&home).arg("status").assert().success();
+
+    ctx.forge_in(&home)
+        .args(["reconcile", "--absorb", "--discard"])
+        .assert()
+        .failure();
+    ctx.forge_in(&home)
+        .arg("reconcile")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "No symlinks replaced by regular files",
+        ));
+}
@@ -0,0 +1,144 @@
+// Tests for relative symlinks and forge config
+#![cfg(unix)]
+
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+#[test]
+fn test_relative_links_survive_moving_home() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    let dots = home.join("dots");
+    fs::create_dir_all(&dots).unwrap();
+    fs::create_dir_all(home.join(".config/app")).unwrap();
+    fs::write(home.join(".vimrc"), "set nu").unwrap();
+    fs::write(home.join(".config/app/app.conf"), "x = 1").unwrap();
+    ctx.forge_in(&home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    for file in [".vimrc", ".config/app/app.conf"] {
+        ctx.forge_in(&home)
+            .arg("stage")
+            .arg(home.join(file))
+            .current_dir(&dots)
+            .assert()
+            .success();
+    }
+
+    ctx.forge_in(&home)
+        .args(["link", "--relative"])
+        .current_dir(&dots)
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_link(home.join(".vimrc")).unwrap(),
+        Path::new("dots/.vimrc")
+    );
+    assert_eq!(
+        fs::read_link(home.join(".config/app/app.conf")).unwrap(),
+        Path::new("../../dots/app.conf")
+    );
+    ctx.forge_in(&home).arg("status").assert().success();
+
+    // Home mounted somewhere else: the links still lead into the managed folder
+    let moved = root.path().join("moved");
+    fs::rename(&home, &moved).unwrap();
+    assert_eq!(
+        fs::read_to_string(moved.join(".config/app/app.conf")).unwrap(),
+        "x = 1"
+    );
+
+    // Unlinking goes through the relative link; without --relative or the
+    // setting, new links stay absolute
+    fs::rename(&moved, &home).unwrap();
+    ctx.forge_in(&home)
+        .args(["unlink", "--yes"])
+        .arg(dots.join(".vimrc"))
+        .current_dir(&dots)
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(home.join(".vimrc")).unwrap(), "set nu");
+    fs::write(home.join(".bashrc"), "alias l=ls").unwrap();
+    ctx.forge_in(&home)
+        .arg("stage")
+        .arg(home.join(".bashrc"))
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .arg("link")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_link(home.join(".bashrc")).unwrap(),
+        dots.join(".bashrc")
+    );
+}
+
+#[test]
+fn test_relative_links_setting() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    let dots = home.join("dotfiles");
+    fs::create_dir_all(dots.join("nvim/.config/nvim")).unwrap();
+    fs::write(dots.join("nvim/.config/nvim/init.lua"), "-- nvim").unwrap();
+    ctx.forge_in(&home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+
+    ctx.forge_in(&home)
+        .arg("config")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("relative_links = false"));
+    ctx.forge_in(&home)
+        .args(["config", "relative_links", "sometimes"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("must be true or false"));
+    ctx.forge_in(&home)
+        .args(["config", "no_such_setting"])
+        .assert()
+        .failure();
+    ctx.forge_in(&home)
+        .args(["config", "relative_links", "true"])
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .args(["config", "relative_links"])
+        .assert()
+        .success()
+        .stdout("true\n");
+
+    ctx.forge_in(&home)
+        .args(["stow", "nvim"])
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_link(home.join(".config")).unwrap(),
+        Path::new("dotfiles/nvim/.config")
+    );
+
+    // Relative links are recognised as stowed and unstowed like absolute ones
+    ctx.forge_in(&home)
+        .args(["stow", "nvim"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("already stowed"));
+    ctx.forge_in(&home)
+        .args(["unstow", "nvim"])
+        .assert()
+        .success();
+    assert!(fs::symlink_metadata(home.join(".config")).is_err());
+}
@@ -0,0 +1,228 @@
+// Tests for forge relocate
+#![cfg(unix)]
+
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::fs;
+use std::path::Path;
+
+mod common;
+
+/// A home with .vimrc linked and .bashrc staged in ~/dots
+fn setup(ctx: &common::TestContext, home: &Path) {
+    let dots = home.join("dots");
+    fs::create_dir_all(&dots).unwrap();
+    fs::write(home.join(".vimrc"), "set nu").unwrap();
+    fs::write(home.join(".bashrc"), "alias l=ls").unwrap();
+    ctx.forge_in(home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(home)
+        .arg("stage")
+        .arg(home.join(".vimrc"))
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(home)
+        .arg("link")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(home)
+        .arg("stage")
+        .arg(home.join(".bashrc"))
+        .current_dir(&dots)
+        .assert()
+        .success();
+}
+
+#[test]
+fn test_relocate_moves_folder_links_and_records() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    setup(&ctx, &home);
+    let new = home.join("src/dotfiles");
+
+    ctx.forge_in(&home)
+        .arg("relocate")
+        .arg(&new)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Relinked: "))
+        .stdout(predicate::str::contains(
+            "2 tracked file(s), 1 symlink(s) relinked",
+        ));
+
+    assert!(!home.join("dots").exists());
+    assert_eq!(
+        fs::read_link(home.join(".vimrc")).unwrap(),
+        new.join(".vimrc")
+    );
+    assert_eq!(fs::read_to_string(home.join(".vimrc")).unwrap(), "set nu");
+    // The staging link inside the folder still leads to the original
+    assert_eq!(
+        fs::read_link(new.join(".bashrc")).unwrap(),
+        home.join(".bashrc")
+    );
+
+    ctx.forge_in(&home)
+        .arg("list")
+        .current_dir(&new)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(".vimrc"))
+        .stdout(predicate::str::contains(".bashrc"));
+    ctx.forge_in(&home)
+        .arg("status")
+        .current_dir(&new)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("src/dotfiles"));
+
+    // Files staged after the move land in the new location
+    fs::write(home.join(".inputrc"), "set bell-style none").unwrap();
+    ctx.forge_in(&home)
+        .arg("stage")
+        .arg(home.join(".inputrc"))
+        .current_dir(&new)
+        .assert()
+        .success();
+    assert!(fs::symlink_metadata(new.join(".inputrc")).is_ok());
+    ctx.forge_in(&home)
+        .arg("unstage")
+        .arg(new.join(".inputrc"))
+        .current_dir(&new)
+        .assert()
+        .success();
+
+    // Undo puts the folder and its links back
+    ctx.forge_in(&home).arg("undo").assert().success();
+    ctx.forge_in(&home).arg("undo").assert().success();
+    ctx.forge_in(&home).arg("undo").assert().success();
+    assert!(!new.exists());
+    assert_eq!(
+        fs::read_link(home.join(".vimrc")).unwrap(),
+        home.join("dots/.vimrc")
+    );
+    ctx.forge_in(&home)
+        .arg("list")
+        .current_dir(home.join("dots"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(".vimrc"));
+}
+
+#[test]
+fn test_relocate_dry_run_and_refusals() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    setup(&ctx, &home);
+
+    ctx.forge_in(&home)
+        .args(["relocate", "--dry-run"])
+        .arg(home.join("elsewhere"))
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Would relink: "))
+        .stdout(predicate::str::contains("Would move 2 tracked file(s)"));
+    assert!(home.join("dots").is_dir());
+    assert!(!home.join("elsewhere").exists());
+
+    fs::create_dir_all(home.join("taken")).unwrap();
+    ctx.forge_in(&home)
+        .arg("relocate")
+        .arg(home.join("taken"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("already exists"));
+    ctx.forge_in(&home)
+        .arg("relocate")
+        .arg(home.join("dots/inner"))
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("into itself"));
+    assert_eq!(
+        fs::read_link(home.join(".vimrc")).unwrap(),
+        home.join("dots/.vimrc")
+    );
+}
+
+#[test]
+fn test_relocate_across_filesystems() {
+    // /dev/shm is a tmpfs on most Linux systems, so a rename into it fails
+    let shm = Path::new("/dev/shm");
+    if !shm.is_dir() {
+        return;
+    }
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    setup(&ctx, &home);
+    let elsewhere = tempfile::tempdir_in(shm).unwrap();
+    let new = elsewhere.path().join("dots");
+
+    ctx.forge_in(&home)
+        .arg("relocate")
+        .arg(&new)
+        .assert()
+        .success();
+    assert!(!home.join("dots").exists());
+    assert_eq!(fs::read_to_string(new.join(".vimrc")).unwrap(), "set nu");
+    assert_eq!(
+        fs::read_link(home.join(".vimrc")).unwrap(),
+        new.join(".vimrc")
+    );
+    ctx.forge_in(&home)
+        .arg("list")
+        .current_dir(&new)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(".vimrc"));
+}
+
+#[test]
+fn test_relocate_relinks_untracked_links_into_the_folder() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    setup(&ctx, &home);
+    let dots = home.join("dots");
+    fs::create_dir_all(dots.join("themes")).unwrap();
+    fs::write(dots.join("themes/dark.toml"), "dark").unwrap();
+
+    // Made by hand, so forge has no record of either
+    let theme = home.join(".config/app/theme.toml");
+    fs::create_dir_all(theme.parent().unwrap()).unwrap();
+    std::os::unix::fs::symlink(dots.join("themes/dark.toml"), &theme).unwrap();
+    let notes = home.join("notes");
+    std::os::unix::fs::symlink("dots/themes", &notes).unwrap();
+    let new = home.join("src/dotfiles");
+
+    ctx.forge_in(&home)
+        .args(["relocate", "--dry-run"])
+        .arg(&new)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!(
+            "Would relink: {}",
+            theme.display()
+        )))
+        .stdout(predicate::str::contains("relink 3 symlink(s)"));
+
+    ctx.forge_in(&home)
+        .arg("relocate")
+        .arg(&new)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("3 symlink(s) relinked"));
+    assert_eq!(fs::read_link(&theme).unwrap(), new.join("themes/dark.toml"));
+    assert_eq!(
+        fs::read_link(&notes).unwrap(),
+        Path::new("src/dotfiles/themes")
+    );
+    assert_eq!(fs::read_to_string(notes.join("dark.toml")).unwrap(), "dark");
+}
@@ -0,0 +1,338 @@
+// Tests for encrypted dotfiles and forge secret
+#![cfg(unix)]
+
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::fs;
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+
+mod common;
+
+fn mode(path: &Path) -> u32 {
+    fs::metadata(path).unwrap().permissions().mode() & 0o777
+}
+
+#[test]
+fn test_secret_add_edit_and_unlink() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    let dots = home.join("dots");
+    fs::create_dir_all(&dots).unwrap();
+    let (deployed, managed) = (home.join(".netrc"), dots.join(".netrc"));
+    fs::write(&deployed, "machine example.com password hunter2\n").unwrap();
+
+    ctx.forge_in(&home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .args(["secret", "add"])
+        .arg(&deployed)
+        .current_dir(&dots)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Created encrypted: "));
+
+    // Only ciphertext is in the managed folder; the decrypted file is private
+    let sealed = fs::read(&managed).unwrap();
+    assert!(sealed.starts_with(b"FORGE-SECRET"));
+    assert!(!String::from_utf8_lossy(&sealed).contains("hunter2"));
+    assert!(!fs::symlink_metadata(&deployed).unwrap().is_symlink());
+    assert_eq!(
+        fs::read_to_string(&deployed).unwrap(),
+        "machine example.com password hunter2\n"
+    );
+    assert_eq!(mode(&deployed), 0o600);
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(encrypted): OK"));
+
+    // A wrong passphrase is refused
+    ctx.forge_in(&home)
+        .args(["secret", "edit"])
+        .arg(&deployed)
+        .env("FORGE_PASSPHRASE", "battery staple")
+        .env("EDITOR", "true")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("wrong passphrase or key file"));
+
+    ctx.forge_in(&home)
+        .args(["secret", "edit"])
+        .arg(&managed)
+        .env("EDITOR", "sed -i s/hunter2/swordfish/")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Encrypted: "));
+    assert_eq!(
+        fs::read_to_string(&deployed).unwrap(),
+        "machine example.com password swordfish\n"
+    );
+    assert!(!String::from_utf8_lossy(&fs::read(&managed).unwrap()).contains("swordfish"));
+    ctx.forge_in(&home).arg("status").assert().success();
+
+    // A change made to the decrypted file is flagged until it is encrypted
+    fs::write(&deployed, "machine example.com password letmein\n").unwrap();
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("decrypted file edited"));
+    ctx.forge_in(&home)
+        .args(["secret", "edit"])
+        .arg(&deployed)
+        .env("EDITOR", "true")
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("forge secret add"));
+    ctx.forge_in(&home)
+        .args(["secret", "add"])
+        .arg(&deployed)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Encrypted: "));
+    ctx.forge_in(&home).arg("status").assert().success();
+
+    // Unlinking leaves the decrypted content in place
+    ctx.forge_in(&home)
+        .args(["unlink", "--yes"])
+        .arg(&managed)
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(&deployed).unwrap(),
+        "machine example.com password letmein\n"
+    );
+}
+
+#[test]
+fn test_secret_rekey_relink_and_pack() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    let dots = home.join("dots");
+    fs::create_dir_all(&dots).unwrap();
+    let (deployed, managed) = (home.join(".token"), dots.join(".token"));
+    fs::write(&deployed, "token-one\n").unwrap();
+
+    ctx.forge_in(&home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .args(["stage", "--mode", "encrypted"])
+        .arg(&deployed)
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .arg("link")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    assert_eq!(mode(&deployed), 0o600);
+
+    // A new passphrase, then a key file
+    ctx.forge_in(&home)
+        .args(["secret", "rekey"])
+        .env("FORGE_NEW_PASSPHRASE", "new words")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Re-encrypted 1 file(s)"));
+    ctx.forge_in(&home).arg("status").assert().success();
+    ctx.forge_in(&home)
+        .args(["secret", "edit"])
+        .arg(&deployed)
+        .env("EDITOR", "true")
+        .assert()
+        .failure();
+    let key = root.path().join("forge.key");
+    fs::write(&key, "0123456789abcdef\n").unwrap();
+    ctx.forge_in(&home)
+        .args(["secret", "rekey", "--key-file"])
+        .arg(&key)
+        .env("FORGE_PASSPHRASE", "new words")
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .args(["secret", "edit"])
+        .arg(&deployed)
+        .env_remove("FORGE_PASSPHRASE")
+        .env("EDITOR", "sed -i s/one/two/")
+        .assert()
+        .success();
+    assert_eq!(fs::read_to_string(&deployed).unwrap(), "token-two\n");
+
+    // A changed encrypted copy, e.g. from a pull, is decrypted by link
+    let two = fs::read(&managed).unwrap();
+    ctx.forge_in(&home)
+        .args(["secret", "edit"])
+        .arg(&deployed)
+        .env("EDITOR", "sed -i s/two/three/")
+        .assert()
+        .success();
+    fs::write(&managed, &two).unwrap();
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "encrypted copy changed, run 'forge link'",
+        ));
+    ctx.forge_in(&home)
+        .arg("link")
+        .current_dir(&dots)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Encrypted files: 1 decrypted"));
+    assert_eq!(fs::read_to_string(&deployed).unwrap(), "token-two\n");
+
+    // Packs hold the ciphertext and decrypt it when installed
+    ctx.forge_in(&home)
+        .args(["start", "packing", "keys"])
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .args(["pack", "--scope", "keys"])
+        .arg(&deployed)
+        .current_dir(&dots)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(encrypted)"));
+    ctx.forge_in(&home)
+        .args(["seal", "--scope", "keys"])
+        .current_dir(&dots)
+        .assert()
+        .success();
+    let archive = fs::read_dir(dots.join(".forge/archives"))
+        .unwrap()
+        .next()
+        .unwrap()
+        .unwrap()
+        .path();
+    let mut zip = zip::ZipArchive::new(fs::File::open(&archive).unwrap()).unwrap();
+    let mut packed = Vec::new();
+    zip.by_name("files/.token")
+        .unwrap()
+        .read_to_end(&mut packed)
+        .unwrap();
+    assert_eq!(packed, fs::read(&managed).unwrap());
+
+    let elsewhere = root.path().join("elsewhere");
+    fs::create_dir_all(&elsewhere).unwrap();
+    ctx.forge_in(&home)
+        .arg("install")
+        .arg(&archive)
+        .arg("--target")
+        .arg(&elsewhere)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(decrypted)"));
+    let installed = fs::read_dir(&elsewhere)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .find(|path| path.is_file())
+        .unwrap();
+    assert_eq!(fs::read_to_string(&installed).unwrap(), "token-two\n");
+    assert_eq!(mode(&installed), 0o600);
+}
+
+#[test]
+fn test_mismatched_passphrase_does_not_encrypt() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    let dots = home.join("dots");
+    fs::create_dir_all(&dots).unwrap();
+    let (netrc, token) = (home.join(".netrc"), home.join(".token"));
+    fs::write(&netrc, "machine example.com password hunter2\n").unwrap();
+    fs::write(&token, "token-one\n").unwrap();
+
+    ctx.forge_in(&home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .args(["secret", "add"])
+        .arg(&netrc)
+        .current_dir(&dots)
+        .assert()
+        .success();
+
+    // A second secret under a mistyped passphrase is refused, not sealed
+    ctx.forge_in(&home)
+        .args(["secret", "add"])
+        .arg(&token)
+        .env("FORGE_PASSPHRASE", "correct hrose")
+        .current_dir(&dots)
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains(
+            "not encrypting with a different passphrase",
+        ));
+    // It is left staged, to be encrypted once the passphrase is right
+    assert!(dots.join(".token").is_symlink());
+    assert_eq!(fs::read_to_string(&token).unwrap(), "token-one\n");
+
+    ctx.forge_in(&home)
+        .args(["secret", "add"])
+        .arg(&token)
+        .current_dir(&dots)
+        .assert()
+        .success();
+    assert!(
+        fs::read(dots.join(".token"))
+            .unwrap()
+            .starts_with(b"FORGE-SECRET")
+    );
+    ctx.forge_in(&home).arg("status").assert().success();
+}
+
+#[test]
+fn test_excessive_key_derivation_cost_is_refused() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    let dots = home.join("dots");
+    fs::create_dir_all(&dots).unwrap();
+    let (deployed, managed) = (home.join(".netrc"), dots.join(".netrc"));
+    fs::write(&deployed, "machine example.com password hunter2\n").unwrap();
+
+    ctx.forge_in(&home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .args(["secret", "add"])
+        .arg(&deployed)
+        .current_dir(&dots)
+        .assert()
+        .success();
+
+    // Passes and lanes follow the magic and memory cost in the header
+    let sealed = fs::read(&managed).unwrap();
+    for (offset, value) in [(4, u32::MAX), (8, 1000)] {
+        let mut crafted = sealed.clone();
+        let at = b"FORGE-SECRET\0\x01".len() + offset;
+        crafted[at..at + 4].copy_from_slice(&u32::to_le_bytes(value));
+        fs::write(&managed, crafted).unwrap();
+        ctx.forge_in(&home)
+            .args(["secret", "edit"])
+            .arg(&managed)
+            .env("EDITOR", "true")
+            .timeout(std::time::Duration::from_secs(60))
+            .assert()
+            .failure()
+            .stderr(predicate::str::contains("key derivation would need"));
+    }
+}
@@ -0,0 +1,144 @@
+// Tests for selection rules, forge rules and profile switching
+#![cfg(unix)]
+
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+mod common;
+
+/// A profile directory and the directory switching deploys into
+fn setup(ctx: &common::TestContext, root: &Path) -> (PathBuf, PathBuf, PathBuf) {
+    let home = root.join("home");
+    let profile = home.join(".forge/profiles/work");
+    let target = home.join("deployed");
+    fs::create_dir_all(profile.join("notes")).unwrap();
+    fs::create_dir_all(&target).unwrap();
+    fs::create_dir_all(ctx.config_path()).unwrap();
+    fs::write(
+        Path::new(ctx.config_path()).join("default_path"),
+        target.to_str().unwrap(),
+    )
+    .unwrap();
+    fs::write(profile.join(".bashrc"), "alias ll='ls -l'\n").unwrap();
+    fs::write(profile.join(".vimrc"), "set number\n").unwrap();
+    fs::write(profile.join("app.conf"), "key = value\n").unwrap();
+    fs::write(profile.join("huge.conf"), vec![b'#'; 4096]).unwrap();
+    fs::write(profile.join("notes/todo.md"), "- nothing\n").unwrap();
+    (home, profile, target)
+}
+
+#[test]
+fn test_switch_links_files_picked_by_default_rules() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let (home, profile, target) = setup(&ctx, root.path());
+
+    ctx.forge_in(&home)
+        .args(["rules", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("include  glob   .bashrc"))
+        .stdout(predicate::str::contains("include  glob   .tmux.conf"));
+
+    // Names without an extension are matched, which the file types never did
+    ctx.forge_in(&home)
+        .args(["switch", "work"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2 files tracked, 0 failed"));
+    assert_eq!(
+        fs::read_link(target.join(".bashrc")).unwrap(),
+        profile.join(".bashrc")
+    );
+    assert!(target.join(".vimrc").is_symlink());
+    assert!(!target.join("app.conf").exists());
+    ctx.forge_in(&home)
+        .args(["list", "--profile", "work"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(".vimrc"))
+        .stdout(predicate::str::contains("app.conf").not());
+}
+
+#[test]
+fn test_rules_precedence_and_rules_test() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let (home, profile, target) = setup(&ctx, root.path());
+
+    let rules = |args: &[&str]| {
+        ctx.forge_in(&home)
+            .arg("rules")
+            .args(args)
+            .assert()
+            .success()
+    };
+    rules(&["include", "ext", ".conf"]);
+    rules(&["include", "regex", "^notes/.*\\.md$"]);
+    rules(&["exclude", "size", ">1K"]);
+    rules(&["exclude", "glob", ".vimrc"]);
+    rules(&["exclude", "glob", ".vimrc"]).stdout(predicate::str::contains("already exists"));
+    ctx.forge_in(&home)
+        .args(["rules", "include", "size", "1M"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("invalid size '1M'"));
+    ctx.forge_in(&home)
+        .args(["rules", "include", "regex", "("])
+        .assert()
+        .failure();
+
+    fs::write(profile.join(".forgeignore"), "notes/\n").unwrap();
+    ctx.forge_in(&home)
+        .args(["rules", "test"])
+        .arg(&profile)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "selected  .bashrc  (#1 include glob .bashrc)",
+        ))
+        .stdout(predicate::str::contains(
+            "selected  app.conf  (#5 include ext .conf)",
+        ))
+        .stdout(predicate::str::contains(
+            "excluded  huge.conf  (#7 exclude size >1K)",
+        ))
+        .stdout(predicate::str::contains(
+            "excluded  .vimrc  (#8 exclude glob .vimrc)",
+        ))
+        .stdout(predicate::str::contains("ignored   notes  ("))
+        .stdout(predicate::str::contains("2 selected, 3 not selected"));
+    assert!(!target.join(".bashrc").exists());
+
+    // The regex rule picks up the notes once they are no longer ignored
+    fs::remove_file(profile.join(".forgeignore")).unwrap();
+    ctx.forge_in(&home)
+        .args(["switch", "work"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("3 files tracked"));
+    assert!(target.join("todo.md").is_symlink());
+    assert!(target.join("app.conf").is_symlink());
+    assert!(!target.join("huge.conf").exists());
+    assert!(!target.join(".vimrc").exists());
+
+    // Without include rules everything that is not excluded is selected
+    for id in 1..=6 {
+        rules(&["remove", &id.to_string()]);
+    }
+    ctx.forge_in(&home)
+        .args(["rules", "remove", "1"])
+        .assert()
+        .failure();
+    ctx.forge_in(&home)
+        .args(["rules", "test"])
+        .arg(&profile)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "selected  .bashrc  (no include rules)",
+        ))
+        .stdout(predicate::str::contains("3 selected, 2 not selected"));
+}
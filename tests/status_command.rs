@@ -0,0 +1,164 @@
+// Tests for drift detection: forge status and dotfile::status
+
+use assert_fs::TempDir;
+use assert_fs::prelude::*;
+use forge_rs::dotfile::status::{LinkState, check_dotfile};
+use forge_rs::dotfile::{DotFile, DotFileStatus};
+use forge_rs::symlink;
+use predicates::prelude::*;
+use std::fs;
+
+mod common;
+
+fn linked(source: &std::path::Path, target: &std::path::Path) -> DotFile {
+    DotFile::with_status(
+        source.to_path_buf(),
+        target.to_path_buf(),
+        None,
+        DotFileStatus::Linked,
+    )
+}
+
+#[test]
+fn test_linked_and_correct() {
+    let temp = TempDir::new().unwrap();
+    let managed = temp.child("managed.conf");
+    managed.write_str("content").unwrap();
+    let original = temp.path().join("original.conf");
+    symlink::create_symlink(managed.path(), &original).unwrap();
+
+    assert_eq!(
+        check_dotfile(&linked(&original, managed.path())),
+        LinkState::Ok
+    );
+}
+
+#[test]
+fn test_link_missing() {
+    let temp = TempDir::new().unwrap();
+    let managed = temp.child("managed.conf");
+    managed.write_str("content").unwrap();
+    let original = temp.path().join("original.conf");
+
+    assert_eq!(
+        check_dotfile(&linked(&original, managed.path())),
+        LinkState::LinkMissing
+    );
+}
+
+#[test]
+fn test_link_points_elsewhere() {
+    let temp = TempDir::new().unwrap();
+    let managed = temp.child("managed.conf");
+    managed.write_str("content").unwrap();
+    let other = temp.child("other.conf");
+    other.write_str("other").unwrap();
+    let original = temp.path().join("original.conf");
+    symlink::create_symlink(other.path(), &original).unwrap();
+
+    assert_eq!(
+        check_dotfile(&linked(&original, managed.path())),
+        LinkState::PointsElsewhere(other.path().to_path_buf())
+    );
+}
+
+#[test]
+fn test_link_replaced_by_regular_file() {
+    let temp = TempDir::new().unwrap();
+    let managed = temp.child("managed.conf");
+    managed.write_str("content").unwrap();
+    let original = temp.child("original.conf");
+    original.write_str("edited in place").unwrap();
+
+    assert_eq!(
+        check_dotfile(&linked(original.path(), managed.path())),
+        LinkState::ReplacedByFile
+    );
+}
+
+#[test]
+fn test_managed_copy_missing() {
+    let temp = TempDir::new().unwrap();
+    let managed = temp.path().join("managed.conf");
+    let original = temp.path().join("original.conf");
+    symlink::create_symlink(&managed, &original).unwrap();
+
+    assert_eq!(
+        check_dotfile(&linked(&original, &managed)),
+        LinkState::ManagedCopyMissing
+    );
+}
+
+#[test]
+fn test_staging_symlink_dangling() {
+    let temp = TempDir::new().unwrap();
+    let original = temp.child("original.conf");
+    original.write_str("content").unwrap();
+    let staging = temp.path().join("staged.conf");
+    symlink::create_symlink(original.path(), &staging).unwrap();
+
+    let staged = DotFile::new(original.path().to_path_buf(), staging.clone(), None);
+    assert_eq!(check_dotfile(&staged), LinkState::Ok);
+
+    fs::remove_file(original.path()).unwrap();
+    assert_eq!(check_dotfile(&staged), LinkState::StagingDangling);
+}
+
+#[test]
+fn test_status_command_exit_code_reflects_drift() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    let source = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+
+    let file = source.child("app.conf");
+    file.write_str("content").unwrap();
+
+    ctx.forge_cmd()
+        .arg("stage")
+        .arg(file.path())
+        .current_dir(managed.path())
+        .assert()
+        .success();
+    ctx.forge_cmd()
+        .arg("link")
+        .current_dir(managed.path())
+        .assert()
+        .success();
+
+    ctx.forge_cmd()
+        .arg("status")
+        .current_dir(managed.path())
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("0 drifted"));
+
+    // Simulate an editor replacing the symlink with a regular file
+    fs::remove_file(file.path()).unwrap();
+    fs::write(file.path(), "edited").unwrap();
+
+    ctx.forge_cmd()
+        .arg("status")
+        .current_dir(managed.path())
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("replaced by regular file"));
+}
+
+#[test]
+fn test_status_fails_when_database_cannot_be_read() {
+    let ctx = common::TestContext::new();
+    let managed = TempDir::new().unwrap();
+    ctx.init_forge_repo(&managed).unwrap();
+
+    let conn = rusqlite::Connection::open(ctx.db_path()).unwrap();
+    conn.execute("DROP TABLE dotfiles", []).unwrap();
+    drop(conn);
+
+    ctx.forge_cmd()
+        .arg("status")
+        .current_dir(managed.path())
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Error fetching dotfiles"));
+}
@@ -0,0 +1,277 @@
+// Tests for GNU Stow compatibility
+#![cfg(unix)]
+
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use rusqlite::Connection;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::Path;
+
+mod common;
+
+/// A home directory with a stow directory `dotfiles` holding three packages,
+/// laid out as `stow vim nvim` leaves it: .vimrc linked file by file, the
+/// nvim config folded into one directory link, and bash never stowed
+fn stowed_home(root: &Path) -> std::path::PathBuf {
+    let home = root.join("home");
+    let stow = home.join("dotfiles");
+    fs::create_dir_all(stow.join("vim")).unwrap();
+    fs::create_dir_all(stow.join("nvim/.config/nvim")).unwrap();
+    fs::create_dir_all(stow.join("bash")).unwrap();
+    fs::write(stow.join("vim/.vimrc"), "set nu").unwrap();
+    fs::write(stow.join("nvim/.config/nvim/init.lua"), "-- nvim").unwrap();
+    fs::write(stow.join("bash/.bashrc"), "alias l=ls").unwrap();
+
+    fs::create_dir(home.join(".config")).unwrap();
+    symlink("dotfiles/vim/.vimrc", home.join(".vimrc")).unwrap();
+    symlink("../dotfiles/nvim/.config/nvim", home.join(".config/nvim")).unwrap();
+    home
+}
+
+fn linked_rows(ctx: &common::TestContext) -> Vec<(String, String)> {
+    let conn = Connection::open(ctx.db_path()).unwrap();
+    let mut stmt = conn
+        .prepare("SELECT source, target FROM dotfiles WHERE status = 'linked' AND active = 1 ORDER BY id")
+        .unwrap();
+    stmt.query_map([], |row| {
+        let source: Vec<u8> = row.get(0)?;
+        let target: Vec<u8> = row.get(1)?;
+        Ok((
+            String::from_utf8(source).unwrap(),
+            String::from_utf8(target).unwrap(),
+        ))
+    })
+    .unwrap()
+    .map(Result::unwrap)
+    .collect()
+}
+
+#[test]
+fn test_adopt_stow_registers_existing_links() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = stowed_home(root.path());
+    let stow = home.join("dotfiles");
+
+    ctx.forge_in(&home)
+        .arg("adopt-stow")
+        .arg(&stow)
+        .arg("--dry-run")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Would adopt 2 link(s) from 3 package(s)",
+        ));
+    assert!(linked_rows(&ctx).is_empty());
+
+    ctx.forge_in(&home)
+        .arg("adopt-stow")
+        .arg(&stow)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Package 'bash' is not stowed"))
+        .stdout(predicate::str::contains(
+            "Adopted 2 link(s) from 3 package(s)",
+        ));
+
+    assert_eq!(
+        linked_rows(&ctx),
+        vec![
+            (
+                "$XDG_CONFIG_HOME/nvim".to_string(),
+                "$FOLDER/nvim/.config/nvim".to_string()
+            ),
+            ("$HOME/.vimrc".to_string(), "$FOLDER/vim/.vimrc".to_string()),
+        ]
+    );
+
+    // Nothing on disk was relinked
+    assert_eq!(
+        fs::read_link(home.join(".vimrc")).unwrap(),
+        Path::new("dotfiles/vim/.vimrc")
+    );
+    assert_eq!(
+        fs::read_link(home.join(".config/nvim")).unwrap(),
+        Path::new("../dotfiles/nvim/.config/nvim")
+    );
+    assert!(!home.join(".bashrc").exists());
+
+    // The stow directory is now a managed folder, and adopting again is a no-op
+    ctx.forge_in(&home)
+        .args(["folders", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("dotfiles"));
+    ctx.forge_in(&home)
+        .arg("adopt-stow")
+        .arg(&stow)
+        .arg("vim")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "Adopted 0 link(s) from 1 package(s): 1 already tracked",
+        ));
+}
+
+#[test]
+fn test_adopt_stow_with_explicit_target() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = stowed_home(root.path());
+
+    // The stow directory lives elsewhere; links point at it absolutely
+    let stow = root.path().join("stow");
+    fs::rename(home.join("dotfiles"), &stow).unwrap();
+    fs::remove_file(home.join(".vimrc")).unwrap();
+    symlink(stow.join("vim/.vimrc"), home.join(".vimrc")).unwrap();
+
+    ctx.forge_in(&home)
+        .arg("adopt-stow")
+        .arg(&stow)
+        .arg("vim")
+        .arg("missing")
+        .args(["--target"])
+        .arg(&home)
+        .args(["--name", "legacy"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No such package: missing"));
+
+    let rows = linked_rows(&ctx);
+    assert_eq!(
+        rows,
+        vec![("$HOME/.vimrc".to_string(), "$FOLDER/vim/.vimrc".to_string())]
+    );
+    ctx.forge_in(&home)
+        .args(["--folder", "legacy", "status"])
+        .assert()
+        .success();
+}
+
+/// A home directory with a managed folder `dotfiles` holding three packages
+fn packages_home(ctx: &common::TestContext, root: &Path) -> std::path::PathBuf {
+    let home = root.join("home");
+    let dots = home.join("dotfiles");
+    fs::create_dir_all(dots.join("vim")).unwrap();
+    fs::create_dir_all(dots.join("nvim/.config/nvim")).unwrap();
+    fs::create_dir_all(dots.join("fish/.config/fish")).unwrap();
+    fs::write(dots.join("vim/.vimrc"), "set nu").unwrap();
+    fs::write(dots.join("nvim/.config/nvim/init.lua"), "-- nvim").unwrap();
+    fs::write(dots.join("fish/.config/fish/config.fish"), "# fish").unwrap();
+    ctx.forge_in(&home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    home
+}
+
+#[test]
+fn test_stow_folds_and_unfolds_shared_directories() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = packages_home(&ctx, root.path());
+    let dots = home.join("dotfiles");
+
+    ctx.forge_in(&home)
+        .args(["stow", "vim", "nvim"])
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_link(home.join(".vimrc")).unwrap(),
+        dots.join("vim/.vimrc")
+    );
+    // Only nvim needs .config, so it is one folded link
+    assert_eq!(
+        fs::read_link(home.join(".config")).unwrap(),
+        dots.join("nvim/.config")
+    );
+
+    // A second package needs .config: it becomes a real directory
+    ctx.forge_in(&home)
+        .args(["stow", "fish"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Unfolded: "));
+    assert!(!home.join(".config").is_symlink());
+    assert_eq!(
+        fs::read_link(home.join(".config/nvim")).unwrap(),
+        dots.join("nvim/.config/nvim")
+    );
+    assert_eq!(
+        fs::read_link(home.join(".config/fish")).unwrap(),
+        dots.join("fish/.config/fish")
+    );
+    ctx.forge_in(&home).arg("status").assert().success();
+
+    // Stowing again changes nothing
+    ctx.forge_in(&home)
+        .args(["stow", "fish"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("already stowed"));
+
+    // With fish gone, .config folds back into one link
+    ctx.forge_in(&home)
+        .args(["unstow", "fish"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Folded: "));
+    assert_eq!(
+        fs::read_link(home.join(".config")).unwrap(),
+        dots.join("nvim/.config")
+    );
+
+    ctx.forge_in(&home)
+        .args(["unstow", "nvim"])
+        .assert()
+        .success();
+    assert!(fs::symlink_metadata(home.join(".config")).is_err());
+    assert!(home.join(".vimrc").is_symlink());
+    assert_eq!(
+        linked_rows(&ctx),
+        vec![("$HOME/.vimrc".to_string(), "$FOLDER/vim/.vimrc".to_string())]
+    );
+
+    // Each step can be undone
+    ctx.forge_in(&home).arg("undo").assert().success();
+    assert_eq!(
+        fs::read_link(home.join(".config")).unwrap(),
+        dots.join("nvim/.config")
+    );
+    ctx.forge_in(&home).arg("undo").assert().success();
+    assert_eq!(
+        fs::read_link(home.join(".config/fish")).unwrap(),
+        dots.join("fish/.config/fish")
+    );
+}
+
+#[test]
+fn test_stow_refuses_conflicts() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = packages_home(&ctx, root.path());
+    fs::create_dir(home.join(".config")).unwrap();
+    fs::create_dir(home.join(".config/fish")).unwrap();
+    fs::write(home.join(".config/fish/config.fish"), "# mine").unwrap();
+
+    ctx.forge_in(&home)
+        .args(["stow", "nvim", "fish"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("Package 'fish' not stowed"));
+
+    // nvim went into the existing directory; fish left everything alone
+    assert!(home.join(".config/nvim").is_symlink());
+    assert_eq!(
+        fs::read_to_string(home.join(".config/fish/config.fish")).unwrap(),
+        "# mine"
+    );
+
+    ctx.forge_in(&home)
+        .args(["stow", "nope"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("No such package: nope"));
+}
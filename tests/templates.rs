@@ -0,0 +1,254 @@
+// Tests for template dotfiles and forge render
+#![cfg(unix)]
+
+use assert_fs::TempDir;
+use predicates::prelude::*;
+use std::fs;
+
+mod common;
+
+#[test]
+fn test_template_link_render_and_unlink() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    let dots = home.join("dots");
+    fs::create_dir_all(&dots).unwrap();
+    let (deployed, managed) = (home.join(".gitconfig"), dots.join(".gitconfig"));
+    fs::write(
+        &deployed,
+        "[user]\n  name = {{ user }}\n  email = {{ var.email }}\n# on {{ os }}, not \\{{ os }}\n",
+    )
+    .unwrap();
+
+    ctx.forge_in(&home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .args(["stage", "--mode", "template"])
+        .arg(&deployed)
+        .current_dir(&dots)
+        .assert()
+        .success();
+
+    // An undefined value stops the link before anything is moved
+    ctx.forge_in(&home)
+        .arg("link")
+        .current_dir(&dots)
+        .assert()
+        .stderr(predicate::str::contains(
+            "'var.email' is not defined; set it with 'forge config var.email VALUE'",
+        ));
+    assert!(
+        fs::read_to_string(&deployed)
+            .unwrap()
+            .contains("{{ var.email }}")
+    );
+
+    ctx.forge_in(&home)
+        .args(["config", "var.email", "alice@home.example"])
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .arg("link")
+        .current_dir(&dots)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Created template: "));
+    let rendered = format!(
+        "[user]\n  name = alice\n  email = alice@home.example\n# on {}, not {{{{ os }}}}\n",
+        std::env::consts::OS
+    );
+    assert_eq!(fs::read_to_string(&deployed).unwrap(), rendered);
+    assert!(
+        fs::read_to_string(&managed)
+            .unwrap()
+            .contains("{{ var.email }}")
+    );
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(template): OK"));
+    ctx.forge_in(&home)
+        .arg("list")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[template]"));
+
+    // A changed value is previewed, then rendered
+    ctx.forge_in(&home)
+        .args(["config", "var.email", "alice@work.example"])
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains(
+            "template changed, run 'forge render'",
+        ));
+    ctx.forge_in(&home)
+        .args(["render", "--check"])
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("(would change)"))
+        .stdout(predicate::str::contains("email = alice@work.example"));
+    assert!(
+        fs::read_to_string(&deployed)
+            .unwrap()
+            .contains("alice@home.example")
+    );
+    ctx.forge_in(&home)
+        .arg("render")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Rendered 1, 0 already up to date"));
+    assert!(
+        fs::read_to_string(&deployed)
+            .unwrap()
+            .contains("alice@work.example")
+    );
+    ctx.forge_in(&home)
+        .args(["render", "--check"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("(up to date)"));
+
+    // Rendering can be undone
+    ctx.forge_in(&home).arg("undo").assert().success();
+    assert!(
+        fs::read_to_string(&deployed)
+            .unwrap()
+            .contains("alice@home.example")
+    );
+    ctx.forge_in(&home).arg("render").assert().success();
+
+    // Linking again renders an edited template
+    fs::write(&managed, "[user]\n  email = {{ var.email }}\n").unwrap();
+    ctx.forge_in(&home)
+        .arg("link")
+        .current_dir(&dots)
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Templates: 1 rendered"));
+    assert_eq!(
+        fs::read_to_string(&deployed).unwrap(),
+        "[user]\n  email = alice@work.example\n"
+    );
+
+    // A hand edit is flagged, and kept in the backup store when replaced
+    fs::write(&deployed, "edited by hand\n").unwrap();
+    ctx.forge_in(&home)
+        .arg("status")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("rendered file edited"));
+    ctx.forge_in(&home)
+        .args(["unlink", "--yes"])
+        .arg(&managed)
+        .assert()
+        .stderr(predicate::str::contains(
+            "changes that are not in the template",
+        ));
+    ctx.forge_in(&home)
+        .arg("render")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(
+            "The edited file was saved as backup #",
+        ));
+    ctx.forge_in(&home)
+        .args(["backups", "list"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("render"));
+
+    // Unlinking leaves the rendered output in place, not the template
+    ctx.forge_in(&home)
+        .args(["unlink", "--yes"])
+        .arg(&managed)
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(&deployed).unwrap(),
+        "[user]\n  email = alice@work.example\n"
+    );
+    ctx.forge_in(&home)
+        .arg("render")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("No templates to render"));
+}
+
+#[test]
+fn test_template_values_from_config_and_environment() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = root.path().join("home");
+    let dots = home.join("dots");
+    fs::create_dir_all(&dots).unwrap();
+    let deployed = home.join(".profile");
+    fs::write(&deployed, "export EDITOR={{ env.FORGE_TEST_EDITOR }}\n").unwrap();
+
+    ctx.forge_in(&home)
+        .arg("init")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .arg("stage")
+        .arg(&deployed)
+        .current_dir(&dots)
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .args(["link", "--mode", "template"])
+        .current_dir(&dots)
+        .assert()
+        .stderr(predicate::str::contains(
+            "environment variable FORGE_TEST_EDITOR is not set",
+        ));
+    ctx.forge_in(&home)
+        .args(["link", "--mode", "template"])
+        .env("FORGE_TEST_EDITOR", "vim")
+        .current_dir(&dots)
+        .assert()
+        .success();
+    assert_eq!(
+        fs::read_to_string(&deployed).unwrap(),
+        "export EDITOR=vim\n"
+    );
+    ctx.forge_in(&home)
+        .arg("status")
+        .env("FORGE_TEST_EDITOR", "nano")
+        .assert()
+        .failure()
+        .stdout(predicate::str::contains("template changed"));
+
+    // User-defined values are listed with the settings and can be removed
+    ctx.forge_in(&home)
+        .args(["config", "var.host-color", "green"])
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .arg("config")
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("var.host-color = green"));
+    ctx.forge_in(&home)
+        .args(["config", "--unset", "var.host-color"])
+        .assert()
+        .success();
+    ctx.forge_in(&home)
+        .args(["config", "var.host-color"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("var.host-color is not set"));
+    ctx.forge_in(&home)
+        .args(["config", "--unset", "relative_links"])
+        .assert()
+        .failure();
+}
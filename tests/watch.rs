@@ -0,0 +1,142 @@
+// Tests for forge watch
+#![cfg(target_os = "linux")]
+
+use assert_fs::TempDir;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::{Child, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::time::{Duration, Instant};
+
+mod common;
+
+/// A running `forge watch` whose output is read line by line
+struct Watch {
+    child: Child,
+    lines: Receiver<String>,
+}
+
+impl Watch {
+    fn start(ctx: &common::TestContext, home: &Path, policy: &str) -> Self {
+        let mut child = ctx
+            .process_in(home)
+            .args(["watch", "--policy", policy])
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = child.stdout.take().unwrap();
+        let (send, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                if send.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut watch = Watch { child, lines };
+        watch.expect("Watching ");
+        watch
+    }
+
+    /// Wait for a line containing `text`, returning it
+    fn expect(&mut self, text: &str) -> String {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut seen = Vec::new();
+        while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+            match self.lines.recv_timeout(left) {
+                Ok(line) if line.contains(text) => return line,
+                Ok(line) => seen.push(line),
+                Err(_) => break,
+            }
+        }
+        panic!("no line containing {:?} in {:?}", text, seen);
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn setup(ctx: &common::TestContext, root: &Path) -> std::path::PathBuf {
+    let home = root.join("home");
+    let dots = home.join("dots");
+    fs::create_dir_all(home.join(".config/nvim")).unwrap();
+    fs::write(home.join(".vimrc"), "set number\n").unwrap();
+    fs::write(home.join(".config/nvim/init.lua"), "-- nvim\n").unwrap();
+
+    let run = |args: &[&str]| {
+        let output = ctx.process_in(&home).args(args).output().unwrap();
+        assert!(
+            output.status.success(),
+            "forge {:?} failed: {:?}",
+            args,
+            output
+        );
+    };
+    run(&["init", "--dir", dots.to_str().unwrap()]);
+    run(&["stage", home.join(".vimrc").to_str().unwrap()]);
+    run(&["stage", home.join(".config/nvim").to_str().unwrap()]);
+    run(&["link"]);
+    home
+}
+
+#[test]
+fn test_watch_repairs_a_symlink_replaced_by_an_editor() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = setup(&ctx, root.path());
+    let (vimrc, managed) = (home.join(".vimrc"), home.join("dots/.vimrc"));
+
+    let mut watch = Watch::start(&ctx, &home, "repair");
+
+    // Save as editors do: write a temporary file and rename it over the link
+    fs::write(home.join(".vimrc.tmp"), "set number\nset ruler\n").unwrap();
+    fs::rename(home.join(".vimrc.tmp"), &vimrc).unwrap();
+    watch.expect("replaced by regular file");
+    watch.expect("Repaired: ");
+
+    assert!(fs::symlink_metadata(&vimrc).unwrap().is_symlink());
+    assert_eq!(
+        fs::read_to_string(&managed).unwrap(),
+        "set number\nset ruler\n"
+    );
+    // The managed copy it replaced was backed up
+    let backups = ctx
+        .process_in(&home)
+        .args(["backups", "list"])
+        .output()
+        .unwrap();
+    assert!(String::from_utf8_lossy(&backups.stdout).contains(".vimrc"));
+    drop(watch);
+
+    let log = fs::read_to_string(Path::new(ctx.config_path()).join("watch.log")).unwrap();
+    assert!(log.contains("Repaired: "), "{}", log);
+}
+
+#[test]
+fn test_watch_reports_deleted_and_new_files() {
+    let ctx = common::TestContext::new();
+    let root = TempDir::new().unwrap();
+    let home = setup(&ctx, root.path());
+    let managed = home.join("dots/.vimrc");
+
+    let mut watch = Watch::start(&ctx, &home, "log");
+
+    fs::write(home.join(".config/nvim/lazy.lua"), "-- plugins\n").unwrap();
+    let line = watch.expect("New file in tracked directory");
+    assert!(line.ends_with(": lazy.lua"), "{}", line);
+
+    fs::remove_file(&managed).unwrap();
+    watch.expect("managed copy missing");
+
+    // Logging never touches anything
+    fs::write(&managed, "set number\n").unwrap();
+    watch.expect("Back in place: ");
+    fs::remove_file(home.join(".vimrc")).unwrap();
+    watch.expect("link missing");
+    assert!(fs::symlink_metadata(home.join(".vimrc")).is_err());
+}